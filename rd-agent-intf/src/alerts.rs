@@ -0,0 +1,225 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::scenario::select_num;
+use super::CmpOp;
+use rd_util::*;
+
+const ALERTS_DOC: &str = "\
+//
+// rd-agent alert rules
+//
+// Rules are evaluated against each per-second report. The fields are
+// looked up in report.json except for the ones starting with cmd. which
+// are looked up in cmd.json.
+//
+//  rules[].name: Alert name, must be unique
+//  rules[].conds[]: Conditions which must all hold for the rule to match
+//  rules[].conds[].field: Dot-separated path, e.g.
+//                         usages.workload.slice.mem_pressures.1
+//  rules[].conds[].op: One of <, <=, >, >=, == and !=
+//  rules[].conds[].value: The number to compare against
+//  rules[].conds[].ref_field: If set, compare against value times this
+//                             field instead, e.g. value 2 and ref_field
+//                             cmd.hashd.A.lat_target for 2x the target
+//  rules[].duration: The conditions must hold this many seconds in a row
+//                    for the alert to become active
+//  rules[].actions[]: What to do when the alert becomes active
+//  rules[].actions[].exec: Run the command as a transient service in
+//                          system.slice, the alert name and condition
+//                          values are passed in RD_ALERT_NAME and
+//                          RD_ALERT_VALUES
+//  rules[].actions[].event: Record an Alert event, see rd_agent_intf::events
+//  rules[].actions[].cmd: JSON merge patch (RFC 7396) to apply to cmd.json,
+//                         e.g. {\"sideloads\": null} to stop all sideloads
+//
+";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AlertCond {
+    pub field: String,
+    pub op: CmpOp,
+    pub value: f64,
+    #[serde(default)]
+    pub ref_field: Option<String>,
+}
+
+impl AlertCond {
+    /// Returns the field value if the condition holds on `doc`, see
+    /// ALERTS_DOC for what `doc` should look like.
+    pub fn eval(&self, doc: &serde_json::Value) -> Option<f64> {
+        let val = select_num(doc, &self.field)?;
+        let rhs = match self.ref_field.as_ref() {
+            Some(rf) => self.value * select_num(doc, rf)?,
+            None => self.value,
+        };
+        if self.op.eval(val, rhs) {
+            Some(val)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for AlertCond {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.ref_field.as_ref() {
+            Some(rf) => write!(
+                f,
+                "{} {} {} * {}",
+                &self.field,
+                self.op.symbol(),
+                self.value,
+                rf
+            ),
+            None => write!(f, "{} {} {}", &self.field, self.op.symbol(), self.value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertAction {
+    Exec(Vec<String>),
+    Event,
+    Cmd(serde_json::Value),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertRule {
+    pub name: String,
+    pub conds: Vec<AlertCond>,
+    pub duration: f64,
+    pub actions: Vec<AlertAction>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertRules {
+    pub rules: Vec<AlertRule>,
+}
+
+impl AlertRules {
+    /// Check the rules. Returns FIELD -> ERROR pairs, empty if valid.
+    pub fn validate(&self) -> BTreeMap<String, String> {
+        let mut errs = BTreeMap::new();
+        let mut check = |field: String, ok: bool, msg: &str| {
+            if !ok {
+                errs.insert(field, msg.to_string());
+            }
+        };
+
+        for (i, rule) in self.rules.iter().enumerate() {
+            let pre = format!("rules[{}]", i);
+            check(
+                format!("{}.name", &pre),
+                !rule.name.trim().is_empty(),
+                "should not be empty",
+            );
+            check(
+                format!("{}.name", &pre),
+                self.rules[..i].iter().all(|r| r.name != rule.name),
+                "should be unique",
+            );
+            check(
+                format!("{}.conds", &pre),
+                !rule.conds.is_empty(),
+                "should have at least one condition",
+            );
+            for (j, cond) in rule.conds.iter().enumerate() {
+                check(
+                    format!("{}.conds[{}].field", &pre, j),
+                    !cond.field.trim().is_empty(),
+                    "should not be empty",
+                );
+                check(
+                    format!("{}.conds[{}].value", &pre, j),
+                    cond.value.is_finite(),
+                    "should be finite",
+                );
+            }
+            check(
+                format!("{}.duration", &pre),
+                rule.duration >= 0.0 && rule.duration.is_finite(),
+                "should be zero or positive",
+            );
+            for (j, act) in rule.actions.iter().enumerate() {
+                let ok = match act {
+                    AlertAction::Exec(args) => !args.is_empty() && !args[0].is_empty(),
+                    AlertAction::Event => true,
+                    AlertAction::Cmd(patch) => patch.is_object(),
+                };
+                check(
+                    format!("{}.actions[{}]", &pre, j),
+                    ok,
+                    "exec should have a command and cmd should be an object",
+                );
+            }
+        }
+        errs
+    }
+}
+
+impl JsonLoad for AlertRules {}
+
+impl JsonSave for AlertRules {
+    fn preamble() -> Option<String> {
+        Some(ALERTS_DOC.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alert_cond() {
+        let doc = serde_json::json!({
+            "hashd": { "A": { "lat": { "ctl": 0.2 } } },
+            "cmd": { "hashd": { "A": { "lat_target": 0.075 } } },
+        });
+        let mut cond = AlertCond {
+            field: "hashd.A.lat.ctl".into(),
+            op: CmpOp::Gt,
+            value: 2.0,
+            ref_field: Some("cmd.hashd.A.lat_target".into()),
+        };
+        assert_eq!(cond.eval(&doc), Some(0.2));
+        cond.value = 3.0;
+        assert_eq!(cond.eval(&doc), None);
+        cond.ref_field = Some("cmd.hashd.B.lat_target".into());
+        assert_eq!(cond.eval(&doc), None);
+        cond.ref_field = None;
+        cond.value = 0.1;
+        assert_eq!(cond.eval(&doc), Some(0.2));
+    }
+
+    #[test]
+    fn test_validate() {
+        let rules: AlertRules = serde_json::from_str(
+            r#"{ "rules": [
+                { "name": "mem", "duration": 10,
+                  "conds": [ { "field": "usages.workload.slice.mem_pressures.1",
+                               "op": ">", "value": 20 } ],
+                  "actions": [ "event", { "exec": ["/bin/true"] },
+                               { "cmd": { "sideloads": null } } ] },
+                { "name": "mem", "duration": -1, "conds": [],
+                  "actions": [ { "exec": [] }, { "cmd": 1 } ] }
+            ] }"#,
+        )
+        .unwrap();
+        let errs = rules.validate();
+        assert_eq!(
+            errs.keys().collect::<Vec<_>>(),
+            vec![
+                "rules[1].actions[0]",
+                "rules[1].actions[1]",
+                "rules[1].conds",
+                "rules[1].duration",
+                "rules[1].name",
+            ]
+        );
+    }
+}
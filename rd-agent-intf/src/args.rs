@@ -6,17 +6,22 @@ use std::sync::Mutex;
 
 use rd_util::*;
 
+const SCENARIO_USAGE: &str = "<FILE> 'Scenario script to run, see rd_agent_intf::scenario'";
+
 lazy_static::lazy_static! {
     static ref ARGS_STR: String = format!(
         "-d, --dir=[TOPDIR]     'Top-level dir for operation and scratch files (default: {dfl_dir})'
          -s, --scratch=[DIR]    'Scratch dir for workloads to use (default: $TOPDIR/scratch)'
          -D, --dev=[NAME]       'Override storage device autodetection (e.g. sda, nvme0n1)'
+             --devs=[NAMES]     'Additional storage devices to manage, comma separated (e.g. sdb,nvme1n1)'
          -r, --rep-retention=[SECS]      '1s report retention in seconds (default: {dfl_rep_ret:.1}h)'
          -R, --rep-1min-retention=[SECS] '1m report retention in seconds (default: {dfl_rep_1m_ret:.1}h)'
              --systemd-timeout=[SECS] 'Systemd timeout (default: {dfl_systemd_timeout})'
-             --passive=[SELS]   'Avoid system config changes (SELS=ALL/all/cpu/mem/io/fs/oomd/none)'
+             --metrics=[ADDR]   'Serve OpenMetrics on HOST:PORT or unix socket PATH'
+             --metrics-textfile=[FILE] 'Dump metrics to FILE for textfile collectors'
+             --passive=[SELS]   'Avoid system config changes (SELS=ALL/all/cpu/mem/io/pids/fs/oomd/none)'
          -a, --args=[FILE]      'Load base command line arguments from FILE'
-             --no-iolat         'Disable bpf-based io latency stat monitoring'
+             --no-iolat         'Estimate io latencies from block stats instead of using bpf'
              --force            'Ignore startup check results and proceed'
              --force-running    'Ignore bench requirements and enter Running state'
              --prepare          'Prepare the files and directories and exit'
@@ -24,6 +29,8 @@ lazy_static::lazy_static! {
              --bench-file=[FILE] 'Bench file name override'
              --reset            'Reset all states except for bench results, linux.tar and testfiles'
              --keep-reports     'Don't delete expired report files, also affects --reset'
+             --rep-store        'Store reports in compressed segments instead of per-report files'
+             --convert-reports  'Fold existing per-report files into segments and exit'
              --bypass           'Skip startup and periodic health checks'
          -v...                  'Sets the level of verbosity'",
         dfl_dir = Args::default().dir,
@@ -41,15 +48,28 @@ lazy_static::lazy_static! {
          -p, --report=[PATH]          'Report file path'"
     );
 
+    static ref REPORT_QUERY_USAGE: String = format!(
+        "<FIELD>...               'Field paths to select (e.g. usages.workload.slice.mem_pressures)'
+         -s, --since=[TIME]       'Start of the range (default: {dfl_since})'
+         -u, --until=[TIME]       'End of the range (default: now)'
+         -m, --minute             'Query the per-minute reports instead of the per-second ones'
+         -A, --aggr=[AGGRS]       'Aggregate values (mean,min,max,count,pNN separated by comma)'
+         -i, --interval=[DUR]     'Aggregate per DUR window instead of the whole range'
+         -o, --format=[FMT]       'Output format (table, csv or json, default: table)'",
+        dfl_since = ReportQueryArgs::default().since,
+    );
+
     static ref HELP_BODY: Mutex<&'static str> = Mutex::new("");
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct EnforceConfig {
     pub crit_mem_prot: bool,
     pub cpu: bool,
     pub mem: bool,
     pub io: bool,
+    pub pids: bool,
     pub fs: bool,
     pub oomd: bool,
 }
@@ -61,6 +81,7 @@ impl Default for EnforceConfig {
             cpu: true,
             mem: true,
             io: true,
+            pids: true,
             fs: true,
             oomd: true,
         }
@@ -74,6 +95,7 @@ impl EnforceConfig {
             cpu: false,
             mem: false,
             io: false,
+            pids: false,
             fs: false,
             oomd: false,
         };
@@ -97,6 +119,7 @@ impl EnforceConfig {
                 "cpu" => self.cpu = false,
                 "mem" => self.mem = false,
                 "io" => self.io = false,
+                "pids" => self.pids = false,
                 "fs" => self.fs = false,
                 "oomd" => self.oomd = false,
                 "none" => *self = Default::default(),
@@ -111,7 +134,7 @@ impl EnforceConfig {
         let mut buf = String::new();
         if !self.crit_mem_prot {
             write!(buf, "ALL").unwrap();
-        } else if !self.cpu && !self.mem && !self.io && !self.fs && !self.oomd {
+        } else if !self.cpu && !self.mem && !self.io && !self.pids && !self.fs && !self.oomd {
             write!(buf, "all").unwrap();
         } else {
             if !self.cpu {
@@ -123,6 +146,9 @@ impl EnforceConfig {
             if !self.io {
                 write!(buf, "io/").unwrap();
             }
+            if !self.pids {
+                write!(buf, "pids/").unwrap();
+            }
             if !self.fs {
                 write!(buf, "fs/").unwrap();
             }
@@ -135,7 +161,7 @@ impl EnforceConfig {
     }
 
     pub fn all(&self) -> bool {
-        self.crit_mem_prot && self.cpu && self.mem && self.io && self.fs && self.oomd
+        self.crit_mem_prot && self.cpu && self.mem && self.io && self.pids && self.fs && self.oomd
     }
 }
 
@@ -162,6 +188,40 @@ impl Default for BanditMemHogArgs {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportQueryFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+/// `rd-agent report` arguments. TIME is a unix timestamp, "now", "-DUR"
+/// relative to now, "HH:MM[:SS]" of today or "YYYY-MM-DD HH:MM[:SS]".
+#[derive(Debug, Clone)]
+pub struct ReportQueryArgs {
+    pub fields: Vec<String>,
+    pub since: String,
+    pub until: String,
+    pub per_min: bool,
+    pub aggrs: Vec<String>,
+    pub interval: Option<f64>,
+    pub format: ReportQueryFormat,
+}
+
+impl Default for ReportQueryArgs {
+    fn default() -> Self {
+        Self {
+            fields: vec![],
+            since: "-10M".into(),
+            until: "now".into(),
+            per_min: false,
+            aggrs: vec![],
+            interval: None,
+            format: ReportQueryFormat::Table,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Bandit {
     MemHog(BanditMemHogArgs),
@@ -173,10 +233,13 @@ pub struct Args {
     pub dir: String,
     pub scratch: Option<String>,
     pub dev: Option<String>,
+    pub devs: Vec<String>,
     pub rep_retention: u64,
     pub rep_1min_retention: u64,
     pub systemd_timeout: f64,
     pub enforce: EnforceConfig,
+    pub metrics: Option<String>,
+    pub metrics_textfile: Option<String>,
 
     #[serde(skip)]
     pub no_iolat: bool,
@@ -195,11 +258,19 @@ pub struct Args {
     #[serde(skip)]
     pub keep_reports: bool,
     #[serde(skip)]
+    pub rep_store: bool,
+    #[serde(skip)]
+    pub convert_reports: bool,
+    #[serde(skip)]
     pub bypass: bool,
     #[serde(skip)]
     pub verbosity: u32,
 
     pub bandit: Option<Bandit>,
+    #[serde(skip)]
+    pub report_query: Option<ReportQueryArgs>,
+    #[serde(skip)]
+    pub scenario: Option<String>,
 }
 
 impl Default for Args {
@@ -208,10 +279,13 @@ impl Default for Args {
             dir: "/var/lib/resctl-demo".into(),
             scratch: None,
             dev: None,
+            devs: vec![],
             rep_retention: 3600,
             rep_1min_retention: 24 * 3600,
             systemd_timeout: systemd::SYSTEMD_DFL_TIMEOUT,
             enforce: Default::default(),
+            metrics: None,
+            metrics_textfile: None,
             no_iolat: false,
             force: false,
             force_running: false,
@@ -220,9 +294,13 @@ impl Default for Args {
             bench_file: None,
             reset: false,
             keep_reports: false,
+            rep_store: false,
+            convert_reports: false,
             bypass: false,
             verbosity: 0,
             bandit: None,
+            report_query: None,
+            scenario: None,
         }
     }
 }
@@ -277,6 +355,39 @@ impl Args {
         }
         updated_base
     }
+
+    fn process_report_query(&mut self, subm: &clap::ArgMatches) {
+        let mut args = ReportQueryArgs::default();
+        if let Some(vals) = subm.values_of("FIELD") {
+            args.fields = vals.map(|x| x.to_owned()).collect();
+        }
+        if let Some(v) = subm.value_of("since") {
+            args.since = v.to_owned();
+        }
+        if let Some(v) = subm.value_of("until") {
+            args.until = v.to_owned();
+        }
+        args.per_min = subm.is_present("minute");
+        if let Some(v) = subm.value_of("aggr") {
+            args.aggrs = v
+                .split(',')
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_owned())
+                .collect();
+        }
+        if let Some(v) = subm.value_of("interval") {
+            args.interval = Some(parse_duration(v).expect("failed to parse \"interval\""));
+        }
+        if let Some(v) = subm.value_of("format") {
+            args.format = match v {
+                "table" => ReportQueryFormat::Table,
+                "csv" => ReportQueryFormat::Csv,
+                "json" => ReportQueryFormat::Json,
+                v => panic!("unknown output format {:?}", v),
+            };
+        }
+        self.report_query = Some(args);
+    }
 }
 
 impl JsonArgs for Args {
@@ -291,6 +402,16 @@ impl JsonArgs for Args {
                     .about("Bandit mode - keep bloating up memory")
                     .args_from_usage(&BANDIT_MEM_HOG_USAGE),
             )
+            .subcommand(
+                clap::SubCommand::with_name("report")
+                    .about("Query and aggregate fields of the stored reports")
+                    .args_from_usage(&REPORT_QUERY_USAGE),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("scenario")
+                    .about("Run a timed scenario script against the running agent")
+                    .args_from_usage(SCENARIO_USAGE),
+            )
             .setting(clap::AppSettings::UnifiedHelpMessage)
             .setting(clap::AppSettings::DeriveDisplayOrder)
             .get_matches()
@@ -328,6 +449,14 @@ impl JsonArgs for Args {
             };
             updated_base = true;
         }
+        if let Some(v) = matches.value_of("devs") {
+            self.devs = v
+                .split(',')
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_string())
+                .collect();
+            updated_base = true;
+        }
 
         if let Some(v) = matches.value_of("rep-retention") {
             self.rep_retention = if v.len() > 0 {
@@ -356,6 +485,24 @@ impl JsonArgs for Args {
             updated_base = true;
         }
 
+        if let Some(v) = matches.value_of("metrics") {
+            self.metrics = if !v.is_empty() {
+                Some(v.to_string())
+            } else {
+                None
+            };
+            updated_base = true;
+        }
+
+        if let Some(v) = matches.value_of("metrics-textfile") {
+            self.metrics_textfile = if !v.is_empty() {
+                Some(v.to_string())
+            } else {
+                None
+            };
+            updated_base = true;
+        }
+
         self.no_iolat = matches.is_present("no-iolat");
         self.force = matches.is_present("force");
         self.force_running = matches.is_present("force-running");
@@ -364,6 +511,8 @@ impl JsonArgs for Args {
         self.bench_file = matches.value_of("bench-file").map(|x| x.to_string());
         self.reset = matches.is_present("reset");
         self.keep_reports = matches.is_present("keep-reports");
+        self.rep_store = matches.is_present("rep-store");
+        self.convert_reports = matches.is_present("convert-reports");
         self.verbosity = Self::verbosity(&matches);
         self.bypass = matches.is_present("bypass");
 
@@ -372,8 +521,13 @@ impl JsonArgs for Args {
             None => self.enforce = Default::default(),
         }
 
-        if let (bandit, Some(subm)) = matches.subcommand() {
-            updated_base |= self.process_bandit(bandit, subm);
+        match matches.subcommand() {
+            ("report", Some(subm)) => self.process_report_query(subm),
+            ("scenario", Some(subm)) => {
+                self.scenario = subm.value_of("FILE").map(|x| x.to_owned());
+            }
+            (bandit, Some(subm)) => updated_base |= self.process_bandit(bandit, subm),
+            _ => {}
         }
 
         updated_base
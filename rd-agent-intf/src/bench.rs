@@ -1,7 +1,8 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
-use anyhow::Result;
+use anyhow::{bail, Result};
 use chrono::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 use std::time::SystemTime;
 
 use rd_util::*;
@@ -21,9 +22,15 @@ const BENCH_DOC: &str = "\
 //  hashd[].mem_frac: Memory size is mem_size * mem_frac, tune this if needed
 //  hashd[].chunk_pages: Memory access chunk size in pages
 //  hashd[].fake_cpu_load: Bench was run with --bench-fake-cpu-load
-//  iocost.devnr: Storage device devnr
-//  iocost.model: Model parameters
-//  iocost.qos: QoS parameters
+//  iocost{}: Per-device iocost parameters keyed by MAJ:MIN, devices without
+//            an entry use the kernel defaults
+//  iocost{}.devnr: Storage device devnr
+//  iocost{}.model: Model parameters
+//  iocost{}.qos: QoS parameters
+//  iocost_devnr: The scratch device the iocost benchmark ran on
+//  iocost_dev_model: Scratch device model
+//  iocost_dev_fwrev: Scratch device firmware revision
+//  iocost_dev_size: Scratch device size
 //
 ";
 
@@ -69,13 +76,38 @@ pub struct IoCostKnobs {
     pub qos: IoCostQoSParams,
 }
 
+/// iocost parameters used to be for the scratch device only. Accept the
+/// single device form too and key it with its devnr.
+fn deserialize_iocost_map<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<String, IoCostKnobs>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum MapOrSingle {
+        Map(BTreeMap<String, IoCostKnobs>),
+        Single(IoCostKnobs),
+    }
+
+    Ok(match MapOrSingle::deserialize(deserializer)? {
+        MapOrSingle::Map(map) => map,
+        MapOrSingle::Single(knobs) if knobs.devnr.is_empty() => BTreeMap::new(),
+        MapOrSingle::Single(knobs) => std::iter::once((knobs.devnr.clone(), knobs)).collect(),
+    })
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BenchKnobs {
     pub timestamp: DateTime<Local>,
     pub hashd_seq: u64,
     pub iocost_seq: u64,
     pub hashd: HashdKnobs,
-    pub iocost: IoCostKnobs,
+    #[serde(deserialize_with = "deserialize_iocost_map")]
+    pub iocost: BTreeMap<String, IoCostKnobs>,
+    #[serde(default)]
+    pub iocost_devnr: String,
     pub iocost_dev_model: String,
     pub iocost_dev_fwrev: String,
     pub iocost_dev_size: u64,
@@ -89,6 +121,7 @@ impl Default for BenchKnobs {
             iocost_seq: 0,
             hashd: Default::default(),
             iocost: Default::default(),
+            iocost_devnr: String::new(),
             iocost_dev_model: String::new(),
             iocost_dev_fwrev: String::new(),
             iocost_dev_size: 0,
@@ -96,9 +129,40 @@ impl Default for BenchKnobs {
     }
 }
 
+impl BenchKnobs {
+    /// iocost parameters of the scratch device, defaults if not benchmarked.
+    pub fn scr_iocost(&self) -> IoCostKnobs {
+        self.iocost
+            .get(&self.iocost_devnr)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Mutable iocost parameters of the scratch device. Fails if the
+    /// scratch device isn't known yet.
+    pub fn scr_iocost_mut(&mut self) -> Result<&mut IoCostKnobs> {
+        if self.iocost_devnr.is_empty() {
+            bail!("scratch device for iocost parameters unknown");
+        }
+        let devnr = self.iocost_devnr.clone();
+        Ok(self
+            .iocost
+            .entry(devnr.clone())
+            .or_insert_with(|| IoCostKnobs {
+                devnr,
+                ..Default::default()
+            }))
+    }
+}
+
 impl JsonLoad for BenchKnobs {
     fn loaded(&mut self, _prev: Option<&mut Self>) -> Result<()> {
-        self.iocost.qos.sanitize();
+        if self.iocost_devnr.is_empty() && self.iocost.len() == 1 {
+            self.iocost_devnr = self.iocost.keys().next().unwrap().clone();
+        }
+        for knobs in self.iocost.values_mut() {
+            knobs.qos.sanitize();
+        }
         Ok(())
     }
 }
@@ -108,3 +172,31 @@ impl JsonSave for BenchKnobs {
         Some(BENCH_DOC.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_iocost_legacy() {
+        let mut bench = BenchKnobs::default();
+        assert!(bench.scr_iocost_mut().is_err());
+        assert!(bench.iocost.is_empty());
+
+        bench.iocost_devnr = "259:0".into();
+        bench.scr_iocost_mut().unwrap().model.rbps = 1 << 30;
+        let mut val = serde_json::to_value(&bench).unwrap();
+
+        // the single device form which predates iocost_devnr
+        val["iocost_devnr"] = serde_json::json!("");
+        val["iocost"] = serde_json::json!({
+            "devnr": "259:0",
+            "model": bench.scr_iocost().model,
+            "qos": bench.scr_iocost().qos,
+        });
+        let mut legacy: BenchKnobs = serde_json::from_value(val).unwrap();
+        legacy.loaded(None).unwrap();
+        assert_eq!(legacy.iocost_devnr, "259:0");
+        assert_eq!(legacy.scr_iocost().model.rbps, 1 << 30);
+    }
+}
@@ -2,6 +2,7 @@
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
+use super::SideloadDefs;
 use rd_util::*;
 
 lazy_static::lazy_static! {
@@ -16,9 +17,11 @@ lazy_static::lazy_static! {
 // Setting it to a number equal to or lower than cancels if currently running.
 // While a benchmark is running, all other workloads are stopped.
 //
-// One or two rd-hashd instances are used as the latency sensitive primary
-// workloads. When both instances are active, between the two, resources are
-// distributed according to their relative weights.
+// Named rd-hashd instances are used as the latency sensitive primary
+// workloads. A and B are configured by default and more can be added by
+// creating new entries. Each instance runs as rd-hashd-NAME.service and the
+// name should only contain alnums, - and _. When multiple instances are
+// active, resources are distributed according to their relative weights.
 //
 // Any number of sysloads and sideloads can be used. The only difference between
 // sysloads and sideloads is that sysloads are run under system.slice without
@@ -35,18 +38,20 @@ lazy_static::lazy_static! {
 //  bench_hashd_args: Extra arguments hashd benchmark
 //  bench_iocost_seq: If > bench::iocost_seq, start benchmark; otherwise, cancel
 //  sideloader.cpu_headroom: Sideload CPU headroom ratio [0.0, 1.0]
-//  hashd[].active: On/off
-//  hashd[].lat_target_pct: Latency target percentile
-//  hashd[].lat_target: Latency target, defaults to 0.1 meaning 100ms
-//  hashd[].rps_target_ratio: RPS target as a ratio of bench::hashd.rps_max,
+//  hashd{{}}.active: On/off
+//  hashd{{}}.lat_target_pct: Latency target percentile
+//  hashd{{}}.lat_target: Latency target, defaults to 0.1 meaning 100ms
+//  hashd{{}}.rps_target_ratio: RPS target as a ratio of bench::hashd.rps_max,
 //                            if >> 1.0, no practical rps limit, default 0.5
-//  hashd[].mem_ratio: Memory footprint adj [0.0, 1.0], null to use bench result
-//  hashd[].file_ratio: Pagecache portion of memory [0.0, 1.0], default ${dfl_file_ratio}
-//  hashd[].file_max_ratio: Max file_ratio, requires hashd restart [0.0, 1.0], default ${dfl_file_max_ratio}
-//  hashd[].file_addr_stdev: Memory access stdev in ratio of mean, null to use ${dfl_file_addr_stdev}
-//  hashd[].anon_addr_stdev: Memory access stdev in ratio of mean, null to use ${dfl_anon_addr_stdev}
-//  hashd[].log_bps: IO write bandwidth, default ${dfl_log_bps}Mbps
-//  hashd[].weight: Relative weight between the two hashd instances
+//  hashd{{}}.mem_ratio: Memory footprint adj [0.0, 1.0], null to use bench result
+//  hashd{{}}.file_ratio: Pagecache portion of memory [0.0, 1.0], default ${dfl_file_ratio}
+//  hashd{{}}.file_max_ratio: Max file_ratio, requires hashd restart [0.0, 1.0], default ${dfl_file_max_ratio}
+//  hashd{{}}.file_addr_stdev: Memory access stdev in ratio of mean, null to use ${dfl_file_addr_stdev}
+//  hashd{{}}.anon_addr_stdev: Memory access stdev in ratio of mean, null to use ${dfl_anon_addr_stdev}
+//  hashd{{}}.log_bps: IO write bandwidth, default ${dfl_log_bps}Mbps
+//  hashd{{}}.weight: Relative weight between the active hashd instances
+//  hashd{{}}.numa_node: Bind CPUs and anon memory to the NUMA node, null to
+//                     disable, requires hashd restart
 //  sysloads{{}}: \"NAME\": \"DEF_ID\" pairs for active sysloads
 //  sideloads{{}}: \"NAME\": \"DEF_ID\" pairs for active sideloads
 //  swappiness: /proc/sys/vm/swappiness, null to leave as-is
@@ -82,6 +87,8 @@ pub struct HashdCmd {
     pub file_max_ratio: f64,
     pub log_bps: u64,
     pub weight: f64,
+    #[serde(default)]
+    pub numa_node: Option<u32>,
 }
 
 impl Default for HashdCmd {
@@ -98,6 +105,7 @@ impl Default for HashdCmd {
             file_max_ratio: rd_hashd_intf::Args::default().file_max_frac,
             log_bps: rd_hashd_intf::Params::default().log_bps,
             weight: 1.0,
+            numa_node: None,
         }
     }
 }
@@ -111,7 +119,8 @@ pub struct Cmd {
     pub bench_hashd_args: Vec<String>,
     pub bench_iocost_seq: u64,
     pub sideloader: SideloaderCmd,
-    pub hashd: [HashdCmd; 2],
+    #[serde(deserialize_with = "super::deserialize_hashd_map")]
+    pub hashd: BTreeMap<String, HashdCmd>,
     pub sysloads: BTreeMap<String, String>,
     pub sideloads: BTreeMap<String, String>,
     pub swappiness: Option<u32>,
@@ -120,9 +129,132 @@ pub struct Cmd {
 }
 
 impl Cmd {
+    /// Access the named rd-hashd instance. If it doesn't exist yet, it's
+    /// created with the default settings.
+    pub fn hashd_mut(&mut self, name: &str) -> &mut HashdCmd {
+        self.hashd.entry(name.into()).or_default()
+    }
+
     pub fn bench_hashd_memory_slack(mem_share: usize) -> usize {
         (mem_share / 8).min(1 << 30)
     }
+
+    /// Check the commands against the documented ranges and `side_defs`.
+    /// Returns FIELD -> ERROR pairs, empty if valid.
+    pub fn validate(&self, side_defs: &SideloadDefs) -> BTreeMap<String, String> {
+        let mut errs = BTreeMap::new();
+        let mut check = |field: String, ok: bool, msg: &str| {
+            if !ok {
+                errs.insert(field, msg.to_string());
+            }
+        };
+        let frac = |v: f64| (0.0..=1.0).contains(&v);
+        let valid_name = |name: &str| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        };
+
+        check(
+            "sideloader.cpu_headroom".into(),
+            frac(self.sideloader.cpu_headroom),
+            "should be in [0.0, 1.0]",
+        );
+        check(
+            "bench_hashd_balloon_size".into(),
+            self.bench_hashd_balloon_size <= total_memory(),
+            "larger than total memory",
+        );
+
+        for (name, hc) in self.hashd.iter() {
+            let pre = format!("hashd.{}", name);
+            check(
+                pre.clone(),
+                valid_name(name),
+                "name should only contain alnums, - and _",
+            );
+            check(
+                format!("{}.lat_target_pct", pre),
+                hc.lat_target_pct > 0.0 && hc.lat_target_pct < 1.0,
+                "should be in (0.0, 1.0)",
+            );
+            check(
+                format!("{}.lat_target", pre),
+                hc.lat_target > 0.0,
+                "should be positive",
+            );
+            check(
+                format!("{}.rps_target_ratio", pre),
+                hc.rps_target_ratio >= 0.0,
+                "should not be negative",
+            );
+            if let Some(v) = hc.mem_ratio {
+                check(
+                    format!("{}.mem_ratio", pre),
+                    frac(v),
+                    "should be in [0.0, 1.0]",
+                );
+            }
+            for (field, v) in &[
+                ("file_addr_stdev", hc.file_addr_stdev),
+                ("anon_addr_stdev", hc.anon_addr_stdev),
+            ] {
+                if let Some(v) = v {
+                    check(format!("{}.{}", pre, field), *v > 0.0, "should be positive");
+                }
+            }
+            check(
+                format!("{}.file_ratio", pre),
+                frac(hc.file_ratio),
+                "should be in [0.0, 1.0]",
+            );
+            check(
+                format!("{}.file_max_ratio", pre),
+                frac(hc.file_max_ratio),
+                "should be in [0.0, 1.0]",
+            );
+            check(
+                format!("{}.weight", pre),
+                hc.weight > 0.0 && hc.weight.is_finite(),
+                "should be positive",
+            );
+            if let Some(node) = hc.numa_node {
+                check(
+                    format!("{}.numa_node", pre),
+                    numa::online_nodes()
+                        .map(|nodes| nodes.contains(&node))
+                        .unwrap_or(false),
+                    "not an online NUMA node",
+                );
+            }
+        }
+
+        for (kind, loads) in &[("sysloads", &self.sysloads), ("sideloads", &self.sideloads)] {
+            for (name, def_id) in loads.iter() {
+                let field = format!("{}.{}", kind, name);
+                if !valid_name(name) {
+                    check(field, false, "name should only contain alnums, - and _");
+                } else {
+                    check(
+                        field,
+                        side_defs.defs.contains_key(def_id),
+                        &format!("unknown DEF_ID {:?}", def_id),
+                    );
+                }
+            }
+        }
+
+        if let Some(v) = self.swappiness {
+            check("swappiness".into(), v <= 200, "should be in [0, 200]");
+        }
+        check(
+            "balloon_ratio".into(),
+            frac(self.balloon_ratio),
+            "should be in [0.0, 1.0]",
+        );
+        errs
+    }
 }
 
 impl Default for Cmd {
@@ -134,7 +266,7 @@ impl Default for Cmd {
             bench_hashd_args: vec![],
             bench_iocost_seq: 0,
             sideloader: SideloaderCmd { cpu_headroom: 0.2 },
-            hashd: Default::default(),
+            hashd: super::hashd_dfl_map(),
             sysloads: BTreeMap::new(),
             sideloads: BTreeMap::new(),
             swappiness: None,
@@ -151,3 +283,33 @@ impl JsonSave for Cmd {
         Some(CMD_DOC.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmd_validate() {
+        let side_defs = SideloadDefs::default();
+        let mut cmd = Cmd::default();
+        cmd.sideloads
+            .insert("build".into(), "build-linux-2x".into());
+        assert!(cmd.validate(&side_defs).is_empty());
+
+        cmd.sideloader.cpu_headroom = 1.5;
+        cmd.balloon_ratio = -0.1;
+        cmd.hashd_mut("A").lat_target_pct = 1.0;
+        cmd.sysloads.insert("bogus".into(), "no-such-def".into());
+        let errs = cmd.validate(&side_defs);
+        let fields: Vec<&str> = errs.keys().map(|k| k.as_str()).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "balloon_ratio",
+                "hashd.A.lat_target_pct",
+                "sideloader.cpu_headroom",
+                "sysloads.bogus"
+            ]
+        );
+    }
+}
@@ -1,5 +1,6 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 use rd_util::*;
 
@@ -10,17 +11,52 @@ static CMD_ACK_DOC: &str = "\
 // When the commands in cmd.rs are accepted for processing, its cmd_seq is
 // copied to this file. This can be used to synchronize command issuing.
 //
+// cmd.json, slices.json and oomd.json are validated when loaded. If a file
+// is rejected, rd-agent keeps running on the last accepted configuration
+// and the offending fields are listed in the matching *_errors map until a
+// valid version is loaded. A rejected cmd.json is still acked so that the
+// errors can be waited upon.
+//
+// An accepted cmd.json is acked after being applied. If applying a part
+// failed, the cmd field and the error are listed in apply_errors.
+//
+//  cmd_seq: The last acked cmd::cmd_seq
+//  cmd_errors: FIELD: ERROR pairs for the last rejected cmd.json
+//  apply_errors: FIELD: ERROR pairs for failures applying the acked cmd.json
+//  slice_errors: FIELD: ERROR pairs for the last rejected slices.json
+//  oomd_errors: FIELD: ERROR pairs for the last rejected oomd.json
+//
 ";
 
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
 #[serde(default)]
 pub struct CmdAck {
     pub cmd_seq: u64,
+    pub cmd_errors: BTreeMap<String, String>,
+    pub apply_errors: BTreeMap<String, String>,
+    pub slice_errors: BTreeMap<String, String>,
+    pub oomd_errors: BTreeMap<String, String>,
 }
 
 impl Default for CmdAck {
     fn default() -> Self {
-        Self { cmd_seq: 0 }
+        Self {
+            cmd_seq: 0,
+            cmd_errors: BTreeMap::new(),
+            apply_errors: BTreeMap::new(),
+            slice_errors: BTreeMap::new(),
+            oomd_errors: BTreeMap::new(),
+        }
+    }
+}
+
+impl CmdAck {
+    /// Format FIELD -> ERROR pairs into a single line for messages.
+    pub fn format_errors(errs: &BTreeMap<String, String>) -> String {
+        errs.iter()
+            .map(|(field, err)| format!("{}: {}", field, err))
+            .collect::<Vec<String>>()
+            .join(", ")
     }
 }
 
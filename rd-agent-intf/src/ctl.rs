@@ -0,0 +1,92 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{prelude::*, BufReader, LineWriter};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use super::Report;
+
+//
+// rd-agent control socket protocol
+//
+// The control socket accepts newline delimited JSON requests and responds
+// with newline delimited JSON responses. The file interface stays fully
+// functional and the socket operates on the same cmd.json, so both can be
+// used at the same time.
+//
+//  {"cmd": {...}}: Apply the JSON merge patch (RFC 7396) to the current
+//                  cmd.json, bump cmd_seq and respond with "ack" once the
+//                  updated command is accepted or "error" if the result is
+//                  invalid.
+//  "subscribe": Respond with a "report" for each per-second report until
+//               the connection is closed. No further requests are read.
+//
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CtlReq {
+    Cmd(serde_json::Value),
+    Subscribe,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CtlResp {
+    Ack { cmd_seq: u64 },
+    Error(String),
+    Report(Box<Report>),
+}
+
+pub struct CtlConn {
+    reader: BufReader<UnixStream>,
+    writer: LineWriter<UnixStream>,
+}
+
+impl CtlConn {
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: LineWriter::new(stream),
+        })
+    }
+
+    pub fn send(&mut self, req: &CtlReq) -> Result<()> {
+        writeln!(self.writer, "{}", serde_json::to_string(req)?)?;
+        Ok(())
+    }
+
+    pub fn recv(&mut self) -> Result<CtlResp> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            bail!("connection closed");
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    /// Apply `patch` to cmd.json and wait for the agent to accept it.
+    /// Returns the acked cmd_seq.
+    pub fn cmd(&mut self, patch: serde_json::Value) -> Result<u64> {
+        self.send(&CtlReq::Cmd(patch))?;
+        match self.recv()? {
+            CtlResp::Ack { cmd_seq } => Ok(cmd_seq),
+            CtlResp::Error(e) => bail!("{}", e),
+            CtlResp::Report(_) => bail!("unexpected report before subscription"),
+        }
+    }
+
+    /// Switch to the report stream. Each next_report() call blocks until
+    /// the next per-second report is available.
+    pub fn subscribe(&mut self) -> Result<()> {
+        self.send(&CtlReq::Subscribe)
+    }
+
+    pub fn next_report(&mut self) -> Result<Report> {
+        match self.recv()? {
+            CtlResp::Report(rep) => Ok(*rep),
+            CtlResp::Error(e) => bail!("{}", e),
+            CtlResp::Ack { .. } => bail!("unexpected ack in report stream"),
+        }
+    }
+}
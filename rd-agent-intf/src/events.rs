@@ -0,0 +1,222 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, prelude::*, BufReader};
+use std::path::PathBuf;
+
+use super::RunnerState;
+
+//
+// Event log
+//
+// Notable events - kills, overload transitions, runner state changes and
+// so on - are appended to EVENTS_FILENAME in the events directory as JSON
+// lines, one Event per line. Once the file grows beyond EVENTS_ROTATE_SIZE,
+// it's rotated to EVENTS_FILENAME.1, the previous EVENTS_FILENAME.1 to .2
+// and so on up to EVENTS_NR_ROTATED. Readers should go through the rotated
+// files from the highest number down and then the current file to see the
+// events in chronological order, which is what EventIter does.
+//
+// Each line carries the unix timestamp in "at", the event type in "type"
+// and the type-specific fields.
+//
+
+pub const EVENTS_FILENAME: &str = "events.log";
+pub const EVENTS_ROTATE_SIZE: u64 = 1 << 20;
+pub const EVENTS_NR_ROTATED: usize = 4;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum EventKind {
+    /// A cgroup was killed by oomd or the built-in engine. `size` is 0 for
+    /// oomd kills, which are picked up from its journal.
+    OomdKill {
+        cgroup: String,
+        why: String,
+        size: u64,
+    },
+    /// The sideloader entered or left overloaded state.
+    SideloaderOverload { overload: bool, why: String },
+    /// The sideloader entered or left critical state.
+    SideloaderCritical { critical: bool, why: String },
+    /// A sideload job was killed by the sideloader.
+    SideloadKill { id: String, why: String },
+    /// rd-agent's runner state changed.
+    RunnerState { from: RunnerState, to: RunnerState },
+    /// A benchmark finished and the results were loaded.
+    BenchDone { bench: String, seq: u64 },
+    /// An alert rule became active or inactive.
+    Alert {
+        name: String,
+        active: bool,
+        values: Vec<f64>,
+    },
+    /// A slice configuration was found diverged and fixed up.
+    SliceFixup {
+        path: String,
+        expected: String,
+        found: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub at: f64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+fn events_path(dir: &str, idx: usize) -> PathBuf {
+    match idx {
+        0 => format!("{}/{}", dir, EVENTS_FILENAME).into(),
+        idx => format!("{}/{}.{}", dir, EVENTS_FILENAME, idx).into(),
+    }
+}
+
+/// Appends events to the events directory, rotating as necessary.
+pub struct EventWriter {
+    dir: String,
+    rotate_size: u64,
+    file: Option<fs::File>,
+    size: u64,
+}
+
+impl EventWriter {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: dir.into(),
+            rotate_size: EVENTS_ROTATE_SIZE,
+            file: None,
+            size: 0,
+        }
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.file = None;
+        for idx in (0..EVENTS_NR_ROTATED).rev() {
+            let from = events_path(&self.dir, idx);
+            if from.exists() {
+                fs::rename(&from, events_path(&self.dir, idx + 1))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, ev: &Event) -> Result<()> {
+        let line = serde_json::to_string(ev)? + "\n";
+
+        if self.file.is_some() && self.size + line.len() as u64 > self.rotate_size {
+            self.rotate()?;
+        }
+        if self.file.is_none() {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(events_path(&self.dir, 0))?;
+            self.size = file.metadata()?.len();
+            self.file = Some(file);
+        }
+
+        self.file.as_mut().unwrap().write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Iterates events in [since, until) across the rotated files in
+/// chronological order. Lines which fail to parse are skipped.
+pub struct EventIter {
+    dir: String,
+    since: f64,
+    until: f64,
+    next_idx: Option<usize>,
+    lines: Option<io::Lines<BufReader<fs::File>>>,
+}
+
+impl EventIter {
+    pub fn new(dir: &str, period: (f64, f64)) -> Self {
+        Self {
+            dir: dir.into(),
+            since: period.0,
+            until: period.1,
+            next_idx: Some(EVENTS_NR_ROTATED),
+            lines: None,
+        }
+    }
+
+    fn open_next(&mut self) -> bool {
+        while let Some(idx) = self.next_idx {
+            self.next_idx = idx.checked_sub(1);
+            if let Ok(f) = fs::File::open(events_path(&self.dir, idx)) {
+                self.lines = Some(BufReader::new(f).lines());
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Iterator for EventIter {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        loop {
+            let line = match self.lines.as_mut().and_then(|lines| lines.next()) {
+                Some(Ok(line)) => line,
+                Some(Err(_)) | None => {
+                    if !self.open_next() {
+                        return None;
+                    }
+                    continue;
+                }
+            };
+            match serde_json::from_str::<Event>(&line) {
+                Ok(ev) if ev.at >= self.until => return None,
+                Ok(ev) if ev.at >= self.since => return Some(ev),
+                Ok(_) => {}
+                Err(e) => warn!("events: Failed to parse {:?} ({:#})", &line, &e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate_and_iter() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let dir = dir.path().to_str().unwrap();
+        let mut writer = EventWriter::new(dir);
+        writer.rotate_size = 256;
+
+        for i in 0..32 {
+            let ev = Event {
+                at: i as f64,
+                kind: EventKind::BenchDone {
+                    bench: "hashd".into(),
+                    seq: i,
+                },
+            };
+            writer.write(&ev).unwrap();
+        }
+        assert!(events_path(dir, EVENTS_NR_ROTATED).exists());
+        assert!(!events_path(dir, EVENTS_NR_ROTATED + 1).exists());
+
+        // the oldest events are rotated out but the rest are in order
+        let ats: Vec<f64> = EventIter::new(dir, (0.0, f64::MAX))
+            .map(|ev| ev.at)
+            .collect();
+        assert_eq!(*ats.last().unwrap(), 31.0);
+        assert!(ats.windows(2).all(|w| w[1] == w[0] + 1.0));
+
+        let ats: Vec<f64> = EventIter::new(dir, (28.0, 30.0)).map(|ev| ev.at).collect();
+        assert_eq!(ats, vec![28.0, 29.0]);
+
+        let line = fs::read_to_string(events_path(dir, 0)).unwrap();
+        assert!(line.starts_with(r#"{"at":"#) && line.contains(r#""type":"BenchDone""#));
+    }
+}
@@ -1,5 +1,6 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 use rd_util::*;
 
@@ -9,19 +10,22 @@ const INDEX_DOC: &str = "\
 //
 //  cmd: Launch and stop workloads and benchmarks
 //  cmd_ack: Command sequence ack
+//  ctl_sock: Unix domain control socket, see rd_agent_intf::ctl
 //  sysreqs: Satisfied and missed system requirements
 //  report: Summary report of the current state (per-second)
-//  report_d: Per-second report directory
+//  report_d: Per-second report directory, see rd_agent_intf::report_store
 //  report_1min: Summary report of the current state (per-minute)
-//  report_1min_d: Per-minute report directory
+//  report_1min_d: Per-minute report directory, see rd_agent_intf::report_store
 //  bench: Benchmark results
 //  slices: Top-level slice resource control configurations
 //  oomd: OOMD on/off and configurations
 //  sideloader_stats: Sideloader status
-//  hashd[].args: rd-hashd arguments
-//  hashd[].params: rd-hashd runtime adjustable parameters
-//  hashd[].report: rd-hashd summary report
+//  hashd{}.args: rd-hashd arguments
+//  hashd{}.params: rd-hashd runtime adjustable parameters
+//  hashd{}.report: rd-hashd summary report
 //  sideload_defs: Side and sys workload definitions
+//  events_d: Event log directory, see rd_agent_intf::events
+//  alerts: Alert rules, see rd_agent_intf::alerts
 //
 ";
 
@@ -36,6 +40,8 @@ pub struct HashdIndex {
 pub struct Index {
     pub cmd: String,
     pub cmd_ack: String,
+    #[serde(default)]
+    pub ctl_sock: String,
     pub sysreqs: String,
     pub report: String,
     pub report_d: String,
@@ -45,8 +51,13 @@ pub struct Index {
     pub slices: String,
     pub oomd: String,
     pub sideloader_status: String,
-    pub hashd: [HashdIndex; 2],
+    #[serde(deserialize_with = "super::deserialize_hashd_map")]
+    pub hashd: BTreeMap<String, HashdIndex>,
     pub sideload_defs: String,
+    #[serde(default)]
+    pub events_d: String,
+    #[serde(default)]
+    pub alerts: String,
 }
 
 impl JsonLoad for Index {}
@@ -1,37 +1,56 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use log::error;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 use std::io;
 
 use rd_util::*;
 
+pub mod alerts;
 pub mod args;
 pub mod bandit_report;
 pub mod bench;
 pub mod cmd;
 pub mod cmd_ack;
+pub mod ctl;
+pub mod events;
 pub mod index;
 pub mod oomd;
 pub mod report;
+pub mod report_store;
+pub mod scenario;
 pub mod side_defs;
 pub mod slices;
 pub mod sysreqs;
 
-pub use args::{Args, Bandit, BanditMemHogArgs, EnforceConfig};
+pub use alerts::{AlertAction, AlertCond, AlertRule, AlertRules};
+pub use args::{Args, Bandit, BanditMemHogArgs, EnforceConfig, ReportQueryArgs, ReportQueryFormat};
 pub use bandit_report::BanditMemHogReport;
 pub use bench::{BenchKnobs, HashdKnobs, IoCostKnobs, BENCH_FILENAME};
 pub use cmd::{Cmd, HashdCmd, SideloaderCmd};
 pub use cmd_ack::CmdAck;
+pub use ctl::{CtlConn, CtlReq, CtlResp};
+pub use events::{Event, EventIter, EventKind, EventWriter};
 pub use index::Index;
-pub use oomd::{OomdKnobs, OomdSliceMemPressureKnobs, OomdSliceSenpaiKnobs};
+pub use oomd::{
+    OomdDetectorGroup, OomdKillPolicy, OomdKnobs, OomdPlugin, OomdRuleset,
+    OomdSliceMemPressureKnobs, OomdSliceSenpaiKnobs,
+};
 pub use report::{
-    BenchHashdReport, BenchIoCostReport, HashdReport, IoCostModelReport, IoCostQoSReport,
-    IoCostReport, IoLatReport, OomdReport, Report, ReportIter, ReportPathIter, ResCtlReport,
-    SideloadReport, SideloaderReport, StatMap, SvcReport, SvcStateReport, SysloadReport,
-    UsageReport,
+    AlertReport, BenchHashdReport, BenchIoCostReport, CgroupEventsReport, ConfigTxnOutcome,
+    ConfigTxnReport, CpusetReport, HashdReport, IoCostModelReport, IoCostQoSReport, IoCostReport,
+    IoDevReport, IoLatReport, IoLatSource, NumaNodeReport, OomdKillReport, OomdReport, PsiAvgs,
+    PsiReport, Report, ReportIter, ReportPathIter, ResCtlReport, SideLifecyclePhase,
+    SideloadReport, SideloaderJobReport, SideloaderReport, StatMap, SvcReport, SvcStateReport,
+    SysloadReport, UsageReport,
+};
+pub use report_store::{ReportSegment, ReportStore};
+pub use scenario::{CmpOp, OnTimeout, Scenario, ScenarioStep, WaitCond};
+pub use side_defs::{IoNiceClass, RestartPolicy, SideloadDefs, SideloadSpec};
+pub use slices::{
+    CpusetPartition, DisableSeqKnobs, IoBackend, IoMaxConfig, MemoryKnob, Slice, SliceConfig,
+    SliceKnobs, SlicePath, ROOT_SLICE,
 };
-pub use side_defs::{SideloadDefs, SideloadSpec};
-pub use slices::{DisableSeqKnobs, MemoryKnob, Slice, SliceConfig, SliceKnobs, ROOT_SLICE};
 pub use sysreqs::{MissedSysReqs, SysReq, SysReqsReport, ALL_SYSREQS_SET};
 
 lazy_static::lazy_static! {
@@ -50,13 +69,19 @@ pub enum RunnerState {
 pub const AGENT_SVC_NAME: &str = "rd-agent.service";
 pub const HASHD_BENCH_SVC_NAME: &str = "rd-hashd-bench.service";
 pub const IOCOST_BENCH_SVC_NAME: &str = "rd-iocost-bench.service";
+pub const HASHD_A: &str = "A";
+pub const HASHD_B: &str = "B";
+pub const HASHD_SVC_PREFIX: &str = "rd-hashd-";
 pub const HASHD_A_SVC_NAME: &str = "rd-hashd-A.service";
 pub const HASHD_B_SVC_NAME: &str = "rd-hashd-B.service";
 pub const OOMD_SVC_NAME: &str = "rd-oomd.service";
-pub const SIDELOADER_SVC_NAME: &str = "rd-sideloader.service";
 pub const SIDELOAD_SVC_PREFIX: &str = "rd-sideload-";
 pub const SYSLOAD_SVC_PREFIX: &str = "rd-sysload-";
 
+pub fn hashd_svc_name(name: &str) -> String {
+    format!("{}{}.service", HASHD_SVC_PREFIX, name)
+}
+
 pub fn sysload_svc_name(name: &str) -> String {
     format!("{}{}.service", SYSLOAD_SVC_PREFIX, name)
 }
@@ -65,6 +90,44 @@ pub fn sideload_svc_name(name: &str) -> String {
     format!("{}{}.service", SIDELOAD_SVC_PREFIX, name)
 }
 
+/// The default rd-hashd instance map - A and B.
+pub fn hashd_dfl_map<T: Default>() -> BTreeMap<String, T> {
+    [HASHD_A, HASHD_B]
+        .iter()
+        .map(|name| (name.to_string(), Default::default()))
+        .collect()
+}
+
+/// rd-hashd instances used to be a fixed two element array. Accept the
+/// array form too and name the elements A, B, C...
+fn deserialize_hashd_map<'de, D, T>(deserializer: D) -> Result<BTreeMap<String, T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum MapOrSeq<T> {
+        Map(BTreeMap<String, T>),
+        Seq(Vec<T>),
+    }
+
+    Ok(match MapOrSeq::deserialize(deserializer)? {
+        MapOrSeq::Map(map) => map,
+        MapOrSeq::Seq(seq) => seq
+            .into_iter()
+            .enumerate()
+            .map(|(idx, v)| {
+                let name = match idx {
+                    0..=25 => ((b'A' + idx as u8) as char).to_string(),
+                    _ => format!("{}", idx),
+                };
+                (name, v)
+            })
+            .collect(),
+    })
+}
+
 #[derive(Default)]
 pub struct AgentFiles {
     pub args_path: String,
@@ -1,5 +1,6 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 use rd_util::*;
 
@@ -10,21 +11,179 @@ const OOMD_DOC: &str = "\
 //  disable_seq: Disable OOMD if >= report::seq
 //  workload.mem_pressure.disable_seq: Disable memory pressure protection in
 //                                     workload.slice if >= report::seq
-//  workload.mem_pressure.threshold: Pressure threshold
-//  workload.mem_pressure.threshold: Pressure duration
+//  workload.mem_pressure.threshold: Pressure threshold in % [1, 100]
+//  workload.mem_pressure.duration: Pressure duration in secs
+//  workload.mem_pressure.kill_policy: How to pick the victim -
+//                                     MemorySizeOrGrowth, Pressure, IoCost
+//                                     or SwapUsage
+//  workload.mem_pressure.kill_targets[]: oomd cgroup patterns to pick the
+//                                        victim from, workload.slice/* if
+//                                        empty
 //  workload.senpai.enable: Enable senpai in workload.slice
 //  workload.senpai.*: Senpai parameters
 //  system.*: The same set of parameters for system.slice
 //  swap_enable: Enable swap depletion protection
 //  swap_threshold: Swap depletion protection free space threshold in %
+//                  [0, 100]
+//  swap_kill_policy: How to pick the victim on swap depletion
+//  swap_kill_targets[]: oomd cgroup patterns to pick the victim from on
+//                       swap depletion, workload, sideload and system
+//                       slices if empty
+//  kill_prefer[]: Cgroups tagged with trusted.oomd_prefer to be killed first
+//  kill_avoid[]: Cgroups tagged with trusted.oomd_avoid to be killed last
+//  rulesets[]: Extra oomd rulesets appended to the generated ones
+//  rulesets[].name: Ruleset name
+//  rulesets[].silence_logs: oomd silence-logs value, e.g. engine
+//  rulesets[].detectors[].name: Detector group name
+//  rulesets[].detectors[].detectors[]: Detectors, all of which should fire
+//  rulesets[].actions[]: Actions to run when any detector group fires
+//  rulesets[].*.name: oomd plugin name
+//  rulesets[].*.args: oomd plugin arguments
 //
 ";
 
+pub const OOMD_DETECTOR_PLUGINS: [&str; 10] = [
+    "continue",
+    "stop",
+    "dump_cgroup_overview",
+    "exists",
+    "memory_above",
+    "memory_reclaim",
+    "nr_dying_descendants",
+    "pressure_above",
+    "pressure_rising_beyond",
+    "swap_free",
+];
+
+pub const OOMD_ACTION_PLUGINS: [&str; 9] = [
+    "continue",
+    "stop",
+    "dump_cgroup_overview",
+    "kill_by_io_cost",
+    "kill_by_memory_size_or_growth",
+    "kill_by_pg_scan",
+    "kill_by_pressure",
+    "kill_by_swap_usage",
+    "senpai",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OomdKillPolicy {
+    #[default]
+    MemorySizeOrGrowth,
+    Pressure,
+    IoCost,
+    SwapUsage,
+}
+
+impl OomdKillPolicy {
+    pub fn plugin(&self) -> &'static str {
+        match self {
+            Self::MemorySizeOrGrowth => "kill_by_memory_size_or_growth",
+            Self::Pressure => "kill_by_pressure",
+            Self::IoCost => "kill_by_io_cost",
+            Self::SwapUsage => "kill_by_swap_usage",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OomdPlugin {
+    pub name: String,
+    #[serde(default)]
+    pub args: BTreeMap<String, String>,
+}
+
+impl OomdPlugin {
+    pub fn new(name: &str, args: &[(&str, &str)]) -> Self {
+        Self {
+            name: name.into(),
+            args: args
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OomdDetectorGroup {
+    pub name: String,
+    pub detectors: Vec<OomdPlugin>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OomdRuleset {
+    pub name: String,
+    #[serde(default)]
+    pub silence_logs: Option<String>,
+    pub detectors: Vec<OomdDetectorGroup>,
+    pub actions: Vec<OomdPlugin>,
+}
+
+impl OomdRuleset {
+    /// Check the ruleset. FIELD -> ERROR pairs are added to `errs` with
+    /// `pre` prefixed to FIELD.
+    pub fn validate(&self, pre: &str, errs: &mut BTreeMap<String, String>) {
+        let mut check = |field: &str, ok: bool, msg: &str| {
+            if !ok {
+                errs.insert(format!("{}.{}", pre, field), msg.to_string());
+            }
+        };
+        let mut plugins = vec![];
+
+        check("name", !self.name.trim().is_empty(), "should not be empty");
+        check(
+            "detectors",
+            !self.detectors.is_empty(),
+            "should have at least one detector group",
+        );
+        for (i, group) in self.detectors.iter().enumerate() {
+            check(
+                &format!("detectors[{}].detectors", i),
+                !group.detectors.is_empty(),
+                "should have at least one detector",
+            );
+            for (j, det) in group.detectors.iter().enumerate() {
+                let field = format!("detectors[{}].detectors[{}]", i, j);
+                plugins.push((field, det, &OOMD_DETECTOR_PLUGINS[..]));
+            }
+        }
+        check(
+            "actions",
+            !self.actions.is_empty(),
+            "should have at least one action",
+        );
+        for (i, act) in self.actions.iter().enumerate() {
+            plugins.push((format!("actions[{}]", i), act, &OOMD_ACTION_PLUGINS[..]));
+        }
+
+        for (field, plugin, known) in plugins.iter() {
+            check(
+                &format!("{}.name", field),
+                known.contains(&plugin.name.as_str()),
+                &format!("unknown oomd plugin {:?}", &plugin.name),
+            );
+            if let Some(cgrp) = plugin.args.get("cgroup") {
+                check(
+                    &format!("{}.args.cgroup", field),
+                    !cgrp.trim().is_empty(),
+                    "should not be empty",
+                );
+            }
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct OomdSliceMemPressureKnobs {
     pub disable_seq: u64,
     pub threshold: u32,
     pub duration: u32,
+    #[serde(default)]
+    pub kill_policy: OomdKillPolicy,
+    #[serde(default)]
+    pub kill_targets: Vec<String>,
 }
 
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
@@ -69,6 +228,16 @@ pub struct OomdKnobs {
     pub system: OomdSliceKnobs,
     pub swap_enable: bool,
     pub swap_threshold: u32,
+    #[serde(default = "OomdKnobs::default_swap_kill_policy")]
+    pub swap_kill_policy: OomdKillPolicy,
+    #[serde(default)]
+    pub swap_kill_targets: Vec<String>,
+    #[serde(default)]
+    pub kill_prefer: Vec<String>,
+    #[serde(default)]
+    pub kill_avoid: Vec<String>,
+    #[serde(default)]
+    pub rulesets: Vec<OomdRuleset>,
 }
 
 impl Default for OomdKnobs {
@@ -80,6 +249,8 @@ impl Default for OomdKnobs {
                     disable_seq: 0,
                     threshold: 50,
                     duration: 30,
+                    kill_policy: Default::default(),
+                    kill_targets: vec![],
                 },
                 senpai: OomdSliceSenpaiKnobs {
                     min_bytes_frac: 0.25,
@@ -91,6 +262,8 @@ impl Default for OomdKnobs {
                     disable_seq: 0,
                     threshold: 50,
                     duration: 30,
+                    kill_policy: Default::default(),
+                    kill_targets: vec![],
                 },
                 senpai: OomdSliceSenpaiKnobs {
                     ..Default::default()
@@ -98,7 +271,94 @@ impl Default for OomdKnobs {
             },
             swap_enable: true,
             swap_threshold: 10,
+            swap_kill_policy: Self::default_swap_kill_policy(),
+            swap_kill_targets: vec![],
+            kill_prefer: vec![],
+            kill_avoid: vec![],
+            rulesets: vec![],
+        }
+    }
+}
+
+impl OomdSliceKnobs {
+    fn validate(&self, pre: &str, errs: &mut BTreeMap<String, String>) {
+        let mut check = |field: &str, ok: bool, msg: &str| {
+            if !ok {
+                errs.insert(format!("{}.{}", pre, field), msg.to_string());
+            }
+        };
+        let mp = &self.mem_pressure;
+        check(
+            "mem_pressure.threshold",
+            (1..=100).contains(&mp.threshold),
+            "should be in [1, 100]",
+        );
+        check(
+            "mem_pressure.duration",
+            mp.duration > 0,
+            "should be positive",
+        );
+        check(
+            "mem_pressure.kill_targets",
+            mp.kill_targets.iter().all(|x| !x.trim().is_empty()),
+            "should not contain empty entries",
+        );
+
+        let sp = &self.senpai;
+        let frac = |v: f64| (0.0..=1.0).contains(&v);
+        check(
+            "senpai.min_bytes_frac",
+            frac(sp.min_bytes_frac),
+            "should be in [0.0, 1.0]",
+        );
+        check(
+            "senpai.max_bytes_frac",
+            frac(sp.max_bytes_frac) && sp.max_bytes_frac >= sp.min_bytes_frac,
+            "should be in [min_bytes_frac, 1.0]",
+        );
+        check("senpai.interval", sp.interval > 0, "should be positive");
+        check(
+            "senpai.stall_threshold",
+            sp.stall_threshold > 0.0 && sp.stall_threshold <= 1.0,
+            "should be in (0.0, 1.0]",
+        );
+        for (field, v) in &[
+            ("senpai.max_probe", sp.max_probe),
+            ("senpai.max_backoff", sp.max_backoff),
+            ("senpai.coeff_probe", sp.coeff_probe),
+            ("senpai.coeff_backoff", sp.coeff_backoff),
+        ] {
+            check(field, *v > 0.0 && v.is_finite(), "should be positive");
+        }
+    }
+}
+
+impl OomdKnobs {
+    fn default_swap_kill_policy() -> OomdKillPolicy {
+        OomdKillPolicy::SwapUsage
+    }
+
+    /// Check the knobs. Returns FIELD -> ERROR pairs, empty if valid.
+    pub fn validate(&self) -> BTreeMap<String, String> {
+        let mut errs = BTreeMap::new();
+        self.workload.validate("workload", &mut errs);
+        self.system.validate("system", &mut errs);
+        if self.swap_threshold > 100 {
+            errs.insert("swap_threshold".into(), "should be in [0, 100]".into());
+        }
+        for (field, cgrps) in &[
+            ("swap_kill_targets", &self.swap_kill_targets),
+            ("kill_prefer", &self.kill_prefer),
+            ("kill_avoid", &self.kill_avoid),
+        ] {
+            if cgrps.iter().any(|x| x.trim().is_empty()) {
+                errs.insert(field.to_string(), "should not contain empty entries".into());
+            }
+        }
+        for (i, rs) in self.rulesets.iter().enumerate() {
+            rs.validate(&format!("rulesets[{}]", i), &mut errs);
         }
+        errs
     }
 }
 
@@ -109,3 +369,51 @@ impl JsonSave for OomdKnobs {
         Some(OOMD_DOC.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ruleset_validate() {
+        let mut knobs = OomdKnobs::default();
+        knobs.rulesets.push(OomdRuleset {
+            name: "io hog".into(),
+            silence_logs: None,
+            detectors: vec![OomdDetectorGroup {
+                name: "io pressure in sideload.slice".into(),
+                detectors: vec![OomdPlugin::new(
+                    "pressure_above",
+                    &[
+                        ("cgroup", "sideload.slice"),
+                        ("resource", "io"),
+                        ("threshold", "80"),
+                        ("duration", "30"),
+                    ],
+                )],
+            }],
+            actions: vec![OomdPlugin::new(
+                "kill_by_io_cost",
+                &[("cgroup", "sideload.slice/*")],
+            )],
+        });
+        assert!(knobs.validate().is_empty());
+
+        let rs = &mut knobs.rulesets[0];
+        rs.detectors[0].detectors[0].name = "pressure_abvoe".into();
+        rs.actions[0].args.insert("cgroup".into(), "".into());
+        rs.detectors.push(OomdDetectorGroup {
+            name: "empty".into(),
+            detectors: vec![],
+        });
+        let errs = knobs.validate();
+        assert_eq!(
+            errs.keys().collect::<Vec<_>>(),
+            vec![
+                "rulesets[0].actions[0].args.cgroup",
+                "rulesets[0].detectors[0].detectors[0].name",
+                "rulesets[0].detectors[1].detectors",
+            ]
+        );
+    }
+}
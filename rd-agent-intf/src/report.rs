@@ -7,6 +7,7 @@ use std::collections::BTreeMap;
 use std::ops;
 use std::time::UNIX_EPOCH;
 
+use super::report_store::{self, ReportSegment};
 use super::RunnerState;
 use rd_util::*;
 
@@ -26,13 +27,29 @@ const REPORT_DOC: &str = "\
 //  oomd.work_senpai: Senpai enabled on workload.slice
 //  oomd.sys_mem_pressure: Memory pressure based kill enabled in system.slice
 //  oomd.sys_senpai: Senpai enabled on system.slice
-//  sideloader.svc.name: sideloader systemd service name
-//  sideloader.svc.state: sideloader systemd service state
+//  oomd.builtin: The built-in engine is used because oomd is unavailable
+//  oomd.nr_kills: Number of kills by oomd or the built-in engine
+//  oomd.last_kill.at: Timestamp of the last kill
+//  oomd.last_kill.cgroup: The killed cgroup
+//  oomd.last_kill.why: Why the cgroup was killed
+//  oomd.last_kill.size: Memory or swap usage of the killed cgroup in bytes,
+//                       0 if killed by oomd
+//  sideloader.svc.name: sideloader name
+//  sideloader.svc.state: Running if the sideloader is active
 //  sideloader.sysconf_warnings: sideloader system configuration warnings
 //  sideloader.overload: sideloader is in overloaded state
 //  sideloader.overload_why: the reason for overloaded state
 //  sideloader.critical: sideloader is in crticial state
-//  sideloader.overload_why: the reason for critical state
+//  sideloader.critical_why: the reason for critical state
+//  sideloader.overload_hold: Seconds left before overload can end
+//  sideloader.cpu_avail: CPU percentage sideload.slice is allowed to use
+//  sideloader.jobs{}.pending: Waiting for overload to end to start
+//  sideloader.jobs{}.frozen: Frozen because of overload
+//  sideloader.jobs{}.frozen_for: Seconds the job has been frozen for
+//  sideloader.jobs{}.throttled: CPU usage was limited in the last interval
+//  sideloader.jobs{}.done: The job finished
+//  sideloader.jobs{}.killed: The job was killed or failed
+//  sideloader.jobs{}.kill_why: Why the sideloader killed the job
 //  bench.hashd.svc.name: rd-hashd benchmark systemd service name
 //  bench.hashd.svc.state: rd-hashd benchmark systemd service state
 //  bench.hashd.phase: rd-hashd benchmark phase
@@ -40,22 +57,83 @@ const REPORT_DOC: &str = "\
 //  bench.hashd.mem_probe_at: the timestamp this memory probing started at
 //  bench.iocost.svc.name: iocost benchmark systemd service name
 //  bench.iocost.svc.state: iocost benchmark systemd service state
-//  hashd[].svc.name: rd-hashd systemd service name
-//  hashd[].svc.state: rd-hashd systemd service state
-//  hashd[].load: Current rps / rps_max
-//  hashd[].rps: Current rps
-//  hashd[].lat_pct: Current control percentile
-//  hashd[].lat: Current control percentile latency
+//  hashd{}.svc.name: rd-hashd systemd service name
+//  hashd{}.svc.state: rd-hashd systemd service state
+//  hashd{}.load: Current rps / rps_max
+//  hashd{}.rps: Current rps
+//  hashd{}.lat_pct: Current control percentile
+//  hashd{}.lat: Current control percentile latency
+//  hashd{}.usage_key: Key of the service's usage in usages{}, empty if
+//                     not tracked
 //  sysloads{}.svc.name: Sysload systemd service name
 //  sysloads{}.svc.state: Sysload systemd service state
+//  sysloads{}.phase: Lifecycle phase - Prep, Running, Cleanup, RestartWait, Done
+//  sysloads{}.nr_restarts: Number of restarts by the restart policy
+//  sysloads{}.completed: Max run time expired or completion marker appeared
+//  sysloads{}.usage_key: Key of the service's usage in usages{}, empty if
+//                        not tracked
 //  sideloads{}.svc.name: Sideload systemd service name
 //  sideloads{}.svc.state: Sideload systemd service state
+//  sideloads{}.phase: Lifecycle phase - Prep, Running, Cleanup, RestartWait, Done
+//  sideloads{}.nr_restarts: Number of restarts by the restart policy
+//  sideloads{}.completed: Max run time expired or completion marker appeared
+//  sideloads{}.usage_key: Key of the service's usage in usages{}, empty if
+//                         not tracked
 //  iocost.model: iocost model parameters currently in effect
 //  iocost.qos: iocost QoS parameters currently in effect
 //  iolat.{read|write|discard|flush}.p*: IO latency distributions
 //  iolat_cum.{read|write|discard|flush}.p*: Cumulative IO latency distributions
+//  iolat.source, iolat_cum.source: Bpf (biolatpcts), BlockStat (estimated
+//                                  from /sys/block stat) or None
+//  iocost, iolat, iolat_cum and io_stat are for the scratch device
+//  io_devs{}: Per managed device, keyed by device name
+//  io_devs{}.devnr: MAJ:MIN
+//  io_devs{}.iocost: iocost parameters and vrate of the device
+//  io_devs{}.iolat: IO latency distributions of the device
+//  io_devs{}.iolat_cum: Cumulative IO latency distributions of the device
+//  io_devs{}.io_stat{}: Per-slice io.stat of the device
+//  usages{}: Keyed by the root, managed slices, hashd, sysload and
+//            sideload services and services in hostcritical.slice
+//  usages{}.io_{rbytes|wbytes|rbps|wbps|usage|util}: For the scratch device,
+//                          see io_devs{}.io_stat{} for the other devices
+//  usages{}.{cpu|mem|io|irq}_stalls: Cumulative some and full stall seconds
+//  usages{}.{cpu|mem|io|irq}_pressures: Some and full pressures during the
+//                                       last interval, derived from the stalls
+//  usages{}.{cpu|mem|io|irq}_psi.{some|full}.avg{10|60|300}: The kernel's
+//                                       running averages in [0.0, 1.0], irq
+//                                       only has full
+//  usages{}.io_lat_use_delay: io.latency use_delay on the scratch device
+//  usages{}.io_lat_delay: Fraction of time delayed by io.latency
+//  usages{}.io_lat_avg: io.latency average latency
+//  usages{}.io_lat_*: All three need blkcg debug stats, see
+//                     /sys/module/blk_cgroup/parameters/blkcg_debug_stats,
+//                     and stay 0 without
+//  usages{}.events.mem_{low|high|max|oom|oom_kill|oom_group_kill}:
+//                                       Cumulative memory.events counters
+//  usages{}.events.swap_{high|max|fail}: Cumulative memory.swap.events counters
+//  usages{}.events.cpu_{nr_periods|nr_throttled|throttled}: Cumulative
+//                                       cpu.stat throttling counters,
+//                                       throttled in seconds
+//  usages{}.events_delta: Increases of the above during the last interval,
+//                         summed over the whole minute in the 1min reports
+//  numa{}.mem_{total|free|used}: Memory of the NUMA node in bytes
+//  numa_stat{}{}: memory.numa_stat of the slice per NUMA node
 //  swappiness: vm.swappiness
 //  zswap_enabled: zswap enabled
+//  cpusets{}.cpus: Effective cpuset.cpus of the slice
+//  cpusets{}.mems: Effective cpuset.mems of the slice
+//  cpusets{}.partition: cpuset.cpus.partition of the slice
+//  config_txn.seq: Number of slice/oomd/sideloader config transactions
+//  config_txn.at: When the last transaction ran, in unix time
+//  config_txn.parts[]: What the last transaction covered - slices, oomd
+//                      and/or sideloader
+//  config_txn.outcome: Committed, Aborted (failed before changing anything),
+//                      RolledBack or RollbackFailed
+//  config_txn.error: Why the last transaction failed
+//  config_txn.rollback_error: Why the rollback failed, e.g. because nothing
+//                            has been committed yet
+//  alerts{}.since: When the alert became active, in unix time
+//  alerts{}.values[]: Field values of the conditions in the last interval
 //
 //
 ";
@@ -87,6 +165,55 @@ pub struct ResCtlReport {
     pub io: bool,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct CpusetReport {
+    pub cpus: String,
+    pub mems: String,
+    pub partition: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct NumaNodeReport {
+    pub mem_total: u64,
+    pub mem_free: u64,
+    pub mem_used: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConfigTxnOutcome {
+    #[default]
+    None,
+    Committed,
+    Aborted,
+    RolledBack,
+    RollbackFailed,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConfigTxnReport {
+    pub seq: u64,
+    pub at: u64,
+    pub parts: Vec<String>,
+    pub outcome: ConfigTxnOutcome,
+    pub error: String,
+    pub rollback_error: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OomdKillReport {
+    pub at: u64,
+    pub cgroup: String,
+    pub why: String,
+    pub size: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AlertReport {
+    pub since: u64,
+    pub values: Vec<f64>,
+}
+
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct OomdReport {
     pub svc: SvcReport,
@@ -94,6 +221,12 @@ pub struct OomdReport {
     pub work_senpai: bool,
     pub sys_mem_pressure: bool,
     pub sys_senpai: bool,
+    #[serde(default)]
+    pub builtin: bool,
+    #[serde(default)]
+    pub nr_kills: u64,
+    #[serde(default)]
+    pub last_kill: Option<OomdKillReport>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -120,6 +253,17 @@ pub struct BenchIoCostReport {
     pub svc: SvcReport,
 }
 
+#[derive(Clone, Serialize, Deserialize, Default)]
+pub struct SideloaderJobReport {
+    pub pending: bool,
+    pub frozen: bool,
+    pub frozen_for: f64,
+    pub throttled: bool,
+    pub done: bool,
+    pub killed: bool,
+    pub kill_why: String,
+}
+
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct SideloaderReport {
     pub svc: SvcReport,
@@ -128,8 +272,17 @@ pub struct SideloaderReport {
     pub overload_why: String,
     pub critical: bool,
     pub critical_why: String,
+    #[serde(default)]
+    pub overload_hold: f64,
+    #[serde(default)]
+    pub cpu_avail: f64,
+    #[serde(default)]
+    pub jobs: BTreeMap<String, SideloaderJobReport>,
 }
 
+impl JsonLoad for SideloaderReport {}
+impl JsonSave for SideloaderReport {}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct HashdReport {
     pub svc: SvcReport,
@@ -144,6 +297,8 @@ pub struct HashdReport {
     pub nr_idle_workers: usize,
     pub mem_probe_size: usize,
     pub mem_probe_at: DateTime<Local>,
+    #[serde(default)]
+    pub usage_key: String,
 }
 
 impl Default for HashdReport {
@@ -161,6 +316,7 @@ impl Default for HashdReport {
             nr_idle_workers: 0,
             mem_probe_size: 0,
             mem_probe_at: DateTime::from(UNIX_EPOCH),
+            usage_key: String::new(),
         }
     }
 }
@@ -192,16 +348,162 @@ impl<T: Into<f64>> ops::DivAssign<T> for HashdReport {
     }
 }
 
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SideLifecyclePhase {
+    Prep,
+    #[default]
+    Running,
+    Cleanup,
+    RestartWait,
+    Done,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SysloadReport {
     pub svc: SvcReport,
     pub scr_path: String,
+    #[serde(default)]
+    pub phase: SideLifecyclePhase,
+    #[serde(default)]
+    pub nr_restarts: u32,
+    #[serde(default)]
+    pub completed: bool,
+    #[serde(default)]
+    pub usage_key: String,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SideloadReport {
     pub svc: SvcReport,
     pub scr_path: String,
+    #[serde(default)]
+    pub phase: SideLifecyclePhase,
+    #[serde(default)]
+    pub nr_restarts: u32,
+    #[serde(default)]
+    pub completed: bool,
+    #[serde(default)]
+    pub usage_key: String,
+}
+
+/// The kernel's PSI running averages, as fractions rather than percents.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PsiAvgs {
+    pub avg10: f64,
+    pub avg60: f64,
+    pub avg300: f64,
+}
+
+impl ops::AddAssign<&PsiAvgs> for PsiAvgs {
+    fn add_assign(&mut self, rhs: &PsiAvgs) {
+        self.avg10 += rhs.avg10;
+        self.avg60 += rhs.avg60;
+        self.avg300 += rhs.avg300;
+    }
+}
+
+impl<T: Into<f64>> ops::DivAssign<T> for PsiAvgs {
+    fn div_assign(&mut self, rhs: T) {
+        let div = rhs.into();
+        self.avg10 /= div;
+        self.avg60 /= div;
+        self.avg300 /= div;
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PsiReport {
+    pub some: PsiAvgs,
+    pub full: PsiAvgs,
+}
+
+impl ops::AddAssign<&PsiReport> for PsiReport {
+    fn add_assign(&mut self, rhs: &PsiReport) {
+        self.some += &rhs.some;
+        self.full += &rhs.full;
+    }
+}
+
+impl<T: Into<f64>> ops::DivAssign<T> for PsiReport {
+    fn div_assign(&mut self, rhs: T) {
+        let div = rhs.into();
+        self.some /= div;
+        self.full /= div;
+    }
+}
+
+/// Counters from memory.events, memory.swap.events and cpu.stat. The
+/// throttled time is in seconds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CgroupEventsReport {
+    pub mem_low: f64,
+    pub mem_high: f64,
+    pub mem_max: f64,
+    pub mem_oom: f64,
+    pub mem_oom_kill: f64,
+    pub mem_oom_group_kill: f64,
+    pub swap_high: f64,
+    pub swap_max: f64,
+    pub swap_fail: f64,
+    pub cpu_nr_periods: f64,
+    pub cpu_nr_throttled: f64,
+    pub cpu_throttled: f64,
+}
+
+impl CgroupEventsReport {
+    fn fields_mut(&mut self) -> [&mut f64; 12] {
+        [
+            &mut self.mem_low,
+            &mut self.mem_high,
+            &mut self.mem_max,
+            &mut self.mem_oom,
+            &mut self.mem_oom_kill,
+            &mut self.mem_oom_group_kill,
+            &mut self.swap_high,
+            &mut self.swap_max,
+            &mut self.swap_fail,
+            &mut self.cpu_nr_periods,
+            &mut self.cpu_nr_throttled,
+            &mut self.cpu_throttled,
+        ]
+    }
+
+    /// Counter increases from `last` to `self`. A counter which went
+    /// backwards, e.g. because the service was restarted, counts from zero.
+    pub fn delta(&self, last: &CgroupEventsReport) -> CgroupEventsReport {
+        let mut delta = *self;
+        let mut last = *last;
+        for (d, l) in delta.fields_mut().iter_mut().zip(last.fields_mut().iter()) {
+            if **d >= **l {
+                **d -= **l;
+            }
+        }
+        delta
+    }
+
+    pub fn oom_killed(&self) -> bool {
+        self.mem_oom_kill > 0.0 || self.mem_oom_group_kill > 0.0
+    }
+}
+
+impl ops::AddAssign<&CgroupEventsReport> for CgroupEventsReport {
+    fn add_assign(&mut self, rhs: &CgroupEventsReport) {
+        let mut rhs = *rhs;
+        for (l, r) in self.fields_mut().iter_mut().zip(rhs.fields_mut().iter()) {
+            **l += **r;
+        }
+    }
+}
+
+impl<T: Into<f64>> ops::DivAssign<T> for CgroupEventsReport {
+    fn div_assign(&mut self, rhs: T) {
+        let div = rhs.into();
+        for v in self.fields_mut().iter_mut() {
+            **v /= div;
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -226,6 +528,28 @@ pub struct UsageReport {
     pub cpu_pressures: (f64, f64),
     pub mem_pressures: (f64, f64),
     pub io_pressures: (f64, f64),
+    #[serde(default)]
+    pub irq_stalls: (f64, f64),
+    #[serde(default)]
+    pub irq_pressures: (f64, f64),
+    #[serde(default)]
+    pub cpu_psi: PsiReport,
+    #[serde(default)]
+    pub mem_psi: PsiReport,
+    #[serde(default)]
+    pub io_psi: PsiReport,
+    #[serde(default)]
+    pub irq_psi: PsiReport,
+    #[serde(default)]
+    pub io_lat_use_delay: f64,
+    #[serde(default)]
+    pub io_lat_delay: f64,
+    #[serde(default)]
+    pub io_lat_avg: f64,
+    #[serde(default)]
+    pub events: CgroupEventsReport,
+    #[serde(default)]
+    pub events_delta: CgroupEventsReport,
 }
 
 impl ops::AddAssign<&UsageReport> for UsageReport {
@@ -255,6 +579,19 @@ impl ops::AddAssign<&UsageReport> for UsageReport {
         self.mem_pressures.1 += rhs.mem_pressures.1;
         self.io_pressures.0 += rhs.io_pressures.0;
         self.io_pressures.1 += rhs.io_pressures.1;
+        self.irq_stalls.0 += rhs.irq_stalls.0;
+        self.irq_stalls.1 += rhs.irq_stalls.1;
+        self.irq_pressures.0 += rhs.irq_pressures.0;
+        self.irq_pressures.1 += rhs.irq_pressures.1;
+        self.cpu_psi += &rhs.cpu_psi;
+        self.mem_psi += &rhs.mem_psi;
+        self.io_psi += &rhs.io_psi;
+        self.irq_psi += &rhs.irq_psi;
+        self.io_lat_use_delay += rhs.io_lat_use_delay;
+        self.io_lat_delay += rhs.io_lat_delay;
+        self.io_lat_avg += rhs.io_lat_avg;
+        self.events += &rhs.events;
+        self.events_delta += &rhs.events_delta;
     }
 }
 
@@ -287,11 +624,37 @@ impl<T: Into<f64>> ops::DivAssign<T> for UsageReport {
         self.mem_pressures.1 /= div;
         self.io_pressures.0 /= div;
         self.io_pressures.1 /= div;
+        self.irq_stalls.0 /= div;
+        self.irq_stalls.1 /= div;
+        self.irq_pressures.0 /= div;
+        self.irq_pressures.1 /= div;
+        self.cpu_psi /= div;
+        self.mem_psi /= div;
+        self.io_psi /= div;
+        self.irq_psi /= div;
+        self.io_lat_use_delay /= div;
+        self.io_lat_delay /= div;
+        self.events /= div;
+        self.events_delta /= div;
+        self.io_lat_avg /= div;
     }
 }
 
+/// Where the IO latency distributions came from. Bpf is the exact
+/// distribution from biolatpcts. BlockStat is estimated from the average
+/// latencies in /sys/block/DEV/stat and in-flight sampling.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IoLatSource {
+    #[default]
+    None,
+    Bpf,
+    BlockStat,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct IoLatReport {
+    #[serde(default)]
+    pub source: IoLatSource,
     #[serde(flatten)]
     pub map: BTreeMap<String, BTreeMap<String, f64>>,
 }
@@ -305,6 +668,7 @@ impl IoLatReport {
 
 impl IoLatReport {
     pub fn accumulate(&mut self, rhs: &IoLatReport) {
+        self.source = rhs.source;
         for key in &["read", "write", "discard", "flush"] {
             let key = key.to_string();
             let lpcts = self.map.get_mut(&key).unwrap();
@@ -328,7 +692,10 @@ impl Default for IoLatReport {
             }
             map.insert(key.to_string(), pcts);
         }
-        Self { map }
+        Self {
+            source: Default::default(),
+            map,
+        }
     }
 }
 
@@ -457,6 +824,16 @@ impl IoCostReport {
 
 pub type StatMap = BTreeMap<String, f64>;
 
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IoDevReport {
+    pub devnr: String,
+    pub iocost: IoCostReport,
+    pub iolat: IoLatReport,
+    pub iolat_cum: IoLatReport,
+    pub io_stat: BTreeMap<String, StatMap>,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Report {
     pub timestamp: DateTime<Local>,
@@ -467,7 +844,8 @@ pub struct Report {
     pub sideloader: SideloaderReport,
     pub bench_hashd: BenchHashdReport,
     pub bench_iocost: BenchIoCostReport,
-    pub hashd: [HashdReport; 2],
+    #[serde(deserialize_with = "super::deserialize_hashd_map")]
+    pub hashd: BTreeMap<String, HashdReport>,
     pub sysloads: BTreeMap<String, SysloadReport>,
     pub sideloads: BTreeMap<String, SideloadReport>,
     pub usages: BTreeMap<String, UsageReport>,
@@ -477,8 +855,20 @@ pub struct Report {
     pub iolat: IoLatReport,
     pub iolat_cum: IoLatReport,
     pub iocost: IoCostReport,
+    #[serde(default)]
+    pub io_devs: BTreeMap<String, IoDevReport>,
+    #[serde(default)]
+    pub numa: BTreeMap<u32, NumaNodeReport>,
+    #[serde(default)]
+    pub numa_stat: BTreeMap<String, BTreeMap<u32, StatMap>>,
     pub swappiness: u32,
     pub zswap_enabled: bool,
+    #[serde(default)]
+    pub cpusets: BTreeMap<String, CpusetReport>,
+    #[serde(default)]
+    pub config_txn: ConfigTxnReport,
+    #[serde(default)]
+    pub alerts: BTreeMap<String, AlertReport>,
 }
 
 impl Default for Report {
@@ -492,7 +882,7 @@ impl Default for Report {
             sideloader: Default::default(),
             bench_hashd: Default::default(),
             bench_iocost: Default::default(),
-            hashd: Default::default(),
+            hashd: super::hashd_dfl_map(),
             sysloads: Default::default(),
             sideloads: Default::default(),
             usages: Default::default(),
@@ -502,8 +892,14 @@ impl Default for Report {
             iolat: Default::default(),
             iolat_cum: Default::default(),
             iocost: Default::default(),
+            io_devs: Default::default(),
+            numa: Default::default(),
+            numa_stat: Default::default(),
             swappiness: 60,
             zswap_enabled: false,
+            cpusets: Default::default(),
+            config_txn: Default::default(),
+            alerts: Default::default(),
         }
     }
 }
@@ -520,6 +916,7 @@ pub struct ReportPathIter {
     dir: String,
     front: u64,
     back: u64,
+    step: u64,
 }
 
 impl ReportPathIter {
@@ -528,8 +925,15 @@ impl ReportPathIter {
             dir: dir.into(),
             front: period.0,
             back: period.1,
+            step: 1,
         }
     }
+
+    /// Visit every `step`th timestamp, e.g. 60 for the per-minute reports.
+    pub fn with_step(mut self, step: u64) -> Self {
+        self.step = step.max(1);
+        self
+    }
 }
 
 impl Iterator for ReportPathIter {
@@ -539,7 +943,7 @@ impl Iterator for ReportPathIter {
             return None;
         }
         let front = self.front;
-        self.front += 1;
+        self.front += self.step;
 
         let path = format!("{}/{}.json", &self.dir, front);
         trace!("ReportPathIter: {}, {}", &path, front);
@@ -549,41 +953,79 @@ impl Iterator for ReportPathIter {
 
 impl DoubleEndedIterator for ReportPathIter {
     fn next_back(&mut self) -> Option<Self::Item> {
-        if self.front >= self.back {
+        // stay on the same stride as the front
+        let back = self.front + self.back.saturating_sub(self.front) / self.step * self.step;
+        if self.front >= back {
             return None;
         }
-        let back = self.back;
-        self.back -= 1;
+        self.back = back - self.step;
 
         Some((format!("{}/{}.json", &self.dir, back).into(), back))
     }
 }
 
+/// Iterates reports in `period`. Each report is looked up in the segment
+/// store first and then in the per-report JSON files, so directories in
+/// either or mixed layouts can be scanned.
 pub struct ReportIter {
     piter: ReportPathIter,
+    dir: String,
+    front_seg: Option<(u64, Option<ReportSegment>)>,
+    back_seg: Option<(u64, Option<ReportSegment>)>,
 }
 
 impl ReportIter {
     pub fn new(dir: &str, period: (u64, u64)) -> Self {
         Self {
             piter: ReportPathIter::new(dir, period),
+            dir: dir.into(),
+            front_seg: None,
+            back_seg: None,
+        }
+    }
+
+    pub fn with_step(mut self, step: u64) -> Self {
+        self.piter = self.piter.with_step(step);
+        self
+    }
+
+    fn load(
+        dir: &str,
+        seg_cache: &mut Option<(u64, Option<ReportSegment>)>,
+        path: &std::path::Path,
+        at: u64,
+    ) -> Result<Report> {
+        let start = report_store::seg_start(at);
+        if seg_cache.as_ref().map(|(s, _)| *s) != Some(start) {
+            let seg = match ReportSegment::open(dir, start) {
+                Ok(v) => v,
+                Err(e) => {
+                    trace!("ReportIter: Failed to open segment {} ({:#})", start, &e);
+                    None
+                }
+            };
+            *seg_cache = Some((start, seg));
+        }
+        if let Some((_, Some(seg))) = seg_cache.as_mut() {
+            if let Some(rep) = seg.get(at) {
+                return rep;
+            }
         }
+        Report::load(path)
     }
 }
 
 impl Iterator for ReportIter {
     type Item = (Result<Report>, u64);
     fn next(&mut self) -> Option<Self::Item> {
-        self.piter
-            .next()
-            .map(|(path, at)| (Report::load(&path), at))
+        let (path, at) = self.piter.next()?;
+        Some((Self::load(&self.dir, &mut self.front_seg, &path, at), at))
     }
 }
 
 impl DoubleEndedIterator for ReportIter {
     fn next_back(&mut self) -> Option<Self::Item> {
-        self.piter
-            .next_back()
-            .map(|(path, at)| (Report::load(&path), at))
+        let (path, at) = self.piter.next_back()?;
+        Some((Self::load(&self.dir, &mut self.back_seg, &path, at), at))
     }
 }
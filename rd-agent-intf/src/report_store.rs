@@ -0,0 +1,546 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Context, Result};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::prelude::*;
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+
+use super::Report;
+use rd_util::*;
+
+//
+// Segmented report store
+//
+// Instead of one JSON file per report, reports can be appended to hourly
+// segments in the report directory. Each segment consists of two files.
+//
+//  START.seg: Individually deflate-compressed compact JSON reports,
+//             back-to-back.
+//  START.idx: Time index. Fixed-size little-endian (timestamp u64, offset
+//             u64, length u32) entries in ascending timestamp order.
+//
+// START is the timestamp at the beginning of the hour. Each report is
+// written to the segment before its index entry, so an index entry never
+// points past the end of the segment. A torn trailing entry left by a crash
+// is ignored by readers and trimmed on the next append.
+//
+// ReportStore::convert_dir() rewrites a segment in .START.convert-staging
+// and then renames the segment and the index into place, the index last.
+// If a crash happens between the two renames, the staging directory is
+// left with only the index, which is the one matching the new segment.
+// Readers use it instead of the stale one and writers finish the commit.
+//
+// Segments and per-second JSON files can coexist in the same directory.
+// ReportIter looks up the segments first and then falls back to the JSON
+// files.
+//
+
+pub const SEG_SPAN: u64 = 3600;
+const IDX_ENT_SIZE: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct IdxEnt {
+    at: u64,
+    off: u64,
+    len: u32,
+}
+
+impl IdxEnt {
+    fn encode(&self) -> [u8; IDX_ENT_SIZE] {
+        let mut buf = [0u8; IDX_ENT_SIZE];
+        buf[0..8].copy_from_slice(&self.at.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.off.to_le_bytes());
+        buf[16..20].copy_from_slice(&self.len.to_le_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        let mut at = [0u8; 8];
+        let mut off = [0u8; 8];
+        let mut len = [0u8; 4];
+        at.copy_from_slice(&buf[0..8]);
+        off.copy_from_slice(&buf[8..16]);
+        len.copy_from_slice(&buf[16..20]);
+        Self {
+            at: u64::from_le_bytes(at),
+            off: u64::from_le_bytes(off),
+            len: u32::from_le_bytes(len),
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.off + self.len as u64
+    }
+}
+
+pub fn seg_start(at: u64) -> u64 {
+    at / SEG_SPAN * SEG_SPAN
+}
+
+fn seg_path(dir: &str, start: u64) -> PathBuf {
+    format!("{}/{}.seg", dir, start).into()
+}
+
+fn idx_path(dir: &str, start: u64) -> PathBuf {
+    format!("{}/{}.idx", dir, start).into()
+}
+
+fn staging_dir(dir: &str, start: u64) -> String {
+    format!("{}/.{}.convert-staging", dir, start)
+}
+
+fn staged_idx_only(staging: &str, start: u64) -> bool {
+    idx_path(staging, start).exists() && !seg_path(staging, start).exists()
+}
+
+/// The index matching the current segment, see the top comment.
+fn live_idx_path(dir: &str, start: u64) -> PathBuf {
+    let staging = staging_dir(dir, start);
+    if staged_idx_only(&staging, start) {
+        idx_path(&staging, start)
+    } else {
+        idx_path(dir, start)
+    }
+}
+
+/// Finish an interrupted segment commit or discard an incomplete staging
+/// directory.
+fn recover_convert(dir: &str, start: u64) -> Result<()> {
+    let staging = staging_dir(dir, start);
+    if !std::path::Path::new(&staging).exists() {
+        return Ok(());
+    }
+    if staged_idx_only(&staging, start) {
+        warn!(
+            "report: Finishing interrupted conversion of segment {}",
+            start
+        );
+        fs::rename(idx_path(&staging, start), idx_path(dir, start))?;
+    }
+    fs::remove_dir_all(&staging)?;
+    Ok(())
+}
+
+fn parse_stamp(name: &str, suffix: &str) -> Option<u64> {
+    name.strip_suffix(suffix)
+        .and_then(|v| v.parse::<u64>().ok())
+}
+
+fn read_idx(buf: &[u8], seg_len: u64) -> Vec<IdxEnt> {
+    let mut ents: Vec<IdxEnt> = vec![];
+    for ent in buf.chunks_exact(IDX_ENT_SIZE).map(IdxEnt::decode) {
+        if ent.end() > seg_len || ents.last().map(|l| l.at >= ent.at).unwrap_or(false) {
+            break;
+        }
+        ents.push(ent);
+    }
+    ents
+}
+
+fn compress(rep: &Report) -> Result<Vec<u8>> {
+    let mut enc = DeflateEncoder::new(Vec::new(), Compression::default());
+    serde_json::to_writer(&mut enc, rep)?;
+    Ok(enc.finish()?)
+}
+
+fn decompress(buf: &[u8]) -> Result<Report> {
+    let mut json = vec![];
+    DeflateDecoder::new(buf).read_to_end(&mut json)?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// Read-only handle on a segment. The index is reloaded on misses past its
+/// end so that a segment which is still being appended to can be followed.
+pub struct ReportSegment {
+    start: u64,
+    seg: fs::File,
+    idx_path: PathBuf,
+    ents: Vec<IdxEnt>,
+}
+
+impl ReportSegment {
+    pub fn open(dir: &str, start: u64) -> Result<Option<Self>> {
+        let idx_path = live_idx_path(dir, start);
+        if !idx_path.exists() {
+            return Ok(None);
+        }
+        let seg = fs::File::open(seg_path(dir, start))?;
+        let mut rs = Self {
+            start,
+            seg,
+            idx_path,
+            ents: vec![],
+        };
+        rs.refresh()?;
+        Ok(Some(rs))
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        let seg_len = self.seg.metadata()?.len();
+        self.ents = read_idx(&fs::read(&self.idx_path)?, seg_len);
+        Ok(())
+    }
+
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn timestamps(&self) -> Vec<u64> {
+        self.ents.iter().map(|ent| ent.at).collect()
+    }
+
+    fn lookup(&mut self, at: u64) -> Option<IdxEnt> {
+        if at < self.start || at >= self.start + SEG_SPAN {
+            return None;
+        }
+        match self.ents.binary_search_by_key(&at, |ent| ent.at) {
+            Ok(pos) => return Some(self.ents[pos]),
+            Err(pos) if pos < self.ents.len() => return None,
+            Err(_) => {}
+        }
+        if let Err(e) = self.refresh() {
+            warn!("report: Failed to reload {:?} ({:#})", &self.idx_path, &e);
+            return None;
+        }
+        match self.ents.binary_search_by_key(&at, |ent| ent.at) {
+            Ok(pos) => Some(self.ents[pos]),
+            Err(_) => None,
+        }
+    }
+
+    fn get_raw(&mut self, at: u64) -> Option<Result<Vec<u8>>> {
+        let ent = self.lookup(at)?;
+        let mut buf = vec![0u8; ent.len as usize];
+        Some(
+            self.seg
+                .read_exact_at(&mut buf, ent.off)
+                .map(|_| buf)
+                .map_err(|e| e.into()),
+        )
+    }
+
+    /// Returns None if the segment doesn't have a report for `at`.
+    pub fn get(&mut self, at: u64) -> Option<Result<Report>> {
+        self.get_raw(at).map(|raw| {
+            raw.and_then(|buf| decompress(&buf))
+                .with_context(|| format!("reading {} from segment {}", at, self.start))
+        })
+    }
+}
+
+struct SegWriter {
+    start: u64,
+    seg: fs::File,
+    idx: fs::File,
+    seg_len: u64,
+    last_at: Option<u64>,
+}
+
+impl SegWriter {
+    fn open(dir: &str, start: u64) -> Result<Self> {
+        recover_convert(dir, start)?;
+        let open = |path: PathBuf| {
+            fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .with_context(|| format!("opening {:?}", &path))
+        };
+        let mut seg = open(seg_path(dir, start))?;
+        let mut idx = open(idx_path(dir, start))?;
+
+        // Drop whatever a previous crash may have left behind.
+        let mut buf = vec![];
+        idx.read_to_end(&mut buf)?;
+        let ents = read_idx(&buf, seg.metadata()?.len());
+        let seg_len = ents.last().map(|ent| ent.end()).unwrap_or(0);
+        idx.set_len((ents.len() * IDX_ENT_SIZE) as u64)?;
+        seg.set_len(seg_len)?;
+        idx.seek(std::io::SeekFrom::End(0))?;
+        seg.seek(std::io::SeekFrom::End(0))?;
+
+        Ok(Self {
+            start,
+            seg,
+            idx,
+            seg_len,
+            last_at: ents.last().map(|ent| ent.at),
+        })
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.seg.sync_all()?;
+        self.idx.sync_all()?;
+        Ok(())
+    }
+
+    fn append(&mut self, at: u64, data: &[u8]) -> Result<()> {
+        if let Some(last_at) = self.last_at {
+            if at <= last_at {
+                bail!("report at {} is not after the last one at {}", at, last_at);
+            }
+        }
+        let ent = IdxEnt {
+            at,
+            off: self.seg_len,
+            len: data.len() as u32,
+        };
+        self.seg.write_all(data)?;
+        self.idx.write_all(&ent.encode())?;
+        self.seg_len += data.len() as u64;
+        self.last_at = Some(at);
+        Ok(())
+    }
+}
+
+/// Append-only writer for a report directory.
+pub struct ReportStore {
+    dir: String,
+    cur: Option<SegWriter>,
+}
+
+impl ReportStore {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: dir.into(),
+            cur: None,
+        }
+    }
+
+    pub fn append(&mut self, at: u64, rep: &Report) -> Result<()> {
+        let start = seg_start(at);
+        if self.cur.as_ref().map(|sw| sw.start) != Some(start) {
+            self.cur = None;
+            self.cur = Some(SegWriter::open(&self.dir, start)?);
+        }
+        let data = compress(rep)?;
+        self.cur.as_mut().unwrap().append(at, &data)
+    }
+
+    /// Start timestamps of all segments in `dir` in ascending order.
+    pub fn segments(dir: &str) -> Result<Vec<u64>> {
+        let mut starts = vec![];
+        for ent in fs::read_dir(dir)?.filter_map(|x| x.ok()) {
+            let name = ent.file_name();
+            if let Some(start) = parse_stamp(&name.to_string_lossy(), ".idx") {
+                starts.push(start);
+            }
+        }
+        starts.sort_unstable();
+        Ok(starts)
+    }
+
+    /// Segment and index files which may contain reports in `period`.
+    pub fn segment_files(dir: &str, period: (u64, u64)) -> Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        for start in Self::segments(dir)? {
+            if start + SEG_SPAN > period.0 && start <= period.1 {
+                paths.push(seg_path(dir, start));
+                paths.push(idx_path(dir, start));
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Remove segments which only contain reports older than `before`.
+    pub fn expire(dir: &str, before: u64) -> Result<()> {
+        for start in Self::segments(dir)? {
+            if start + SEG_SPAN > before {
+                continue;
+            }
+            for path in &[idx_path(dir, start), seg_path(dir, start)] {
+                match fs::remove_file(path) {
+                    Ok(()) => debug!("report: Removed stale segment file {:?}", path),
+                    Err(e) => warn!("report: Failed to remove {:?} ({:?})", path, &e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fold the per-report JSON files in `dir` into segments and remove
+    /// them. Reports which are already in a segment take precedence. This
+    /// rewrites the affected segments and shouldn't race against a writer.
+    /// Returns the number of converted reports.
+    pub fn convert_dir(dir: &str) -> Result<usize> {
+        let mut groups = BTreeMap::<u64, Vec<(u64, PathBuf)>>::new();
+        for ent in fs::read_dir(dir)?.filter_map(|x| x.ok()) {
+            let name = ent.file_name();
+            if let Some(at) = parse_stamp(&name.to_string_lossy(), ".json") {
+                groups
+                    .entry(seg_start(at))
+                    .or_default()
+                    .push((at, ent.path()));
+            }
+        }
+
+        let mut nr_converted = 0;
+        for (start, files) in groups.into_iter() {
+            recover_convert(dir, start)?;
+            let mut recs = BTreeMap::<u64, Vec<u8>>::new();
+            if let Some(mut seg) = ReportSegment::open(dir, start)? {
+                for at in seg.timestamps() {
+                    recs.insert(at, seg.get_raw(at).unwrap()?);
+                }
+            }
+
+            let mut converted = vec![];
+            for (at, path) in files.into_iter() {
+                if recs.contains_key(&at) {
+                    converted.push(path);
+                    continue;
+                }
+                match Report::load(&path) {
+                    Ok(rep) => {
+                        recs.insert(at, compress(&rep)?);
+                        converted.push(path);
+                    }
+                    Err(e) => warn!("report: Skipping {:?} ({:#})", &path, &e),
+                }
+            }
+
+            let staging = staging_dir(dir, start);
+            fs::create_dir(&staging)?;
+            let mut sw = SegWriter::open(&staging, start)?;
+            for (at, data) in recs.iter() {
+                sw.append(*at, data)?;
+            }
+            sw.sync()?;
+            drop(sw);
+            // the index goes last, see the top comment
+            fs::rename(seg_path(&staging, start), seg_path(dir, start))?;
+            fs::rename(idx_path(&staging, start), idx_path(dir, start))?;
+            fs::remove_dir(&staging)?;
+
+            for path in converted.iter() {
+                fs::remove_file(path).with_context(|| format!("removing {:?}", path))?;
+            }
+            debug!(
+                "report: Converted {} reports into segment {} in {:?}",
+                converted.len(),
+                start,
+                dir
+            );
+            nr_converted += converted.len();
+        }
+        Ok(nr_converted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_store() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let dir = dir.path().to_str().unwrap();
+        let base = 10 * SEG_SPAN - 2;
+        let dfl_seq = Report::default().seq;
+
+        let mut store = ReportStore::new(dir);
+        for at in base..base + 4 {
+            let rep = Report {
+                seq: at,
+                ..Default::default()
+            };
+            store.append(at, &rep).unwrap();
+        }
+        assert!(store.append(base + 1, &Report::default()).is_err());
+        drop(store);
+
+        // A torn trailing index entry must be ignored and trimmed.
+        let idx = idx_path(dir, seg_start(base + 3));
+        let mut f = fs::OpenOptions::new().append(true).open(&idx).unwrap();
+        f.write_all(&[0u8; 7]).unwrap();
+        drop(f);
+        let mut store = ReportStore::new(dir);
+        store.append(base + 5, &Report::default()).unwrap();
+        drop(store);
+
+        fs::write(
+            format!("{}/{}.json", dir, base + 4),
+            serde_json::to_vec(&Report::default()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(ReportStore::convert_dir(dir).unwrap(), 1);
+
+        assert_eq!(ReportStore::segments(dir).unwrap().len(), 2);
+        let seqs: Vec<Option<u64>> = crate::ReportIter::new(dir, (base, base + 7))
+            .map(|(rep, _)| rep.ok().map(|rep| rep.seq))
+            .collect();
+        assert_eq!(
+            seqs,
+            vec![
+                Some(base),
+                Some(base + 1),
+                Some(base + 2),
+                Some(base + 3),
+                Some(dfl_seq),
+                Some(dfl_seq),
+                None
+            ]
+        );
+
+        ReportStore::expire(dir, base + 2).unwrap();
+        assert_eq!(ReportStore::segments(dir).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_report_path_iter_step() {
+        let iter = || crate::ReportPathIter::new("", (100, 350)).with_step(60);
+        let fwd: Vec<u64> = iter().map(|(_, at)| at).collect();
+        assert_eq!(fwd, vec![100, 160, 220, 280, 340]);
+
+        // backwards, the positions must line up with the front's
+        let mut iter = iter();
+        assert_eq!(iter.next().map(|(_, at)| at), Some(100));
+        assert_eq!(iter.next_back().map(|(_, at)| at), Some(340));
+        assert_eq!(iter.next_back().map(|(_, at)| at), Some(280));
+        assert_eq!(iter.next().map(|(_, at)| at), Some(160));
+    }
+
+    #[test]
+    fn test_interrupted_convert() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let dir = dir.path().to_str().unwrap();
+        let start = 10 * SEG_SPAN;
+        let rep = |seq: u64| Report {
+            seq,
+            ..Default::default()
+        };
+        let seqs = || -> Vec<Option<u64>> {
+            crate::ReportIter::new(dir, (start, start + 3))
+                .map(|(rep, _)| rep.ok().map(|rep| rep.seq))
+                .collect()
+        };
+
+        let mut store = ReportStore::new(dir);
+        store.append(start + 1, &rep(1)).unwrap();
+        drop(store);
+
+        // crash after the rewritten segment was renamed but not the index
+        let staging = staging_dir(dir, start);
+        fs::create_dir(&staging).unwrap();
+        let mut sw = SegWriter::open(&staging, start).unwrap();
+        sw.append(start, &compress(&rep(10)).unwrap()).unwrap();
+        sw.append(start + 1, &compress(&rep(1)).unwrap()).unwrap();
+        drop(sw);
+        fs::rename(seg_path(&staging, start), seg_path(dir, start)).unwrap();
+
+        // readers pick up the staged index which matches the new segment
+        assert_eq!(seqs(), vec![Some(10), Some(1), None]);
+
+        // and the next writer finishes the commit
+        let mut store = ReportStore::new(dir);
+        store.append(start + 2, &rep(2)).unwrap();
+        drop(store);
+        assert!(!std::path::Path::new(&staging).exists());
+        assert_eq!(seqs(), vec![Some(10), Some(1), Some(2)]);
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use rd_util::*;
+
+const SCENARIO_DOC: &str = "\
+//
+// rd-agent scenario script
+//
+// Steps are executed in order by \"rd-agent scenario FILE\" against the
+// rd-agent instance running on the same top-level directory.
+//
+//  steps[].at: Offset in seconds from the start of the scenario. A step
+//              whose offset has already passed, e.g. because an earlier
+//              wait took longer, is executed right away.
+//  steps[].name: Optional label for the log
+//  steps[].cmd: JSON merge patch (RFC 7396) to apply to cmd.json
+//  steps[].slices: JSON merge patch to apply to slices.json
+//  steps[].oomd: JSON merge patch to apply to oomd.json
+//  steps[].wait: Conditions on report.json to wait for after applying the
+//                patches. All must hold at the same time.
+//  steps[].wait[].field: Dot-separated path in report.json, e.g.
+//                        usages.workload.slice.mem_pressures.0
+//  steps[].wait[].op: One of <, <=, >, >=, == and !=
+//  steps[].wait[].value: The number to compare against
+//  steps[].timeout: Give up waiting after this many seconds, 0 for no limit
+//  steps[].on_timeout: \"abort\" or \"continue\" the scenario on timeout
+//
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CmpOp {
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+}
+
+impl CmpOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+        }
+    }
+
+    pub fn eval(&self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            Self::Lt => lhs < rhs,
+            Self::Le => lhs <= rhs,
+            Self::Gt => lhs > rhs,
+            Self::Ge => lhs >= rhs,
+            Self::Eq => lhs == rhs,
+            Self::Ne => lhs != rhs,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WaitCond {
+    pub field: String,
+    pub op: CmpOp,
+    pub value: f64,
+}
+
+/// Numeric value of `field` in `rep`. Bools read as 0 and 1 and other
+/// non-numeric fields as None.
+pub(crate) fn select_num(rep: &serde_json::Value, field: &str) -> Option<f64> {
+    match json_select(rep, field)? {
+        serde_json::Value::Number(v) => v.as_f64(),
+        serde_json::Value::Bool(v) => Some(*v as u32 as f64),
+        _ => None,
+    }
+}
+
+impl WaitCond {
+    /// Returns the current value of the field if the condition holds on
+    /// `rep`, the JSON value of a report. Non-numeric fields, except for
+    /// bools which read as 0 and 1, never satisfy the condition.
+    pub fn eval(&self, rep: &serde_json::Value) -> Option<f64> {
+        let val = select_num(rep, &self.field)?;
+        if self.op.eval(val, self.value) {
+            Some(val)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for WaitCond {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {} {}", &self.field, self.op.symbol(), self.value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnTimeout {
+    #[default]
+    Abort,
+    Continue,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScenarioStep {
+    pub at: f64,
+    pub name: String,
+    pub cmd: Option<serde_json::Value>,
+    pub slices: Option<serde_json::Value>,
+    pub oomd: Option<serde_json::Value>,
+    pub wait: Vec<WaitCond>,
+    pub timeout: f64,
+    pub on_timeout: OnTimeout,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scenario {
+    pub steps: Vec<ScenarioStep>,
+}
+
+impl Scenario {
+    pub fn validate(&self) -> Result<()> {
+        let mut last_at = 0.0;
+        for (idx, step) in self.steps.iter().enumerate() {
+            if step.at < last_at {
+                bail!(
+                    "step {} at {}s is before the previous one at {}s",
+                    idx,
+                    step.at,
+                    last_at
+                );
+            }
+            last_at = step.at;
+
+            for (what, patch) in &[
+                ("cmd", &step.cmd),
+                ("slices", &step.slices),
+                ("oomd", &step.oomd),
+            ] {
+                if let Some(patch) = patch {
+                    if !patch.is_object() {
+                        bail!("step {} {} patch is not an object", idx, what);
+                    }
+                }
+            }
+            if step.timeout < 0.0 {
+                bail!("step {} has negative timeout", idx);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl JsonLoad for Scenario {}
+
+impl JsonSave for Scenario {
+    fn preamble() -> Option<String> {
+        Some(SCENARIO_DOC.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scenario() {
+        let scn: Scenario = serde_json::from_str(
+            r#"{ "steps": [
+                { "at": 0, "cmd": { "hashd": { "A": { "rps_target_ratio": 0.5 } } } },
+                { "at": 30, "wait": [
+                    { "field": "usages.workload.slice.mem_pressures.0", "op": ">=", "value": 0.1 }
+                ], "timeout": 60, "on_timeout": "continue" }
+            ] }"#,
+        )
+        .unwrap();
+        scn.validate().unwrap();
+        assert_eq!(scn.steps[1].on_timeout, OnTimeout::Continue);
+
+        let cond = &scn.steps[1].wait[0];
+        let rep = serde_json::json!({
+            "usages": { "workload.slice": { "mem_pressures": [0.25, 0.1] } }
+        });
+        assert_eq!(cond.eval(&rep), Some(0.25));
+        let rep = serde_json::json!({
+            "usages": { "workload.slice": { "mem_pressures": [0.05, 0.1] } }
+        });
+        assert_eq!(cond.eval(&rep), None);
+
+        let mut bad = scn.clone();
+        bad.steps[1].at = -1.0;
+        assert!(bad.validate().is_err());
+    }
+}
@@ -10,13 +10,126 @@ const SIDE_DEF_DOC: &str = "\
 //
 //  DEF_ID.args[]: Command arguments
 //  DEF_ID.frozen_exp: Sideloader frozen expiration duration
+//  DEF_ID.cpu_weight: Optional CPU weight override [1..10000]
+//  DEF_ID.io_weight: Optional IO weight override [1..10000]
+//  DEF_ID.mem_high: Optional memory.high in bytes
+//  DEF_ID.mem_max: Optional memory.max in bytes
+//  DEF_ID.envs[]: Extra environment variables in \"KEY=VAL\" format
+//  DEF_ID.working_dir: Optional working directory, defaults to the scratch dir
+//  DEF_ID.nice: Optional nice level [-20..19]
+//  DEF_ID.ionice_class: Optional IO scheduling class - RealTime, BestEffort, Idle
+//  DEF_ID.ionice_prio: Optional IO scheduling priority [0..7]
+//  DEF_ID.prep_cmd[]: Command to run in the working dir before each start
+//  DEF_ID.cleanup_cmd[]: Command to run in the working dir after each exit
+//                        Both hooks run as transient services in the
+//                        load's slice with the same overrides
+//  DEF_ID.restart: Restart policy - Never, OnFailure, Always
+//  DEF_ID.restart_delay: Initial restart delay in secs, doubled on each retry
+//  DEF_ID.restart_delay_max: Maximum restart delay in secs
+//  DEF_ID.max_run_time: Optional run time limit in secs, completes on expiration
+//  DEF_ID.completion_marker: Optional file relative to the working dir
+//                            whose appearance completes the load
 //
 ";
 
+const DFL_RESTART_DELAY: u32 = 1;
+const DFL_RESTART_DELAY_MAX: u32 = 300;
+
+fn dfl_restart_delay() -> u32 {
+    DFL_RESTART_DELAY
+}
+
+fn dfl_restart_delay_max() -> u32 {
+    DFL_RESTART_DELAY_MAX
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IoNiceClass {
+    RealTime,
+    BestEffort,
+    Idle,
+}
+
+impl IoNiceClass {
+    /// IOPRIO_CLASS_* value as expected by systemd's IOSchedulingClass.
+    pub fn ioprio_class(&self) -> i32 {
+        match self {
+            Self::RealTime => 1,
+            Self::BestEffort => 2,
+            Self::Idle => 3,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure,
+    Always,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SideloadSpec {
     pub args: Vec<String>,
     pub frozen_exp: u32,
+    #[serde(default)]
+    pub cpu_weight: Option<u64>,
+    #[serde(default)]
+    pub io_weight: Option<u64>,
+    #[serde(default)]
+    pub mem_high: Option<u64>,
+    #[serde(default)]
+    pub mem_max: Option<u64>,
+    #[serde(default)]
+    pub envs: Vec<String>,
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    #[serde(default)]
+    pub nice: Option<i32>,
+    #[serde(default)]
+    pub ionice_class: Option<IoNiceClass>,
+    #[serde(default)]
+    pub ionice_prio: Option<u32>,
+    #[serde(default)]
+    pub prep_cmd: Vec<String>,
+    #[serde(default)]
+    pub cleanup_cmd: Vec<String>,
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    #[serde(default = "dfl_restart_delay")]
+    pub restart_delay: u32,
+    #[serde(default = "dfl_restart_delay_max")]
+    pub restart_delay_max: u32,
+    #[serde(default)]
+    pub max_run_time: Option<u32>,
+    #[serde(default)]
+    pub completion_marker: Option<String>,
+}
+
+impl Default for SideloadSpec {
+    fn default() -> Self {
+        Self {
+            args: vec![],
+            frozen_exp: 0,
+            cpu_weight: None,
+            io_weight: None,
+            mem_high: None,
+            mem_max: None,
+            envs: vec![],
+            working_dir: None,
+            nice: None,
+            ionice_class: None,
+            ionice_prio: None,
+            prep_cmd: vec![],
+            cleanup_cmd: vec![],
+            restart: Default::default(),
+            restart_delay: DFL_RESTART_DELAY,
+            restart_delay_max: DFL_RESTART_DELAY_MAX,
+            max_run_time: None,
+            completion_marker: None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -40,6 +153,7 @@ impl Default for SideloadDefs {
                             "2".into(),
                         ],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -47,6 +161,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["build-linux.sh".into(), "allmodconfig".into(), "1".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -54,6 +169,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["build-linux.sh".into(), "allmodconfig".into(), "2".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -61,6 +177,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["build-linux.sh".into(), "allmodconfig".into(), "4".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -68,6 +185,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["build-linux.sh".into(), "allmodconfig".into(), "8".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -75,6 +193,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["build-linux.sh".into(), "allmodconfig".into(), "16".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -82,6 +201,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["build-linux.sh".into(), "allmodconfig".into(), "32".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -89,6 +209,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["build-linux.sh".into(), "allmodconfig".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -96,6 +217,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["build-linux.sh".into(), "allnoconfig".into(), "1".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -103,6 +225,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["build-linux.sh".into(), "defconfig".into(), "1".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -110,6 +233,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["mem-hog.sh".into(), "10%".into(), "0%".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -117,6 +241,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["mem-hog.sh".into(), "25%".into(), "0%".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -124,6 +249,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["mem-hog.sh".into(), "50%".into(), "0%".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -131,6 +257,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["mem-hog.sh".into(), "100%".into(), "0%".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -138,6 +265,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["mem-hog.sh".into(), "200%".into(), "0%".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -145,6 +273,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["mem-hog.sh".into(), "1000%".into(), "100%".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -152,6 +281,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["read-bomb.py".into(), "1024".into(), "16384".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -159,6 +289,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["burn-cpus.sh".into(), "1".into(), "2".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -166,6 +297,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["burn-cpus.sh".into(), "1".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -173,6 +305,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["burn-cpus.sh".into(), "2".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
                 (
@@ -180,6 +313,7 @@ impl Default for SideloadDefs {
                     SideloadSpec {
                         args: vec!["inodesteal-test.py".into()],
                         frozen_exp: 30,
+                        ..Default::default()
                     },
                 ),
             ]
@@ -1,5 +1,5 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use enum_iterator::IntoEnumIterator;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
@@ -15,14 +15,43 @@ const SLICE_DOC: &str = "\
 //
 // Memory configuration can be either None or Bytes.
 //
+// Additional slices can be declared by adding entries to slices. SLICE_ID
+// is the path from the cgroup root, e.g. \"batch.slice\" or
+// \"workload.slice/tier2.slice\". Following systemd naming, the latter is
+// managed as the workload-tier2.slice unit.
+//
 //  disable_seqs.cpu: Disable CPU control if >= report::seq
 //  disable_seqs.mem: Disable memory control if >= report::seq
 //  disable_seqs.io: Disable IO control if >= report::seq
+//  io_backend: IO protection backend - IoCost or IoLatency
 //  slices.SLICE_ID.cpu_weight: CPU weight [1..10000]
-//  slices.SLICE_ID.io_weight: IO weight [1..10000]
+//  slices.SLICE_ID.io_weight: IO weight [1..10000], IoCost backend only
+//  slices.SLICE_ID.io_latency_target: io.latency target in usecs on the
+//                                     scratch device, IoLatency backend only
 //  slices.SLICE_ID.mem_min: memory.min
 //  slices.SLICE_ID.mem_low: memory.low
 //  slices.SLICE_ID.mem_high: memory.high
+//  slices.SLICE_ID.cpu_max_quota: cpu.max quota in usecs, null for max
+//  slices.SLICE_ID.cpu_max_period: cpu.max period in usecs
+//  slices.SLICE_ID.io_max.MAJ:MIN.rbps: io.max read bytes per second
+//  slices.SLICE_ID.io_max.MAJ:MIN.wbps: io.max write bytes per second
+//  slices.SLICE_ID.io_max.MAJ:MIN.riops: io.max read IOs per second
+//  slices.SLICE_ID.io_max.MAJ:MIN.wiops: io.max write IOs per second
+//  slices.SLICE_ID.mem_max: memory.max
+//  slices.SLICE_ID.mem_swap_max: memory.swap.max
+//  slices.SLICE_ID.mem_zswap_max: memory.zswap.max
+//  slices.SLICE_ID.pids_max: pids.max, null for max
+//  slices.SLICE_ID.cpuset_cpus: cpuset.cpus, e.g. \"0-3,8\", empty to inherit
+//  slices.SLICE_ID.cpuset_mems: cpuset.mems, empty to inherit
+//  slices.SLICE_ID.cpuset_partition: cpuset.cpus.partition - Member, Root
+//                                    or Isolated
+//
+// Hard limits are applied only when the matching controller is enforced
+// (see --passive). pids.max is gated by the \"pids\" selector. io.max
+// limits which aren't specified or null are set to max. cpuset.cpus and
+// partition follow CPU control and cpuset.mems memory control. A partition
+// root requires cpuset_cpus which don't overlap with its siblings. The
+// cpuset controller is required only if any slice configures cpuset.
 //
 ";
 
@@ -60,6 +89,75 @@ impl Slice {
     }
 }
 
+/// A slice managed by rd-agent - either one of the built-in `Slice`s or
+/// a user-defined one identified by its path from the cgroup root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlicePath {
+    name: String,
+    unit_name: String,
+    cgrp: String,
+    builtin: Option<Slice>,
+}
+
+impl SlicePath {
+    pub fn new(name: &str) -> Result<Self> {
+        if let Some(slice) = Slice::into_enum_iter().find(|slc| slc.name() == name) {
+            return Ok(slice.into());
+        }
+
+        let mut prefix = String::new();
+        let mut cgrp = String::from("/sys/fs/cgroup");
+        for comp in name.split('/') {
+            let base = match comp.strip_suffix(".slice") {
+                Some(v) if !v.is_empty() && v.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => v,
+                _ => bail!(
+                    "invalid slice {:?}, each component should be NAME.slice with NAME in [a-zA-Z0-9_]",
+                    name
+                ),
+            };
+            if !prefix.is_empty() {
+                prefix += "-";
+            }
+            prefix += base;
+            cgrp += &format!("/{}.slice", &prefix);
+        }
+
+        Ok(Self {
+            name: name.into(),
+            unit_name: prefix + ".slice",
+            cgrp,
+            builtin: None,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn unit_name(&self) -> &str {
+        &self.unit_name
+    }
+
+    pub fn cgrp(&self) -> &str {
+        &self.cgrp
+    }
+
+    pub fn builtin(&self) -> Option<Slice> {
+        self.builtin
+    }
+}
+
+impl From<Slice> for SlicePath {
+    fn from(slice: Slice) -> Self {
+        Self {
+            name: slice.name().into(),
+            unit_name: slice.name().into(),
+            cgrp: slice.cgrp().into(),
+            builtin: Some(slice),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum MemoryKnob {
     None,
@@ -85,13 +183,66 @@ impl MemoryKnob {
     }
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IoMaxConfig {
+    pub rbps: Option<u64>,
+    pub wbps: Option<u64>,
+    pub riops: Option<u64>,
+    pub wiops: Option<u64>,
+}
+
+impl IoMaxConfig {
+    /// Parse a "MAJ:MIN" device key.
+    pub fn parse_devnr(devnr: &str) -> Result<(u32, u32)> {
+        let mut it = devnr.splitn(2, ':');
+        match (
+            it.next().map(|x| x.parse::<u32>()),
+            it.next().map(|x| x.parse::<u32>()),
+        ) {
+            (Some(Ok(maj)), Some(Ok(min))) => Ok((maj, min)),
+            _ => bail!("invalid io_max device {:?}, should be MAJ:MIN", devnr),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CpusetPartition {
+    #[default]
+    Member,
+    Root,
+    Isolated,
+}
+
+impl CpusetPartition {
+    pub fn cgrp_str(&self) -> &'static str {
+        match self {
+            Self::Member => "member",
+            Self::Root => "root",
+            Self::Isolated => "isolated",
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SliceConfig {
     pub cpu_weight: u32,
     pub io_weight: u32,
     pub mem_min: MemoryKnob,
     pub mem_low: MemoryKnob,
     pub mem_high: MemoryKnob,
+    pub cpu_max_quota: Option<u64>,
+    pub cpu_max_period: u64,
+    pub io_max: BTreeMap<String, IoMaxConfig>,
+    pub mem_max: MemoryKnob,
+    pub mem_swap_max: MemoryKnob,
+    pub mem_zswap_max: MemoryKnob,
+    pub pids_max: Option<u64>,
+    pub cpuset_cpus: String,
+    pub cpuset_mems: String,
+    pub cpuset_partition: CpusetPartition,
+    pub io_latency_target: Option<u64>,
 }
 
 impl Default for SliceConfig {
@@ -102,6 +253,17 @@ impl Default for SliceConfig {
             mem_min: Default::default(),
             mem_low: Default::default(),
             mem_high: Default::default(),
+            cpu_max_quota: None,
+            cpu_max_period: Self::DFL_CPU_MAX_PERIOD,
+            io_max: Default::default(),
+            mem_max: Default::default(),
+            mem_swap_max: Default::default(),
+            mem_zswap_max: Default::default(),
+            pids_max: None,
+            cpuset_cpus: "".into(),
+            cpuset_mems: "".into(),
+            cpuset_partition: Default::default(),
+            io_latency_target: None,
         }
     }
 }
@@ -110,6 +272,7 @@ impl SliceConfig {
     pub const DFL_SYS_CPU_RATIO: f64 = 0.1;
     pub const DFL_SYS_IO_RATIO: f64 = 0.1;
     pub const DFL_MEM_MARGIN: f64 = 0.25;
+    pub const DFL_CPU_MAX_PERIOD: u64 = 100_000;
 
     pub fn dfl_mem_margin(total: usize, fb_prod: bool) -> u64 {
         let margin = (total as f64 * Self::DFL_MEM_MARGIN) as u64;
@@ -169,10 +332,18 @@ pub struct DisableSeqKnobs {
     pub io: u64,
 }
 
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IoBackend {
+    #[default]
+    IoCost,
+    IoLatency,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(default)]
 pub struct SliceKnobs {
     pub disable_seqs: DisableSeqKnobs,
+    pub io_backend: IoBackend,
     pub slices: BTreeMap<String, SliceConfig>,
     #[serde(skip)]
     pub work_mem_low_none: bool,
@@ -186,6 +357,7 @@ impl Default for SliceKnobs {
         }
         Self {
             disable_seqs: Default::default(),
+            io_backend: Default::default(),
             slices,
             work_mem_low_none: false,
         }
@@ -194,7 +366,10 @@ impl Default for SliceKnobs {
 
 impl JsonLoad for SliceKnobs {
     fn loaded(&mut self, _prev: Option<&mut Self>) -> Result<()> {
-        let sk = self.slices.get(Slice::Work.name()).unwrap();
+        let sk = match self.slices.get(Slice::Work.name()) {
+            Some(v) => v,
+            None => bail!("{:?} missing", Slice::Work.name()),
+        };
         self.work_mem_low_none = if let MemoryKnob::None = sk.mem_low {
             true
         } else {
@@ -211,6 +386,100 @@ impl JsonSave for SliceKnobs {
 }
 
 impl SliceKnobs {
+    /// All managed slices, the built-in ones followed by the user-defined
+    /// ones. Parents always precede their children.
+    pub fn managed_slices(&self) -> Vec<SlicePath> {
+        let mut slices: Vec<SlicePath> = Slice::into_enum_iter().map(Into::into).collect();
+        for name in self.slices.keys() {
+            match SlicePath::new(name) {
+                Ok(sp) if sp.builtin().is_none() => slices.push(sp),
+                _ => {}
+            }
+        }
+        slices
+    }
+
+    /// Check the slice configurations. Returns FIELD -> ERROR pairs, empty
+    /// if valid.
+    pub fn validate(&self) -> BTreeMap<String, String> {
+        let mut errs = BTreeMap::new();
+        let mut check = |field: String, res: Result<()>| {
+            if let Err(e) = res {
+                errs.insert(field, format!("{:#}", &e));
+            }
+        };
+
+        for slc in Slice::into_enum_iter() {
+            if !self.slices.contains_key(slc.name()) {
+                check(
+                    format!("slices.{}", slc.name()),
+                    Err(anyhow!("built-in slice missing")),
+                );
+            }
+        }
+
+        for (name, sk) in self.slices.iter() {
+            let pre = format!("slices.{}", name);
+            check(pre.clone(), SlicePath::new(name).map(|_| ()));
+            for (field, v) in &[("cpu_weight", sk.cpu_weight), ("io_weight", sk.io_weight)] {
+                if !(1..=10000).contains(v) {
+                    check(
+                        format!("{}.{}", pre, field),
+                        Err(anyhow!("should be in [1..10000]")),
+                    );
+                }
+            }
+            if sk.cpu_max_quota == Some(0) {
+                check(
+                    format!("{}.cpu_max_quota", pre),
+                    Err(anyhow!("should be positive or null")),
+                );
+            }
+            if !(1000..=1_000_000).contains(&sk.cpu_max_period) {
+                check(
+                    format!("{}.cpu_max_period", pre),
+                    Err(anyhow!("should be in [1000..1000000]")),
+                );
+            }
+            for devnr in sk.io_max.keys() {
+                check(
+                    format!("{}.io_max.{}", pre, devnr),
+                    IoMaxConfig::parse_devnr(devnr).map(|_| ()),
+                );
+            }
+            if sk.io_latency_target == Some(0) {
+                check(
+                    format!("{}.io_latency_target", pre),
+                    Err(anyhow!("should be positive or null")),
+                );
+            }
+            let cpus = parse_cpulist(&sk.cpuset_cpus);
+            let cpus_empty = cpus.as_ref().map(|v| v.is_empty()).unwrap_or(false);
+            check(format!("{}.cpuset_cpus", pre), cpus.map(|_| ()));
+            check(
+                format!("{}.cpuset_mems", pre),
+                parse_cpulist(&sk.cpuset_mems).map(|_| ()),
+            );
+            if sk.cpuset_partition != CpusetPartition::Member && cpus_empty {
+                check(
+                    format!("{}.cpuset_partition", pre),
+                    Err(anyhow!("requires cpuset_cpus")),
+                );
+            }
+        }
+        errs
+    }
+
+    /// Whether any slice configures cpuset. The cpuset controller is
+    /// required and verified only if so.
+    pub fn uses_cpuset(&self) -> bool {
+        self.slices.values().any(|sk| {
+            !sk.cpuset_cpus.trim().is_empty()
+                || !sk.cpuset_mems.trim().is_empty()
+                || sk.cpuset_partition != CpusetPartition::Member
+        })
+    }
+
     pub fn controlls_disabled(&self, seq: u64) -> bool {
         let dseqs = &self.disable_seqs;
         dseqs.cpu >= seq || dseqs.mem >= seq || dseqs.io >= seq
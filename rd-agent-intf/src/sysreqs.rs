@@ -40,10 +40,12 @@ lazy_static::lazy_static! {
 pub enum SysReq {
     Controllers,
     Freezer,
+    Cpuset,
     MemCgRecursiveProt,
     MemShadowInodeProt, // Enforced only by resctl-bench
     IoCost,
     IoCostVer,
+    IoLatency,
     NoOtherIoControllers,
     AnonBalance,
     Btrfs,
@@ -0,0 +1,284 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::Result;
+use crossbeam::channel::{self, RecvTimeoutError};
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::process::{Child, Command, Stdio};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+
+use super::cmd::Runner;
+use super::{ctl, events};
+use rd_agent_intf::{AlertAction, AlertReport, AlertRule, AlertRules, EventKind, Report, Slice};
+use rd_util::*;
+
+//
+// Alert rules
+//
+// Each per-second report is evaluated against the rules in alerts.json.
+// When all conditions of a rule hold for its duration, the alert becomes
+// active and its actions are run once. The alert stays active and is
+// reported in Report::alerts until any of the conditions stops holding.
+//
+
+const REPORT_QUEUE_DEPTH: usize = 16;
+
+#[derive(Debug, PartialEq)]
+enum Transition {
+    Activated,
+    Deactivated,
+}
+
+#[derive(Debug, Default)]
+struct RuleState {
+    match_since: Option<f64>,
+    active_since: Option<f64>,
+    values: Vec<f64>,
+}
+
+impl RuleState {
+    fn update(
+        &mut self,
+        rule: &AlertRule,
+        doc: &serde_json::Value,
+        now: f64,
+    ) -> Option<Transition> {
+        let values: Option<Vec<f64>> = rule.conds.iter().map(|cond| cond.eval(doc)).collect();
+        match values {
+            Some(values) => {
+                self.values = values;
+                let since = *self.match_since.get_or_insert(now);
+                if self.active_since.is_none() && now - since >= rule.duration {
+                    self.active_since = Some(now);
+                    Some(Transition::Activated)
+                } else {
+                    None
+                }
+            }
+            None => {
+                self.match_since = None;
+                self.active_since.take().map(|_| Transition::Deactivated)
+            }
+        }
+    }
+}
+
+struct AlertWorker {
+    runner: Runner,
+    rules_file: JsonConfigFile<AlertRules>,
+    states: BTreeMap<String, RuleState>,
+    children: Vec<Child>,
+}
+
+impl AlertWorker {
+    fn maybe_reload(&mut self) {
+        let prev = self.rules_file.data.clone();
+        match self.rules_file.maybe_reload() {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => {
+                warn!("alerts: Failed to reload rules ({:#})", &e);
+                return;
+            }
+        }
+
+        let errs = self.rules_file.data.validate();
+        if !errs.is_empty() {
+            warn!(
+                "alerts: Rejecting invalid rules, keeping the previous ones ({})",
+                errs.iter()
+                    .map(|(k, v)| format!("{}: {}", k, v))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+            self.rules_file.data = prev;
+            return;
+        }
+
+        info!("alerts: Loaded {} rules", self.rules_file.data.rules.len());
+        let rules = &self.rules_file.data.rules;
+        self.states
+            .retain(|name, _| rules.iter().any(|rule| &rule.name == name));
+    }
+
+    /// Run `args` as a transient service in system.slice so that it's
+    /// accounted and controlled like the rest of the system.
+    fn exec(&mut self, args: &[String], name: &str, values: &[f64]) {
+        let values: Vec<String> = values.iter().map(|v| format!("{}", v)).collect();
+        match Command::new("systemd-run")
+            .args(["--wait", "--quiet", "--collect"])
+            .arg("--slice")
+            .arg(Slice::Sys.name())
+            .arg("-E")
+            .arg(format!("RD_ALERT_NAME={}", name))
+            .arg("-E")
+            .arg(format!("RD_ALERT_VALUES={}", values.join(",")))
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => self.children.push(child),
+            Err(e) => warn!("alerts: Failed to run {:?} for {:?} ({:#})", args, name, &e),
+        }
+    }
+
+    fn fire(&mut self, rule: &AlertRule, values: &[f64]) {
+        for act in rule.actions.iter() {
+            match act {
+                AlertAction::Exec(args) => self.exec(args, &rule.name, values),
+                AlertAction::Event => events::record(EventKind::Alert {
+                    name: rule.name.clone(),
+                    active: true,
+                    values: values.to_vec(),
+                }),
+                AlertAction::Cmd(patch) => {
+                    if let Err(e) = ctl::patch_cmd(&self.runner, patch) {
+                        warn!(
+                            "alerts: Failed to apply cmd patch for {:?} ({:#})",
+                            &rule.name, &e
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn step(&mut self, rep: &Report) -> Result<()> {
+        let mut doc = serde_json::to_value(rep)?;
+        doc["cmd"] = serde_json::to_value(&self.runner.data.lock().unwrap().sobjs.cmd_file.data)?;
+        let now = unix_now_f64();
+
+        let rules = self.rules_file.data.rules.clone();
+        for rule in rules.iter() {
+            let state = self.states.entry(rule.name.clone()).or_default();
+            let values = state.values.clone();
+            match state.update(rule, &doc, now) {
+                Some(Transition::Activated) => {
+                    let values = state.values.clone();
+                    info!("alerts: {:?} active, values={:?}", &rule.name, &values);
+                    self.fire(rule, &values);
+                }
+                Some(Transition::Deactivated) => {
+                    info!("alerts: {:?} cleared", &rule.name);
+                    if rule.actions.contains(&AlertAction::Event) {
+                        events::record(EventKind::Alert {
+                            name: rule.name.clone(),
+                            active: false,
+                            values,
+                        });
+                    }
+                }
+                None => {}
+            }
+        }
+
+        let active: BTreeMap<String, AlertReport> = self
+            .states
+            .iter()
+            .filter_map(|(name, state)| {
+                Some((
+                    name.clone(),
+                    AlertReport {
+                        since: state.active_since? as u64,
+                        values: state.values.clone(),
+                    },
+                ))
+            })
+            .collect();
+        self.runner.data.lock().unwrap().alerts = active;
+
+        self.children
+            .retain_mut(|child| !matches!(child.try_wait(), Ok(Some(_)) | Err(_)));
+        Ok(())
+    }
+
+    fn run(mut self) {
+        let (tx, rx) = channel::bounded::<Report>(REPORT_QUEUE_DEPTH);
+        self.runner.report_subs.lock().unwrap().push(tx);
+
+        while !prog_exiting() {
+            let rep = match rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(rep) => rep,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            self.maybe_reload();
+            if let Err(e) = self.step(&rep) {
+                warn!("alerts: Failed to evaluate rules ({:#})", &e);
+            }
+        }
+    }
+}
+
+pub struct AlertEngine {
+    join_handle: Option<JoinHandle<()>>,
+}
+
+impl AlertEngine {
+    pub fn new(path: &str, runner: Runner) -> Result<Self> {
+        let rules_file = JsonConfigFile::<AlertRules>::load_or_create(Some(&path.to_string()))?;
+        let errs = rules_file.data.validate();
+        if !errs.is_empty() {
+            anyhow::bail!("invalid rules in {:?} ({:?})", path, &errs);
+        }
+
+        let worker = AlertWorker {
+            runner,
+            rules_file,
+            states: Default::default(),
+            children: vec![],
+        };
+        Ok(Self {
+            join_handle: Some(spawn(move || worker.run())),
+        })
+    }
+}
+
+impl Drop for AlertEngine {
+    fn drop(&mut self) {
+        if let Some(jh) = self.join_handle.take() {
+            jh.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rd_agent_intf::{AlertCond, CmpOp};
+
+    #[test]
+    fn test_rule_state() {
+        let rule = AlertRule {
+            name: "pressure".into(),
+            conds: vec![AlertCond {
+                field: "pres".into(),
+                op: CmpOp::Gt,
+                value: 20.0,
+                ref_field: None,
+            }],
+            duration: 10.0,
+            actions: vec![AlertAction::Event],
+        };
+        let doc = |v: f64| serde_json::json!({ "pres": v });
+        let mut state = RuleState::default();
+
+        assert_eq!(state.update(&rule, &doc(30.0), 0.0), None);
+        assert_eq!(state.update(&rule, &doc(30.0), 9.0), None);
+        // dipping below restarts the duration
+        assert_eq!(state.update(&rule, &doc(10.0), 10.0), None);
+        assert_eq!(state.update(&rule, &doc(30.0), 11.0), None);
+        assert_eq!(
+            state.update(&rule, &doc(40.0), 21.0),
+            Some(Transition::Activated)
+        );
+        assert_eq!(state.values, vec![40.0]);
+        assert_eq!(state.update(&rule, &doc(40.0), 22.0), None);
+        assert_eq!(
+            state.update(&rule, &doc(10.0), 23.0),
+            Some(Transition::Deactivated)
+        );
+        assert_eq!(state.update(&rule, &doc(10.0), 24.0), None);
+    }
+}
@@ -1,5 +1,5 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use chrono::prelude::*;
 use log::{debug, info, warn};
 use scan_fmt::scan_fmt;
@@ -12,7 +12,7 @@ use rd_util::*;
 
 use rd_agent_intf::{Slice, HASHD_BENCH_SVC_NAME, IOCOST_BENCH_SVC_NAME};
 
-use super::{hashd, Config, HashdSel};
+use super::{hashd, Config, HashdSel, IoDev};
 
 pub const IOCOST_QOS_PATH: &str = "/sys/fs/cgroup/io.cost.qos";
 const IOCOST_MODEL_PATH: &str = "/sys/fs/cgroup/io.cost.model";
@@ -23,7 +23,7 @@ pub fn start_hashd_bench(
     mem_high: u64,
     mut extra_args: Vec<String>,
 ) -> Result<TransientService> {
-    let mut args = hashd::hashd_path_args(&cfg, HashdSel::A);
+    let mut args = hashd::hashd_path_args(cfg, &HashdSel::a());
     args.push(format!("--bench-log-bps={}", log_bps));
     args.push("--bench".into());
     args.append(&mut extra_args);
@@ -51,7 +51,7 @@ pub fn start_iocost_bench(cfg: &Config) -> Result<TransientService> {
     ];
     debug!("args: {:#?}", &args);
 
-    if let Err(e) = iocost_on_off(false, cfg) {
+    if let Err(e) = scr_iocost_on_off(false, cfg) {
         warn!(
             "bench: Failed to turn off iocost for benchmark on {:?} ({:?})",
             &cfg.scr_dev, &e
@@ -66,15 +66,16 @@ pub fn start_iocost_bench(cfg: &Config) -> Result<TransientService> {
     match svc.start() {
         Ok(()) => Ok(svc),
         Err(e) => {
-            let _ = iocost_on_off(true, cfg);
+            let _ = scr_iocost_on_off(true, cfg);
             Err(e)
         }
     }
 }
 
 pub fn update_hashd(knobs: &mut BenchKnobs, cfg: &Config, hashd_seq: u64) -> Result<()> {
-    let args = rd_hashd_intf::Args::load(&cfg.hashd_paths(HashdSel::A).args)?;
-    let params = rd_hashd_intf::Params::load(&cfg.hashd_paths(HashdSel::A).params)?;
+    let a_paths = cfg.hashd_paths(&HashdSel::a());
+    let args = rd_hashd_intf::Args::load(&a_paths.args)?;
+    let params = rd_hashd_intf::Params::load(&a_paths.params)?;
 
     knobs.hashd.hash_size = params.file_size_mean;
     knobs.hashd.rps_max = params.rps_max as u32;
@@ -90,14 +91,11 @@ pub fn update_hashd(knobs: &mut BenchKnobs, cfg: &Config, hashd_seq: u64) -> Res
     }
     knobs.timestamp = DateTime::from(SystemTime::now());
 
-    fs::copy(
-        &cfg.hashd_paths(HashdSel::A).args,
-        &cfg.hashd_paths(HashdSel::B).args,
-    )?;
-    fs::copy(
-        &cfg.hashd_paths(HashdSel::A).params,
-        &cfg.hashd_paths(HashdSel::B).params,
-    )?;
+    for sel in cfg.hashd_sels().iter().skip(1) {
+        let paths = cfg.hashd_paths(sel);
+        fs::copy(&a_paths.args, &paths.args)?;
+        fs::copy(&a_paths.params, &paths.params)?;
+    }
     Ok(())
 }
 
@@ -125,7 +123,13 @@ pub fn update_iocost(knobs: &mut BenchKnobs, cfg: &Config, iocost_seq: u64) -> R
 
     let (dev_model, dev_fwrev, dev_size) = devname_to_model_fwrev_size(&cfg.scr_dev)?;
 
-    knobs.iocost = iocost;
+    // the scratch device may have been renumbered since the last bench
+    let devnr = iocost.devnr.clone();
+    if knobs.iocost_devnr != devnr {
+        knobs.iocost.remove(&knobs.iocost_devnr);
+    }
+    knobs.iocost.insert(devnr.clone(), iocost);
+    knobs.iocost_devnr = devnr;
     knobs.iocost_dev_model = dev_model;
     knobs.iocost_dev_fwrev = dev_fwrev;
     knobs.iocost_dev_size = dev_size;
@@ -134,39 +138,57 @@ pub fn update_iocost(knobs: &mut BenchKnobs, cfg: &Config, iocost_seq: u64) -> R
     Ok(())
 }
 
-pub fn iocost_on_off(enable: bool, cfg: &Config) -> Result<()> {
-    if !cfg.enforce.io {
-        return Ok(());
-    }
+/// Whether iocost is enabled on any of the managed devices.
+pub fn iocost_enabled(cfg: &Config) -> Result<bool> {
+    let qos = read_cgroup_nested_keyed_file(IOCOST_QOS_PATH)?;
+    Ok(cfg.io_devs.iter().any(|dev| {
+        qos.get(&dev.devnr_str())
+            .and_then(|kv| kv.get("enable"))
+            .map(|v| v == "1")
+            .unwrap_or(false)
+    }))
+}
+
+fn dev_iocost_on_off(enable: bool, dev: &IoDev) -> Result<()> {
     write_one_line(
         IOCOST_QOS_PATH,
-        &format!(
-            "{}:{} enable={}",
-            cfg.scr_devnr.0,
-            cfg.scr_devnr.1,
-            if enable { 1 } else { 0 },
-        ),
+        &format!("{} enable={}", dev.devnr_str(), if enable { 1 } else { 0 },),
     )
 }
 
-pub fn apply_iocost(knobs: &BenchKnobs, cfg: &Config) -> Result<()> {
+fn scr_iocost_on_off(enable: bool, cfg: &Config) -> Result<()> {
     if !cfg.enforce.io {
         return Ok(());
     }
-    if knobs.iocost_seq == 0 {
-        info!(
-            "iocost: Enabling on {:?} with default parameters",
-            &cfg.scr_dev
-        );
-        return iocost_on_off(true, cfg);
+    dev_iocost_on_off(enable, &cfg.io_devs[0])
+}
+
+pub fn iocost_on_off(enable: bool, cfg: &Config) -> Result<()> {
+    if !cfg.enforce.io {
+        return Ok(());
+    }
+    for dev in cfg.io_devs.iter() {
+        dev_iocost_on_off(enable, dev).with_context(|| format!("{:?}", &dev.name))?;
     }
+    Ok(())
+}
 
-    let (maj, min) = cfg.scr_devnr;
-    let model = &knobs.iocost.model;
+fn apply_dev_iocost(dev: &IoDev, knobs: Option<&IoCostKnobs>) -> Result<()> {
+    let knobs = match knobs {
+        Some(v) => v,
+        None => {
+            info!(
+                "iocost: Enabling on {:?} with default parameters",
+                &dev.name
+            );
+            return dev_iocost_on_off(true, dev);
+        }
+    };
+
+    let model = &knobs.model;
     let model_line = format!(
-        "{}:{} model=linear rbps={} rseqiops={} rrandiops={} wbps={} wseqiops={} wrandiops={}",
-        maj,
-        min,
+        "{} model=linear rbps={} rseqiops={} rrandiops={} wbps={} wseqiops={} wrandiops={}",
+        dev.devnr_str(),
         model.rbps,
         model.rseqiops,
         model.rrandiops,
@@ -176,16 +198,44 @@ pub fn apply_iocost(knobs: &BenchKnobs, cfg: &Config) -> Result<()> {
     );
     info!(
         "iocost: Enabling on {:?} with benchmarked parameters",
-        &cfg.scr_dev
+        &dev.name
     );
     debug!("iocost.model: {}", &model_line);
     write_one_line(IOCOST_MODEL_PATH, &model_line)?;
 
-    let qos = &knobs.iocost.qos;
+    let qos = &knobs.qos;
     let qos_line = format!(
-        "{}:{} rpct={:.2} rlat={} wpct={:.2} wlat={} min={:.2} max={:.2}",
-        maj, min, qos.rpct, qos.rlat, qos.wpct, qos.wlat, qos.min, qos.max
+        "{} rpct={:.2} rlat={} wpct={:.2} wlat={} min={:.2} max={:.2}",
+        dev.devnr_str(),
+        qos.rpct,
+        qos.rlat,
+        qos.wpct,
+        qos.wlat,
+        qos.min,
+        qos.max
     );
     debug!("iocost.qos: {}", &qos_line);
     write_one_line(IOCOST_QOS_PATH, &qos_line)
 }
+
+/// Apply the iocost parameters to all managed devices. The scratch device
+/// uses the benchmark result once available, the others their entries in
+/// `knobs.iocost` if present.
+pub fn apply_iocost(knobs: &BenchKnobs, cfg: &Config) -> Result<()> {
+    if !cfg.enforce.io {
+        return Ok(());
+    }
+    for dev in cfg.io_devs.iter() {
+        let dev_knobs = if dev.devnr == cfg.scr_devnr {
+            if knobs.iocost_seq > 0 {
+                Some(knobs.scr_iocost())
+            } else {
+                None
+            }
+        } else {
+            knobs.iocost.get(&dev.devnr_str()).cloned()
+        };
+        apply_dev_iocost(dev, dev_knobs.as_ref()).with_context(|| format!("{:?}", &dev.name))?;
+    }
+    Ok(())
+}
@@ -1,18 +1,22 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use anyhow::{Context, Result};
+use crossbeam::channel::{self, Receiver, Sender};
 use log::{debug, error, info, warn};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::sync::{Arc, Mutex};
-use std::thread::sleep;
 use std::time::{Duration, Instant};
 use systemd::UnitState as US;
 
-use rd_agent_intf::{RunnerState, Slice, HASHD_BENCH_SVC_NAME, IOCOST_BENCH_SVC_NAME};
+use rd_agent_intf::{
+    AlertReport, Cmd, CmdAck, EventKind, Report, RunnerState, Slice, HASHD_A, HASHD_BENCH_SVC_NAME,
+    IOCOST_BENCH_SVC_NAME,
+};
 use rd_util::*;
 
 use super::hashd::HashdSet;
 use super::side::{Balloon, SideRunner, Sideload, Sysload};
-use super::{bench, report, slices};
+use super::txn::{ConfigTxn, TxnParts};
+use super::{alerts, bench, ctl, events, metrics, report, slices};
 use super::{Config, SysObjs};
 
 const HEALTH_CHECK_INTV: Duration = Duration::from_secs(10);
@@ -26,6 +30,7 @@ pub struct RunnerData {
     warned_bench: bool,
     warned_init: bool,
     force_apply: bool,
+    pub ctl_cmd_pending: bool,
 
     pub bench_hashd: Option<TransientService>,
     pub bench_iocost: Option<TransientService>,
@@ -33,22 +38,27 @@ pub struct RunnerData {
     pub hashd_set: HashdSet,
     pub side_runner: SideRunner,
     pub balloon: Balloon,
+    pub config_txn: ConfigTxn,
+    pub alerts: BTreeMap<String, AlertReport>,
 }
 
 impl RunnerData {
     fn new(cfg: Config, sobjs: SysObjs) -> Self {
         let cfg = Arc::new(cfg);
         Self {
-            sobjs,
             state: Idle,
             warned_bench: false,
             warned_init: false,
             force_apply: false,
+            ctl_cmd_pending: false,
             bench_hashd: None,
             bench_iocost: None,
-            hashd_set: HashdSet::new(&cfg),
+            hashd_set: HashdSet::new(cfg.clone()),
             side_runner: SideRunner::new(cfg.clone()),
             balloon: Balloon::new(cfg.clone()),
+            config_txn: ConfigTxn::new(),
+            alerts: Default::default(),
+            sobjs,
             cfg,
         }
     }
@@ -78,13 +88,23 @@ impl RunnerData {
         svcs
     }
 
+    fn set_state(&mut self, state: RunnerState) {
+        if self.state != state {
+            events::record(EventKind::RunnerState {
+                from: self.state,
+                to: state,
+            });
+            self.state = state;
+        }
+    }
+
     fn become_idle(&mut self) {
         info!("cmd: Transitioning to Idle state");
         self.bench_hashd = None;
         self.bench_iocost = None;
         self.hashd_set.stop();
         self.side_runner.stop();
-        self.state = Idle;
+        self.set_state(Idle);
     }
 
     fn maybe_reload_one<T: JsonLoad + JsonSave>(cfile: &mut JsonConfigFile<T>) -> bool {
@@ -101,9 +121,67 @@ impl RunnerData {
         }
     }
 
+    /// Like maybe_reload_one() but the new contents are checked with
+    /// `validate`. Returns None if not reloaded and the validation errors
+    /// otherwise. If there are any, the previous contents are restored so
+    /// that we keep running on the last good configuration.
+    fn maybe_reload_validated<T, F>(
+        cfile: &mut JsonConfigFile<T>,
+        validate: F,
+    ) -> Option<BTreeMap<String, String>>
+    where
+        T: JsonLoad + JsonSave + Clone,
+        F: FnOnce(&T) -> BTreeMap<String, String>,
+    {
+        let prev = cfile.data.clone();
+        if !Self::maybe_reload_one(cfile) {
+            return None;
+        }
+        let errs = validate(&cfile.data);
+        if !errs.is_empty() {
+            warn!(
+                "cmd: Rejecting {:?} ({})",
+                cfile.path.as_ref().unwrap(),
+                CmdAck::format_errors(&errs)
+            );
+            cfile.data = prev;
+        }
+        Some(errs)
+    }
+
+    /// Record `errs` in `slot` and return whether the reload was accepted.
+    fn record_errors(
+        res: Option<BTreeMap<String, String>>,
+        slot: &mut BTreeMap<String, String>,
+        ack_dirty: &mut bool,
+    ) -> bool {
+        match res {
+            Some(errs) => {
+                let accepted = errs.is_empty();
+                if *slot != errs {
+                    *slot = errs;
+                    *ack_dirty = true;
+                }
+                accepted
+            }
+            None => false,
+        }
+    }
+
+    fn commit_cmd_ack(&mut self) {
+        if let Err(e) = self.sobjs.cmd_ack_file.commit() {
+            warn!(
+                "cmd: Failed to update {:?} ({:?})",
+                &self.cfg.cmd_ack_path, &e
+            );
+        }
+    }
+
     fn maybe_reload(&mut self) -> bool {
         let sobjs = &mut self.sobjs;
         let last_cpu_headroom = sobjs.cmd_file.data.sideloader.cpu_headroom;
+        let ack = &mut sobjs.cmd_ack_file.data;
+        let mut ack_dirty = false;
 
         // Configs are controlled by benchmarks while they're running, don't
         // reload.
@@ -112,40 +190,54 @@ impl RunnerData {
             _ => {
                 let force = self.force_apply;
                 self.force_apply = false;
+                let re_slice = Self::record_errors(
+                    Self::maybe_reload_validated(&mut sobjs.slice_file, |sk| sk.validate()),
+                    &mut ack.slice_errors,
+                    &mut ack_dirty,
+                );
+                let re_oomd = Self::record_errors(
+                    Self::maybe_reload_validated(&mut sobjs.oomd.file, |knobs| knobs.validate()),
+                    &mut ack.oomd_errors,
+                    &mut ack_dirty,
+                );
                 (
                     Self::maybe_reload_one(&mut sobjs.bench_file) || force,
-                    Self::maybe_reload_one(&mut sobjs.slice_file) || force,
+                    re_slice || force,
                     Self::maybe_reload_one(&mut sobjs.side_def_file) || force,
-                    Self::maybe_reload_one(&mut sobjs.oomd.file) || force,
+                    re_oomd || force,
                 )
             }
         };
-        let re_cmd = Self::maybe_reload_one(&mut sobjs.cmd_file);
+
+        // A rejected cmd.json is still acked, with the errors, so that the
+        // issuer doesn't have to time out.
+        let side_defs = &sobjs.side_def_file.data;
+        let mut new_seq = 0;
+        let re_cmd = match Self::maybe_reload_validated(&mut sobjs.cmd_file, |cmd: &Cmd| {
+            new_seq = cmd.cmd_seq;
+            cmd.validate(side_defs)
+        }) {
+            Some(errs) => {
+                if !errs.is_empty() {
+                    sobjs.cmd_file.data.cmd_seq = new_seq;
+                }
+                ack.cmd_errors = errs;
+                true
+            }
+            None => false,
+        };
 
         let mem_size = sobjs.bench_file.data.hashd.actual_mem_size();
 
         if re_bench {
             if let Err(e) = bench::apply_iocost(&mut sobjs.bench_file.data, &self.cfg) {
                 warn!(
-                    "cmd: Failed to apply changed iocost configuration on {:?} ({:?})",
-                    self.cfg.scr_dev, &e
+                    "cmd: Failed to apply changed iocost configuration ({:#})",
+                    &e
                 );
             }
         }
 
-        if re_bench || re_slice {
-            if let Err(e) = slices::apply_slices(&mut sobjs.slice_file.data, mem_size, &self.cfg) {
-                warn!("cmd: Failed to apply updated slice overrides ({:?})", &e);
-            }
-        }
-
-        if (re_bench || re_oomd) && self.cfg.enforce.oomd {
-            if let Err(e) = sobjs.oomd.apply() {
-                error!("cmd: Failed to apply oomd configuration ({:?})", &e);
-                panic!();
-            }
-        }
-
         let mut apply_sideloader = false;
 
         if re_slice {
@@ -154,12 +246,12 @@ impl RunnerData {
                 .data
                 .controlls_disabled(super::instance_seq())
             {
-                if sobjs.sideloader.svc.unit.state == US::Running {
+                if sobjs.sideloader.is_running() {
                     info!("cmd: Controllers are being forced off, disabling sideloader");
-                    let _ = sobjs.sideloader.svc.unit.stop();
+                    sobjs.sideloader.stop();
                 }
             } else {
-                if sobjs.sideloader.svc.unit.state != US::Running {
+                if !sobjs.sideloader.is_running() {
                     info!("cmd: All controller enabled, enabling sideloader");
                     apply_sideloader = true;
                 }
@@ -174,13 +266,17 @@ impl RunnerData {
             apply_sideloader = true;
         }
 
-        if apply_sideloader && self.cfg.enforce.all() {
-            let sideloader_cmd = &sobjs.cmd_file.data.sideloader;
-            let slice_knobs = &sobjs.slice_file.data;
-            if let Err(e) = sobjs.sideloader.apply(sideloader_cmd, slice_knobs) {
-                error!("cmd: Failed to apply sideloader changes ({:?})", &e);
-                panic!();
-            }
+        let parts = TxnParts {
+            slices: re_bench || re_slice,
+            oomd: re_bench || re_oomd,
+            sideloader: apply_sideloader,
+        };
+        if parts.any() {
+            self.config_txn.run(parts, sobjs, mem_size, &self.cfg);
+        }
+
+        if ack_dirty && !re_cmd {
+            self.commit_cmd_ack();
         }
 
         re_bench || re_cmd || re_slice
@@ -230,23 +326,37 @@ impl RunnerData {
         Ok(())
     }
 
+    /// Apply cmd.json and ack it. Failures are recorded in the ack's
+    /// apply_errors so that the issuer can tell that the command didn't
+    /// fully take effect.
     fn apply_cmd(
         &mut self,
         removed_sysloads: &mut Vec<Sysload>,
         removed_sideloads: &mut Vec<Sideload>,
+    ) -> Result<bool> {
+        let mut errs = BTreeMap::new();
+        let res = self.apply_cmd_inner(removed_sysloads, removed_sideloads, &mut errs);
+        if let Err(e) = res.as_ref() {
+            errs.insert("cmd".into(), format!("{:#}", e));
+        }
+
+        let ack = &mut self.sobjs.cmd_ack_file.data;
+        ack.cmd_seq = self.sobjs.cmd_file.data.cmd_seq;
+        ack.apply_errors = errs;
+        self.commit_cmd_ack();
+        res
+    }
+
+    fn apply_cmd_inner(
+        &mut self,
+        removed_sysloads: &mut Vec<Sysload>,
+        removed_sideloads: &mut Vec<Sideload>,
+        errs: &mut BTreeMap<String, String>,
     ) -> Result<bool> {
         let cmd = &self.sobjs.cmd_file.data;
         let bench = &self.sobjs.bench_file.data;
         let mut repeat = false;
 
-        self.sobjs.cmd_ack_file.data.cmd_seq = cmd.cmd_seq;
-        if let Err(e) = self.sobjs.cmd_ack_file.commit() {
-            warn!(
-                "cmd: Failed to update {:?} ({:?})",
-                &self.cfg.cmd_ack_path, &e
-            );
-        }
-
         self.apply_swappiness(cmd.swappiness)?;
         self.apply_zswap_enabled(cmd.zswap_enabled)?;
 
@@ -254,7 +364,7 @@ impl RunnerData {
             Idle => {
                 if cmd.bench_iocost_seq > bench.iocost_seq {
                     self.bench_iocost = Some(bench::start_iocost_bench(&*self.cfg)?);
-                    self.state = BenchIoCost;
+                    self.set_state(BenchIoCost);
                     self.force_apply = true;
                 } else if cmd.bench_hashd_seq > bench.hashd_seq {
                     if bench.iocost_seq > 0 || self.cfg.force_running {
@@ -264,20 +374,20 @@ impl RunnerData {
                                 to_gb(cmd.bench_hashd_balloon_size),
                                 &e
                             );
-                            panic!();
+                            errs.insert("bench_hashd_balloon_size".into(), format!("{:#}", &e));
                         }
 
                         self.sobjs.oomd.stop();
 
                         self.bench_hashd = Some(bench::start_hashd_bench(
                             &*self.cfg,
-                            cmd.hashd[0].log_bps,
+                            cmd.hashd.get(HASHD_A).cloned().unwrap_or_default().log_bps,
                             0,
                             cmd.bench_hashd_args.clone(),
                         )?);
                         self.hashd_set.mark_bench_start();
 
-                        self.state = BenchHashd;
+                        self.set_state(BenchHashd);
                         self.force_apply = true;
                     } else if !self.warned_bench {
                         warn!("cmd: iocost benchmark must be run before hashd benchmark");
@@ -285,7 +395,7 @@ impl RunnerData {
                     }
                 } else if bench.hashd_seq > 0 || self.cfg.force_running {
                     info!("cmd: Transitioning to Running state");
-                    self.state = Running;
+                    self.set_state(Running);
                     repeat = true;
                 } else if !self.warned_init {
                     warn!("cmd: hashd benchmark hasn't been run yet, staying idle");
@@ -299,7 +409,7 @@ impl RunnerData {
                 } else {
                     if let Err(e) = self.apply_workloads() {
                         error!("cmd: Failed to apply workload changes ({:?})", &e);
-                        panic!();
+                        errs.insert("hashd".into(), format!("{:#}", &e));
                     }
 
                     let side_defs = &self.sobjs.side_def_file.data;
@@ -311,6 +421,7 @@ impl RunnerData {
                         Some(removed_sysloads),
                     ) {
                         warn!("cmd: Failed to apply sysload changes ({:?})", &e);
+                        errs.insert("sysloads".into(), format!("{:#}", &e));
                     }
                     let sideload_target = &self.sobjs.cmd_file.data.sideloads;
                     if let Err(e) = self.side_runner.apply_sideloads(
@@ -320,6 +431,7 @@ impl RunnerData {
                         Some(removed_sideloads),
                     ) {
                         warn!("cmd: Failed to apply sideload changes ({:?})", &e);
+                        errs.insert("sideloads".into(), format!("{:#}", &e));
                     }
 
                     let balloon_size = ((total_memory() as f64)
@@ -331,7 +443,7 @@ impl RunnerData {
                             to_gb(balloon_size),
                             &e
                         );
-                        panic!();
+                        errs.insert("balloon_ratio".into(), format!("{:#}", &e));
                     }
                 }
             }
@@ -370,14 +482,20 @@ impl RunnerData {
                         info!("cmd: benchmark finished, loading the results");
                         let cmd = &mut self.sobjs.cmd_file.data;
                         let bf = &mut self.sobjs.bench_file;
-                        if self.state == BenchHashd {
+                        let (which, seq) = if self.state == BenchHashd {
                             bench::update_hashd(&mut bf.data, &self.cfg, cmd.bench_hashd_seq)?;
                             bf.save()?;
+                            ("hashd", cmd.bench_hashd_seq)
                         } else {
                             bench::update_iocost(&mut bf.data, &self.cfg, cmd.bench_iocost_seq)?;
                             bf.save()?;
                             bench::apply_iocost(&bf.data, &self.cfg)?;
-                        }
+                            ("iocost", cmd.bench_iocost_seq)
+                        };
+                        events::record(EventKind::BenchDone {
+                            bench: which.into(),
+                            seq,
+                        });
                         self.become_idle();
                         Ok(())
                     }
@@ -388,6 +506,10 @@ impl RunnerData {
                     }
                 }
             }
+            Running => {
+                self.side_runner.check_lifecycles();
+                Ok(())
+            }
             _ => Ok(()),
         }
     }
@@ -396,15 +518,28 @@ impl RunnerData {
 #[derive(Clone)]
 pub struct Runner {
     pub data: Arc<Mutex<RunnerData>>,
+    pub report_subs: Arc<Mutex<Vec<Sender<Report>>>>,
+    wake_tx: Sender<()>,
+    wake_rx: Receiver<()>,
 }
 
 impl Runner {
     pub fn new(cfg: Config, sobjs: SysObjs) -> Self {
+        let (wake_tx, wake_rx) = channel::bounded(1);
         Self {
             data: Arc::new(Mutex::new(RunnerData::new(cfg, sobjs))),
+            report_subs: Default::default(),
+            wake_tx,
+            wake_rx,
         }
     }
 
+    /// Cut the runner's inter-iteration sleep short so that pending
+    /// commands, e.g. ctl_cmd_pending, are picked up immediately.
+    pub fn wake(&self) {
+        let _ = self.wake_tx.try_send(());
+    }
+
     pub fn run(&mut self) {
         let mut reporter = None;
         let mut last_health_check_at = Instant::now();
@@ -413,6 +548,40 @@ impl Runner {
 
         let mut data = self.data.lock().unwrap();
 
+        let _ctl_server = match ctl::CtlServer::new(&data.cfg.ctl_sock_path, self.clone()) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                warn!(
+                    "cmd: Failed to open control socket {:?} ({:?})",
+                    &data.cfg.ctl_sock_path, &e
+                );
+                None
+            }
+        };
+
+        let cfg = data.cfg.clone();
+        let _metrics_exporter = match (&cfg.metrics_addr, &cfg.metrics_textfile) {
+            (None, None) => None,
+            (addr, textfile) => match metrics::MetricsExporter::new(
+                addr.as_deref(),
+                textfile.as_deref(),
+                self.clone(),
+            ) {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    warn!("cmd: Failed to start metrics exporter ({:?})", &e);
+                    None
+                }
+            },
+        };
+        let _alert_engine = match alerts::AlertEngine::new(&cfg.alerts_path, self.clone()) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                warn!("cmd: Failed to start alert engine ({:?})", &e);
+                None
+            }
+        };
+
         while !prog_exiting() {
             // apply commands and check for completions
             let mut removed_sysloads = Vec::new();
@@ -453,8 +622,8 @@ impl Runner {
                 });
             }
 
-            // sleep a bit and start the next iteration
-            sleep(Duration::from_millis(100));
+            // sleep a bit or until woken up and start the next iteration
+            let _ = self.wake_rx.recv_timeout(Duration::from_millis(100));
 
             data = self.data.lock().unwrap();
             let now = Instant::now();
@@ -472,15 +641,18 @@ impl Runner {
                 }
 
                 if data.cfg.enforce.io {
-                    let iosched = match data.state {
-                        BenchIoCost => "none",
-                        _ => "mq-deadline",
-                    };
-                    if let Err(e) = super::set_iosched(&data.cfg.scr_dev, iosched) {
-                        error!(
-                            "cfg: Failed to set {:?} iosched on {:?} ({})",
-                            iosched, &data.cfg.scr_dev, &e
-                        );
+                    for dev in data.cfg.io_devs.iter() {
+                        // only the scratch device is benchmarked
+                        let iosched = match data.state {
+                            BenchIoCost if dev.devnr == data.cfg.scr_devnr => "none",
+                            _ => "mq-deadline",
+                        };
+                        if let Err(e) = super::set_iosched(&dev.name, iosched) {
+                            error!(
+                                "cfg: Failed to set {:?} iosched on {:?} ({})",
+                                iosched, &dev.name, &e
+                            );
+                        }
                     }
                 }
 
@@ -492,6 +664,15 @@ impl Runner {
                 cmd_pending = true;
                 verify_pending = true;
             }
+
+            if data.ctl_cmd_pending {
+                data.ctl_cmd_pending = false;
+                cmd_pending = true;
+            }
         }
+
+        // The alert engine may be waiting on the lock, release it before
+        // the engine gets joined.
+        drop(data);
     }
 }
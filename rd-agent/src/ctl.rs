@@ -0,0 +1,268 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Context, Result};
+use crossbeam::channel::{self, RecvTimeoutError, TrySendError};
+use log::{debug, info, warn};
+use std::fs;
+use std::io::{self, prelude::*, BufReader, LineWriter};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::cmd::Runner;
+use rd_agent_intf::{Cmd, CmdAck, CtlReq, CtlResp, Report, SideloadDefs};
+use rd_util::*;
+
+const CMD_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+const REPORT_QUEUE_DEPTH: usize = 16;
+const MAX_CONNS: usize = 16;
+const CONN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn send_resp(writer: &mut LineWriter<UnixStream>, resp: &CtlResp) -> Result<()> {
+    writeln!(writer, "{}", serde_json::to_string(resp)?)?;
+    Ok(())
+}
+
+/// Merge `patch` into `cur` and validate the result. cmd_seq is always
+/// bumped so that the ack can be waited upon.
+fn merge_cmd_patch(cur: &Cmd, patch: &serde_json::Value, side_defs: &SideloadDefs) -> Result<Cmd> {
+    let mut val = serde_json::to_value(cur)?;
+    json_merge_patch(&mut val, patch);
+    let mut cmd: Cmd = serde_json::from_value(val).context("invalid cmd")?;
+    let errs = cmd.validate(side_defs);
+    if !errs.is_empty() {
+        bail!("invalid cmd ({})", CmdAck::format_errors(&errs));
+    }
+
+    cmd.cmd_seq = cmd.cmd_seq.max(cur.cmd_seq + 1);
+    Ok(cmd)
+}
+
+/// Apply `patch` to cmd.json and wake the runner without waiting for the
+/// ack. Returns the new cmd_seq.
+pub fn patch_cmd(runner: &Runner, patch: &serde_json::Value) -> Result<u64> {
+    let mut data = runner.data.lock().unwrap();
+    let sobjs = &mut data.sobjs;
+    let cmd_file = &mut sobjs.cmd_file;
+
+    let cmd = merge_cmd_patch(&cmd_file.data, patch, &sobjs.side_def_file.data)?;
+    let seq = cmd.cmd_seq;
+
+    cmd_file.data = cmd;
+    cmd_file.save().context("updating cmd file")?;
+    // Don't let the file watcher pick up our own update.
+    if let Some(path) = cmd_file.path.as_ref() {
+        cmd_file.loaded_mod = fs::metadata(path)?.modified()?;
+    }
+    data.ctl_cmd_pending = true;
+    drop(data);
+
+    runner.wake();
+    Ok(seq)
+}
+
+fn apply_cmd_patch(runner: &Runner, patch: &serde_json::Value) -> Result<u64> {
+    let seq = patch_cmd(runner, patch)?;
+
+    let started_at = Instant::now();
+    loop {
+        let data = runner.data.lock().unwrap();
+        let ack = &data.sobjs.cmd_ack_file.data;
+        if ack.cmd_seq >= seq {
+            if ack.cmd_seq == seq && !ack.apply_errors.is_empty() {
+                bail!(
+                    "cmd_seq {} failed to apply ({})",
+                    seq,
+                    CmdAck::format_errors(&ack.apply_errors)
+                );
+            }
+            return Ok(seq);
+        }
+        drop(data);
+
+        if prog_exiting() {
+            bail!("agent exiting");
+        }
+        if started_at.elapsed() >= CMD_ACK_TIMEOUT {
+            bail!("timeout waiting for cmd_seq {} ack", seq);
+        }
+        sleep(Duration::from_millis(10));
+    }
+}
+
+fn stream_reports(runner: &Runner, writer: &mut LineWriter<UnixStream>) -> Result<()> {
+    let (tx, rx) = channel::bounded::<Report>(REPORT_QUEUE_DEPTH);
+    runner.report_subs.lock().unwrap().push(tx);
+
+    while !prog_exiting() {
+        match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(rep) => send_resp(writer, &CtlResp::Report(Box::new(rep)))?,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    Ok(())
+}
+
+fn serve_conn(stream: UnixStream, runner: Runner) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    // Wake up periodically so that the connection can be joined on exit.
+    stream.set_read_timeout(Some(CONN_POLL_INTERVAL))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = LineWriter::new(stream);
+
+    let mut buf = String::new();
+    while !prog_exiting() {
+        // A timed out read_line() keeps what it consumed in buf, retry.
+        match reader.read_line(&mut buf) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e.into()),
+        }
+        let line = std::mem::take(&mut buf);
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let req = match serde_json::from_str::<CtlReq>(&line) {
+            Ok(v) => v,
+            Err(e) => {
+                send_resp(
+                    &mut writer,
+                    &CtlResp::Error(format!("invalid request ({})", &e)),
+                )?;
+                continue;
+            }
+        };
+
+        match req {
+            CtlReq::Cmd(patch) => {
+                let resp = match apply_cmd_patch(&runner, &patch) {
+                    Ok(cmd_seq) => {
+                        debug!("ctl: cmd_seq {} acked", cmd_seq);
+                        CtlResp::Ack { cmd_seq }
+                    }
+                    Err(e) => CtlResp::Error(format!("{:#}", &e)),
+                };
+                send_resp(&mut writer, &resp)?;
+            }
+            CtlReq::Subscribe => return stream_reports(&runner, &mut writer),
+        }
+    }
+    Ok(())
+}
+
+/// Push a new per-second report to all subscribers. Subscribers which
+/// went away are dropped. Slow ones miss reports instead of stalling us.
+pub fn publish_report(runner: &Runner, rep: &Report) {
+    let mut subs = runner.report_subs.lock().unwrap();
+    subs.retain(|tx| !matches!(tx.try_send(rep.clone()), Err(TrySendError::Disconnected(_))));
+}
+
+pub struct CtlServer {
+    path: String,
+    join_handle: Option<JoinHandle<()>>,
+}
+
+impl CtlServer {
+    fn listen(listener: UnixListener, runner: Runner) {
+        let mut conns: Vec<JoinHandle<()>> = vec![];
+
+        while !prog_exiting() {
+            // reap finished connections
+            let (done, live): (Vec<_>, Vec<_>) = conns.drain(..).partition(|jh| jh.is_finished());
+            for jh in done {
+                let _ = jh.join();
+            }
+            conns = live;
+
+            match listener.accept() {
+                Ok((stream, _)) if conns.len() >= MAX_CONNS => {
+                    warn!("ctl: Rejecting connection, {} already open", conns.len());
+                    let _ = send_resp(
+                        &mut LineWriter::new(stream),
+                        &CtlResp::Error("too many connections".into()),
+                    );
+                }
+                Ok((stream, _)) => {
+                    let runner = runner.clone();
+                    conns.push(spawn(move || {
+                        if let Err(e) = serve_conn(stream, runner) {
+                            debug!("ctl: Connection terminated ({:#})", &e);
+                        }
+                    }));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    sleep(CONN_POLL_INTERVAL);
+                }
+                Err(e) => {
+                    warn!("ctl: Failed to accept connection ({:?})", &e);
+                    sleep(CONN_POLL_INTERVAL);
+                }
+            }
+        }
+
+        for jh in conns {
+            let _ = jh.join();
+        }
+    }
+
+    pub fn new(path: &str, runner: Runner) -> Result<Self> {
+        let _ = fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        info!("ctl: Listening on {:?}", path);
+
+        let jh = spawn(move || Self::listen(listener, runner));
+        Ok(Self {
+            path: path.into(),
+            join_handle: Some(jh),
+        })
+    }
+}
+
+impl Drop for CtlServer {
+    fn drop(&mut self) {
+        if let Some(jh) = self.join_handle.take() {
+            jh.join().unwrap();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_cmd_patch() {
+        let side_defs = SideloadDefs::default();
+        let cur = Cmd {
+            cmd_seq: 3,
+            sideloads: [("build".to_string(), "build-linux-half".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+            ..Default::default()
+        };
+
+        // an empty object merges nothing
+        let cmd =
+            merge_cmd_patch(&cur, &serde_json::json!({ "sideloads": {} }), &side_defs).unwrap();
+        assert_eq!(cmd.sideloads.len(), 1);
+        assert_eq!(cmd.cmd_seq, 4);
+
+        // null removes the member which clears all sideloads
+        let cmd =
+            merge_cmd_patch(&cur, &serde_json::json!({ "sideloads": null }), &side_defs).unwrap();
+        assert!(cmd.sideloads.is_empty());
+        assert_eq!(cmd.cmd_seq, 4);
+
+        assert!(merge_cmd_patch(
+            &cur,
+            &serde_json::json!({ "sideloads": { "build": "nonexistent" } }),
+            &side_defs
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,33 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// Process-wide event recorder. Events come from many threads - the
+// runner, sideloader, built-in oomd and oomd journal tailer - so the
+// writer lives behind a global mutex. Recording is a noop until init() is
+// called, which keeps unit tests from writing anywhere.
+//
+use log::warn;
+use std::sync::Mutex;
+
+use rd_agent_intf::{Event, EventKind, EventWriter};
+use rd_util::*;
+
+lazy_static::lazy_static! {
+    static ref EVENT_WRITER: Mutex<Option<EventWriter>> = Mutex::new(None);
+}
+
+pub fn init(dir: &str) {
+    EVENT_WRITER.lock().unwrap().replace(EventWriter::new(dir));
+}
+
+pub fn record(kind: EventKind) {
+    let mut writer = EVENT_WRITER.lock().unwrap();
+    if let Some(writer) = writer.as_mut() {
+        let ev = Event {
+            at: unix_now_f64(),
+            kind,
+        };
+        if let Err(e) = writer.write(&ev) {
+            warn!("events: Failed to record {:?} ({:#})", &ev, &e);
+        }
+    }
+}
@@ -1,20 +1,21 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use anyhow::{bail, Result};
 use log::{debug, info, warn};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::io;
 use std::path::Path;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use rd_agent_intf::{HashdCmd, HashdKnobs, HashdReport, Slice, HASHD_A_SVC_NAME, HASHD_B_SVC_NAME};
+use rd_agent_intf::{HashdCmd, HashdKnobs, HashdReport, Slice, HASHD_A};
 use rd_hashd_intf;
 use rd_util::*;
 
 use super::Config;
 use super::HashdSel;
 
-pub fn hashd_path_args(cfg: &Config, sel: HashdSel) -> Vec<String> {
-    let paths = &cfg.hashd_paths[sel as usize];
+pub fn hashd_path_args(cfg: &Config, sel: &HashdSel) -> Vec<String> {
+    let paths = cfg.hashd_paths(sel);
     let mut args = vec![
         paths.bin.clone(),
         "--args".into(),
@@ -44,17 +45,39 @@ pub struct Hashd {
     lat_target_pct: f64,
     rps_max: u32,
     file_max_ratio: f64,
+    numa_node: Option<u32>,
     svc: Option<TransientService>,
     started_at: Option<SystemTime>,
 }
 
 impl Hashd {
+    fn new(cfg: &Config, sel: &HashdSel) -> Self {
+        let paths = cfg.hashd_paths(sel);
+        Self {
+            name: sel.svc_name(),
+            params_path: paths.params,
+            report_path: paths.report,
+            path_args: hashd_path_args(cfg, sel),
+            lat_target_pct: rd_hashd_intf::Params::default().lat_target_pct,
+            rps_max: 1,
+            file_max_ratio: rd_hashd_intf::Args::default().file_max_frac,
+            numa_node: None,
+            svc: None,
+            started_at: None,
+        }
+    }
+
     fn start(&mut self, mem_size: u64) -> Result<()> {
         let mut args = self.path_args.clone();
         args.push("--size".into());
         args.push(format!("{}", mem_size));
         args.push("--file-max".into());
         args.push(format!("{}", self.file_max_ratio));
+        args.push("--numa-node".into());
+        args.push(match self.numa_node {
+            Some(node) => format!("{}", node),
+            None => "".into(),
+        });
         debug!("args: {:#?}", &args);
 
         let mut svc = TransientService::new_sys(self.name.clone(), args, Vec::new(), Some(0o002))?;
@@ -166,7 +189,7 @@ impl Hashd {
     }
 
     fn update_resctl(&mut self, mem_low: u64, frac: f64) -> Result<()> {
-        let mut svc = self.svc.as_mut().unwrap();
+        let svc = self.svc.as_mut().unwrap();
 
         svc.unit.resctl = systemd::UnitResCtl {
             cpu_weight: Some((100.0 * frac).ceil() as u64),
@@ -223,114 +246,164 @@ impl Hashd {
             nr_idle_workers: hashd_r.hasher.nr_idle_workers,
             mem_probe_size: hashd_r.mem_probe_size,
             mem_probe_at: hashd_r.mem_probe_at,
+            usage_key: String::new(),
         })
     }
 }
 
 pub struct HashdSet {
-    hashd: [Hashd; 2],
+    cfg: Arc<Config>,
+    hashd: BTreeMap<String, Hashd>,
 }
 
 impl HashdSet {
-    pub fn new(cfg: &Config) -> Self {
-        Self {
-            hashd: [
-                Hashd {
-                    name: HASHD_A_SVC_NAME.into(),
-                    params_path: cfg.hashd_paths(HashdSel::A).params.clone(),
-                    report_path: cfg.hashd_paths(HashdSel::A).report.clone(),
-                    path_args: hashd_path_args(cfg, HashdSel::A),
-                    lat_target_pct: rd_hashd_intf::Params::default().lat_target_pct,
-                    rps_max: 1,
-                    file_max_ratio: rd_hashd_intf::Args::default().file_max_frac,
-                    svc: None,
-                    started_at: None,
-                },
-                Hashd {
-                    name: HASHD_B_SVC_NAME.into(),
-                    params_path: cfg.hashd_paths(HashdSel::B).params.clone(),
-                    report_path: cfg.hashd_paths(HashdSel::B).report.clone(),
-                    path_args: hashd_path_args(cfg, HashdSel::B),
-                    lat_target_pct: rd_hashd_intf::Params::default().lat_target_pct,
-                    rps_max: 1,
-                    file_max_ratio: rd_hashd_intf::Args::default().file_max_frac,
-                    svc: None,
-                    started_at: None,
-                },
-            ],
+    pub fn new(cfg: Arc<Config>) -> Self {
+        let hashd = [HashdSel::a(), HashdSel::b()]
+            .iter()
+            .map(|sel| (sel.name().to_string(), Hashd::new(&cfg, sel)))
+            .collect();
+        Self { cfg, hashd }
+    }
+
+    fn add(&mut self, sel: &HashdSel) -> Result<()> {
+        info!("hashd: Adding new instance {:?}", sel.name());
+        self.cfg.prep_hashd(sel)?;
+        self.hashd
+            .insert(sel.name().to_string(), Hashd::new(&self.cfg, sel));
+        if let Err(e) = super::update_index(&self.cfg) {
+            warn!("hashd: Failed to update index ({:#})", &e);
         }
+        Ok(())
     }
 
-    fn weights_to_fracs(cmd: &[HashdCmd; 2]) -> [f64; 2] {
-        match (cmd[0].active, cmd[1].active) {
-            (false, false) => return [0.0, 0.0],
-            (true, false) => return [1.0, 0.0],
-            (false, true) => return [0.0, 1.0],
-            (true, true) => {}
+    /// Distribute resources among the active instances according to their
+    /// weights. Each active instance gets at least 20% of the even share.
+    fn weights_to_fracs(cmd: &BTreeMap<String, HashdCmd>) -> BTreeMap<String, f64> {
+        let mut fracs: BTreeMap<String, f64> = cmd.keys().map(|k| (k.clone(), 0.0)).collect();
+        let active: Vec<(&String, f64)> = cmd
+            .iter()
+            .filter(|(_, hc)| hc.active)
+            .map(|(name, hc)| (name, hc.weight.max(0.0)))
+            .collect();
+
+        match active.len() {
+            0 => return fracs,
+            1 => {
+                fracs.insert(active[0].0.clone(), 1.0);
+                return fracs;
+            }
+            _ => {}
         }
 
-        let sum = cmd[0].weight + cmd[1].weight;
+        let nr_active = active.len() as f64;
+        let sum: f64 = active.iter().map(|(_, w)| w).sum();
         if sum <= 0.0 {
             warn!(
-                "hashd: Invalid weights ({}, {}), using (0.5, 0.5)",
-                cmd[0].weight, cmd[1].weight
+                "hashd: Invalid weights {:?}, distributing evenly",
+                active.iter().map(|(_, w)| w).collect::<Vec<_>>()
             );
-            return [0.5, 0.5];
+            for (name, _) in active.iter() {
+                fracs.insert(name.to_string(), 1.0 / nr_active);
+            }
+            return fracs;
         }
 
-        let (w0, w1) = (cmd[0].weight / sum, cmd[1].weight / sum);
-        if w0 < 0.1 {
-            [0.1, 0.9]
-        } else if w1 < 0.1 {
-            [0.9, 0.1]
-        } else {
-            [w0, w1]
+        // Pin the ones below the minimum and scale the rest to fill the
+        // remainder. Scaling can push more below the minimum, repeat.
+        let min = 0.2 / nr_active;
+        let mut pinned = HashSet::<&String>::new();
+        loop {
+            let free_sum: f64 = active
+                .iter()
+                .filter(|(name, _)| !pinned.contains(name))
+                .map(|(_, w)| w)
+                .sum();
+            let free_share = 1.0 - min * pinned.len() as f64;
+
+            let mut repeat = false;
+            for (name, w) in active.iter() {
+                let frac = match pinned.contains(name) {
+                    true => min,
+                    false => w / free_sum * free_share,
+                };
+                if frac < min && !pinned.contains(name) {
+                    pinned.insert(name);
+                    repeat = true;
+                }
+                fracs.insert(name.to_string(), frac);
+            }
+            if !repeat {
+                return fracs;
+            }
         }
     }
 
-    pub fn apply(&mut self, cmd: &[HashdCmd; 2], knobs: &HashdKnobs, mem_low: u64) -> Result<()> {
-        let fracs = Self::weights_to_fracs(cmd);
+    pub fn apply(
+        &mut self,
+        cmd: &BTreeMap<String, HashdCmd>,
+        knobs: &HashdKnobs,
+        mem_low: u64,
+    ) -> Result<()> {
+        let mut valid = BTreeMap::new();
+        for (name, hc) in cmd.iter() {
+            match HashdSel::new(name) {
+                Ok(sel) => {
+                    if !self.hashd.contains_key(name) {
+                        self.add(&sel)?;
+                    }
+                    valid.insert(name.clone(), hc.clone());
+                }
+                Err(e) => warn!("hashd: Ignoring ({:#})", &e),
+            }
+        }
+        let cmd = valid;
+
+        let fracs = Self::weights_to_fracs(&cmd);
         debug!("hashd: fracs={:?}", &fracs);
 
         // handle the goners first
-        for i in 0..2 {
-            if !cmd[i].active && self.hashd[i].svc.is_some() {
-                self.hashd[i].svc = None;
-                self.hashd[i].started_at = None;
+        for (name, hashd) in self.hashd.iter_mut() {
+            let active = cmd.get(name).map(|hc| hc.active).unwrap_or(false);
+            if !active && hashd.svc.is_some() {
+                hashd.svc = None;
+                hashd.started_at = None;
             }
         }
 
-        // adjust the args
-        for i in 0..2 {
-            if self.hashd[i].svc.is_some() && cmd[i].file_max_ratio != self.hashd[i].file_max_ratio
-            {
+        for (name, hc) in cmd.iter() {
+            let hashd = self.hashd.get_mut(name).unwrap();
+            let frac = fracs[name];
+
+            // adjust the args
+            if hashd.svc.is_some() && hc.file_max_ratio != hashd.file_max_ratio {
                 info!(
                     "hashd: file_max_ratio updated for active hashd {}, need a restart",
-                    i
+                    name
                 );
             }
-            self.hashd[i].file_max_ratio = cmd[i].file_max_ratio;
-        }
+            hashd.file_max_ratio = hc.file_max_ratio;
+            if hashd.svc.is_some() && hc.numa_node != hashd.numa_node {
+                info!(
+                    "hashd: numa_node updated for active hashd {}, need a restart",
+                    name
+                );
+            }
+            hashd.numa_node = hc.numa_node;
 
-        // adjust the params files
-        for i in 0..2 {
-            if fracs[i] != 0.0 {
-                self.hashd[i].update_params(knobs, &cmd[i], fracs[i])?;
+            // adjust the params files
+            if frac != 0.0 {
+                hashd.update_params(knobs, hc, frac)?;
             }
-        }
 
-        // start missing ones
-        for i in 0..2 {
-            if cmd[i].active && self.hashd[i].svc.is_none() {
-                self.hashd[i].start(knobs.mem_size)?;
+            // start missing ones
+            if hc.active && hashd.svc.is_none() {
+                hashd.start(knobs.mem_size)?;
             }
-        }
 
-        // update resctl params
-        for i in 0..2 {
-            if self.hashd[i].svc.is_some() {
-                debug!("hashd: updating resctl on {:?}", &self.hashd[i].name);
-                self.hashd[i].update_resctl(mem_low, fracs[i])?;
+            // update resctl params
+            if hashd.svc.is_some() {
+                debug!("hashd: updating resctl on {:?}", &hashd.name);
+                hashd.update_resctl(mem_low, frac)?;
             }
         }
 
@@ -338,39 +411,84 @@ impl HashdSet {
     }
 
     pub fn mark_bench_start(&mut self) {
-        self.hashd[0].started_at = Some(SystemTime::now());
+        self.hashd.get_mut(HASHD_A).unwrap().started_at = Some(SystemTime::now());
     }
 
     pub fn stop(&mut self) {
-        for i in 0..2 {
-            if self.hashd[i].svc.is_some() {
-                self.hashd[i].svc = None;
-                self.hashd[i].started_at = None;
+        for hashd in self.hashd.values_mut() {
+            if hashd.svc.is_some() {
+                hashd.svc = None;
+                hashd.started_at = None;
             }
         }
     }
 
     pub fn all_svcs(&self) -> HashSet<(String, String)> {
         let mut svcs = HashSet::<(String, String)>::new();
-        if self.hashd[0].svc.is_some() {
-            svcs.insert((
-                HASHD_A_SVC_NAME.to_owned(),
-                format!("{}/{}", Slice::Work.cgrp(), HASHD_A_SVC_NAME),
-            ));
-        }
-        if self.hashd[1].svc.is_some() {
-            svcs.insert((
-                HASHD_B_SVC_NAME.to_owned(),
-                format!("{}/{}", Slice::Work.cgrp(), HASHD_B_SVC_NAME),
-            ));
+        for hashd in self.hashd.values() {
+            if hashd.svc.is_some() {
+                svcs.insert((
+                    hashd.name.clone(),
+                    format!("{}/{}", Slice::Work.cgrp(), &hashd.name),
+                ));
+            }
         }
         svcs
     }
 
-    pub fn report(&mut self, expiration: SystemTime) -> Result<[HashdReport; 2]> {
-        Ok([
-            self.hashd[0].report(expiration)?,
-            self.hashd[1].report(expiration)?,
-        ])
+    pub fn report(&mut self, expiration: SystemTime) -> Result<BTreeMap<String, HashdReport>> {
+        let mut reports = BTreeMap::new();
+        for (name, hashd) in self.hashd.iter_mut() {
+            reports.insert(name.clone(), hashd.report(expiration)?);
+        }
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashdSet;
+    use rd_agent_intf::HashdCmd;
+    use std::collections::BTreeMap;
+
+    fn cmds(weights: &[(bool, f64)]) -> BTreeMap<String, HashdCmd> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, (active, weight))| {
+                (
+                    format!("{}", i),
+                    HashdCmd {
+                        active: *active,
+                        weight: *weight,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn fracs(weights: &[(bool, f64)]) -> Vec<f64> {
+        HashdSet::weights_to_fracs(&cmds(weights))
+            .values()
+            .map(|v| (v * 1000.0).round() / 1000.0)
+            .collect()
+    }
+
+    #[test]
+    fn test_weights_to_fracs() {
+        assert_eq!(fracs(&[(false, 1.0), (false, 1.0)]), vec![0.0, 0.0]);
+        assert_eq!(fracs(&[(true, 1.0), (false, 1.0)]), vec![1.0, 0.0]);
+        assert_eq!(fracs(&[(true, 1.0), (true, 3.0)]), vec![0.25, 0.75]);
+        assert_eq!(fracs(&[(true, 0.01), (true, 1.0)]), vec![0.1, 0.9]);
+        assert_eq!(fracs(&[(true, 0.0), (true, 0.0)]), vec![0.5, 0.5]);
+        assert_eq!(
+            fracs(&[(true, 1.0), (true, 1.0), (false, 5.0), (true, 2.0)]),
+            vec![0.25, 0.25, 0.0, 0.5]
+        );
+
+        let fs = fracs(&[(true, 0.001), (true, 0.001), (true, 1.0), (true, 1.0)]);
+        assert_eq!(fs, vec![0.05, 0.05, 0.45, 0.45]);
+        assert!((fs.iter().sum::<f64>() - 1.0).abs() < 0.001);
     }
 }
@@ -0,0 +1,258 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// IO latency estimation from the block layer statistics, used when
+// biolatpcts can't run because bcc is missing or --no-iolat is specified.
+//
+// /sys/dev/block/MAJ:MIN/stat only carries the number of completed IOs and
+// the total milliseconds they took for each IO type, so the mean latency
+// of an interval is exact but the shape of the distribution isn't known.
+// The percentiles are filled in assuming exponentially distributed
+// latencies around the mean. The inflight file is sampled during the
+// interval so that IOs which are stuck without completing still show up
+// as latencies as long as the interval.
+//
+use anyhow::{bail, Result};
+use crossbeam::channel::{self, Receiver, RecvTimeoutError, Sender};
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rd_agent_intf::IoLatReport;
+use rd_util::*;
+
+const IO_TYPES: [&str; 4] = ["read", "write", "discard", "flush"];
+const NR_INFLIGHT_SAMPLES: u32 = 10;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct BlockStat {
+    // ios and ticks in msecs for each of IO_TYPES
+    ios: [u64; 4],
+    ticks: [u64; 4],
+}
+
+impl BlockStat {
+    fn read(devnr: (u32, u32)) -> Result<Self> {
+        let path = format!("/sys/dev/block/{}:{}/stat", devnr.0, devnr.1);
+        let line = read_one_line(&path)?;
+        let f: Vec<u64> = line
+            .split_whitespace()
+            .map(|x| x.parse::<u64>())
+            .collect::<Result<_, _>>()?;
+        if f.len() < 11 {
+            bail!("too few fields in {:?}", &path);
+        }
+        let get = |idx: usize| f.get(idx).cloned().unwrap_or(0);
+        // see Documentation/admin-guide/iostats.rst
+        Ok(Self {
+            ios: [get(0), get(4), get(11), get(15)],
+            ticks: [get(3), get(7), get(14), get(16)],
+        })
+    }
+}
+
+fn read_inflight(devnr: (u32, u32)) -> Result<(u64, u64)> {
+    let path = format!("/sys/dev/block/{}:{}/inflight", devnr.0, devnr.1);
+    let line = read_one_line(&path)?;
+    let mut toks = line.split_whitespace().map(|x| x.parse::<u64>());
+    match (toks.next(), toks.next()) {
+        (Some(Ok(r)), Some(Ok(w))) => Ok((r, w)),
+        _ => bail!("failed to parse {:?}", &path),
+    }
+}
+
+/// Latency at percentile `pct` of an exponential distribution with `mean`.
+fn exp_quantile(mean: f64, pct: f64) -> f64 {
+    if pct >= 1.0 {
+        // unbounded, report the same as the highest finite percentile,
+        // computed the same way as fill_pcts() so that the two match
+        return exp_quantile(mean, 99.999 / 100.0);
+    }
+    -mean * (1.0 - pct).ln()
+}
+
+fn fill_pcts(mean: f64) -> BTreeMap<String, f64> {
+    IoLatReport::PCTS
+        .iter()
+        .map(|pct| {
+            let frac = pct.parse::<f64>().unwrap() / 100.0;
+            (pct.to_string(), exp_quantile(mean, frac))
+        })
+        .collect()
+}
+
+/// Estimate IO latency distributions between `last` and `cur`. `stalled`
+/// indicates the IO types which had IOs in flight throughout `dur`.
+fn estimate(last: &BlockStat, cur: &BlockStat, stalled: [bool; 4], dur: f64) -> IoLatReport {
+    let mut rep = IoLatReport::default();
+    for (idx, key) in IO_TYPES.iter().enumerate() {
+        let ios = cur.ios[idx].saturating_sub(last.ios[idx]);
+        let ticks = cur.ticks[idx].saturating_sub(last.ticks[idx]);
+        let mean = if ios > 0 {
+            ticks as f64 / 1000.0 / ios as f64
+        } else if stalled[idx] {
+            dur
+        } else {
+            continue;
+        };
+        rep.map.insert(key.to_string(), fill_pcts(mean));
+    }
+    rep
+}
+
+fn format_iolat(rep: &IoLatReport) -> String {
+    let mut top = json::JsonValue::new_object();
+    for (key, pcts) in rep.map.iter() {
+        let mut obj = json::JsonValue::new_object();
+        for (pct, v) in pcts.iter() {
+            obj[pct.as_str()] = (*v).into();
+        }
+        top[key.as_str()] = obj;
+    }
+    top.dump()
+}
+
+/// Emulates biolatpcts: each output line is the JSON of the latency
+/// percentiles. With a positive interval, a line is output every `intv`.
+/// Otherwise, the cumulative distribution since start is output on each
+/// kick.
+pub struct BlockStatLat {
+    kick_tx: Option<Sender<()>>,
+    jh: Option<JoinHandle<()>>,
+}
+
+impl BlockStatLat {
+    /// Sample inflight over `intv` and return which IO types had IOs in
+    /// flight in all samples. None if `kick_rx` got disconnected.
+    fn sample_inflight(
+        devnr: (u32, u32),
+        intv: Duration,
+        kick_rx: &Receiver<()>,
+    ) -> Option<[bool; 4]> {
+        let mut stalled = [true, true, false, false];
+        for _ in 0..NR_INFLIGHT_SAMPLES {
+            match kick_rx.recv_timeout(intv / NR_INFLIGHT_SAMPLES) {
+                Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+            match read_inflight(devnr) {
+                Ok((r, w)) => {
+                    stalled[0] &= r > 0;
+                    stalled[1] &= w > 0;
+                }
+                Err(_) => stalled = [false; 4],
+            }
+        }
+        Some(stalled)
+    }
+
+    fn run(
+        devnr: (u32, u32),
+        intv: Option<Duration>,
+        tx: Sender<String>,
+        kick_rx: Receiver<()>,
+    ) -> Result<()> {
+        let base = BlockStat::read(devnr)?;
+        let mut last = base;
+        let mut last_at = Instant::now();
+
+        loop {
+            let stalled = match intv {
+                Some(intv) => match Self::sample_inflight(devnr, intv, &kick_rx) {
+                    Some(v) => v,
+                    None => return Ok(()),
+                },
+                None => match kick_rx.recv() {
+                    Ok(()) => [false; 4],
+                    Err(_) => return Ok(()),
+                },
+            };
+
+            let cur = match BlockStat::read(devnr) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("iolat: Failed to read block stat ({:?})", &e);
+                    continue;
+                }
+            };
+            let now = Instant::now();
+            let rep = match intv {
+                Some(_) => {
+                    let dur = now.duration_since(last_at).as_secs_f64();
+                    estimate(&last, &cur, stalled, dur)
+                }
+                None => estimate(&base, &cur, [false; 4], 0.0),
+            };
+            last = cur;
+            last_at = now;
+
+            if tx.send(format_iolat(&rep)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// `intv` follows biolatpcts' -i, "-1" for cumulative.
+    pub fn start(devnr: (u32, u32), name: &str, intv: &str, tx: Sender<String>) -> Result<Self> {
+        let intv = match intv.parse::<f64>()? {
+            v if v > 0.0 => Some(Duration::from_secs_f64(v)),
+            _ => None,
+        };
+        let (kick_tx, kick_rx) = channel::unbounded::<()>();
+        let name = name.to_string();
+        let jh = spawn(move || {
+            if let Err(e) = Self::run(devnr, intv, tx, kick_rx) {
+                warn!("iolat: {} failed ({:?})", &name, &e);
+            }
+            debug!("iolat: {} exiting", &name);
+        });
+        Ok(Self {
+            kick_tx: Some(kick_tx),
+            jh: Some(jh),
+        })
+    }
+
+    pub fn kick(&self) {
+        if let Some(tx) = self.kick_tx.as_ref() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+impl Drop for BlockStatLat {
+    fn drop(&mut self) {
+        self.kick_tx.take();
+        if let Some(jh) = self.jh.take() {
+            let _ = jh.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let last = BlockStat {
+            ios: [100, 100, 0, 0],
+            ticks: [100, 200, 0, 0],
+        };
+        let cur = BlockStat {
+            ios: [200, 100, 0, 0],
+            ticks: [300, 200, 0, 0],
+        };
+        let rep = estimate(&last, &cur, [false, true, false, false], 1.0);
+
+        // 100 reads took 200ms, 2ms mean
+        let read = &rep.map["read"];
+        assert_eq!(read["00"], 0.0);
+        assert!((read["50"] - 0.002 * 2f64.ln()).abs() < 1e-9);
+        assert!(read["99"] > read["90"]);
+        assert_eq!(read["100"], read["99.999"]);
+
+        // no write completed while in flight throughout the interval
+        assert!((rep.map["write"]["50"] - 2f64.ln()).abs() < 1e-9);
+        assert_eq!(rep.map["discard"]["99"], 0.0);
+    }
+}
@@ -3,6 +3,7 @@ use anyhow::{anyhow, bail, Result};
 use log::{debug, error, info, trace, warn};
 use proc_mounts::MountInfo;
 use scan_fmt::scan_fmt;
+use std::collections::BTreeMap;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
@@ -14,20 +15,30 @@ use std::thread::sleep;
 use std::time::Duration;
 use sysinfo::{ProcessExt, SystemExt};
 
+mod alerts;
 mod bandit;
 mod bench;
 mod cmd;
+mod ctl;
+mod events;
 mod hashd;
+mod iolat;
+mod metrics;
 mod misc;
 mod oomd;
+mod oomd_builtin;
+mod query;
 mod report;
+mod scenario;
 mod side;
 mod sideloader;
 mod slices;
+mod txn;
 
 use rd_agent_intf::{
-    Args, BenchKnobs, Cmd, CmdAck, EnforceConfig, MissedSysReqs, Report, SideloadDefs, SliceKnobs,
-    SvcReport, SvcStateReport, SysReq, SysReqsReport, ALL_SYSREQS_SET, OOMD_SVC_NAME,
+    hashd_svc_name, Args, BenchKnobs, Cmd, CmdAck, EnforceConfig, IoBackend, MissedSysReqs, Report,
+    ReportStore, SideloadDefs, SliceKnobs, SvcReport, SvcStateReport, SysReq, SysReqsReport,
+    ALL_SYSREQS_SET, HASHD_A, HASHD_B, OOMD_SVC_NAME,
 };
 use rd_util::*;
 use report::clear_old_report_files;
@@ -114,10 +125,41 @@ fn set_iosched(dev: &str, iosched: &str) -> Result<()> {
     Ok(())
 }
 
-#[derive(Copy, Clone, Debug)]
-pub enum HashdSel {
-    A = 0,
-    B = 1,
+lazy_static::lazy_static! {
+    static ref HASHD_NAME_RE: regex::Regex = regex::Regex::new("^[a-zA-Z0-9_-]+$").unwrap();
+}
+
+/// Selects a rd-hashd instance by its name. A and B always exist and A is
+/// the one used for benchmarks.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HashdSel(String);
+
+impl HashdSel {
+    pub fn new(name: &str) -> Result<Self> {
+        if !HASHD_NAME_RE.is_match(name) {
+            bail!(
+                "Invalid hashd name {:?}, should only contain alnums, - and _",
+                name
+            );
+        }
+        Ok(Self(name.into()))
+    }
+
+    pub fn a() -> Self {
+        Self(HASHD_A.into())
+    }
+
+    pub fn b() -> Self {
+        Self(HASHD_B.into())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    pub fn svc_name(&self) -> String {
+        hashd_svc_name(&self.0)
+    }
 }
 
 #[derive(Debug)]
@@ -137,6 +179,20 @@ pub struct IoCostPaths {
     pub result: String,
 }
 
+/// A storage device under IO control. The scratch device is always the
+/// first one.
+#[derive(Debug, Clone)]
+pub struct IoDev {
+    pub name: String,
+    pub devnr: (u32, u32),
+}
+
+impl IoDev {
+    pub fn devnr_str(&self) -> String {
+        format!("{}:{}", self.devnr.0, self.devnr.1)
+    }
+}
+
 #[derive(Debug)]
 pub struct Config {
     pub top_path: String,
@@ -144,18 +200,24 @@ pub struct Config {
     pub scr_dev: String,
     pub scr_devnr: (u32, u32),
     pub scr_dev_forced: bool,
+    pub io_devs: Vec<IoDev>,
     pub index_path: String,
     pub sysreqs_path: String,
     pub cmd_path: String,
     pub cmd_ack_path: String,
+    pub ctl_sock_path: String,
+    pub metrics_addr: Option<String>,
+    pub metrics_textfile: Option<String>,
     pub report_path: String,
     pub report_1min_path: String,
     pub report_d_path: String,
     pub report_1min_d_path: String,
+    pub events_d_path: String,
     pub bench_path: String,
     pub slices_path: String,
+    pub alerts_path: String,
     pub agent_bin: String,
-    pub hashd_paths: [HashdPaths; 2],
+    pub hashd_bin: String,
     pub misc_bin_path: String,
     pub biolatpcts_bin: Option<String>,
     pub iocost_paths: IoCostPaths,
@@ -163,9 +225,7 @@ pub struct Config {
     pub oomd_sys_svc: Option<String>,
     pub oomd_cfg_path: String,
     pub oomd_daemon_cfg_path: String,
-    pub sideloader_bin: String,
     pub sideloader_daemon_jobs_path: String,
-    pub sideloader_daemon_cfg_path: String,
     pub sideloader_daemon_status_path: String,
     pub side_defs_path: String,
     pub side_bin_path: String,
@@ -175,6 +235,7 @@ pub struct Config {
     pub side_linux_tar_path: Option<String>,
 
     pub rep_retention: Option<u64>,
+    pub rep_store: bool,
     pub rep_1min_retention: Option<u64>,
     pub force_running: bool,
     pub bypass: bool,
@@ -182,9 +243,8 @@ pub struct Config {
     pub enforce: EnforceConfig,
 
     pub sr_failed: MissedSysReqs,
-    sr_iosched: Option<String>,
-    sr_wbt: Option<u64>,
-    sr_wbt_path: Option<String>,
+    sr_iosched: Vec<(String, String)>,
+    sr_wbt: Vec<(String, u64)>,
     sr_swappiness: Option<u32>,
     sr_zswap_enabled: Option<bool>,
     sr_oomd_sys_svc: Option<systemd::Unit>,
@@ -303,6 +363,27 @@ impl Config {
                 .to_string(),
         };
 
+        let scr_devnr = storage_info::devname_to_devnr(&scr_dev).unwrap();
+        let mut io_devs = vec![IoDev {
+            name: scr_dev.clone(),
+            devnr: scr_devnr,
+        }];
+        for name in args.devs.iter() {
+            if io_devs.iter().any(|dev| &dev.name == name) {
+                continue;
+            }
+            match storage_info::devname_to_devnr(name) {
+                Ok(devnr) => io_devs.push(IoDev {
+                    name: name.clone(),
+                    devnr,
+                }),
+                Err(e) => {
+                    error!("cfg: Failed to lookup devnr for {:?} ({:#})", name, &e);
+                    panic!();
+                }
+            }
+        }
+
         let agent_bin = find_bin("rd-agent", exe_dir().ok())
             .expect("Failed to find rd-agent bin")
             .to_str()
@@ -344,6 +425,9 @@ impl Config {
         Self::prep_dir(&report_d_path);
         Self::prep_dir(&report_1min_d_path);
 
+        let events_d_path = top_path.clone() + "/events.d";
+        Self::prep_dir(&events_d_path);
+
         let bench_path = top_path.clone()
             + "/"
             + match args.bench_file.as_ref() {
@@ -351,8 +435,10 @@ impl Config {
                 Some(name) => name,
             };
 
-        Self::prep_dir(&(top_path.clone() + "/hashd-A"));
-        Self::prep_dir(&(top_path.clone() + "/hashd-B"));
+        for name in [HASHD_A, HASHD_B].iter() {
+            Self::prep_dir(&format!("{}/hashd-{}", &top_path, name));
+            Self::prep_dir(&format!("{}/hashd-{}/testfiles", &scr_path, name));
+        }
         Self::prep_dir(&(top_path.clone() + "/oomd"));
 
         let sideloader_jobs_d = top_path.clone() + "/sideloader/jobs.d";
@@ -373,38 +459,26 @@ impl Config {
         }
 
         Self {
-            scr_devnr: storage_info::devname_to_devnr(&scr_dev).unwrap(),
+            scr_devnr,
             scr_dev,
             scr_dev_forced: args.dev.is_some(),
+            io_devs,
             index_path: top_path.clone() + "/index.json",
             sysreqs_path: top_path.clone() + "/sysreqs.json",
             cmd_path: top_path.clone() + "/cmd.json",
             cmd_ack_path: top_path.clone() + "/cmd-ack.json",
+            ctl_sock_path: top_path.clone() + "/ctl.sock",
+            metrics_addr: args.metrics.clone(),
+            metrics_textfile: args.metrics_textfile.clone(),
             report_path: top_path.clone() + "/report.json",
             report_1min_path: top_path.clone() + "/report-1min.json",
             report_d_path,
             report_1min_d_path,
+            events_d_path,
             bench_path,
             slices_path: top_path.clone() + "/slices.json",
             agent_bin,
-            hashd_paths: [
-                HashdPaths {
-                    bin: hashd_bin.clone(),
-                    args: top_path.clone() + "/hashd-A/args.json",
-                    params: top_path.clone() + "/hashd-A/params.json",
-                    report: top_path.clone() + "/hashd-A/report.json",
-                    tf: Self::prep_dir(&(scr_path.clone() + "/hashd-A/testfiles")),
-                    log_dir: scr_path.clone() + "/hashd-A/logs",
-                },
-                HashdPaths {
-                    bin: hashd_bin.clone(),
-                    args: top_path.clone() + "/hashd-B/args.json",
-                    params: top_path.clone() + "/hashd-B/params.json",
-                    report: top_path.clone() + "/hashd-B/report.json",
-                    tf: Self::prep_dir(&(scr_path.clone() + "/hashd-B/testfiles")),
-                    log_dir: scr_path.clone() + "/hashd-B/logs",
-                },
-            ],
+            hashd_bin,
             misc_bin_path: misc_bin_path.clone(),
             biolatpcts_bin,
             iocost_paths: IoCostPaths {
@@ -415,9 +489,8 @@ impl Config {
             oomd_bin,
             oomd_sys_svc,
             oomd_cfg_path: top_path.clone() + "/oomd.json",
+            alerts_path: top_path.clone() + "/alerts.json",
             oomd_daemon_cfg_path: top_path.clone() + "/oomd/config.json",
-            sideloader_bin: misc_bin_path.clone() + "/sideloader.py",
-            sideloader_daemon_cfg_path: top_path.clone() + "/sideloader/config.json",
             sideloader_daemon_jobs_path: top_path.clone() + "/sideloader/jobs.d",
             sideloader_daemon_status_path: top_path.clone() + "/sideloader/status.json",
             side_defs_path: top_path.clone() + "/sideload-defs.json",
@@ -434,6 +507,7 @@ impl Config {
             } else {
                 Some(args.rep_retention)
             },
+            rep_store: args.rep_store,
             rep_1min_retention: if args.keep_reports {
                 None
             } else {
@@ -445,9 +519,8 @@ impl Config {
             enforce: args.enforce.clone(),
 
             sr_failed: Default::default(),
-            sr_iosched: None,
-            sr_wbt: None,
-            sr_wbt_path: None,
+            sr_iosched: vec![],
+            sr_wbt: vec![],
             sr_swappiness: None,
             sr_zswap_enabled: None,
             sr_oomd_sys_svc: None,
@@ -500,6 +573,27 @@ impl Config {
         }
     }
 
+    fn check_iolatency(&mut self) {
+        // io.latency files only show up in non-root cgroups
+        let mut nr_cgrps = 0;
+        for path in glob::glob("/sys/fs/cgroup/*.slice/io.stat")
+            .unwrap()
+            .filter_map(Result::ok)
+        {
+            nr_cgrps += 1;
+            if path.with_file_name("io.latency").exists() {
+                return;
+            }
+        }
+
+        if nr_cgrps > 0 {
+            self.sr_failed.add(
+                SysReq::IoLatency,
+                "cgroup2 io.latency controller unavailable",
+            );
+        }
+    }
+
     fn check_one_fs(&mut self, path: &str, prefix: &str, enforce: bool) -> Option<MountInfo> {
         let mi = match path_to_mountpoint(path) {
             Ok(v) => v,
@@ -617,11 +711,67 @@ impl Config {
         );
     }
 
+    fn check_one_iosched(&mut self, dev: &str) -> String {
+        if self.enforce.io {
+            if let Ok(v) = read_iosched(dev) {
+                self.sr_iosched.push((dev.to_string(), v));
+            }
+            if let Err(e) = set_iosched(dev, "mq-deadline") {
+                self.sr_failed.add(
+                    SysReq::IoSched,
+                    &format!("Failed to set mq-deadline iosched on {:?} ({})", dev, &e),
+                );
+            }
+        }
+
+        match read_iosched(dev) {
+            Ok(v) => {
+                if v != "mq-deadline" {
+                    self.sr_failed.add(
+                        SysReq::IoSched,
+                        &format!("cfg: iosched on {:?} is {} instead of mq-deadline", dev, v),
+                    );
+                }
+                v
+            }
+            Err(e) => {
+                self.sr_failed.add(
+                    SysReq::IoSched,
+                    &format!("Failed to read iosched for {:?} ({})", dev, &e),
+                );
+                "UNKNOWN".into()
+            }
+        }
+    }
+
+    fn check_one_wbt(&mut self, dev: &str) -> Result<()> {
+        let wbt_path = format!("/sys/block/{}/queue/wbt_lat_usec", dev);
+        if let Ok(line) = read_one_line(&wbt_path) {
+            let wbt = line.trim().parse::<u64>()?;
+            if wbt != 0 {
+                if self.enforce.io {
+                    info!("cfg: wbt is enabled on {:?}, disabling", dev);
+                    if let Err(e) = write_one_line(&wbt_path, "0") {
+                        self.sr_failed.add(
+                            SysReq::NoWbt,
+                            &format!("Failed to disable wbt on {:?} ({})", dev, &e),
+                        );
+                    }
+                    self.sr_wbt.push((wbt_path, wbt));
+                } else {
+                    self.sr_failed
+                        .add(SysReq::NoWbt, &format!("wbt is enabled on {:?}", dev));
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn startup_checks(&mut self) -> Result<()> {
         let sys = sysinfo::System::new();
 
         // Obtain rd-hashd version.
-        let output = Command::new(&self.hashd_paths[0].bin)
+        let output = Command::new(&self.hashd_bin)
             .arg("--version")
             .output()
             .expect("cfg: \"rd-hashd --version\" failed");
@@ -700,14 +850,29 @@ impl Config {
             }
         }
 
+        let slice_knobs = SliceKnobs::load(&self.slices_path).unwrap_or_default();
+        if slice_knobs.uses_cpuset() && !buf.contains("cpuset") {
+            self.sr_failed.add(
+                SysReq::Cpuset,
+                "cgroup2 cpuset controller not available but slices.json configures cpuset",
+            );
+        }
+
         if !Path::new("/sys/fs/cgroup/system.slice/cgroup.freeze").exists() {
             self.sr_failed
                 .add(SysReq::Freezer, "cgroup2 freezer not available");
         }
 
         // IO controllers
-        self.check_iocost(self.enforce.io);
-        slices::check_other_io_controllers(&mut self.sr_failed);
+        match slice_knobs.io_backend {
+            IoBackend::IoCost => self.check_iocost(self.enforce.io),
+            IoBackend::IoLatency => self.check_iolatency(),
+        }
+        // io.max and io.latency configs from our own slices.json are expected
+        slices::check_other_io_controllers(
+            &mut self.sr_failed,
+            &slices::own_io_ctrl_files(&slice_knobs),
+        );
 
         // anon memory balance
         match read_cgroup_flat_keyed_file("/proc/vmstat") {
@@ -751,72 +916,20 @@ impl Config {
             }
         }
 
-        // mq-deadline scheduler
-        if self.enforce.io {
-            if let Ok(v) = read_iosched(&self.scr_dev) {
-                self.sr_iosched = Some(v);
-            }
-            if let Err(e) = set_iosched(&self.scr_dev, "mq-deadline") {
-                self.sr_failed.add(
-                    SysReq::IoSched,
-                    &format!(
-                        "Failed to set mq-deadline iosched on {:?} ({})",
-                        &self.scr_dev, &e
-                    ),
-                );
-            }
-        }
-
-        let scr_dev_iosched = match read_iosched(&self.scr_dev) {
-            Ok(v) => {
-                if v != "mq-deadline" {
-                    self.sr_failed.add(
-                        SysReq::IoSched,
-                        &format!(
-                            "cfg: iosched on {:?} is {} instead of mq-deadline",
-                            &self.scr_dev, v
-                        ),
-                    );
-                }
-                v
-            }
-            Err(e) => {
-                self.sr_failed.add(
-                    SysReq::IoSched,
-                    &format!("Failed to read iosched for {:?} ({})", &self.scr_dev, &e),
-                );
-                "UNKNOWN".into()
-            }
-        };
-
-        // wbt should be disabled
-        let wbt_path = format!("/sys/block/{}/queue/wbt_lat_usec", &self.scr_dev);
-        if let Ok(line) = read_one_line(&wbt_path) {
-            let wbt = line.trim().parse::<u64>()?;
-            if wbt != 0 {
-                if self.enforce.io {
-                    info!("cfg: wbt is enabled on {:?}, disabling", &self.scr_dev);
-                    if let Err(e) = write_one_line(&wbt_path, "0") {
-                        self.sr_failed.add(
-                            SysReq::NoWbt,
-                            &format!("Failed to disable wbt on {:?} ({})", &self.scr_dev, &e),
-                        );
-                    }
-                    self.sr_wbt = Some(wbt);
-                    self.sr_wbt_path = Some(wbt_path);
-                } else {
-                    self.sr_failed.add(
-                        SysReq::NoWbt,
-                        &format!("wbt is enabled on {:?}", &self.scr_dev),
-                    );
-                }
+        // mq-deadline scheduler and no wbt on all managed devices
+        let mut scr_dev_iosched = String::new();
+        for dev in self.io_devs.clone().iter() {
+            let iosched = self.check_one_iosched(&dev.name);
+            if dev.name == self.scr_dev {
+                scr_dev_iosched = iosched;
             }
+            self.check_one_wbt(&dev.name)?;
         }
 
-        // swap should be on the same device as scratch
+        // swap should be on the same device as scratch or another managed one
         for swap_dev in swap_devnames()?.iter() {
             let dev = swap_dev.to_str().unwrap_or_default().to_string();
-            if dev != self.scr_dev {
+            if !self.io_devs.iter().any(|io_dev| io_dev.name == dev) {
                 if self.scr_dev_forced {
                     let det_scr_dev = path_to_devname(&self.scr_path).unwrap_or_default();
                     if dev != det_scr_dev.to_str().unwrap_or_default() {
@@ -829,7 +942,7 @@ impl Config {
                     self.sr_failed.add(
                         SysReq::SwapOnScratch,
                         &format!(
-                            "Swap backing dev {:?} is different from scratch backing dev {:?}",
+                            "Swap backing dev {:?} is neither the scratch backing dev {:?} nor in --devs",
                             &swap_dev, self.scr_dev
                         ),
                     );
@@ -893,14 +1006,11 @@ impl Config {
             }
         }
 
-        // do we have oomd?
+        // oomd::Oomd falls back to the built-in engine if oomd is missing
         if let Err(e) = &self.oomd_bin {
-            self.sr_failed.add(
-                SysReq::Oomd,
-                &format!(
-                    "Failed to find oomd ({:#}), see https://github.com/facebookincubator/oomd",
-                    &e
-                ),
+            warn!(
+                "cfg: Failed to find oomd ({:#}), falling back to the built-in engine, see https://github.com/facebookincubator/oomd",
+                &e
             );
         }
 
@@ -1024,8 +1134,58 @@ impl Config {
         }
     }
 
-    pub fn hashd_paths(&self, sel: HashdSel) -> &HashdPaths {
-        &self.hashd_paths[sel as usize]
+    pub fn hashd_paths(&self, sel: &HashdSel) -> HashdPaths {
+        let top = format!("{}/hashd-{}", &self.top_path, sel.name());
+        let scr = format!("{}/hashd-{}", &self.scr_path, sel.name());
+        HashdPaths {
+            bin: self.hashd_bin.clone(),
+            args: top.clone() + "/args.json",
+            params: top.clone() + "/params.json",
+            report: top + "/report.json",
+            tf: scr.clone() + "/testfiles",
+            log_dir: scr + "/logs",
+        }
+    }
+
+    /// All rd-hashd instances which have been set up so far.
+    pub fn hashd_sels(&self) -> Vec<HashdSel> {
+        let mut sels = vec![HashdSel::a(), HashdSel::b()];
+        for path in glob::glob(&format!("{}/hashd-*", &self.top_path))
+            .unwrap()
+            .filter_map(Result::ok)
+        {
+            if !path.is_dir() {
+                continue;
+            }
+            let name = path.file_name().unwrap().to_str().unwrap_or_default();
+            if let Ok(sel) = HashdSel::new(name.trim_start_matches("hashd-")) {
+                if !sels.contains(&sel) {
+                    sels.push(sel);
+                }
+            }
+        }
+        sels
+    }
+
+    /// Create the directories for a new rd-hashd instance. The
+    /// configuration files are copied from A.
+    pub fn prep_hashd(&self, sel: &HashdSel) -> Result<()> {
+        let paths = self.hashd_paths(sel);
+        fs::create_dir_all(Path::new(&paths.args).parent().unwrap())?;
+        fs::create_dir_all(&paths.tf)?;
+
+        let a_paths = self.hashd_paths(&HashdSel::a());
+        for (src, dst) in [
+            (&a_paths.args, &paths.args),
+            (&a_paths.params, &paths.params),
+        ]
+        .iter()
+        {
+            if !Path::new(dst).exists() && Path::new(src).exists() {
+                fs::copy(src, dst)?;
+            }
+        }
+        Ok(())
     }
 
     pub fn memcg_recursive_prot(&self) -> bool {
@@ -1035,13 +1195,15 @@ impl Config {
 
 impl Drop for Config {
     fn drop(&mut self) {
-        if let Some(iosched) = self.sr_iosched.as_ref() {
-            if let Err(e) = set_iosched(&self.scr_dev, iosched) {
-                error!("cfg: Failed to restore iosched to {:?} ({:#})", iosched, &e);
+        for (dev, iosched) in self.sr_iosched.iter() {
+            if let Err(e) = set_iosched(dev, iosched) {
+                error!(
+                    "cfg: Failed to restore iosched on {:?} to {:?} ({:#})",
+                    dev, iosched, &e
+                );
             }
         }
-        if let Some(wbt) = self.sr_wbt {
-            let path = self.sr_wbt_path.as_ref().unwrap();
+        for (path, wbt) in self.sr_wbt.iter() {
             info!("cfg: Restoring {:?} to {}", path, wbt);
             if let Err(e) = write_one_line(path, &format!("{}", wbt)) {
                 error!("cfg: Failed to restore {:?} ({:#})", &path, &e);
@@ -1072,19 +1234,18 @@ impl Drop for Config {
 }
 
 fn reset_agent_states(cfg: &Config) {
+    let hashd_sels = cfg.hashd_sels();
+    let hashd_paths: Vec<HashdPaths> = hashd_sels.iter().map(|sel| cfg.hashd_paths(sel)).collect();
+
     let mut paths = vec![
         &cfg.index_path,
         &cfg.sysreqs_path,
         &cfg.cmd_path,
         &cfg.slices_path,
-        &cfg.hashd_paths[0].args,
-        &cfg.hashd_paths[0].params,
-        &cfg.hashd_paths[1].args,
-        &cfg.hashd_paths[1].params,
         &cfg.misc_bin_path,
         &cfg.oomd_cfg_path,
         &cfg.oomd_daemon_cfg_path,
-        &cfg.sideloader_daemon_cfg_path,
+        &cfg.alerts_path,
         &cfg.sideloader_daemon_jobs_path,
         &cfg.sideloader_daemon_status_path,
         &cfg.side_defs_path,
@@ -1093,8 +1254,16 @@ fn reset_agent_states(cfg: &Config) {
         &cfg.sys_scr_path,
     ];
 
+    for hp in hashd_paths.iter() {
+        paths.append(&mut vec![&hp.args, &hp.params]);
+    }
+
     if cfg.rep_retention.is_some() {
-        paths.append(&mut vec![&cfg.report_path, &cfg.report_d_path]);
+        paths.append(&mut vec![
+            &cfg.report_path,
+            &cfg.report_d_path,
+            &cfg.events_d_path,
+        ]);
     }
 
     if cfg.rep_1min_retention.is_some() {
@@ -1131,23 +1300,16 @@ fn reset_agent_states(cfg: &Config) {
 
     info!("cfg: Preparing hashd config files...");
 
-    let mut hashd_args = hashd::hashd_path_args(&cfg, HashdSel::A);
+    let mut hashd_args = hashd::hashd_path_args(cfg, &HashdSel::a());
     hashd_args.push("--prepare-config".into());
 
     Command::new(hashd_args.remove(0))
         .args(hashd_args)
         .status()
         .expect("cfg: Failed to run rd-hashd --prepare-config");
-    fs::copy(
-        &cfg.hashd_paths(HashdSel::A).args,
-        &cfg.hashd_paths(HashdSel::B).args,
-    )
-    .unwrap();
-    fs::copy(
-        &cfg.hashd_paths(HashdSel::A).params,
-        &cfg.hashd_paths(HashdSel::B).params,
-    )
-    .unwrap();
+    for sel in hashd_sels.iter().skip(1) {
+        cfg.prep_hashd(sel).unwrap();
+    }
 }
 
 pub struct SysObjs {
@@ -1161,17 +1323,44 @@ pub struct SysObjs {
     enforce_cfg: EnforceConfig,
 }
 
+/// There's no last good configuration to fall back to on startup. Use the
+/// defaults instead if `errs` isn't empty.
+fn reject_on_startup<T: Default>(path: &str, data: &mut T, errs: &BTreeMap<String, String>) {
+    if !errs.is_empty() {
+        error!(
+            "cfg: Invalid {:?}, using the defaults ({})",
+            path,
+            CmdAck::format_errors(errs)
+        );
+        *data = Default::default();
+    }
+}
+
 impl SysObjs {
     fn new(cfg: &Config) -> Self {
         let bench_file = JsonConfigFile::load_or_create(Some(&cfg.bench_path)).unwrap();
 
-        let slice_file = JsonConfigFile::load_or_create(Some(&cfg.slices_path)).unwrap();
+        let mut slice_file: JsonConfigFile<SliceKnobs> =
+            JsonConfigFile::load_or_create(Some(&cfg.slices_path)).unwrap();
 
-        let side_def_file = JsonConfigFile::load_or_create(Some(&cfg.side_defs_path)).unwrap();
+        let side_def_file: JsonConfigFile<SideloadDefs> =
+            JsonConfigFile::load_or_create(Some(&cfg.side_defs_path)).unwrap();
 
-        let cmd_file = JsonConfigFile::load_or_create(Some(&cfg.cmd_path)).unwrap();
+        let mut cmd_file: JsonConfigFile<Cmd> =
+            JsonConfigFile::load_or_create(Some(&cfg.cmd_path)).unwrap();
 
-        let cmd_ack_file = JsonReportFile::new(Some(&cfg.cmd_ack_path));
+        let mut oomd = oomd::Oomd::new(cfg).unwrap();
+
+        let mut cmd_ack_file = JsonReportFile::<CmdAck>::new(Some(&cfg.cmd_ack_path));
+        let ack = &mut cmd_ack_file.data;
+        ack.slice_errors = slice_file.data.validate();
+        reject_on_startup(&cfg.slices_path, &mut slice_file.data, &ack.slice_errors);
+        ack.oomd_errors = oomd.file.data.validate();
+        reject_on_startup(&cfg.oomd_cfg_path, &mut oomd.file.data, &ack.oomd_errors);
+        ack.cmd_errors = cmd_file.data.validate(&side_def_file.data);
+        let cmd_seq = cmd_file.data.cmd_seq;
+        reject_on_startup(&cfg.cmd_path, &mut cmd_file.data, &ack.cmd_errors);
+        cmd_file.data.cmd_seq = cmd_seq;
         cmd_ack_file.commit().unwrap();
 
         let rep_seq = match Report::load(&cfg.report_path) {
@@ -1184,7 +1373,7 @@ impl SysObjs {
             bench_file,
             slice_file,
             side_def_file,
-            oomd: oomd::Oomd::new(&cfg).unwrap(),
+            oomd,
             sideloader: sideloader::Sideloader::new(&cfg).unwrap(),
             cmd_file,
             cmd_ack_file,
@@ -1196,17 +1385,31 @@ impl SysObjs {
 impl Drop for SysObjs {
     fn drop(&mut self) {
         debug!("cfg: Clearing slice configurations");
-        if let Err(e) = slices::clear_slices(&self.enforce_cfg) {
+        if let Err(e) = slices::clear_slices(&self.slice_file.data, &self.enforce_cfg) {
             warn!("cfg: Failed to clear slice configurations ({:#})", &e);
         }
     }
 }
 
-fn update_index(cfg: &Config) -> Result<()> {
+pub fn update_index(cfg: &Config) -> Result<()> {
+    let mut hashd = BTreeMap::new();
+    for sel in cfg.hashd_sels().iter() {
+        let paths = cfg.hashd_paths(sel);
+        hashd.insert(
+            sel.name().to_string(),
+            rd_agent_intf::index::HashdIndex {
+                args: paths.args,
+                params: paths.params,
+                report: paths.report,
+            },
+        );
+    }
+
     let index = rd_agent_intf::index::Index {
         sysreqs: cfg.sysreqs_path.clone(),
         cmd: cfg.cmd_path.clone(),
         cmd_ack: cfg.cmd_ack_path.clone(),
+        ctl_sock: cfg.ctl_sock_path.clone(),
         report: cfg.report_path.clone(),
         report_d: cfg.report_d_path.clone(),
         report_1min: cfg.report_1min_path.clone(),
@@ -1215,19 +1418,10 @@ fn update_index(cfg: &Config) -> Result<()> {
         slices: cfg.slices_path.clone(),
         oomd: cfg.oomd_cfg_path.clone(),
         sideloader_status: cfg.sideloader_daemon_status_path.clone(),
-        hashd: [
-            rd_agent_intf::index::HashdIndex {
-                args: cfg.hashd_paths[0].args.clone(),
-                params: cfg.hashd_paths[0].params.clone(),
-                report: cfg.hashd_paths[0].report.clone(),
-            },
-            rd_agent_intf::index::HashdIndex {
-                args: cfg.hashd_paths[1].args.clone(),
-                params: cfg.hashd_paths[1].params.clone(),
-                report: cfg.hashd_paths[1].report.clone(),
-            },
-        ],
+        hashd,
         sideload_defs: cfg.side_defs_path.clone(),
+        events_d: cfg.events_d_path.clone(),
+        alerts: cfg.alerts_path.clone(),
     };
 
     index.save(&cfg.index_path)
@@ -1252,6 +1446,16 @@ fn main() {
         return;
     }
 
+    if let Some(query) = args_file.data.report_query.as_ref() {
+        query::query_main(&args_file.data.dir, query);
+        return;
+    }
+
+    if let Some(path) = args_file.data.scenario.as_ref() {
+        scenario::scenario_main(&args_file.data.dir, path);
+        return;
+    }
+
     systemd::set_systemd_timeout(args_file.data.systemd_timeout);
 
     let mut cfg = Config::new(&args_file);
@@ -1264,8 +1468,9 @@ fn main() {
         error!("cfg: Failed to update {:?} ({:#})", &cfg.index_path, &e);
         panic!();
     }
+    events::init(&cfg.events_d_path);
 
-    if let Err(e) = misc::prepare_misc_bins(&cfg, args_file.data.prepare) {
+    if let Err(e) = misc::prepare_misc_bins(&mut cfg, args_file.data.prepare) {
         error!("cfg: Failed to prepare misc support binaries ({:#})", &e);
         panic!();
     }
@@ -1285,9 +1490,13 @@ fn main() {
         }
     }
 
-    let mut _iocost_sys_save = None;
+    let mut _iocost_sys_saves = vec![];
     if !cfg.bypass {
-        _iocost_sys_save = Some(IoCostSysSave::read_from_sys(cfg.scr_devnr));
+        _iocost_sys_saves = cfg
+            .io_devs
+            .iter()
+            .map(|dev| IoCostSysSave::read_from_sys(dev.devnr))
+            .collect::<Vec<_>>();
         if let Err(e) = cfg.startup_checks() {
             if args_file.data.force {
                 warn!(
@@ -1301,6 +1510,16 @@ fn main() {
         }
     }
 
+    if args_file.data.convert_reports {
+        for d_path in &[&cfg.report_d_path, &cfg.report_1min_d_path] {
+            match ReportStore::convert_dir(d_path) {
+                Ok(nr) => info!("report: Converted {} reports in {:?}", nr, d_path),
+                Err(e) => error!("report: Failed to convert {:?} ({:#})", d_path, &e),
+            }
+        }
+        return;
+    }
+
     if args_file.data.prepare {
         // ReportFiles init is responsible for clearing old report files
         // but we aren't gonna get there. Clear them explicitly.
@@ -1326,10 +1545,7 @@ fn main() {
     trace!("{:#?}", &cfg);
 
     if let Err(e) = bench::apply_iocost(&sobjs.bench_file.data, &cfg) {
-        error!(
-            "cfg: Failed to configure iocost controller on {:?} ({:#})",
-            cfg.scr_dev, &e
-        );
+        error!("cfg: Failed to configure iocost controller ({:#})", &e);
         panic!();
     }
 
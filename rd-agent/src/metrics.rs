@@ -0,0 +1,599 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::Result;
+use crossbeam::channel::{self, RecvTimeoutError};
+use log::{debug, info, warn};
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io::{self, prelude::*, BufReader};
+use std::net::TcpListener;
+use std::os::unix::net::UnixListener;
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::Duration;
+
+use super::cmd::Runner;
+use rd_agent_intf::{IoLatReport, Report, RunnerState, SvcReport, SvcStateReport};
+use rd_util::*;
+
+//
+// OpenMetrics exporter
+//
+// Each per-second report is converted to OpenMetrics text exposition and
+// served over HTTP on either a TCP address or a unix domain socket. The
+// same metrics can also be dumped to a file for node_exporter's textfile
+// collector, in which case the Prometheus text format is used as the
+// textfile collector doesn't understand OpenMetrics specific types.
+//
+
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+const REPORT_QUEUE_DEPTH: usize = 16;
+const CONN_TIMEOUT: Duration = Duration::from_secs(5);
+
+const RUNNER_STATES: [RunnerState; 4] = [
+    RunnerState::Idle,
+    RunnerState::Running,
+    RunnerState::BenchHashd,
+    RunnerState::BenchIoCost,
+];
+
+const SVC_STATES: [SvcStateReport; 4] = [
+    SvcStateReport::Running,
+    SvcStateReport::Exited,
+    SvcStateReport::Failed,
+    SvcStateReport::Other,
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum MetricType {
+    Gauge,
+    Counter,
+    StateSet,
+}
+
+struct MetricsBuf {
+    buf: String,
+    openmetrics: bool,
+}
+
+fn escape_label(val: &str) -> String {
+    val.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(val: f64) -> String {
+    if val.is_nan() {
+        "NaN".into()
+    } else if val.is_infinite() {
+        if val > 0.0 { "+Inf" } else { "-Inf" }.into()
+    } else {
+        format!("{}", val)
+    }
+}
+
+impl MetricsBuf {
+    fn new(openmetrics: bool) -> Self {
+        Self {
+            buf: String::new(),
+            openmetrics,
+        }
+    }
+
+    fn family(&mut self, name: &str, mtype: MetricType, help: &str) {
+        let (name, type_str) = match (mtype, self.openmetrics) {
+            (MetricType::Gauge, _) => (name.to_string(), "gauge"),
+            (MetricType::Counter, true) => (name.to_string(), "counter"),
+            (MetricType::Counter, false) => (name.to_string() + "_total", "counter"),
+            (MetricType::StateSet, true) => (name.to_string(), "stateset"),
+            (MetricType::StateSet, false) => (name.to_string(), "gauge"),
+        };
+        writeln!(self.buf, "# TYPE {} {}", &name, type_str).unwrap();
+        writeln!(self.buf, "# HELP {} {}", &name, help).unwrap();
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], val: f64) {
+        self.buf.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+                .collect();
+            write!(self.buf, "{{{}}}", labels.join(",")).unwrap();
+        }
+        writeln!(self.buf, " {}", format_value(val)).unwrap();
+    }
+
+    fn finish(mut self) -> String {
+        if self.openmetrics {
+            self.buf.push_str("# EOF\n");
+        }
+        self.buf
+    }
+}
+
+fn format_iolat(mb: &mut MetricsBuf, name: &str, help: &str, iolat: &IoLatReport) {
+    mb.family(name, MetricType::Gauge, help);
+    for (io_type, pcts) in iolat.map.iter() {
+        for (pct, lat) in pcts.iter() {
+            mb.sample(name, &[("io_type", io_type), ("pct", pct)], *lat);
+        }
+    }
+}
+
+fn format_svc_states(mb: &mut MetricsBuf, svcs: &[(&str, &str, &SvcReport)]) {
+    mb.family(
+        "rd_svc_state",
+        MetricType::StateSet,
+        "Systemd service state",
+    );
+    for (kind, id, svc) in svcs.iter() {
+        for state in SVC_STATES.iter() {
+            let state_str = format!("{:?}", state);
+            mb.sample(
+                "rd_svc_state",
+                &[
+                    ("kind", kind),
+                    ("id", id),
+                    ("svc", &svc.name),
+                    ("rd_svc_state", &state_str),
+                ],
+                if svc.state == *state { 1.0 } else { 0.0 },
+            );
+        }
+    }
+}
+
+/// Convert @rep to OpenMetrics text exposition, or to Prometheus text
+/// format if !@openmetrics.
+pub fn format_metrics(rep: &Report, openmetrics: bool) -> String {
+    let mut mb = MetricsBuf::new(openmetrics);
+
+    mb.family(
+        "rd_report_timestamp_seconds",
+        MetricType::Gauge,
+        "When the report was generated",
+    );
+    mb.sample(
+        "rd_report_timestamp_seconds",
+        &[],
+        rep.timestamp.timestamp_millis() as f64 / 1000.0,
+    );
+
+    mb.family("rd_runner_state", MetricType::StateSet, "rd-agent state");
+    for state in RUNNER_STATES.iter() {
+        let state_str = format!("{:?}", state);
+        mb.sample(
+            "rd_runner_state",
+            &[("rd_runner_state", &state_str)],
+            if rep.state == *state { 1.0 } else { 0.0 },
+        );
+    }
+
+    // per-cgroup usages - top-level slices, services and hostcritical.slice
+    // services, all labeled by their usages{} key
+    type UsageFn = fn(&rd_agent_intf::UsageReport) -> f64;
+    let usage_metrics: [(&str, MetricType, &str, UsageFn); 12] = [
+        ("rd_cpu_util", MetricType::Gauge, "CPU utilization", |u| {
+            u.cpu_util
+        }),
+        (
+            "rd_cpu_sys_util",
+            MetricType::Gauge,
+            "System CPU utilization",
+            |u| u.cpu_sys,
+        ),
+        (
+            "rd_cpu_usage_seconds",
+            MetricType::Counter,
+            "Cumulative CPU usage",
+            |u| u.cpu_usage,
+        ),
+        ("rd_memory_bytes", MetricType::Gauge, "Memory usage", |u| {
+            u.mem_bytes as f64
+        }),
+        ("rd_swap_bytes", MetricType::Gauge, "Swap usage", |u| {
+            u.swap_bytes as f64
+        }),
+        (
+            "rd_swap_free_bytes",
+            MetricType::Gauge,
+            "Swap available",
+            |u| u.swap_free as f64,
+        ),
+        (
+            "rd_io_read_bytes",
+            MetricType::Counter,
+            "Cumulative bytes read from the scratch device",
+            |u| u.io_rbytes as f64,
+        ),
+        (
+            "rd_io_write_bytes",
+            MetricType::Counter,
+            "Cumulative bytes written to the scratch device",
+            |u| u.io_wbytes as f64,
+        ),
+        (
+            "rd_io_read_bps",
+            MetricType::Gauge,
+            "Read bytes per second",
+            |u| u.io_rbps as f64,
+        ),
+        (
+            "rd_io_write_bps",
+            MetricType::Gauge,
+            "Write bytes per second",
+            |u| u.io_wbps as f64,
+        ),
+        (
+            "rd_io_util",
+            MetricType::Gauge,
+            "iocost IO utilization",
+            |u| u.io_util,
+        ),
+        (
+            "rd_io_usage_seconds",
+            MetricType::Counter,
+            "Cumulative iocost IO usage",
+            |u| u.io_usage,
+        ),
+    ];
+    for (name, mtype, help, get) in usage_metrics.iter() {
+        mb.family(name, *mtype, help);
+        let sample_name = match mtype {
+            MetricType::Counter => format!("{}_total", name),
+            _ => name.to_string(),
+        };
+        for (cgrp, usage) in rep.usages.iter() {
+            mb.sample(&sample_name, &[("cgroup", cgrp)], get(usage));
+        }
+    }
+
+    // PSI
+    mb.family(
+        "rd_pressure_stall_seconds",
+        MetricType::Counter,
+        "Cumulative PSI stall time",
+    );
+    for (cgrp, usage) in rep.usages.iter() {
+        for (resource, stalls) in &[
+            ("cpu", usage.cpu_stalls),
+            ("memory", usage.mem_stalls),
+            ("io", usage.io_stalls),
+        ] {
+            for (kind, val) in &[("some", stalls.0), ("full", stalls.1)] {
+                mb.sample(
+                    "rd_pressure_stall_seconds_total",
+                    &[("cgroup", cgrp), ("resource", resource), ("kind", kind)],
+                    *val,
+                );
+            }
+        }
+    }
+    mb.family(
+        "rd_pressure_ratio",
+        MetricType::Gauge,
+        "PSI stall time ratio during the last interval",
+    );
+    for (cgrp, usage) in rep.usages.iter() {
+        for (resource, pressures) in &[
+            ("cpu", usage.cpu_pressures),
+            ("memory", usage.mem_pressures),
+            ("io", usage.io_pressures),
+        ] {
+            for (kind, val) in &[("some", pressures.0), ("full", pressures.1)] {
+                mb.sample(
+                    "rd_pressure_ratio",
+                    &[("cgroup", cgrp), ("resource", resource), ("kind", kind)],
+                    *val,
+                );
+            }
+        }
+    }
+
+    // IO latencies and iocost
+    format_iolat(
+        &mut mb,
+        "rd_iolat_seconds",
+        "IO latency percentiles during the last interval",
+        &rep.iolat,
+    );
+    format_iolat(
+        &mut mb,
+        "rd_iolat_cum_seconds",
+        "Cumulative IO latency percentiles",
+        &rep.iolat_cum,
+    );
+    mb.family("rd_iocost_vrate", MetricType::Gauge, "iocost vrate");
+    mb.sample("rd_iocost_vrate", &[], rep.iocost.vrate);
+    mb.family(
+        "rd_dev_iocost_vrate",
+        MetricType::Gauge,
+        "iocost vrate per managed device",
+    );
+    for (dev, dr) in rep.io_devs.iter() {
+        mb.sample("rd_dev_iocost_vrate", &[("dev", dev)], dr.iocost.vrate);
+    }
+
+    // hashd
+    mb.family("rd_hashd_load", MetricType::Gauge, "rd-hashd rps / rps_max");
+    for (inst, hr) in rep.hashd.iter() {
+        mb.sample("rd_hashd_load", &[("instance", inst)], hr.load);
+    }
+    mb.family(
+        "rd_hashd_rps",
+        MetricType::Gauge,
+        "rd-hashd requests per second",
+    );
+    for (inst, hr) in rep.hashd.iter() {
+        mb.sample("rd_hashd_rps", &[("instance", inst)], hr.rps);
+    }
+    mb.family(
+        "rd_hashd_latency_seconds",
+        MetricType::Gauge,
+        "rd-hashd request latency percentiles",
+    );
+    for (inst, hr) in rep.hashd.iter() {
+        let lat = &hr.lat;
+        for (pct, val) in &[
+            ("min", lat.min),
+            ("01", lat.p01),
+            ("05", lat.p05),
+            ("10", lat.p10),
+            ("16", lat.p16),
+            ("50", lat.p50),
+            ("84", lat.p84),
+            ("90", lat.p90),
+            ("95", lat.p95),
+            ("99", lat.p99),
+            ("99.9", lat.p99_9),
+            ("99.99", lat.p99_99),
+            ("99.999", lat.p99_999),
+            ("max", lat.max),
+            ("ctl", lat.ctl),
+        ] {
+            mb.sample(
+                "rd_hashd_latency_seconds",
+                &[("instance", inst), ("pct", pct)],
+                *val,
+            );
+        }
+    }
+    mb.family(
+        "rd_hashd_in_flight",
+        MetricType::Gauge,
+        "rd-hashd requests in flight",
+    );
+    for (inst, hr) in rep.hashd.iter() {
+        mb.sample(
+            "rd_hashd_in_flight",
+            &[("instance", inst)],
+            hr.nr_in_flight as f64,
+        );
+    }
+
+    // service states
+    let mut svcs: Vec<(&str, &str, &SvcReport)> = vec![
+        ("oomd", "", &rep.oomd.svc),
+        ("sideloader", "", &rep.sideloader.svc),
+        ("bench_hashd", "", &rep.bench_hashd.svc),
+        ("bench_iocost", "", &rep.bench_iocost.svc),
+    ];
+    for (inst, hr) in rep.hashd.iter() {
+        svcs.push(("hashd", inst, &hr.svc));
+    }
+    for (name, sr) in rep.sysloads.iter() {
+        svcs.push(("sysload", name, &sr.svc));
+    }
+    for (name, sr) in rep.sideloads.iter() {
+        svcs.push(("sideload", name, &sr.svc));
+    }
+    format_svc_states(&mut mb, &svcs);
+
+    mb.finish()
+}
+
+fn serve_conn<S: Read + Write>(stream: S, metrics: &Mutex<String>) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut req = String::new();
+    reader.read_line(&mut req)?;
+    // drain the headers
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut toks = req.split_whitespace();
+    let (status, ctype, body) = match (toks.next(), toks.next()) {
+        (Some("GET"), Some(path)) if path == "/" || path.starts_with("/metrics") => (
+            "200 OK",
+            OPENMETRICS_CONTENT_TYPE,
+            metrics.lock().unwrap().clone(),
+        ),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "Not Found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method Not Allowed\n".to_string(),
+        ),
+    };
+
+    let stream = reader.get_mut();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        ctype,
+        body.len(),
+        &body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    fn bind(addr: &str) -> Result<Self> {
+        if addr.starts_with('/') {
+            let _ = fs::remove_file(addr);
+            let listener = UnixListener::bind(addr)?;
+            listener.set_nonblocking(true)?;
+            Ok(Self::Unix(listener))
+        } else {
+            let listener = TcpListener::bind(addr)?;
+            listener.set_nonblocking(true)?;
+            Ok(Self::Tcp(listener))
+        }
+    }
+
+    /// Each scrape is a single short request and the streams are bounded
+    /// by CONN_TIMEOUT, so connections are served inline one at a time.
+    fn accept_and_serve(&self, metrics: &Mutex<String>) -> io::Result<()> {
+        let res = match self {
+            Self::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(CONN_TIMEOUT))?;
+                stream.set_write_timeout(Some(CONN_TIMEOUT))?;
+                serve_conn(stream, metrics)
+            }
+            Self::Unix(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(CONN_TIMEOUT))?;
+                stream.set_write_timeout(Some(CONN_TIMEOUT))?;
+                serve_conn(stream, metrics)
+            }
+        };
+        if let Err(e) = res {
+            debug!("metrics: Connection terminated ({:#})", &e);
+        }
+        Ok(())
+    }
+}
+
+fn write_textfile(path: &str, body: &str) -> Result<()> {
+    // The collector may read at any time, make the update atomic.
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, body)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+pub struct MetricsExporter {
+    sock_path: Option<String>,
+    join_handles: Vec<JoinHandle<()>>,
+}
+
+impl MetricsExporter {
+    fn collect(runner: Runner, metrics: Arc<Mutex<String>>, serve: bool, textfile: Option<String>) {
+        let (tx, rx) = channel::bounded::<Report>(REPORT_QUEUE_DEPTH);
+        runner.report_subs.lock().unwrap().push(tx);
+
+        while !prog_exiting() {
+            let rep = match rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(rep) => rep,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            if serve {
+                *metrics.lock().unwrap() = format_metrics(&rep, true);
+            }
+            if let Some(path) = textfile.as_ref() {
+                if let Err(e) = write_textfile(path, &format_metrics(&rep, false)) {
+                    warn!("metrics: Failed to update {:?} ({:#})", path, &e);
+                }
+            }
+        }
+    }
+
+    fn listen(listener: Listener, metrics: Arc<Mutex<String>>) {
+        while !prog_exiting() {
+            match listener.accept_and_serve(&metrics) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    sleep(Duration::from_millis(100));
+                }
+                Err(e) => {
+                    warn!("metrics: Failed to accept connection ({:?})", &e);
+                    sleep(Duration::from_millis(100));
+                }
+            }
+        }
+    }
+
+    pub fn new(addr: Option<&str>, textfile: Option<&str>, runner: Runner) -> Result<Self> {
+        let metrics = Arc::new(Mutex::new(MetricsBuf::new(true).finish()));
+        let mut join_handles = vec![];
+
+        if let Some(addr) = addr {
+            let listener = Listener::bind(addr)?;
+            info!("metrics: Listening on {:?}", addr);
+            let metrics = metrics.clone();
+            join_handles.push(spawn(move || Self::listen(listener, metrics)));
+        }
+
+        let serve = addr.is_some();
+        let textfile = textfile.map(|x| x.to_string());
+        join_handles.push(spawn(move || {
+            Self::collect(runner, metrics, serve, textfile)
+        }));
+
+        Ok(Self {
+            sock_path: addr.filter(|x| x.starts_with('/')).map(|x| x.to_string()),
+            join_handles,
+        })
+    }
+}
+
+impl Drop for MetricsExporter {
+    fn drop(&mut self) {
+        for jh in self.join_handles.drain(..) {
+            jh.join().unwrap();
+        }
+        if let Some(path) = self.sock_path.as_ref() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_metrics;
+    use rd_agent_intf::{HashdReport, Report, UsageReport};
+
+    #[test]
+    fn test_format_metrics() {
+        let mut rep = Report::default();
+        rep.usages.insert(
+            "workload.slice".into(),
+            UsageReport {
+                cpu_util: 0.5,
+                io_pressures: (0.25, 0.125),
+                ..Default::default()
+            },
+        );
+        rep.hashd.insert("A".into(), HashdReport::default());
+        rep.hashd.get_mut("A").unwrap().rps = 100.0;
+
+        let om = format_metrics(&rep, true);
+        assert!(om.ends_with("# EOF\n"));
+        assert!(om.contains("# TYPE rd_cpu_usage_seconds counter\n"));
+        assert!(om.contains("rd_cpu_util{cgroup=\"workload.slice\"} 0.5\n"));
+        assert!(om.contains(
+            "rd_pressure_ratio{cgroup=\"workload.slice\",resource=\"io\",kind=\"full\"} 0.125\n"
+        ));
+        assert!(om.contains("rd_hashd_rps{instance=\"A\"} 100\n"));
+        assert!(om.contains("# TYPE rd_svc_state stateset\n"));
+
+        let prom = format_metrics(&rep, false);
+        assert!(!prom.contains("# EOF"));
+        assert!(prom.contains("# TYPE rd_cpu_usage_seconds_total counter\n"));
+        assert!(prom.contains("# TYPE rd_svc_state gauge\n"));
+    }
+}
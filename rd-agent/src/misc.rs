@@ -1,6 +1,7 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use super::{prepare_bin_file, Config};
 use anyhow::Result;
+use log::warn;
 use std::process::Command;
 
 use rd_util::*;
@@ -9,12 +10,11 @@ use rd_util::*;
 // giving up.
 pub const BCC_RETRIES: u32 = 2;
 
-const MISC_BINS: [(&str, &[u8]); 4] = [
+const MISC_BINS: [(&str, &[u8]); 3] = [
     (
         "iocost_coef_gen.py",
         include_bytes!("misc/iocost_coef_gen.py"),
     ),
-    ("sideloader.py", include_bytes!("misc/sideloader.py")),
     ("biolatpcts.py", include_bytes!("misc/biolatpcts.py")),
     (
         "biolatpcts_wrapper.sh",
@@ -22,7 +22,7 @@ const MISC_BINS: [(&str, &[u8]); 4] = [
     ),
 ];
 
-pub fn prepare_misc_bins(cfg: &Config, prepare_only: bool) -> Result<()> {
+pub fn prepare_misc_bins(cfg: &mut Config, prepare_only: bool) -> Result<()> {
     for (name, body) in &MISC_BINS {
         prepare_bin_file(&format!("{}/{}", &cfg.misc_bin_path, name), body)?;
     }
@@ -37,7 +37,14 @@ pub fn prepare_misc_bins(cfg: &Config, prepare_only: bool) -> Result<()> {
                 "is bcc working? https://github.com/iovisor/bcc",
             ) {
                 Ok(()) => break,
-                Err(e) if retries == 0 => return Err(e),
+                Err(e) if retries == 0 => {
+                    warn!(
+                        "cfg: biolatpcts failed ({:#}), estimating IO latencies from block stats",
+                        &e
+                    );
+                    cfg.biolatpcts_bin = None;
+                    break;
+                }
                 Err(_) => retries -= 1,
             }
         }
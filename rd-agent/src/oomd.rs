@@ -1,208 +1,282 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
-use anyhow::Result;
+use anyhow::{bail, Result};
 use log::{debug, warn};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::ffi::CString;
 use std::fs;
 use std::io::prelude::*;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 use rd_util::*;
 
 use rd_agent_intf::{
-    OomdKnobs, OomdReport, OomdSliceMemPressureKnobs, OomdSliceSenpaiKnobs, Slice, OOMD_SVC_NAME,
+    EventKind, OomdDetectorGroup, OomdKillPolicy, OomdKillReport, OomdKnobs, OomdPlugin,
+    OomdReport, OomdRuleset, OomdSliceMemPressureKnobs, OomdSliceSenpaiKnobs, Slice, SvcReport,
+    SvcStateReport, OOMD_SVC_NAME,
 };
 
+use super::events;
+use super::oomd_builtin::BuiltinOomd;
 use super::Config;
 
-const OOMD_RULE_HEAD: &str = r#"{
-    "rulesets": ["#;
-
-const _OOMD_RULE_OVERVIEW: &str = r#"
-        {
-            "name": "system overview",
-            "silence-logs": "engine",
-            "detectors": [
-                [
-                    "records system stats",
-                    {
-                        "name": "dump_cgroup_overview",
-                        "args": {
-                            "cgroup": "workload.slice,system.slice"
-                        }
-                    }
-                ]
-            ],
-            "actions": [
-                {
-                    "name": "continue",
-                    "args": {
-                    }
-                }
-            ]
-        }"#;
-
-const OOMD_RULE_MEMORY: &str = r#"
-        {
-            "name": "protection against heavy __SLICE__ thrashing",
-            "detectors": [
-                [
-                    "Sustained thrashing in __SLICE__",
-                    {
-                        "name": "pressure_above",
-                        "args": {
-                            "cgroup": "__SLICE__",
-                            "resource": "memory",
-                            "threshold": "__THRESHOLD__",
-                            "duration": "__DURATION__"
-                        }
-                    },
-                    {
-                        "name": "memory_reclaim",
-                        "args": {
-                            "cgroup": "__SLICE__",
-                            "duration": "10"
-                        }
-                    }
-                ]
+const BUILTIN_SVC_NAME: &str = "rd-agent-oomd-builtin";
+
+const SWAP_KILL_TARGETS: &str = "workload.slice/*,sideload.slice/*,system.slice/*";
+const XATTR_PREFER: &str = "trusted.oomd_prefer";
+const XATTR_AVOID: &str = "trusted.oomd_avoid";
+
+fn kill_action(policy: OomdKillPolicy, targets: &[String], default_targets: &str) -> OomdPlugin {
+    let cgroup = match targets.len() {
+        0 => default_targets.to_string(),
+        _ => targets.join(","),
+    };
+    let mut args = vec![("cgroup", cgroup.as_str())];
+    if policy == OomdKillPolicy::Pressure {
+        args.push(("resource", "memory"));
+    }
+    OomdPlugin::new(policy.plugin(), &args)
+}
+
+fn ruleset_mem_pressure(knobs: &OomdSliceMemPressureKnobs, slice: Slice) -> OomdRuleset {
+    let slice = slice.name();
+    OomdRuleset {
+        name: format!("protection against heavy {} thrashing", slice),
+        silence_logs: None,
+        detectors: vec![OomdDetectorGroup {
+            name: format!("Sustained thrashing in {}", slice),
+            detectors: vec![
+                OomdPlugin::new(
+                    "pressure_above",
+                    &[
+                        ("cgroup", slice),
+                        ("resource", "memory"),
+                        ("threshold", &format!("{}", knobs.threshold)),
+                        ("duration", &format!("{}", knobs.duration)),
+                    ],
+                ),
+                OomdPlugin::new("memory_reclaim", &[("cgroup", slice), ("duration", "10")]),
             ],
-            "actions": [
-                {
-                    "name": "kill_by_memory_size_or_growth",
-                    "args": {
-                        "cgroup": "__SLICE__/*"
-                    }
-                }
-            ]
-        }"#;
-
-fn oomd_rule_memory(slice: &str, threshold: u32, duration: u32) -> String {
-    OOMD_RULE_MEMORY
-        .to_string()
-        .replace("__SLICE__", slice)
-        .replace("__THRESHOLD__", &format!("{}", threshold))
-        .replace("__DURATION__", &format!("{}", duration))
+        }],
+        actions: vec![kill_action(
+            knobs.kill_policy,
+            &knobs.kill_targets,
+            &format!("{}/*", slice),
+        )],
+    }
 }
 
-const OOMD_RULE_SWAP: &str = r#"
-        {
-            "name": "protection against low swap",
-            "detectors": [
-                [
-                    "free swap goes below __THRESHOLD__ percent",
-                    {
-                        "name": "swap_free",
-                        "args": {
-                            "threshold_pct": "__THRESHOLD__"
-                        }
-                    }
-                ]
+fn ruleset_swap(knobs: &OomdKnobs) -> OomdRuleset {
+    let threshold = format!("{}", knobs.swap_threshold);
+    OomdRuleset {
+        name: "protection against low swap".into(),
+        silence_logs: None,
+        detectors: vec![OomdDetectorGroup {
+            name: format!("free swap goes below {} percent", threshold),
+            detectors: vec![OomdPlugin::new(
+                "swap_free",
+                &[("threshold_pct", &threshold)],
+            )],
+        }],
+        actions: vec![kill_action(
+            knobs.swap_kill_policy,
+            &knobs.swap_kill_targets,
+            SWAP_KILL_TARGETS,
+        )],
+    }
+}
+
+fn ruleset_senpai(knobs: &OomdSliceSenpaiKnobs, slice: Slice, mem_size: u64) -> OomdRuleset {
+    let slice = slice.name();
+    let bytes = |frac: f64| format!("{}", (frac * mem_size as f64).round() as u64);
+    OomdRuleset {
+        name: format!("{} senpai ruleset", slice),
+        silence_logs: Some("engine".into()),
+        detectors: vec![OomdDetectorGroup {
+            name: "continue detector group".into(),
+            detectors: vec![OomdPlugin::new("continue", &[])],
+        }],
+        actions: vec![OomdPlugin::new(
+            "senpai",
+            &[
+                ("limit_min_bytes", &bytes(knobs.min_bytes_frac)),
+                ("limit_max_bytes", &bytes(knobs.max_bytes_frac)),
+                ("interval", &format!("{}", knobs.interval)),
+                (
+                    "pressure_ms",
+                    &format!("{}", (knobs.stall_threshold * TO_MSEC).round()),
+                ),
+                ("max_probe", &format!("{}", knobs.max_probe)),
+                ("max_backoff", &format!("{}", knobs.max_backoff)),
+                ("coeff_probe", &format!("{}", knobs.coeff_probe)),
+                ("coeff_backoff", &format!("{}", knobs.coeff_backoff)),
+                ("cgroup", slice),
             ],
-            "actions": [
-                {
-                    "name": "kill_by_swap_usage",
-                    "args": {
-                        "cgroup": "workload.slice/*,sideload.slice/*,system.slice/*"
-                    }
-                }
-            ]
-        }"#;
+        )],
+    }
+}
 
-fn oomd_rule_swap(threshold: u32) -> String {
-    OOMD_RULE_SWAP
-        .to_string()
-        .replace("__THRESHOLD__", &format!("{}", threshold))
+/// Build the full list of rulesets - the ones generated from the knobs
+/// followed by the user-specified extra ones.
+fn build_rulesets(knobs: &OomdKnobs, seq: u64, mem_size: u64) -> Vec<OomdRuleset> {
+    let mut rulesets = vec![];
+    for (sk, slice) in &[(&knobs.workload, Slice::Work), (&knobs.system, Slice::Sys)] {
+        if sk.mem_pressure.disable_seq < seq {
+            rulesets.push(ruleset_mem_pressure(&sk.mem_pressure, *slice));
+        }
+    }
+    for (sk, slice) in &[(&knobs.workload, Slice::Work), (&knobs.system, Slice::Sys)] {
+        if sk.senpai.enable {
+            rulesets.push(ruleset_senpai(&sk.senpai, *slice, mem_size));
+        }
+    }
+    if knobs.swap_enable {
+        rulesets.push(ruleset_swap(knobs));
+    }
+    rulesets.extend(knobs.rulesets.iter().cloned());
+    rulesets
 }
 
-const OOMD_RULE_TAIL: &str = r#"
-    ]
+fn validate_rulesets(rulesets: &[OomdRuleset]) -> Result<()> {
+    let mut errs = BTreeMap::new();
+    for (i, rs) in rulesets.iter().enumerate() {
+        rs.validate(&format!("rulesets[{}]", i), &mut errs);
+    }
+    if !errs.is_empty() {
+        let errs: Vec<String> = errs.iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+        bail!("invalid oomd config ({})", errs.join(", "));
+    }
+    Ok(())
 }
-"#;
-
-const OOMD_RULE_SENPAI: &str = r#"
-        {
-            "name": "__SLICE__ senpai ruleset",
-            "silence-logs": "engine",
-            "detectors": [
-                [
-                    "continue detector group",
-                    {
-                        "name": "continue",
-                        "args": {}
-                    }
-                ]
-            ],
-            "actions": [
-                {
-                    "name": "senpai",
-                    "args": {
-                        "limit_min_bytes": "__MIN_BYTES__",
-                        "limit_max_bytes": "__MAX_BYTES__",
-                        "interval": "__INTERVAL__",
-                        "pressure_ms": "__PRES_THR__",
-                        "max_probe": "__MAX_PROBE__",
-                        "max_backoff": "__MAX_BACKOFF__",
-                        "coeff_probe": "__COEFF_PROBE__",
-                        "coeff_backoff": "__COEFF_BACKOFF__",
-                        "cgroup": "__SLICE__"
-                    }
-                }
-            ]
-        }"#;
-
-fn oomd_rule_senpai(
-    slice: &str,
-    min_bytes: u64,
-    max_bytes: u64,
-    interval: u32,
-    pres_thr: f64,
-    max_probe: f64,
-    max_backoff: f64,
-    coeff_probe: f64,
-    coeff_backoff: f64,
-) -> String {
-    OOMD_RULE_SENPAI
-        .to_string()
-        .replace("__MIN_BYTES__", &format!("{}", min_bytes))
-        .replace("__MAX_BYTES__", &format!("{}", max_bytes))
-        .replace("__INTERVAL__", &format!("{}", interval))
-        .replace("__PRES_THR__", &format!("{}", (pres_thr * TO_MSEC).round()))
-        .replace("__MAX_PROBE__", &format!("{}", max_probe))
-        .replace("__MAX_BACKOFF__", &format!("{}", max_backoff))
-        .replace("__COEFF_PROBE__", &format!("{}", coeff_probe))
-        .replace("__COEFF_BACKOFF__", &format!("{}", coeff_backoff))
-        .replace("__SLICE__", slice)
+
+fn plugin_to_json(plugin: &OomdPlugin) -> serde_json::Value {
+    json!({ "name": plugin.name, "args": plugin.args })
+}
+
+/// oomd wants each detector group as an array of its name followed by the
+/// detectors.
+fn ruleset_to_json(rs: &OomdRuleset) -> serde_json::Value {
+    let detectors: Vec<serde_json::Value> = rs
+        .detectors
+        .iter()
+        .map(|group| {
+            let mut arr = vec![json!(group.name)];
+            arr.extend(group.detectors.iter().map(plugin_to_json));
+            serde_json::Value::Array(arr)
+        })
+        .collect();
+    let actions: Vec<serde_json::Value> = rs.actions.iter().map(plugin_to_json).collect();
+
+    let mut obj = json!({
+        "name": rs.name,
+        "detectors": detectors,
+        "actions": actions,
+    });
+    if let Some(silence) = rs.silence_logs.as_ref() {
+        obj["silence-logs"] = json!(silence);
+    }
+    obj
+}
+
+fn format_oomd_cfg(rulesets: &[OomdRuleset]) -> Result<String> {
+    let rulesets: Vec<serde_json::Value> = rulesets.iter().map(ruleset_to_json).collect();
+    Ok(serde_json::to_string_pretty(&json!({ "rulesets": rulesets }))? + "\n")
 }
 
-fn oomd_cfg_slice_mem_pressure(knobs: &OomdSliceMemPressureKnobs, slice: Slice) -> String {
-    let mut oomd_cfg = String::new();
-    if knobs.disable_seq >= super::instance_seq() {
-        return oomd_cfg;
+fn set_cgrp_xattr(cgrp: &str, name: &str, set: bool) -> Result<()> {
+    let path = CString::new(format!("/sys/fs/cgroup/{}", cgrp.trim_matches('/')))?;
+    let name = CString::new(name)?;
+    let ret = unsafe {
+        if set {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                b"1".as_ptr() as *const libc::c_void,
+                1,
+                0,
+            )
+        } else {
+            libc::removexattr(path.as_ptr(), name.as_ptr())
+        }
+    };
+    if ret < 0 {
+        bail!("{}", std::io::Error::last_os_error());
     }
-    oomd_cfg += &oomd_rule_memory(slice.name(), knobs.threshold, knobs.duration);
-    oomd_cfg
+    Ok(())
 }
 
-fn oomd_cfg_slice_senpai(knobs: &OomdSliceSenpaiKnobs, slice: Slice, mem_size: u64) -> String {
-    let mut oomd_cfg = String::new();
-    if !knobs.enable {
-        return oomd_cfg;
+/// Parse oomd's "Killed N ... in CGROUP" log message into the killed cgroup
+/// relative to /sys/fs/cgroup. Messages may be prefixed with the source
+/// location.
+fn parse_daemon_kill(msg: &str) -> Option<String> {
+    let (_, rest) = msg.split_once("Killed ")?;
+    let nr_killed = rest.split_whitespace().next()?.parse::<u64>().ok()?;
+    let (_, cgrp) = rest.rsplit_once(" in ")?;
+    if nr_killed == 0 {
+        return None;
     }
-    oomd_cfg += &oomd_rule_senpai(
-        slice.name(),
-        (knobs.min_bytes_frac * mem_size as f64).round() as u64,
-        (knobs.max_bytes_frac * mem_size as f64).round() as u64,
-        knobs.interval,
-        knobs.stall_threshold,
-        knobs.max_probe,
-        knobs.max_backoff,
-        knobs.coeff_probe,
-        knobs.coeff_backoff,
-    );
-    oomd_cfg
+    Some(
+        cgrp.trim()
+            .trim_start_matches("/sys/fs/cgroup")
+            .trim_matches('/')
+            .to_string(),
+    )
+}
+
+/// Kill counts of the oomd daemon, collected from its journal. Each kill is
+/// also recorded in the events log.
+#[derive(Default)]
+struct DaemonKills {
+    nr_kills: u64,
+    last_kill: Option<OomdKillReport>,
+}
+
+fn tail_daemon_kills(kills: Arc<Mutex<DaemonKills>>) -> JournalTailer {
+    // journalctl replays the last message, ignore what predates the daemon
+    let started_at = SystemTime::now();
+    JournalTailer::new(
+        &[OOMD_SVC_NAME],
+        1,
+        Box::new(move |msgs, _flush| {
+            let msg = &msgs[0];
+            if msg.at < started_at {
+                return;
+            }
+            if let Some(cgroup) = parse_daemon_kill(&msg.msg) {
+                let why = msg.msg.trim().to_string();
+                events::record(EventKind::OomdKill {
+                    cgroup: cgroup.clone(),
+                    why: why.clone(),
+                    size: 0,
+                });
+                let mut kills = kills.lock().unwrap();
+                kills.nr_kills += 1;
+                kills.last_kill = Some(OomdKillReport {
+                    at: unix_now(),
+                    cgroup,
+                    why,
+                    size: 0,
+                });
+            }
+        }),
+    )
+}
+
+/// The oomd binary to run. None, which makes Oomd::apply start the
+/// built-in engine, if oomd couldn't be found.
+fn daemon_bin(oomd_bin: &Result<String>) -> Option<String> {
+    oomd_bin.as_ref().ok().cloned()
 }
 
 pub struct Oomd {
     bin: Option<String>,
     daemon_cfg_path: String,
     svc: Option<TransientService>,
+    svc_tailer: Option<JournalTailer>,
+    svc_kills: Arc<Mutex<DaemonKills>>,
+    builtin: Option<BuiltinOomd>,
+    tagged: Vec<(String, &'static str)>,
 
     pub file: JsonConfigFile<OomdKnobs>,
 }
@@ -211,22 +285,23 @@ impl Oomd {
     pub fn new(cfg: &Config) -> Result<Self> {
         let file = JsonConfigFile::<OomdKnobs>::load_or_create(Some(&cfg.oomd_cfg_path.clone()))?;
 
-        let bin = match cfg.oomd_bin.as_ref() {
-            Ok(v) => Some(v.clone()),
-            Err(_) => None,
-        };
-
         Ok(Self {
-            bin,
+            bin: daemon_bin(&cfg.oomd_bin),
             daemon_cfg_path: cfg.oomd_daemon_cfg_path.clone(),
             file,
             svc: None,
+            svc_tailer: None,
+            svc_kills: Default::default(),
+            builtin: None,
+            tagged: vec![],
         })
     }
 
     pub fn stop(&mut self) {
         debug!("oomd: Stoppping");
         self.svc = None;
+        self.svc_tailer = None;
+        self.builtin = None;
 
         // clean up after senpai
         for slice in &[Slice::Work, Slice::Sys] {
@@ -241,51 +316,47 @@ impl Oomd {
         }
     }
 
-    pub fn apply(&mut self) -> Result<()> {
-        if self.bin.is_none() {
-            warn!("oomd: Configuration update requested but oomd is not available");
-            return Ok(());
+    fn update_kill_prefs(&mut self) {
+        for (cgrp, xattr) in self.tagged.drain(..) {
+            if let Err(e) = set_cgrp_xattr(&cgrp, xattr, false) {
+                debug!("oomd: Failed to clear {} on {:?} ({:#})", xattr, &cgrp, &e);
+            }
         }
 
-        if self.svc.is_some() {
+        let knobs = &self.file.data;
+        let prefs = knobs
+            .kill_prefer
+            .iter()
+            .map(|cgrp| (cgrp, XATTR_PREFER))
+            .chain(knobs.kill_avoid.iter().map(|cgrp| (cgrp, XATTR_AVOID)));
+        for (cgrp, xattr) in prefs {
+            match set_cgrp_xattr(cgrp, xattr, true) {
+                Ok(()) => self.tagged.push((cgrp.clone(), xattr)),
+                Err(e) => warn!("oomd: Failed to set {} on {:?} ({:#})", xattr, cgrp, &e),
+            }
+        }
+    }
+
+    pub fn apply(&mut self) -> Result<()> {
+        let seq = super::instance_seq();
+        let rulesets = build_rulesets(&self.file.data, seq, total_memory() as u64);
+        validate_rulesets(&rulesets)?;
+
+        if self.svc.is_some() || self.builtin.is_some() {
             self.stop();
         }
+        self.update_kill_prefs();
 
         let knobs = &self.file.data;
 
-        let mut oomd_cfg = OOMD_RULE_HEAD.to_string();
-        let mut oomd_cfg_append = |x: &str| {
-            if let Some('}') = oomd_cfg.chars().last() {
-                oomd_cfg += ",";
+        if self.bin.is_none() {
+            if knobs.disable_seq < seq {
+                self.builtin = Some(BuiltinOomd::start(knobs, seq));
             }
-            oomd_cfg += x;
-        };
-
-        //oomd_cfg += OOMD_RULE_OVERVIEW;
-        oomd_cfg_append(&oomd_cfg_slice_mem_pressure(
-            &knobs.workload.mem_pressure,
-            Slice::Work,
-        ));
-        oomd_cfg_append(&oomd_cfg_slice_mem_pressure(
-            &knobs.system.mem_pressure,
-            Slice::Sys,
-        ));
-        oomd_cfg_append(&oomd_cfg_slice_senpai(
-            &knobs.workload.senpai,
-            Slice::Work,
-            total_memory() as u64,
-        ));
-        oomd_cfg_append(&oomd_cfg_slice_senpai(
-            &knobs.system.senpai,
-            Slice::Sys,
-            total_memory() as u64,
-        ));
-
-        if knobs.swap_enable {
-            oomd_cfg_append(&oomd_rule_swap(knobs.swap_threshold));
+            return Ok(());
         }
 
-        oomd_cfg += OOMD_RULE_TAIL;
+        let oomd_cfg = format_oomd_cfg(&rulesets)?;
 
         debug!("oomd: Updating {:?}", &self.daemon_cfg_path);
         let mut f = fs::OpenOptions::new()
@@ -295,7 +366,7 @@ impl Oomd {
             .open(&self.daemon_cfg_path)?;
         f.write_all(oomd_cfg.as_ref())?;
 
-        if knobs.disable_seq >= super::instance_seq() {
+        if knobs.disable_seq >= seq {
             return Ok(());
         }
 
@@ -315,6 +386,7 @@ impl Oomd {
             .set_restart_always()
             .start()?;
         self.svc = Some(svc);
+        self.svc_tailer = Some(tail_daemon_kills(self.svc_kills.clone()));
         Ok(())
     }
 
@@ -324,9 +396,20 @@ impl Oomd {
     }
 
     pub fn report(&mut self) -> Result<OomdReport> {
-        let svc_r = match &mut self.svc {
-            Some(svc) => super::svc_refresh_and_report(&mut svc.unit)?,
-            None => Default::default(),
+        let svc_r = match (&mut self.svc, &self.builtin) {
+            (Some(svc), _) => super::svc_refresh_and_report(&mut svc.unit)?,
+            (None, Some(_)) => SvcReport {
+                name: BUILTIN_SVC_NAME.into(),
+                state: SvcStateReport::Running,
+            },
+            (None, None) => Default::default(),
+        };
+        let (nr_kills, last_kill) = match &self.builtin {
+            Some(builtin) => builtin.kills(),
+            None => {
+                let kills = self.svc_kills.lock().unwrap();
+                (kills.nr_kills, kills.last_kill.clone())
+            }
         };
 
         let seq = super::instance_seq();
@@ -338,6 +421,105 @@ impl Oomd {
             work_senpai: knobs.workload.senpai.enable,
             sys_mem_pressure: knobs.system.mem_pressure.disable_seq < seq,
             sys_senpai: knobs.system.senpai.enable,
+            builtin: self.builtin.is_some(),
+            nr_kills,
+            last_kill,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_rulesets() {
+        let mut knobs = OomdKnobs::default();
+        knobs.workload.senpai.enable = true;
+        knobs.system.mem_pressure.disable_seq = 1;
+        knobs.system.mem_pressure.kill_policy = OomdKillPolicy::Pressure;
+        knobs.workload.mem_pressure.kill_policy = OomdKillPolicy::IoCost;
+        knobs.workload.mem_pressure.kill_targets =
+            vec!["workload.slice/*".into(), "sideload.slice/*".into()];
+
+        // mem pressure protection disabled on system.slice through seq 1
+        let rulesets = build_rulesets(&knobs, 1, 1 << 30);
+        let names: Vec<&str> = rulesets.iter().map(|rs| rs.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "protection against heavy workload.slice thrashing",
+                "workload.slice senpai ruleset",
+                "protection against low swap",
+            ]
+        );
+
+        let rulesets = build_rulesets(&knobs, 2, 1 << 30);
+        let names: Vec<&str> = rulesets.iter().map(|rs| rs.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "protection against heavy workload.slice thrashing",
+                "protection against heavy system.slice thrashing",
+                "workload.slice senpai ruleset",
+                "protection against low swap",
+            ]
+        );
+        assert!(validate_rulesets(&rulesets).is_ok());
+
+        let kill = &rulesets[0].actions[0];
+        assert_eq!(kill.name, "kill_by_io_cost");
+        assert_eq!(kill.args["cgroup"], "workload.slice/*,sideload.slice/*");
+        let kill = &rulesets[1].actions[0];
+        assert_eq!(kill.name, "kill_by_pressure");
+        assert_eq!(kill.args["cgroup"], "system.slice/*");
+        assert_eq!(kill.args["resource"], "memory");
+        assert_eq!(rulesets[2].actions[0].args["limit_min_bytes"], "268435456");
+        assert_eq!(rulesets[2].actions[0].args["pressure_ms"], "75");
+        assert_eq!(rulesets[3].actions[0].args["cgroup"], SWAP_KILL_TARGETS);
+    }
+
+    #[test]
+    fn test_parse_daemon_kill() {
+        assert_eq!(
+            parse_daemon_kill(
+                "[../src/oomd/plugins/BaseKillPlugin.cpp:196] Killed 12 in /sys/fs/cgroup/workload.slice/rd-hashd-A.service"
+            ),
+            Some("workload.slice/rd-hashd-A.service".to_string())
+        );
+        assert_eq!(
+            parse_daemon_kill("Killed 3 processes in system.slice/rd-sysload-mem-hog.service"),
+            Some("system.slice/rd-sysload-mem-hog.service".to_string())
+        );
+        assert_eq!(
+            parse_daemon_kill("Killed 0 in /sys/fs/cgroup/sideload.slice"),
+            None
+        );
+        assert_eq!(parse_daemon_kill("Trying to kill sideload.slice"), None);
+        assert_eq!(parse_daemon_kill("Killed 3 processes"), None);
+    }
+
+    #[test]
+    fn test_daemon_bin() {
+        // a missing oomd isn't fatal, the built-in engine takes over
+        assert_eq!(daemon_bin(&Err(anyhow::anyhow!("not found"))), None);
+        assert_eq!(
+            daemon_bin(&Ok("/usr/bin/oomd".into())),
+            Some("/usr/bin/oomd".to_string())
+        );
+    }
+
+    #[test]
+    fn test_ruleset_to_json() {
+        let rs = ruleset_senpai(&Default::default(), Slice::Sys, 1 << 30);
+        let js = ruleset_to_json(&rs);
+        assert_eq!(js["silence-logs"], "engine");
+        assert_eq!(js["detectors"][0][0], "continue detector group");
+        assert_eq!(js["detectors"][0][1]["name"], "continue");
+        assert_eq!(js["actions"][0]["args"]["cgroup"], "system.slice");
+
+        let mut bad = rs.clone();
+        bad.actions[0].name = "kill_everything".into();
+        assert!(validate_rulesets(&[rs, bad]).is_err());
+    }
+}
@@ -0,0 +1,579 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// Built-in fallback for oomd which is used when a working oomd binary isn't
+// available. It implements the subset of oomd that OomdKnobs configures
+// directly against cgroupfs:
+//
+// * Memory pressure protection - when the full memory pressure of a slice
+//   stays above the threshold for the duration while the slice is
+//   reclaiming, a victim is picked from the kill targets and killed.
+//
+// * Swap depletion protection - when free swap drops below the threshold,
+//   a victim is picked from the swap kill targets and killed.
+//
+// * Senpai - memory.high is adjusted every interval so that the memory
+//   stall time stays around stall_threshold, probing down the memory
+//   footprint while there's no pressure and backing off otherwise.
+//
+// Victims are picked by the kill policy among the cgroups that the targets
+// expand to. IO cost isn't tracked, so IoCost picks by memory size like
+// MemorySizeOrGrowth. Cgroups under kill_prefer are picked before and the
+// ones under kill_avoid after all others. Extra rulesets are ignored.
+//
+// After each kill, further kills are held off for KILL_COOLDOWN to give
+// the system time to recover, like oomd's post action delay.
+//
+use anyhow::{anyhow, Result};
+use crossbeam::channel::{self, Receiver, RecvTimeoutError, Sender};
+use log::{error, info, warn};
+use std::fs;
+use std::panic;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+
+use rd_agent_intf::{
+    EventKind, OomdKillPolicy, OomdKillReport, OomdKnobs, OomdSliceMemPressureKnobs,
+    OomdSliceSenpaiKnobs, Slice,
+};
+use rd_util::*;
+
+use super::events;
+
+const INTV: f64 = 1.0;
+const KILL_COOLDOWN: f64 = 15.0;
+const RECLAIM_WINDOW: f64 = 10.0;
+const CGRP_ROOT: &str = "/sys/fs/cgroup";
+const SWAP_KILL_SLICES: [Slice; 3] = [Slice::Work, Slice::Side, Slice::Sys];
+
+/// Tracks how long a pressure has been staying above a threshold.
+#[derive(Debug, Default)]
+struct PressureTrigger {
+    above_since: Option<f64>,
+}
+
+impl PressureTrigger {
+    /// Returns true if `pressure` has been above `threshold` for `duration`.
+    fn update(&mut self, pressure: f64, threshold: f64, duration: f64, now: f64) -> bool {
+        if pressure <= threshold {
+            self.above_since = None;
+            return false;
+        }
+        let since = *self.above_since.get_or_insert(now);
+        now - since >= duration
+    }
+}
+
+/// Senpai limit adjustment. `stall_ms` is the memory stall time
+/// accumulated during the last interval. Returns the new memory.high.
+fn senpai_adjust(knobs: &OomdSliceSenpaiKnobs, limit: u64, stall_ms: f64, mem_size: u64) -> u64 {
+    let pres_thr = knobs.stall_threshold * TO_MSEC;
+    let error = (pres_thr - stall_ms) / pres_thr;
+    let factor = if error > 0.0 {
+        (error / knobs.coeff_probe).min(knobs.max_probe)
+    } else {
+        (error / knobs.coeff_backoff).max(-knobs.max_backoff)
+    };
+
+    let min = (knobs.min_bytes_frac * mem_size as f64).round();
+    let max = (knobs.max_bytes_frac * mem_size as f64).round();
+    (limit as f64 * (1.0 - factor)).max(min).min(max) as u64
+}
+
+/// Memory pressure of `kind` ("some" or "full"), the larger of avg10 and
+/// avg60 like oomd.
+fn read_mem_pressure(cgrp: &str, kind: &str) -> Result<f64> {
+    let path = format!("{}/memory.pressure", cgrp);
+    let pres = read_cgroup_nested_keyed_file(&path)?;
+    let line = pres
+        .get(kind)
+        .ok_or(anyhow!("{:?} missing in {:?}", kind, &path))?;
+    let field = |key: &str| {
+        line.get(key)
+            .ok_or(anyhow!("{:?} missing in {:?}", key, &path))
+    };
+    Ok(field("avg10")?
+        .parse::<f64>()?
+        .max(field("avg60")?.parse::<f64>()?))
+}
+
+fn read_some_pressure_total(cgrp: &str) -> Result<u64> {
+    let path = format!("{}/memory.pressure", cgrp);
+    let pres = read_cgroup_nested_keyed_file(&path)?;
+    Ok(pres
+        .get("some")
+        .and_then(|some| some.get("total"))
+        .ok_or(anyhow!("\"some total\" missing in {:?}", &path))?
+        .parse::<u64>()?)
+}
+
+fn read_u64_or_max(path: &str) -> Result<Option<u64>> {
+    match read_one_line(path)?.trim() {
+        "max" => Ok(None),
+        v => Ok(Some(v.parse::<u64>()?)),
+    }
+}
+
+fn child_cgrps(cgrp: &str) -> Vec<String> {
+    match fs::read_dir(cgrp) {
+        Ok(rd) => rd
+            .filter_map(|ent| ent.ok())
+            .filter(|ent| ent.file_type().map(|ft| ft.is_dir()).unwrap_or(false))
+            .map(|ent| ent.path().to_string_lossy().to_string())
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+fn read_cgrp_u64(cgrp: &str, knob: &str) -> Option<u64> {
+    read_one_line(format!("{}/{}", cgrp, knob))
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+}
+
+/// Expand oomd cgroup patterns into cgroupfs paths. "SLICE/*" selects the
+/// children of SLICE, anything else the cgroup itself.
+fn expand_targets(targets: &[String]) -> Vec<String> {
+    targets
+        .iter()
+        .flat_map(|x| x.split(','))
+        .map(|x| x.trim().trim_matches('/'))
+        .filter(|x| !x.is_empty())
+        .flat_map(|x| match x.strip_suffix("/*") {
+            Some(parent) => child_cgrps(&format!("{}/{}", CGRP_ROOT, parent)),
+            None => vec![format!("{}/{}", CGRP_ROOT, x)],
+        })
+        .collect()
+}
+
+fn under_any(cgrp: &str, list: &[String]) -> bool {
+    let rel = cgrp.trim_start_matches(CGRP_ROOT).trim_matches('/');
+    list.iter()
+        .map(|x| x.trim_matches('/'))
+        .any(|x| rel == x || (rel.starts_with(x) && rel[x.len()..].starts_with('/')))
+}
+
+/// Usage reported for the victim - swap for SwapUsage, memory otherwise.
+fn victim_size(cgrp: &str, policy: OomdKillPolicy) -> u64 {
+    match policy {
+        OomdKillPolicy::SwapUsage => read_cgrp_u64(cgrp, "memory.swap.current"),
+        _ => read_cgrp_u64(cgrp, "memory.current"),
+    }
+    .unwrap_or(0)
+}
+
+fn victim_score(cgrp: &str, policy: OomdKillPolicy) -> Option<u64> {
+    match policy {
+        OomdKillPolicy::Pressure => read_mem_pressure(cgrp, "some")
+            .ok()
+            .map(|v| (v * 100.0) as u64),
+        _ => Some(victim_size(cgrp, policy)),
+    }
+}
+
+/// Pick the cgroup to kill among `targets` according to `policy`.
+/// Returns the cgroup path and its size.
+fn pick_victim(
+    targets: &[String],
+    policy: OomdKillPolicy,
+    prefer: &[String],
+    avoid: &[String],
+) -> Option<(String, u64)> {
+    expand_targets(targets)
+        .into_iter()
+        .filter_map(|cgrp| {
+            let score = victim_score(&cgrp, policy)?;
+            let tier = match (under_any(&cgrp, prefer), under_any(&cgrp, avoid)) {
+                (true, _) => 2,
+                (false, false) => 1,
+                (false, true) => 0,
+            };
+            Some(((tier, score), cgrp))
+        })
+        .filter(|((_, score), _)| *score > 0)
+        .max_by_key(|(key, _)| *key)
+        .map(|(_, cgrp)| {
+            let size = victim_size(&cgrp, policy);
+            (cgrp, size)
+        })
+}
+
+fn kill_pids(cgrp: &str) -> Result<usize> {
+    let mut nr_killed = 0;
+    for pid in fs::read_to_string(format!("{}/cgroup.procs", cgrp))?
+        .lines()
+        .filter_map(|x| x.trim().parse::<i32>().ok())
+    {
+        if unsafe { libc::kill(pid, libc::SIGKILL) } == 0 {
+            nr_killed += 1;
+        }
+    }
+    for child in child_cgrps(cgrp) {
+        nr_killed += kill_pids(&child)?;
+    }
+    Ok(nr_killed)
+}
+
+fn kill_cgrp(cgrp: &str) -> Result<()> {
+    let kill_path = format!("{}/cgroup.kill", cgrp);
+    if Path::new(&kill_path).exists() {
+        write_one_line(&kill_path, "1")
+    } else {
+        kill_pids(cgrp).map(|_| ())
+    }
+}
+
+struct SliceState {
+    slice: Slice,
+    mem_pressure: OomdSliceMemPressureKnobs,
+    senpai: OomdSliceSenpaiKnobs,
+    trigger: PressureTrigger,
+    last_pgscan: u64,
+    reclaimed_at: f64,
+    senpai_last_total: Option<u64>,
+    senpai_next_at: f64,
+}
+
+impl SliceState {
+    fn kill_targets(&self) -> Vec<String> {
+        match self.mem_pressure.kill_targets.len() {
+            0 => vec![format!("{}/*", self.slice.name())],
+            _ => self.mem_pressure.kill_targets.clone(),
+        }
+    }
+
+    /// Returns the kill reason if the memory pressure protection triggers.
+    fn check_mem_pressure(&mut self, seq: u64, now: f64) -> Result<Option<String>> {
+        let cgrp = self.slice.cgrp();
+
+        let pgscan = read_cgroup_flat_keyed_file(&format!("{}/memory.stat", cgrp))?
+            .iter()
+            .filter(|(k, _)| k.starts_with("pgscan"))
+            .map(|(_, v)| *v)
+            .max()
+            .unwrap_or(0);
+        if pgscan > self.last_pgscan {
+            self.reclaimed_at = now;
+        }
+        self.last_pgscan = pgscan;
+
+        let knobs = &self.mem_pressure;
+        if knobs.disable_seq >= seq {
+            return Ok(None);
+        }
+
+        let pressure = read_mem_pressure(cgrp, "full")?;
+        let sustained =
+            self.trigger
+                .update(pressure, knobs.threshold as f64, knobs.duration as f64, now);
+        if sustained && now - self.reclaimed_at <= RECLAIM_WINDOW {
+            Ok(Some(format!(
+                "memory pressure {:.2} above {} for {}s in {}",
+                pressure,
+                knobs.threshold,
+                knobs.duration,
+                self.slice.name()
+            )))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn run_senpai(&mut self, mem_size: u64, now: f64) -> Result<()> {
+        let knobs = &self.senpai;
+        if !knobs.enable || now < self.senpai_next_at {
+            return Ok(());
+        }
+        self.senpai_next_at = now + knobs.interval as f64;
+
+        let cgrp = self.slice.cgrp();
+        let total = read_some_pressure_total(cgrp)?;
+        let last_total = self.senpai_last_total.replace(total);
+        let stall_ms = match last_total {
+            Some(last) => total.saturating_sub(last) as f64 / 1000.0,
+            None => return Ok(()),
+        };
+
+        let high_path = format!("{}/memory.high", cgrp);
+        let limit = match read_u64_or_max(&high_path)? {
+            Some(v) => v,
+            None => read_one_line(format!("{}/memory.current", cgrp))?
+                .trim()
+                .parse::<u64>()?,
+        };
+        let new_limit = senpai_adjust(knobs, limit, stall_ms, mem_size);
+        if new_limit != limit {
+            write_one_line(&high_path, &format!("{}", new_limit))?;
+        }
+        Ok(())
+    }
+}
+
+struct Worker {
+    seq: u64,
+    swap_enable: bool,
+    swap_threshold: u32,
+    swap_kill_policy: OomdKillPolicy,
+    swap_kill_targets: Vec<String>,
+    kill_prefer: Vec<String>,
+    kill_avoid: Vec<String>,
+    slices: Vec<SliceState>,
+    kill_hold_until: f64,
+    shared: Arc<Mutex<BuiltinOomdState>>,
+    term_rx: Receiver<()>,
+}
+
+impl Worker {
+    fn check_swap(&self) -> Result<Option<String>> {
+        if !self.swap_enable {
+            return Ok(None);
+        }
+        let mstat = procfs::Meminfo::new()?;
+        if mstat.swap_total == 0 {
+            return Ok(None);
+        }
+        let free_pct = mstat.swap_free as f64 / mstat.swap_total as f64 * 100.0;
+        if free_pct < self.swap_threshold as f64 {
+            Ok(Some(format!(
+                "free swap {:.2}% below {}%",
+                free_pct, self.swap_threshold
+            )))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn kill(&mut self, targets: &[String], policy: OomdKillPolicy, why: String, now: f64) {
+        let (victim, size) = match pick_victim(targets, policy, &self.kill_prefer, &self.kill_avoid)
+        {
+            Some(v) => v,
+            None => {
+                warn!("oomd-builtin: No victim found for {}", &why);
+                return;
+            }
+        };
+        self.kill_hold_until = now + KILL_COOLDOWN;
+
+        info!(
+            "oomd-builtin: Killing {:?} ({:?}, {:.2}G), {}",
+            &victim,
+            policy,
+            to_gb(size),
+            &why
+        );
+        if let Err(e) = kill_cgrp(&victim) {
+            warn!("oomd-builtin: Failed to kill {:?} ({:#})", &victim, &e);
+            return;
+        }
+
+        events::record(EventKind::OomdKill {
+            cgroup: victim.clone(),
+            why: why.clone(),
+            size,
+        });
+
+        let mut state = self.shared.lock().unwrap();
+        state.nr_kills += 1;
+        state.last_kill = Some(OomdKillReport {
+            at: now as u64,
+            cgroup: victim,
+            why,
+            size,
+        });
+    }
+
+    fn step(&mut self, now: f64) {
+        let mem_size = total_memory() as u64;
+        let mut mem_kill = None;
+
+        for ss in self.slices.iter_mut() {
+            match ss.check_mem_pressure(self.seq, now) {
+                Ok(Some(why)) if mem_kill.is_none() => {
+                    mem_kill = Some((ss.kill_targets(), ss.mem_pressure.kill_policy, why))
+                }
+                Ok(_) => {}
+                Err(e) => warn!(
+                    "oomd-builtin: Failed to check {} ({:#})",
+                    ss.slice.name(),
+                    &e
+                ),
+            }
+            if let Err(e) = ss.run_senpai(mem_size, now) {
+                warn!(
+                    "oomd-builtin: Senpai failed on {} ({:#})",
+                    ss.slice.name(),
+                    &e
+                );
+            }
+        }
+
+        let swap_kill = match self.check_swap() {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("oomd-builtin: Failed to check swap ({:#})", &e);
+                None
+            }
+        };
+
+        if now < self.kill_hold_until {
+            return;
+        }
+        if let Some(why) = swap_kill {
+            let targets = self.swap_kill_targets.clone();
+            self.kill(&targets, self.swap_kill_policy, why, now);
+        } else if let Some((targets, policy, why)) = mem_kill {
+            self.kill(&targets, policy, why, now);
+        }
+    }
+
+    fn run_inner(mut self) {
+        loop {
+            self.step(unix_now_f64());
+            match self.term_rx.recv_timeout(Duration::from_secs_f64(INTV)) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => break,
+            }
+        }
+    }
+
+    fn run(self) {
+        if let Err(e) = panic::catch_unwind(panic::AssertUnwindSafe(|| self.run_inner())) {
+            error!("oomd-builtin: Worker thread panicked ({:?})", &e);
+            set_prog_exiting();
+        }
+    }
+}
+
+#[derive(Default)]
+struct BuiltinOomdState {
+    nr_kills: u64,
+    last_kill: Option<OomdKillReport>,
+}
+
+pub struct BuiltinOomd {
+    shared: Arc<Mutex<BuiltinOomdState>>,
+    term_tx: Option<Sender<()>>,
+    jh: Option<JoinHandle<()>>,
+}
+
+impl BuiltinOomd {
+    pub fn start(knobs: &OomdKnobs, seq: u64) -> Self {
+        let shared = Arc::new(Mutex::new(BuiltinOomdState::default()));
+        let (term_tx, term_rx) = channel::unbounded::<()>();
+
+        let slices = [(Slice::Work, &knobs.workload), (Slice::Sys, &knobs.system)]
+            .iter()
+            .map(|(slice, sk)| SliceState {
+                slice: *slice,
+                mem_pressure: sk.mem_pressure.clone(),
+                senpai: sk.senpai.clone(),
+                trigger: Default::default(),
+                last_pgscan: 0,
+                reclaimed_at: 0.0,
+                senpai_last_total: None,
+                senpai_next_at: 0.0,
+            })
+            .collect();
+
+        let worker = Worker {
+            seq,
+            swap_enable: knobs.swap_enable,
+            swap_threshold: knobs.swap_threshold,
+            swap_kill_policy: knobs.swap_kill_policy,
+            swap_kill_targets: match knobs.swap_kill_targets.len() {
+                0 => SWAP_KILL_SLICES
+                    .iter()
+                    .map(|slice| format!("{}/*", slice.name()))
+                    .collect(),
+                _ => knobs.swap_kill_targets.clone(),
+            },
+            kill_prefer: knobs.kill_prefer.clone(),
+            kill_avoid: knobs.kill_avoid.clone(),
+            slices,
+            kill_hold_until: 0.0,
+            shared: shared.clone(),
+            term_rx,
+        };
+        info!("oomd-builtin: Starting, oomd is not available");
+        let jh = spawn(move || worker.run());
+
+        Self {
+            shared,
+            term_tx: Some(term_tx),
+            jh: Some(jh),
+        }
+    }
+
+    pub fn kills(&self) -> (u64, Option<OomdKillReport>) {
+        let state = self.shared.lock().unwrap();
+        (state.nr_kills, state.last_kill.clone())
+    }
+}
+
+impl Drop for BuiltinOomd {
+    fn drop(&mut self) {
+        self.term_tx.take();
+        if let Some(jh) = self.jh.take() {
+            jh.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pressure_trigger() {
+        let mut trig = PressureTrigger::default();
+        assert!(!trig.update(60.0, 50.0, 3.0, 0.0));
+        assert!(!trig.update(60.0, 50.0, 3.0, 2.0));
+        assert!(trig.update(60.0, 50.0, 3.0, 3.0));
+        // dropping below resets the duration
+        assert!(!trig.update(40.0, 50.0, 3.0, 4.0));
+        assert!(!trig.update(60.0, 50.0, 3.0, 5.0));
+        assert!(trig.update(60.0, 50.0, 3.0, 8.0));
+    }
+
+    #[test]
+    fn test_under_any() {
+        let list = vec![
+            "workload.slice/".to_string(),
+            "system.slice/foo".to_string(),
+        ];
+        assert!(under_any("/sys/fs/cgroup/workload.slice/bar", &list));
+        assert!(under_any("/sys/fs/cgroup/workload.slice", &list));
+        assert!(under_any("/sys/fs/cgroup/system.slice/foo/x", &list));
+        assert!(!under_any("/sys/fs/cgroup/system.slice/foobar", &list));
+        assert!(!under_any("/sys/fs/cgroup/sideload.slice/x", &list));
+    }
+
+    #[test]
+    fn test_senpai_adjust() {
+        let knobs = OomdSliceSenpaiKnobs {
+            enable: true,
+            min_bytes_frac: 0.25,
+            ..Default::default()
+        };
+        let mem_size = 1 << 30;
+        let limit = 1 << 29;
+
+        // no stall, probe down by max_probe
+        let probed = senpai_adjust(&knobs, limit, 0.0, mem_size);
+        assert_eq!(probed, (limit as f64 * (1.0 - knobs.max_probe)) as u64);
+
+        // stalling at the threshold, stay put
+        assert_eq!(senpai_adjust(&knobs, limit, 75.0, mem_size), limit);
+
+        // heavy stall, back off but not more than max_backoff
+        let backed = senpai_adjust(&knobs, limit, 750.0, mem_size);
+        assert!(backed > limit && backed <= 2 * limit);
+        assert_eq!(senpai_adjust(&knobs, limit, 1e9, mem_size), mem_size);
+
+        // clamped to [min_bytes_frac, max_bytes_frac]
+        assert_eq!(senpai_adjust(&knobs, 1 << 20, 0.0, mem_size), mem_size / 4);
+    }
+}
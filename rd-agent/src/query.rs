@@ -0,0 +1,451 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{anyhow, bail, Result};
+use chrono::prelude::*;
+use log::{error, warn};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::process::exit;
+
+use rd_agent_intf::{ReportIter, ReportQueryArgs, ReportQueryFormat};
+use rd_util::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Aggr {
+    Mean,
+    Min,
+    Max,
+    Count,
+    Pct(f64),
+}
+
+impl Aggr {
+    fn parse(input: &str) -> Result<Self> {
+        Ok(match input {
+            "mean" => Self::Mean,
+            "min" => Self::Min,
+            "max" => Self::Max,
+            "count" => Self::Count,
+            v if v.starts_with('p') => {
+                let pct = v[1..]
+                    .parse::<f64>()
+                    .map_err(|_| anyhow!("invalid percentile {:?}", v))?;
+                if !(0.0..=100.0).contains(&pct) {
+                    bail!("percentile {:?} out of range", v);
+                }
+                Self::Pct(pct)
+            }
+            v => bail!("unknown aggregation {:?}", v),
+        })
+    }
+
+    /// `sorted` must be sorted in ascending order and not empty.
+    fn calc(&self, sorted: &[f64]) -> f64 {
+        match self {
+            Self::Mean => sorted.iter().sum::<f64>() / sorted.len() as f64,
+            Self::Min => sorted[0],
+            Self::Max => sorted[sorted.len() - 1],
+            Self::Count => sorted.len() as f64,
+            Self::Pct(pct) => {
+                let pos = pct / 100.0 * (sorted.len() - 1) as f64;
+                let (lo, hi) = (pos.floor() as usize, pos.ceil() as usize);
+                sorted[lo] + (sorted[hi] - sorted[lo]) * (pos - lo as f64)
+            }
+        }
+    }
+}
+
+fn parse_time(input: &str, now: u64) -> Result<u64> {
+    if input == "now" {
+        return Ok(now);
+    }
+    if let Some(dur) = input.strip_prefix('-') {
+        return Ok(now.saturating_sub(parse_duration(dur)?.round() as u64));
+    }
+    if let Ok(v) = input.parse::<u64>() {
+        return Ok(v);
+    }
+
+    let local = |ndt: NaiveDateTime| {
+        Local
+            .from_local_datetime(&ndt)
+            .earliest()
+            .map(|dt| dt.timestamp() as u64)
+            .ok_or_else(|| anyhow!("invalid local time {:?}", input))
+    };
+    for fmt in &["%H:%M:%S", "%H:%M"] {
+        if let Ok(t) = NaiveTime::parse_from_str(input, fmt) {
+            return local(Local::today().naive_local().and_time(t));
+        }
+    }
+    for fmt in &[
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+    ] {
+        if let Ok(ndt) = NaiveDateTime::parse_from_str(input, fmt) {
+            return local(ndt);
+        }
+    }
+    bail!("failed to parse time {:?}", input)
+}
+
+fn flatten(prefix: &str, val: &Value, out: &mut Vec<(String, Value)>) {
+    match val {
+        Value::Object(map) => {
+            for (k, v) in map.iter() {
+                flatten(&format!("{}.{}", prefix, k), v, out);
+            }
+        }
+        Value::Array(vals) => {
+            for (i, v) in vals.iter().enumerate() {
+                flatten(&format!("{}.{}", prefix, i), v, out);
+            }
+        }
+        v => out.push((prefix.to_string(), v.clone())),
+    }
+}
+
+fn to_f64(val: &Value) -> Option<f64> {
+    match val {
+        Value::Number(v) => v.as_f64(),
+        Value::Bool(v) => Some(*v as u32 as f64),
+        _ => None,
+    }
+}
+
+fn format_num(v: f64) -> String {
+    if v.fract() == 0.0 && v.abs() < 1e15 {
+        format!("{}", v as i64)
+    } else {
+        format!("{:.4}", v)
+    }
+}
+
+fn format_val(val: &Value) -> String {
+    match val {
+        Value::String(v) => v.clone(),
+        Value::Null => "-".into(),
+        v => match to_f64(v) {
+            Some(v) => format_num(v),
+            None => v.to_string(),
+        },
+    }
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[derive(Default)]
+struct Columns {
+    names: Vec<String>,
+    idx: HashMap<String, usize>,
+}
+
+impl Columns {
+    fn index(&mut self, name: &str) -> usize {
+        if let Some(idx) = self.idx.get(name) {
+            return *idx;
+        }
+        self.names.push(name.to_string());
+        self.idx.insert(name.to_string(), self.names.len() - 1);
+        self.names.len() - 1
+    }
+}
+
+fn print_table(header: &[String], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in rows.iter() {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.len());
+        }
+    }
+    let line = |row: &[String]| {
+        let mut buf = String::new();
+        for (i, cell) in row.iter().enumerate() {
+            if i == 0 {
+                buf += &format!("{:<w$}", cell, w = widths[i]);
+            } else {
+                buf += &format!("  {:>w$}", cell, w = widths[i]);
+            }
+        }
+        println!("{}", buf.trim_end());
+    };
+    line(header);
+    for row in rows.iter() {
+        line(row);
+    }
+}
+
+fn output(format: ReportQueryFormat, header: Vec<String>, rows: Vec<Vec<String>>) {
+    match format {
+        ReportQueryFormat::Table => print_table(&header, &rows),
+        ReportQueryFormat::Csv => {
+            for row in Some(&header).into_iter().chain(rows.iter()) {
+                let row: Vec<String> = row.iter().map(|x| csv_escape(x)).collect();
+                println!("{}", row.join(","));
+            }
+        }
+        ReportQueryFormat::Json => unreachable!(),
+    }
+}
+
+struct Query {
+    args: ReportQueryArgs,
+    aggrs: Vec<(String, Aggr)>,
+    fields: Vec<String>,
+    step: u64,
+}
+
+impl Query {
+    fn new(args: &ReportQueryArgs) -> Result<Self> {
+        if args.fields.is_empty() {
+            bail!("no field specified");
+        }
+        let mut aggrs = vec![];
+        for name in args.aggrs.iter() {
+            aggrs.push((name.clone(), Aggr::parse(name)?));
+        }
+        if args.interval.is_some() && aggrs.is_empty() {
+            bail!("--interval requires --aggr");
+        }
+        Ok(Self {
+            args: args.clone(),
+            aggrs,
+            fields: args.fields.clone(),
+            step: if args.per_min { 60 } else { 1 },
+        })
+    }
+
+    /// Returns the flattened leaves of the selected fields.
+    fn extract(&self, rep: &Value, found: &mut [bool]) -> Vec<(String, Value)> {
+        let mut leaves = vec![];
+        for (i, field) in self.fields.iter().enumerate() {
+            if let Some(val) = json_select(rep, field) {
+                flatten(field, val, &mut leaves);
+                found[i] = true;
+            }
+        }
+        leaves
+    }
+
+    fn run(&self, dir: &str, period: (u64, u64)) -> Result<()> {
+        let mut samples: Vec<(u64, Vec<(String, Value)>)> = vec![];
+        let mut found = vec![false; self.fields.len()];
+        let start = period.0 + (self.step - period.0 % self.step) % self.step;
+        for (rep, at) in ReportIter::new(dir, (start, period.1 + 1)).with_step(self.step) {
+            let rep = match rep {
+                Ok(v) => v,
+                Err(e) => {
+                    match e.downcast_ref::<io::Error>() {
+                        Some(ie) if ie.raw_os_error() == Some(libc::ENOENT) => {}
+                        _ => warn!("report: Failed to load report at {} ({:#})", at, &e),
+                    }
+                    continue;
+                }
+            };
+            samples.push((at, self.extract(&serde_json::to_value(&rep)?, &mut found)));
+        }
+
+        if samples.is_empty() {
+            bail!("no report found in {}", format_period(period));
+        }
+        for (field, found) in self.fields.iter().zip(found.iter()) {
+            if !found {
+                warn!("report: {:?} not found in any report", field);
+            }
+        }
+
+        if self.aggrs.is_empty() {
+            self.output_samples(samples);
+        } else {
+            self.output_aggrs(samples, period);
+        }
+        Ok(())
+    }
+
+    fn output_samples(&self, samples: Vec<(u64, Vec<(String, Value)>)>) {
+        let mut cols = Columns::default();
+        let mut rows = vec![];
+        for (at, leaves) in samples.into_iter() {
+            let mut row = vec![None; cols.names.len()];
+            for (name, val) in leaves.into_iter() {
+                let idx = cols.index(&name);
+                if idx >= row.len() {
+                    row.resize(idx + 1, None);
+                }
+                row[idx] = Some(val);
+            }
+            rows.push((at, row));
+        }
+
+        if self.args.format == ReportQueryFormat::Json {
+            for (at, row) in rows.into_iter() {
+                let mut map = serde_json::Map::new();
+                map.insert("timestamp".into(), at.into());
+                for (name, val) in cols.names.iter().zip(row) {
+                    if let Some(val) = val {
+                        map.insert(name.clone(), val);
+                    }
+                }
+                println!("{}", Value::Object(map));
+            }
+            return;
+        }
+
+        let table = self.args.format == ReportQueryFormat::Table;
+        let mut header = vec![if table { "time" } else { "timestamp" }.to_string()];
+        header.extend(cols.names.iter().cloned());
+        let rows = rows
+            .into_iter()
+            .map(|(at, row)| {
+                let mut out = vec![if table {
+                    format_unix_time(at)
+                } else {
+                    at.to_string()
+                }];
+                out.extend((0..cols.names.len()).map(|i| match row.get(i) {
+                    Some(Some(val)) => format_val(val),
+                    _ => "".into(),
+                }));
+                out
+            })
+            .collect();
+        output(self.args.format, header, rows);
+    }
+
+    fn output_aggrs(&self, samples: Vec<(u64, Vec<(String, Value)>)>, period: (u64, u64)) {
+        // (window start, window end) -> column -> values
+        let mut cols = Columns::default();
+        let mut windows = BTreeMap::<(u64, u64), BTreeMap<usize, Vec<f64>>>::new();
+        for (at, leaves) in samples.into_iter() {
+            let win = match self.args.interval {
+                Some(intv) => {
+                    let intv = (intv.round() as u64).max(self.step);
+                    let start = period.0 + (at - period.0) / intv * intv;
+                    (start, (start + intv - 1).min(period.1))
+                }
+                None => period,
+            };
+            let win = windows.entry(win).or_default();
+            for (name, val) in leaves.iter() {
+                if let Some(v) = to_f64(val) {
+                    win.entry(cols.index(name)).or_default().push(v);
+                }
+            }
+        }
+
+        let mut rows = vec![];
+        for ((from, to), mut win) in windows.into_iter() {
+            for (idx, vals) in win.iter_mut() {
+                vals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let res: Vec<f64> = self.aggrs.iter().map(|(_, a)| a.calc(vals)).collect();
+                rows.push((from, to, &cols.names[*idx], res));
+            }
+        }
+
+        match self.args.format {
+            ReportQueryFormat::Json => {
+                for (from, to, name, res) in rows.into_iter() {
+                    let mut map = serde_json::Map::new();
+                    map.insert("since".into(), from.into());
+                    map.insert("until".into(), to.into());
+                    map.insert("field".into(), name.clone().into());
+                    for ((aggr, _), v) in self.aggrs.iter().zip(res) {
+                        map.insert(aggr.clone(), v.into());
+                    }
+                    println!("{}", Value::Object(map));
+                }
+            }
+            format => {
+                let table = format == ReportQueryFormat::Table;
+                let mut header: Vec<String> = if table {
+                    vec!["period".into(), "field".into()]
+                } else {
+                    vec!["since".into(), "until".into(), "field".into()]
+                };
+                header.extend(self.aggrs.iter().map(|(name, _)| name.clone()));
+                let rows = rows
+                    .into_iter()
+                    .map(|(from, to, name, res)| {
+                        let mut out = if table {
+                            vec![
+                                format!("{} - {}", format_unix_time(from), format_unix_time(to)),
+                                name.clone(),
+                            ]
+                        } else {
+                            vec![from.to_string(), to.to_string(), name.clone()]
+                        };
+                        out.extend(res.into_iter().map(format_num));
+                        out
+                    })
+                    .collect();
+                output(format, header, rows);
+            }
+        }
+    }
+}
+
+pub fn query_main(top_dir: &str, args: &ReportQueryArgs) {
+    let run = || -> Result<()> {
+        let query = Query::new(args)?;
+        let now = unix_now();
+        let period = (parse_time(&args.since, now)?, parse_time(&args.until, now)?);
+        if period.0 > period.1 {
+            bail!("empty range {}", format_period(period));
+        }
+        let dir = if args.per_min {
+            format!("{}/report-1min.d", top_dir)
+        } else {
+            format!("{}/report.d", top_dir)
+        };
+        query.run(&dir, period)
+    };
+
+    if let Err(e) = run() {
+        error!("report: {:#}", &e);
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_and_aggr() {
+        let val = serde_json::json!({
+            "usages": {
+                "workload.slice": { "mem_pressures": [0.5, 0.25] },
+                "workload": { "slice": 1 },
+            },
+        });
+        assert_eq!(
+            json_select(&val, "usages.workload.slice.mem_pressures.1"),
+            Some(&serde_json::json!(0.25))
+        );
+
+        let mut leaves = vec![];
+        flatten(
+            "x",
+            json_select(&val, "usages.workload.slice").unwrap(),
+            &mut leaves,
+        );
+        let names: Vec<&str> = leaves.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, vec!["x.mem_pressures.0", "x.mem_pressures.1"]);
+
+        let sorted = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(Aggr::parse("mean").unwrap().calc(&sorted), 3.0);
+        assert_eq!(Aggr::parse("p50").unwrap().calc(&sorted), 3.0);
+        assert_eq!(Aggr::parse("p75").unwrap().calc(&sorted), 4.0);
+        assert_eq!(Aggr::parse("p87.5").unwrap().calc(&sorted), 4.5);
+        assert_eq!(Aggr::parse("max").unwrap().calc(&sorted), 5.0);
+        assert!(Aggr::parse("p101").is_err());
+    }
+}
@@ -1,7 +1,7 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use anyhow::{anyhow, bail, Result};
 use chrono::prelude::*;
-use crossbeam::channel::{self, select, Receiver, Sender};
+use crossbeam::channel::{self, Receiver, RecvError, Select, Sender};
 use enum_iterator::IntoEnumIterator;
 use log::{debug, error, info, trace, warn};
 use nix::sys::signal::{kill, Signal};
@@ -19,10 +19,13 @@ use std::thread::{spawn, JoinHandle};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use super::cmd::Runner;
-use super::Config;
+use super::iolat::BlockStatLat;
+use super::{Config, IoDev};
 use rd_agent_intf::{
-    report::StatMap, BenchHashdReport, BenchIoCostReport, HashdReport, IoCostReport, IoLatReport,
-    Report, ResCtlReport, Slice, UsageReport, ROOT_SLICE,
+    report::StatMap, report_store::seg_start, BenchHashdReport, BenchIoCostReport,
+    CgroupEventsReport, CpusetReport, HashdReport, IoCostReport, IoDevReport, IoLatReport,
+    IoLatSource, NumaNodeReport, PsiAvgs, PsiReport, Report, ReportStore, ResCtlReport, Slice,
+    SliceKnobs, SvcReport, UsageReport, HASHD_A, ROOT_SLICE,
 };
 use rd_util::*;
 
@@ -36,34 +39,61 @@ struct Usage {
     io_rbytes: u64,
     io_wbytes: u64,
     io_usage: u64,
+    io_lat_use_delay: u64,
+    io_lat_delay_nsec: u64,
+    io_lat_avg_usec: u64,
     cpu_stalls: (f64, f64),
     mem_stalls: (f64, f64),
     io_stalls: (f64, f64),
+    irq_stalls: (f64, f64),
+    cpu_psi: PsiReport,
+    mem_psi: PsiReport,
+    io_psi: PsiReport,
+    irq_psi: PsiReport,
+    events: CgroupEventsReport,
     mem_stat: StatMap,
-    io_stat: StatMap,
+    numa_stat: BTreeMap<u32, StatMap>,
+    io_stats: BTreeMap<String, StatMap>,
 }
 
-fn read_stalls(path: &str) -> Result<(f64, f64)> {
+/// Returns the cumulative some and full stall seconds and the kernel's
+/// running averages.
+fn read_stalls(path: &str) -> Result<((f64, f64), PsiReport)> {
     let f = fs::OpenOptions::new().read(true).open(path)?;
     let r = BufReader::new(f);
     let (mut some, mut full) = (None, None);
+    let mut psi = PsiReport::default();
 
     for line in r.lines().filter_map(|x| x.ok()) {
-        if let Ok((which, v)) = scan_fmt!(
+        if let Ok((which, avg10, avg60, avg300, v)) = scan_fmt!(
             &line,
-            "{} avg10={*f} avg60={*f} avg300={*f} total={d}",
+            "{} avg10={f} avg60={f} avg300={f} total={d}",
             String,
+            f64,
+            f64,
+            f64,
             u64
         ) {
-            match (which.as_ref(), v) {
-                ("some", v) => some = Some(v as f64 / 1_000_000.0),
-                ("full", v) => full = Some(v as f64 / 1_000_000.0),
+            let avgs = PsiAvgs {
+                avg10: avg10 / 100.0,
+                avg60: avg60 / 100.0,
+                avg300: avg300 / 100.0,
+            };
+            match which.as_ref() {
+                "some" => {
+                    some = Some(v as f64 / 1_000_000.0);
+                    psi.some = avgs;
+                }
+                "full" => {
+                    full = Some(v as f64 / 1_000_000.0);
+                    psi.full = avgs;
+                }
                 _ => {}
             }
         }
     }
 
-    Ok((some.unwrap_or(0.0), full.unwrap_or(0.0)))
+    Ok(((some.unwrap_or(0.0), full.unwrap_or(0.0)), psi))
 }
 
 fn read_stat_file(path: &str) -> Result<StatMap> {
@@ -71,7 +101,60 @@ fn read_stat_file(path: &str) -> Result<StatMap> {
     Ok(map.iter().map(|(k, v)| (k.clone(), *v as f64)).collect())
 }
 
-fn read_system_usage(devnr: (u32, u32)) -> Result<(Usage, f64)> {
+/// memory.numa_stat is keyed by stat with per-node values, e.g. "anon
+/// N0=4096 N1=0". Turn it around so that it's keyed by node.
+fn read_numa_stat(path: &str) -> Result<BTreeMap<u32, StatMap>> {
+    let mut numa_stat = BTreeMap::<u32, StatMap>::new();
+    for (key, nodes) in read_cgroup_nested_keyed_file(path)?.into_iter() {
+        for (node, v) in nodes.into_iter() {
+            if let (Ok(node), Ok(v)) = (scan_fmt!(&node, "N{d}", u32), v.parse::<f64>()) {
+                numa_stat.entry(node).or_default().insert(key.clone(), v);
+            }
+        }
+    }
+    Ok(numa_stat)
+}
+
+fn read_numa_nodes() -> BTreeMap<u32, NumaNodeReport> {
+    let mut reps = BTreeMap::new();
+    let nodes = match numa::online_nodes() {
+        Ok(v) => v,
+        Err(e) => {
+            debug!("report: Failed to read online NUMA nodes ({:?})", &e);
+            return reps;
+        }
+    };
+    for node in nodes.into_iter() {
+        match numa::read_node_meminfo(node) {
+            Ok(mi) => {
+                reps.insert(
+                    node,
+                    NumaNodeReport {
+                        mem_total: mi.total,
+                        mem_free: mi.free,
+                        mem_used: mi.used,
+                    },
+                );
+            }
+            Err(e) => debug!("report: Failed to read NUMA node {} ({:?})", node, &e),
+        }
+    }
+    reps
+}
+
+fn io_stat_to_stat_map(is: HashMap<String, String>) -> StatMap {
+    is.into_iter()
+        .map(|(k, v)| (k, v.parse::<f64>().unwrap_or(0.0)))
+        .collect()
+}
+
+fn read_io_stat_u64(is: &HashMap<String, String>, key: &str) -> u64 {
+    is.get(key)
+        .and_then(|v| scan_fmt!(v, "{}", u64).ok())
+        .unwrap_or(0)
+}
+
+fn read_system_usage(devs: &[IoDev]) -> Result<(Usage, f64)> {
     let kstat = procfs::KernelStats::new()?;
     let cpu = &kstat.total;
     let mut cpu_total = cpu.user as f64
@@ -95,10 +178,13 @@ fn read_system_usage(devnr: (u32, u32)) -> Result<(Usage, f64)> {
     let mem_bytes = mstat.mem_total - mstat.mem_free;
     let swap_bytes = mstat.swap_total - mstat.swap_free;
 
+    // The io usage fields cover only the scratch device, devs[0]. The
+    // per-device numbers are reported through io_stats.
+    let scr_devnr = devs[0].devnr;
     let mut io_rbytes = 0;
     let mut io_wbytes = 0;
     for dstat in linux_proc::diskstats::DiskStats::from_system()?.iter() {
-        if dstat.major == devnr.0 as u64 && dstat.minor == devnr.1 as u64 {
+        if dstat.major == scr_devnr.0 as u64 && dstat.minor == scr_devnr.1 as u64 {
             io_rbytes = dstat.sectors_read * 512;
             io_wbytes = dstat.sectors_written * 512;
         }
@@ -113,20 +199,27 @@ fn read_system_usage(devnr: (u32, u32)) -> Result<(Usage, f64)> {
         }
     };
 
+    let numa_stat = read_numa_stat("/sys/fs/cgroup/memory.numa_stat").unwrap_or_default();
+
     let mut io_usage = 0;
-    let mut io_stat = Default::default();
+    let mut io_stats = BTreeMap::new();
     if let Ok(mut is) = read_cgroup_nested_keyed_file("/sys/fs/cgroup/io.stat") {
-        if let Some(is) = is.remove(&format!("{}:{}", devnr.0, devnr.1)) {
-            if let Some(val) = is.get("cost.usage") {
-                io_usage = scan_fmt!(&val, "{}", u64).unwrap_or(0);
+        for (idx, dev) in devs.iter().enumerate() {
+            if let Some(is) = is.remove(&dev.devnr_str()) {
+                if idx == 0 {
+                    io_usage = read_io_stat_u64(&is, "cost.usage");
+                }
+                io_stats.insert(dev.devnr_str(), io_stat_to_stat_map(is));
             }
-            io_stat = is
-                .into_iter()
-                .map(|(k, v)| (k, v.parse::<f64>().unwrap_or(0.0)))
-                .collect();
         }
     }
 
+    let (cpu_stalls, cpu_psi) = read_stalls("/proc/pressure/cpu")?;
+    let (mem_stalls, mem_psi) = read_stalls("/proc/pressure/memory")?;
+    let (io_stalls, io_psi) = read_stalls("/proc/pressure/io")?;
+    // irq pressure needs CONFIG_IRQ_TIME_ACCOUNTING and a recent kernel
+    let (irq_stalls, irq_psi) = read_stalls("/proc/pressure/irq").unwrap_or_default();
+
     Ok((
         Usage {
             cpu_busy,
@@ -138,10 +231,17 @@ fn read_system_usage(devnr: (u32, u32)) -> Result<(Usage, f64)> {
             io_wbytes,
             io_usage,
             mem_stat,
-            io_stat,
-            cpu_stalls: read_stalls("/proc/pressure/cpu")?,
-            mem_stalls: read_stalls("/proc/pressure/memory")?,
-            io_stalls: read_stalls("/proc/pressure/io")?,
+            numa_stat,
+            io_stats,
+            cpu_stalls,
+            mem_stalls,
+            io_stalls,
+            irq_stalls,
+            cpu_psi,
+            mem_psi,
+            io_psi,
+            irq_psi,
+            ..Default::default()
         },
         cpu_total,
     ))
@@ -178,7 +278,7 @@ fn read_swap_free(cgrp: &str) -> Result<u64> {
     Ok(free)
 }
 
-fn read_cgroup_usage(cgrp: &str, devnr: (u32, u32)) -> Usage {
+fn read_cgroup_usage(cgrp: &str, devs: &[IoDev]) -> Usage {
     let mut usage: Usage = Default::default();
 
     if let Ok(cs) = read_cgroup_flat_keyed_file(&(cgrp.to_string() + "/cpu.stat")) {
@@ -188,6 +288,30 @@ fn read_cgroup_usage(cgrp: &str, devnr: (u32, u32)) -> Usage {
         if let Some(v) = cs.get("system_usec") {
             usage.cpu_sys = *v as f64 / 1_000_000.0;
         }
+        let ev = &mut usage.events;
+        let get = |key: &str| cs.get(key).cloned().unwrap_or(0) as f64;
+        ev.cpu_nr_periods = get("nr_periods");
+        ev.cpu_nr_throttled = get("nr_throttled");
+        ev.cpu_throttled = get("throttled_usec") / 1_000_000.0;
+    }
+
+    if let Ok(me) = read_cgroup_flat_keyed_file(&(cgrp.to_string() + "/memory.events")) {
+        let ev = &mut usage.events;
+        let get = |key: &str| me.get(key).cloned().unwrap_or(0) as f64;
+        ev.mem_low = get("low");
+        ev.mem_high = get("high");
+        ev.mem_max = get("max");
+        ev.mem_oom = get("oom");
+        ev.mem_oom_kill = get("oom_kill");
+        ev.mem_oom_group_kill = get("oom_group_kill");
+    }
+
+    if let Ok(se) = read_cgroup_flat_keyed_file(&(cgrp.to_string() + "/memory.swap.events")) {
+        let ev = &mut usage.events;
+        let get = |key: &str| se.get(key).cloned().unwrap_or(0) as f64;
+        ev.swap_high = get("high");
+        ev.swap_max = get("max");
+        ev.swap_fail = get("fail");
     }
 
     if let Ok(line) = read_one_line(&(cgrp.to_string() + "/memory.current")) {
@@ -215,39 +339,103 @@ fn read_cgroup_usage(cgrp: &str, devnr: (u32, u32)) -> Usage {
         }
     };
 
+    let numa_stat_path = cgrp.to_string() + "/memory.numa_stat";
+    usage.numa_stat = match read_numa_stat(&numa_stat_path) {
+        Ok(v) => v,
+        Err(e) => {
+            debug!("report: Failed to read {} ({:?})", &numa_stat_path, &e);
+            Default::default()
+        }
+    };
+
     if let Ok(mut is) = read_cgroup_nested_keyed_file(&(cgrp.to_string() + "/io.stat")) {
-        if let Some(is) = is.remove(&format!("{}:{}", devnr.0, devnr.1)) {
-            if let Some(val) = is.get("rbytes") {
-                usage.io_rbytes = scan_fmt!(&val, "{}", u64).unwrap_or(0);
-            }
-            if let Some(val) = is.get("wbytes") {
-                usage.io_wbytes = scan_fmt!(&val, "{}", u64).unwrap_or(0);
-            }
-            if let Some(val) = is.get("cost.usage") {
-                usage.io_usage = scan_fmt!(&val, "{}", u64).unwrap_or(0);
+        for (idx, dev) in devs.iter().enumerate() {
+            let is = match is.remove(&dev.devnr_str()) {
+                Some(v) => v,
+                None => continue,
+            };
+            // The io usage fields cover only the scratch device. io.latency
+            // is only configured there too.
+            if idx == 0 {
+                usage.io_rbytes = read_io_stat_u64(&is, "rbytes");
+                usage.io_wbytes = read_io_stat_u64(&is, "wbytes");
+                usage.io_usage = read_io_stat_u64(&is, "cost.usage");
+                usage.io_lat_use_delay = read_io_stat_u64(&is, "use_delay");
+                usage.io_lat_delay_nsec = read_io_stat_u64(&is, "delay_nsec");
+                usage.io_lat_avg_usec = read_io_stat_u64(&is, "avg_lat");
             }
-            usage.io_stat = is
-                .into_iter()
-                .map(|(k, v)| (k, v.parse::<f64>().unwrap_or(0.0)))
-                .collect();
+            usage
+                .io_stats
+                .insert(dev.devnr_str(), io_stat_to_stat_map(is));
         }
     }
 
-    if let Ok(v) = read_stalls(&(cgrp.to_string() + "/cpu.pressure")) {
-        usage.cpu_stalls = v;
+    if let Ok((stalls, psi)) = read_stalls(&(cgrp.to_string() + "/cpu.pressure")) {
+        usage.cpu_stalls = stalls;
+        usage.cpu_psi = psi;
+    }
+    if let Ok((stalls, psi)) = read_stalls(&(cgrp.to_string() + "/memory.pressure")) {
+        usage.mem_stalls = stalls;
+        usage.mem_psi = psi;
     }
-    if let Ok(v) = read_stalls(&(cgrp.to_string() + "/memory.pressure")) {
-        usage.mem_stalls = v;
+    if let Ok((stalls, psi)) = read_stalls(&(cgrp.to_string() + "/io.pressure")) {
+        usage.io_stalls = stalls;
+        usage.io_psi = psi;
     }
-    if let Ok(v) = read_stalls(&(cgrp.to_string() + "/io.pressure")) {
-        usage.io_stalls = v;
+    if let Ok((stalls, psi)) = read_stalls(&(cgrp.to_string() + "/irq.pressure")) {
+        usage.irq_stalls = stalls;
+        usage.irq_psi = psi;
     }
 
     usage
 }
 
+fn read_cpusets(knobs: &SliceKnobs) -> BTreeMap<String, CpusetReport> {
+    let mut cgrps = vec![(ROOT_SLICE.to_string(), "/sys/fs/cgroup".to_string())];
+    for slice in knobs.managed_slices() {
+        cgrps.push((slice.name().to_string(), slice.cgrp().to_string()));
+    }
+
+    let mut cpusets = BTreeMap::new();
+    for (name, cgrp) in cgrps.into_iter() {
+        let cpus = match read_one_line(&(cgrp.clone() + "/cpuset.cpus.effective")) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        cpusets.insert(
+            name,
+            CpusetReport {
+                cpus,
+                mems: read_one_line(&(cgrp.clone() + "/cpuset.mems.effective")).unwrap_or_default(),
+                partition: read_one_line(&(cgrp + "/cpuset.cpus.partition")).unwrap_or_default(),
+            },
+        );
+    }
+    cpusets
+}
+
+/// Services under hostcritical.slice, e.g. oomd, sideloader and rd-agent.
+fn read_host_svcs(host_cgrp: &str) -> Vec<(String, String)> {
+    let dir = match fs::read_dir(host_cgrp) {
+        Ok(v) => v,
+        Err(e) => {
+            trace!("report: Failed to read {:?} ({:?})", host_cgrp, &e);
+            return Vec::new();
+        }
+    };
+    dir.filter_map(|x| x.ok())
+        .filter(|x| x.path().is_dir())
+        .filter_map(|x| x.file_name().into_string().ok())
+        .filter(|name| name.ends_with(".service"))
+        .map(|name| {
+            let cgrp = format!("{}/{}", host_cgrp, &name);
+            (name, cgrp)
+        })
+        .collect()
+}
+
 pub struct UsageTracker {
-    devnr: (u32, u32),
+    devs: Vec<IoDev>,
     at: Instant,
     cpu_total: f64,
     usages: HashMap<String, Usage>,
@@ -255,9 +443,9 @@ pub struct UsageTracker {
 }
 
 impl UsageTracker {
-    fn new(devnr: (u32, u32), runner: Runner) -> Self {
+    fn new(devs: Vec<IoDev>, runner: Runner) -> Self {
         let mut us = Self {
-            devnr,
+            devs,
             at: Instant::now(),
             cpu_total: 0.0,
             usages: HashMap::new(),
@@ -278,18 +466,24 @@ impl UsageTracker {
     fn read_usages(&self) -> Result<(HashMap<String, Usage>, f64)> {
         let mut usages = HashMap::new();
 
-        let (us, cpu_total) = read_system_usage(self.devnr)?;
+        let (us, cpu_total) = read_system_usage(&self.devs)?;
         usages.insert(ROOT_SLICE.into(), us);
-        for slice in Slice::into_enum_iter() {
+        let (slices, all_svcs) = {
+            let data = self.runner.data.lock().unwrap();
+            (data.sobjs.slice_file.data.managed_slices(), data.all_svcs())
+        };
+        for slice in slices.iter() {
             usages.insert(
                 slice.name().to_string(),
-                read_cgroup_usage(slice.cgrp(), self.devnr),
+                read_cgroup_usage(slice.cgrp(), &self.devs),
             );
         }
 
-        let all_svcs = self.runner.data.lock().unwrap().all_svcs();
-        for (svc, cgrp) in all_svcs.into_iter() {
-            usages.insert(svc, read_cgroup_usage(&cgrp, self.devnr));
+        for (svc, cgrp) in all_svcs
+            .into_iter()
+            .chain(read_host_svcs(Slice::Host.cgrp()))
+        {
+            usages.insert(svc, read_cgroup_usage(&cgrp, &self.devs));
         }
         Ok((usages, cpu_total))
     }
@@ -324,6 +518,14 @@ impl UsageTracker {
             rep.swap_free = cur.swap_free;
             rep.io_rbytes = cur.io_rbytes;
             rep.io_wbytes = cur.io_wbytes;
+            rep.io_lat_use_delay = cur.io_lat_use_delay as f64;
+            rep.io_lat_avg = cur.io_lat_avg_usec as f64 / 1_000_000.0;
+            rep.cpu_psi = cur.cpu_psi;
+            rep.mem_psi = cur.mem_psi;
+            rep.io_psi = cur.io_psi;
+            rep.irq_psi = cur.irq_psi;
+            rep.events = cur.events;
+            rep.events_delta = cur.events.delta(&last.events);
 
             if dur > 0.0 {
                 if cur.io_rbytes >= last.io_rbytes {
@@ -334,9 +536,16 @@ impl UsageTracker {
                 }
                 rep.io_util = (cur.io_usage - last.io_usage) as f64 / 1_000_000.0 / dur;
                 rep.io_usage = cur.io_usage as f64 / 1_000_000.0;
+                if cur.io_lat_delay_nsec >= last.io_lat_delay_nsec {
+                    rep.io_lat_delay = ((cur.io_lat_delay_nsec - last.io_lat_delay_nsec) as f64
+                        / 1_000_000_000.0
+                        / dur)
+                        .min(1.0);
+                }
                 rep.cpu_stalls = cur.cpu_stalls;
                 rep.mem_stalls = cur.mem_stalls;
                 rep.io_stalls = cur.io_stalls;
+                rep.irq_stalls = cur.irq_stalls;
                 rep.cpu_pressures = (
                     ((cur.cpu_stalls.0 - last.cpu_stalls.0) / dur)
                         .min(1.0)
@@ -361,6 +570,10 @@ impl UsageTracker {
                         .min(1.0)
                         .max(0.0),
                 );
+                rep.irq_pressures = (
+                    ((cur.irq_stalls.0 - last.irq_stalls.0) / dur).clamp(0.0, 1.0),
+                    ((cur.irq_stalls.1 - last.irq_stalls.1) / dur).clamp(0.0, 1.0),
+                );
             }
 
             reps.insert(unit.into(), rep);
@@ -379,18 +592,24 @@ struct ReportFile {
     retention: Option<u64>,
     path: String,
     d_path: String,
+    store: Option<ReportStore>,
     next_at: u64,
     usage_tracker: UsageTracker,
-    hashd_acc: [HashdReport; 2],
+    hashd_acc: BTreeMap<String, HashdReport>,
     mem_stat_acc: BTreeMap<String, StatMap>,
     io_stat_acc: BTreeMap<String, StatMap>,
     vmstat_acc: StatMap,
     iolat_acc: IoLatReport,
     iocost_acc: IoCostReport,
+    io_devs_acc: BTreeMap<String, IoDevReport>,
     nr_samples: u32,
 }
 
 pub fn clear_old_report_files(d_path: &str, retention: Option<u64>, now: u64) -> Result<()> {
+    if let Some(retention) = retention {
+        ReportStore::expire(d_path, now.saturating_sub(retention))?;
+    }
+
     for path in fs::read_dir(d_path)?
         .filter_map(|x| x.ok())
         .map(|x| x.path())
@@ -408,7 +627,7 @@ pub fn clear_old_report_files(d_path: &str, retention: Option<u64>, now: u64) ->
             Ok(v) => v,
             Err(_) => continue,
         };
-        if stamp < now - retention.unwrap() {
+        if stamp < now.saturating_sub(retention.unwrap()) {
             if let Err(e) = fs::remove_file(&path) {
                 warn!(
                     "report: Failed to remove stale report {:?} ({:?})",
@@ -428,7 +647,8 @@ impl ReportFile {
         retention: Option<u64>,
         path: &str,
         d_path: &str,
-        devnr: (u32, u32),
+        store: bool,
+        devs: Vec<IoDev>,
         runner: Runner,
     ) -> ReportFile {
         let now = unix_now();
@@ -438,14 +658,20 @@ impl ReportFile {
             retention,
             path: path.into(),
             d_path: d_path.into(),
+            store: if store {
+                Some(ReportStore::new(d_path))
+            } else {
+                None
+            },
             next_at: ((now / intv) + 1) * intv,
-            usage_tracker: UsageTracker::new(devnr, runner),
+            usage_tracker: UsageTracker::new(devs, runner),
             hashd_acc: Default::default(),
             mem_stat_acc: Default::default(),
             io_stat_acc: Default::default(),
             vmstat_acc: Default::default(),
             iolat_acc: Default::default(),
             iocost_acc: Default::default(),
+            io_devs_acc: Default::default(),
             nr_samples: 0,
         };
 
@@ -489,38 +715,51 @@ impl ReportFile {
         }
     }
 
-    fn tick(&mut self, base_report: &Report, now: u64) {
-        for i in 0..2 {
-            self.hashd_acc[i] += &base_report.hashd[i];
+    fn tick(&mut self, base_report: &Report, now: u64) -> Option<Report> {
+        for (name, rep) in base_report.hashd.iter() {
+            *self.hashd_acc.entry(name.clone()).or_default() += rep;
         }
         Self::acc_slice_stat_map(&mut self.mem_stat_acc, &base_report.mem_stat);
         Self::acc_slice_stat_map(&mut self.io_stat_acc, &base_report.io_stat);
         Self::acc_stat_map(&mut self.vmstat_acc, &base_report.vmstat);
         self.iolat_acc.accumulate(&base_report.iolat);
         self.iocost_acc += &base_report.iocost;
+        for (name, rep) in base_report.io_devs.iter() {
+            let acc = self.io_devs_acc.entry(name.clone()).or_default();
+            acc.iolat.accumulate(&rep.iolat);
+            acc.iocost += &rep.iocost;
+        }
         self.nr_samples += 1;
 
         if now < self.next_at {
-            return;
+            return None;
         }
 
         trace!("report: Reporting {}s summary at {}", self.intv, now);
         let was_at = self.next_at - self.intv;
         self.next_at = (now / self.intv + 1) * self.intv;
 
-        // fill in report
-        let report_path = format!("{}/{}.json", &self.d_path, now / self.intv * self.intv);
+        // fill in report, with the segment store only the current one is
+        // written out as a file
+        let at = now / self.intv * self.intv;
+        let report_path = if self.store.is_some() {
+            self.path.clone()
+        } else {
+            format!("{}/{}.json", &self.d_path, at)
+        };
         let mut report_file = JsonReportFile::<Report>::new(Some(&report_path));
         report_file.data = base_report.clone();
         let report = &mut report_file.data;
 
-        for i in 0..2 {
-            self.hashd_acc[i] /= self.nr_samples;
-            report.hashd[i] = HashdReport {
-                svc: report.hashd[i].svc.clone(),
-                phase: report.hashd[i].phase,
-                ..self.hashd_acc[i].clone()
-            };
+        for (name, rep) in report.hashd.iter_mut() {
+            if let Some(acc) = self.hashd_acc.get_mut(name) {
+                *acc /= self.nr_samples;
+                *rep = HashdReport {
+                    svc: rep.svc.clone(),
+                    phase: rep.phase,
+                    ..acc.clone()
+                };
+            }
         }
         self.hashd_acc = Default::default();
 
@@ -543,51 +782,103 @@ impl ReportFile {
         report.iocost = self.iocost_acc.clone();
         self.iocost_acc = Default::default();
 
+        for (name, rep) in report.io_devs.iter_mut() {
+            if let Some(acc) = self.io_devs_acc.get_mut(name) {
+                acc.iocost /= self.nr_samples;
+                rep.iolat = acc.iolat.clone();
+                rep.iocost = acc.iocost.clone();
+            }
+        }
+        self.io_devs_acc.clear();
+
         self.nr_samples = 0;
 
+        // The usages aren't averaged over the per-second samples. The
+        // interval's own tracker diffs against its previous update, so
+        // events_delta is the sum of the events over the whole interval.
         report.usages = match self.usage_tracker.update() {
             Ok(v) => v,
             Err(e) => {
                 warn!("report: Failed to update {}s usages ({:?})", self.intv, &e);
-                return;
+                return None;
             }
         };
 
+        // point each service at its usages{} entry so that interference
+        // can be traced back to the culprit
+        let usages = &report.usages;
+        let usage_key = |svc: &SvcReport| match usages.contains_key(&svc.name) {
+            true => svc.name.clone(),
+            false => String::new(),
+        };
+        for rep in report.hashd.values_mut() {
+            rep.usage_key = usage_key(&rep.svc);
+        }
+        for rep in report.sysloads.values_mut() {
+            rep.usage_key = usage_key(&rep.svc);
+        }
+        for rep in report.sideloads.values_mut() {
+            rep.usage_key = usage_key(&rep.svc);
+        }
+
+        let scr_devnr = self.usage_tracker.devs[0].devnr_str();
         for slice in &[ROOT_SLICE, Slice::Work.name(), Slice::Sys.name()] {
             if let Some(usage) = self.usage_tracker.usages.get(&slice.to_string()) {
                 report
                     .mem_stat
                     .insert(slice.to_string(), usage.mem_stat.clone());
+                report.io_stat.insert(
+                    slice.to_string(),
+                    usage.io_stats.get(&scr_devnr).cloned().unwrap_or_default(),
+                );
+                for dev_rep in report.io_devs.values_mut() {
+                    if let Some(is) = usage.io_stats.get(&dev_rep.devnr) {
+                        dev_rep.io_stat.insert(slice.to_string(), is.clone());
+                    }
+                }
+            }
+        }
+
+        let numa_slices = Slice::into_enum_iter().map(|slice| slice.name());
+        for slice in std::iter::once(ROOT_SLICE).chain(numa_slices) {
+            if let Some(usage) = self.usage_tracker.usages.get(slice) {
                 report
-                    .io_stat
-                    .insert(slice.to_string(), usage.io_stat.clone());
+                    .numa_stat
+                    .insert(slice.to_string(), usage.numa_stat.clone());
             }
         }
+        report.numa = read_numa_nodes();
 
         match read_stat_file("/proc/vmstat") {
             Ok(map) => report.vmstat = map,
             Err(e) => warn!("report: Failed to read vmstat ({:?})", &e),
         }
 
-        // write out to the unix timestamped file
+        // write out to the unix timestamped or current report file
         if let Err(e) = report_file.commit() {
             warn!("report: Failed to write {}s summary ({:?})", self.intv, &e);
         }
 
-        // symlink the current report file
-        let staging_path = format!("{}.staging", &self.path);
-        let _ = fs::remove_file(&staging_path);
-        if let Err(e) = symlink(&report_path, &staging_path) {
-            warn!(
-                "report: Failed to symlink {:?} to {:?} ({:?})",
-                &report_path, &staging_path, &e
-            );
-        }
-        if let Err(e) = fs::rename(&staging_path, &self.path) {
-            warn!(
-                "report: Failed to move {:?} to {:?} ({:?})",
-                &staging_path, &self.path, &e
-            );
+        if let Some(store) = self.store.as_mut() {
+            if let Err(e) = store.append(at, &report_file.data) {
+                warn!("report: Failed to store {}s summary ({:#})", self.intv, &e);
+            }
+        } else {
+            // symlink the current report file
+            let staging_path = format!("{}.staging", &self.path);
+            let _ = fs::remove_file(&staging_path);
+            if let Err(e) = symlink(&report_path, &staging_path) {
+                warn!(
+                    "report: Failed to symlink {:?} to {:?} ({:?})",
+                    &report_path, &staging_path, &e
+                );
+            }
+            if let Err(e) = fs::rename(&staging_path, &self.path) {
+                warn!(
+                    "report: Failed to move {:?} to {:?} ({:?})",
+                    &staging_path, &self.path, &e
+                );
+            }
         }
 
         // delete expired ones
@@ -597,7 +888,14 @@ impl ReportFile {
                 trace!("report: Removing expired {:?}", &path);
                 let _ = fs::remove_file(&path);
             }
+            if self.store.is_some() && seg_start(was_at) != seg_start(now) {
+                if let Err(e) = ReportStore::expire(&self.d_path, now.saturating_sub(retention)) {
+                    warn!("report: Failed to expire segments ({:#})", &e);
+                }
+            }
         }
+
+        Some(std::mem::take(&mut report_file.data))
     }
 }
 
@@ -606,10 +904,10 @@ struct IoLatReader {
     devnr: (u32, u32),
     name: String,
     intv: String,
-    tx: Option<Sender<String>>,
     rx: Option<Receiver<String>>,
     child: Option<Child>,
     jh: Option<JoinHandle<()>>,
+    blkstat: Option<BlockStatLat>,
 }
 
 impl IoLatReader {
@@ -656,26 +954,33 @@ impl IoLatReader {
             self.child = Some(child);
             self.jh = Some(jh);
         } else {
-            self.tx = Some(tx);
+            self.blkstat = Some(BlockStatLat::start(self.devnr, &self.name, &self.intv, tx)?);
         }
         Ok(())
     }
 
-    fn new(cfg: &Config, name: &str, intv: &str) -> Result<Self> {
+    fn new(cfg: &Config, devnr: (u32, u32), name: &str, intv: &str) -> Result<Self> {
         let mut iolat = Self {
             biolatpcts_bin: cfg.biolatpcts_bin.as_ref().map(|x| x.to_owned()),
-            devnr: cfg.scr_devnr,
+            devnr,
             name: name.to_owned(),
             intv: intv.to_owned(),
-            tx: None,
             rx: None,
             child: None,
             jh: None,
+            blkstat: None,
         };
         iolat.reset()?;
         Ok(iolat)
     }
 
+    fn source(&self) -> IoLatSource {
+        match self.biolatpcts_bin {
+            Some(_) => IoLatSource::Bpf,
+            None => IoLatSource::BlockStat,
+        }
+    }
+
     fn kick(&self) {
         if self.child.is_some() {
             kill(
@@ -684,11 +989,14 @@ impl IoLatReader {
             )
             .unwrap();
         }
+        if let Some(blkstat) = self.blkstat.as_ref() {
+            blkstat.kick();
+        }
     }
 
     fn disconnect(&mut self) {
-        self.tx.take();
         self.rx.take();
+        self.blkstat.take();
         if self.child.is_some() {
             let _ = self.child.as_mut().unwrap().kill();
             let _ = self.child.as_mut().unwrap().wait();
@@ -703,14 +1011,30 @@ impl Drop for IoLatReader {
     }
 }
 
+/// The per-second and cumulative iolat readers of a managed device.
+struct IoDevLatReaders {
+    iolat: IoLatReader,
+    iolat_cum: IoLatReader,
+    iolat_retries: u32,
+    iolat_cum_retries: u32,
+    iolat_cum_kicked_at: SystemTime,
+}
+
+enum ReportEvent {
+    IoLat(usize, Result<String, RecvError>),
+    IoLatCum(usize, Result<String, RecvError>),
+    Term(Result<(), RecvError>),
+    Timeout,
+}
+
 struct ReportWorker {
     runner: Runner,
     term_rx: Receiver<()>,
     report_file: ReportFile,
     report_file_1min: ReportFile,
-    iolat: IoLatReport,
-    iolat_cum: IoLatReport,
-    iocost_devnr: (u32, u32),
+    io_devs: Vec<IoDev>,
+    iolat: Vec<IoLatReport>,
+    iolat_cum: Vec<IoLatReport>,
 }
 
 impl ReportWorker {
@@ -719,7 +1043,8 @@ impl ReportWorker {
         // ReportFile init may try to lock runner. Fetch all the needed data
         // and unlock it.
         let cfg = &rdata.cfg;
-        let scr_devnr = cfg.scr_devnr;
+        let io_devs = cfg.io_devs.clone();
+        let rep_store = cfg.rep_store;
         let (rep_ret, rep_path, rep_d_path) = (
             cfg.rep_retention,
             cfg.report_path.clone(),
@@ -739,7 +1064,8 @@ impl ReportWorker {
                 rep_ret,
                 &rep_path,
                 &rep_d_path,
-                scr_devnr,
+                rep_store,
+                io_devs.clone(),
                 runner.clone(),
             ),
             report_file_1min: ReportFile::new(
@@ -747,13 +1073,14 @@ impl ReportWorker {
                 rep_1min_ret,
                 &rep_1min_path,
                 &rep_1min_d_path,
-                scr_devnr,
+                rep_store,
+                io_devs.clone(),
                 runner.clone(),
             ),
 
-            iolat: Default::default(),
-            iolat_cum: Default::default(),
-            iocost_devnr: scr_devnr,
+            iolat: vec![Default::default(); io_devs.len()],
+            iolat_cum: vec![Default::default(); io_devs.len()],
+            io_devs,
             runner,
         })
     }
@@ -769,7 +1096,7 @@ impl ReportWorker {
         let (bench_hashd, bench_hashd_phase) = match runner.bench_hashd.as_mut() {
             Some(svc) => (
                 super::svc_refresh_and_report(&mut svc.unit)?,
-                hashd[0].phase,
+                hashd[HASHD_A].phase,
             ),
             None => (Default::default(), Default::default()),
         };
@@ -778,6 +1105,21 @@ impl ReportWorker {
             None => Default::default(),
         };
 
+        let mut io_devs = BTreeMap::new();
+        for (idx, dev) in self.io_devs.iter().enumerate() {
+            io_devs.insert(
+                dev.name.clone(),
+                IoDevReport {
+                    devnr: dev.devnr_str(),
+                    iocost: IoCostReport::read(dev.devnr)?,
+                    iolat: self.iolat[idx].clone(),
+                    iolat_cum: self.iolat_cum[idx].clone(),
+                    ..Default::default()
+                },
+            );
+        }
+        let iocost = io_devs[&self.io_devs[0].name].iocost.clone();
+
         let seq = super::instance_seq();
         let dseqs = &runner.sobjs.slice_file.data.disable_seqs;
         let resctl = ResCtlReport {
@@ -796,18 +1138,22 @@ impl ReportWorker {
             bench_hashd: BenchHashdReport {
                 svc: bench_hashd,
                 phase: bench_hashd_phase,
-                mem_probe_size: hashd[0].mem_probe_size,
-                mem_probe_at: hashd[0].mem_probe_at,
+                mem_probe_size: hashd[HASHD_A].mem_probe_size,
+                mem_probe_at: hashd[HASHD_A].mem_probe_at,
             },
             bench_iocost: BenchIoCostReport { svc: bench_iocost },
             hashd,
             sysloads: runner.side_runner.report_sysloads()?,
             sideloads: runner.side_runner.report_sideloads()?,
-            iolat: self.iolat.clone(),
-            iolat_cum: self.iolat_cum.clone(),
-            iocost: IoCostReport::read(self.iocost_devnr)?,
+            alerts: runner.alerts.clone(),
+            iolat: self.iolat[0].clone(),
+            iolat_cum: self.iolat_cum[0].clone(),
+            iocost,
+            io_devs,
             swappiness: read_swappiness()?,
             zswap_enabled: read_zswap_enabled()?,
+            cpusets: read_cpusets(&runner.sobjs.slice_file.data),
+            config_txn: runner.config_txn.report.clone(),
             ..Default::default()
         })
     }
@@ -850,24 +1196,62 @@ impl ReportWorker {
         }
     }
 
+    fn select(&self, readers: &[IoDevLatReaders], timeout: Duration) -> ReportEvent {
+        let mut sel = Select::new();
+        for rds in readers.iter() {
+            sel.recv(rds.iolat.rx.as_ref().unwrap());
+            sel.recv(rds.iolat_cum.rx.as_ref().unwrap());
+        }
+        let term_idx = sel.recv(&self.term_rx);
+
+        let oper = match sel.select_timeout(timeout) {
+            Ok(v) => v,
+            Err(_) => return ReportEvent::Timeout,
+        };
+        let idx = oper.index();
+        if idx == term_idx {
+            return ReportEvent::Term(oper.recv(&self.term_rx));
+        }
+        let rds = &readers[idx / 2];
+        if idx % 2 == 0 {
+            ReportEvent::IoLat(idx / 2, oper.recv(rds.iolat.rx.as_ref().unwrap()))
+        } else {
+            ReportEvent::IoLatCum(idx / 2, oper.recv(rds.iolat_cum.rx.as_ref().unwrap()))
+        }
+    }
+
     fn run_inner(mut self) {
         let mut next_at = unix_now() + 1;
 
         let runner = self.runner.data.lock().unwrap();
         let cfg = &runner.cfg;
 
-        let mut iolat = IoLatReader::new(cfg, "iolat", "1").unwrap();
-        let mut iolat_cum = IoLatReader::new(cfg, "iolat_cum", "-1").unwrap();
+        let mut readers: Vec<IoDevLatReaders> = self
+            .io_devs
+            .iter()
+            .map(|dev| IoDevLatReaders {
+                iolat: IoLatReader::new(cfg, dev.devnr, &format!("iolat-{}", &dev.name), "1")
+                    .unwrap(),
+                iolat_cum: IoLatReader::new(
+                    cfg,
+                    dev.devnr,
+                    &format!("iolat_cum-{}", &dev.name),
+                    "-1",
+                )
+                .unwrap(),
+                iolat_retries: crate::misc::BCC_RETRIES,
+                iolat_cum_retries: crate::misc::BCC_RETRIES,
+                iolat_cum_kicked_at: UNIX_EPOCH,
+            })
+            .collect();
 
         drop(runner);
         let mut sleep_dur = Duration::from_secs(0);
-        let mut iolat_retries = crate::misc::BCC_RETRIES;
-        let mut iolat_cum_retries = crate::misc::BCC_RETRIES;
-        let mut iolat_cum_kicked_at = UNIX_EPOCH;
 
         'outer: loop {
-            select! {
-                recv(iolat.rx.as_ref().unwrap()) -> res => {
+            match self.select(&readers, sleep_dur) {
+                ReportEvent::IoLat(idx, res) => {
+                    let rds = &mut readers[idx];
                     // the cumulative instance doesn't have an interval,
                     // kick it and run it at the same pace as the 1s one. If
                     // we stalled for a while, we may busy loop here kicking
@@ -875,44 +1259,61 @@ impl ReportWorker {
                     // handler to hit maximum recursion limit and fail.
                     // Don't kick in quick succession.
                     let now = SystemTime::now();
-                    match now.duration_since(iolat_cum_kicked_at) {
+                    match now.duration_since(rds.iolat_cum_kicked_at) {
                         Ok(dur) => {
                             if dur.as_secs_f64() > 0.1 {
-                                iolat_cum.kick();
-                                iolat_cum_kicked_at = now;
+                                rds.iolat_cum.kick();
+                                rds.iolat_cum_kicked_at = now;
                             }
                         }
-                        Err(_) => iolat_cum_kicked_at = now,
+                        Err(_) => rds.iolat_cum_kicked_at = now,
                     }
 
                     match res {
-                        Ok(line) => {
-                            match Self::parse_iolat_output(&line) {
-                                Ok(v) => self.iolat = v,
-                                Err(e) => warn!("report: failed to parse iolat output ({:?})", &e),
+                        Ok(line) => match Self::parse_iolat_output(&line) {
+                            Ok(v) => {
+                                self.iolat[idx] = IoLatReport {
+                                    source: rds.iolat.source(),
+                                    ..v
+                                }
                             }
+                            Err(e) => warn!(
+                                "report: failed to parse iolat output for {:?} ({:?})",
+                                &self.io_devs[idx].name, &e
+                            ),
+                        },
+                        Err(e) => {
+                            Self::maybe_retry_iolat(&mut rds.iolat_retries, &mut rds.iolat, &e)
                         }
-                        Err(e) => Self::maybe_retry_iolat(&mut iolat_retries, &mut iolat, &e),
                     }
-                },
-                recv(iolat_cum.rx.as_ref().unwrap()) -> res => {
+                }
+                ReportEvent::IoLatCum(idx, res) => {
+                    let rds = &mut readers[idx];
                     match res {
-                        Ok(line) => {
-                            match Self::parse_iolat_output(&line) {
-                                Ok(v) => self.iolat_cum = v,
-                                Err(e) => warn!("report: failed to parse iolat_cum output ({:?})", &e),
+                        Ok(line) => match Self::parse_iolat_output(&line) {
+                            Ok(v) => {
+                                self.iolat_cum[idx] = IoLatReport {
+                                    source: rds.iolat_cum.source(),
+                                    ..v
+                                }
                             }
-                        }
-                        Err(e) => Self::maybe_retry_iolat(&mut iolat_cum_retries, &mut iolat_cum, &e),
-                    }
-                },
-                recv(self.term_rx) -> term => {
-                    if let Err(e) = term {
-                        info!("report: Term ({})", &e);
-                        break 'outer;
+                            Err(e) => warn!(
+                                "report: failed to parse iolat_cum output for {:?} ({:?})",
+                                &self.io_devs[idx].name, &e
+                            ),
+                        },
+                        Err(e) => Self::maybe_retry_iolat(
+                            &mut rds.iolat_cum_retries,
+                            &mut rds.iolat_cum,
+                            &e,
+                        ),
                     }
-                },
-                recv(channel::after(sleep_dur)) -> _ => (),
+                }
+                ReportEvent::Term(Err(e)) => {
+                    info!("report: Term ({})", &e);
+                    break 'outer;
+                }
+                ReportEvent::Term(Ok(())) | ReportEvent::Timeout => (),
             }
 
             let sleep_till = UNIX_EPOCH + Duration::from_secs(next_at) + Duration::from_millis(500);
@@ -937,7 +1338,9 @@ impl ReportWorker {
                 }
             };
 
-            self.report_file.tick(&base_report, now);
+            if let Some(report) = self.report_file.tick(&base_report, now) {
+                super::ctl::publish_report(&self.runner, &report);
+            }
             self.report_file_1min.tick(&base_report, now);
 
             // Report generation and writing could have taken a while. If we
@@ -981,3 +1384,45 @@ impl Drop for Reporter {
         jh.join().unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_host_svcs() {
+        let host_cgrp = std::env::temp_dir()
+            .join(format!("rd-agent-test-host-svcs-{}", std::process::id()))
+            .join("hostcritical.slice");
+        let _ = fs::remove_dir_all(&host_cgrp);
+        for dir in &[
+            "oomd.service",
+            "rd-agent.service",
+            "session-1.scope",
+            "sub.slice",
+        ] {
+            fs::create_dir_all(host_cgrp.join(dir)).unwrap();
+        }
+        fs::write(host_cgrp.join("stray.service"), "").unwrap();
+
+        let host_cgrp_str = host_cgrp.to_str().unwrap();
+        let mut svcs = read_host_svcs(host_cgrp_str);
+        svcs.sort();
+        fs::remove_dir_all(host_cgrp.parent().unwrap()).unwrap();
+
+        assert_eq!(
+            svcs,
+            vec![
+                (
+                    "oomd.service".to_string(),
+                    format!("{}/oomd.service", host_cgrp_str)
+                ),
+                (
+                    "rd-agent.service".to_string(),
+                    format!("{}/rd-agent.service", host_cgrp_str)
+                ),
+            ]
+        );
+        assert!(read_host_svcs("/nonexistent/hostcritical.slice").is_empty());
+    }
+}
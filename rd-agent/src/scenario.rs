@@ -0,0 +1,209 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Context, Result};
+use log::{error, info, warn};
+use serde::{de::DeserializeOwned, Serialize};
+use std::process::exit;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use rd_agent_intf::{AgentFiles, Cmd, CmdAck, OnTimeout, Scenario, ScenarioStep};
+use rd_util::*;
+
+const CMD_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTV: Duration = Duration::from_millis(250);
+
+fn patch_file<T>(file: &mut JsonConfigFile<T>, patch: &serde_json::Value) -> Result<()>
+where
+    T: JsonLoad + JsonSave + Serialize + DeserializeOwned,
+{
+    if file.path.is_none() {
+        bail!("file not found");
+    }
+    let mut val = serde_json::to_value(&file.data)?;
+    json_merge_patch(&mut val, patch);
+    file.data = serde_json::from_value(val)?;
+    file.save()
+}
+
+struct ScenarioRunner {
+    af: AgentFiles,
+    started_at: Instant,
+}
+
+impl ScenarioRunner {
+    fn log(&self, msg: &str) {
+        info!(
+            "scenario: [{} +{:.1}s] {}",
+            format_unix_time(unix_now()),
+            self.started_at.elapsed().as_secs_f64(),
+            msg
+        );
+    }
+
+    fn sleep_until(&self, offset: f64) -> Result<()> {
+        let target = self.started_at + Duration::from_secs_f64(offset);
+        while Instant::now() < target {
+            if prog_exiting() {
+                bail!("program exiting");
+            }
+            sleep(POLL_INTV.min(target - Instant::now()));
+        }
+        Ok(())
+    }
+
+    fn apply_cmd(&mut self, patch: &serde_json::Value) -> Result<u64> {
+        self.af.refresh();
+        let cmd_file = &mut self.af.cmd;
+        let mut val = serde_json::to_value(&cmd_file.data)?;
+        json_merge_patch(&mut val, patch);
+        let mut cmd: Cmd = serde_json::from_value(val).context("invalid cmd")?;
+        cmd.cmd_seq = cmd.cmd_seq.max(cmd_file.data.cmd_seq + 1);
+        let seq = cmd.cmd_seq;
+        cmd_file.data = cmd;
+        cmd_file.save().context("updating cmd file")?;
+
+        let started_at = Instant::now();
+        loop {
+            self.af.refresh();
+            let ack = &self.af.cmd_ack.data;
+            if ack.cmd_seq == seq && !ack.cmd_errors.is_empty() {
+                bail!(
+                    "cmd_seq {} rejected ({})",
+                    seq,
+                    CmdAck::format_errors(&ack.cmd_errors)
+                );
+            }
+            if ack.cmd_seq == seq && !ack.apply_errors.is_empty() {
+                bail!(
+                    "cmd_seq {} failed to apply ({})",
+                    seq,
+                    CmdAck::format_errors(&ack.apply_errors)
+                );
+            }
+            if ack.cmd_seq >= seq {
+                return Ok(seq);
+            }
+            if prog_exiting() {
+                bail!("program exiting");
+            }
+            if started_at.elapsed() >= CMD_ACK_TIMEOUT {
+                bail!("timeout waiting for cmd_seq {} ack", seq);
+            }
+            sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Returns false if timed out.
+    fn wait(&mut self, step: &ScenarioStep) -> Result<bool> {
+        let started_at = Instant::now();
+        loop {
+            self.af.refresh();
+            let rep = serde_json::to_value(&self.af.report.data)?;
+            let vals: Vec<Option<f64>> = step.wait.iter().map(|cond| cond.eval(&rep)).collect();
+            if vals.iter().all(|v| v.is_some()) {
+                for (cond, val) in step.wait.iter().zip(vals.iter()) {
+                    self.log(&format!("  satisfied {} ({})", cond, val.unwrap()));
+                }
+                return Ok(true);
+            }
+            if step.timeout > 0.0 && started_at.elapsed().as_secs_f64() >= step.timeout {
+                return Ok(false);
+            }
+            if prog_exiting() {
+                bail!("program exiting");
+            }
+            sleep(POLL_INTV);
+        }
+    }
+
+    fn run_step(&mut self, idx: usize, step: &ScenarioStep) -> Result<()> {
+        self.sleep_until(step.at)?;
+
+        let late = self.started_at.elapsed().as_secs_f64() - step.at;
+        self.log(&format!(
+            "step {}{} (at {}s{})",
+            idx,
+            if step.name.is_empty() {
+                "".into()
+            } else {
+                format!(" {:?}", &step.name)
+            },
+            step.at,
+            if late >= 1.0 {
+                format!(", {:.1}s late", late)
+            } else {
+                "".into()
+            }
+        ));
+
+        // Pick up changes made by others since the last step so that the
+        // patches don't revert them.
+        if step.slices.is_some() || step.oomd.is_some() {
+            self.af.refresh();
+        }
+        if let Some(patch) = step.slices.as_ref() {
+            patch_file(&mut self.af.slices, patch).context("patching slices")?;
+            self.log("  patched slices");
+        }
+        if let Some(patch) = step.oomd.as_ref() {
+            patch_file(&mut self.af.oomd, patch).context("patching oomd")?;
+            self.log("  patched oomd");
+        }
+        if let Some(patch) = step.cmd.as_ref() {
+            let seq = self.apply_cmd(patch).context("applying cmd")?;
+            self.log(&format!("  cmd_seq {} acked", seq));
+        }
+
+        if !step.wait.is_empty() {
+            for cond in step.wait.iter() {
+                self.log(&format!("  waiting for {}", cond));
+            }
+            if !self.wait(step)? {
+                match step.on_timeout {
+                    OnTimeout::Abort => bail!("step {} timed out waiting", idx),
+                    OnTimeout::Continue => {
+                        self.log(&format!("  timed out after {}s, continuing", step.timeout))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, scn: &Scenario) -> Result<()> {
+        self.started_at = Instant::now();
+        self.log(&format!("starting {} steps", scn.steps.len()));
+        for (idx, step) in scn.steps.iter().enumerate() {
+            self.run_step(idx, step)?;
+        }
+        self.log("done");
+        Ok(())
+    }
+}
+
+pub fn scenario_main(top_dir: &str, path: &str) {
+    let run = || -> Result<()> {
+        let scn = Scenario::load(path).with_context(|| format!("loading {:?}", path))?;
+        scn.validate()?;
+
+        let mut af = AgentFiles::new(top_dir);
+        af.refresh();
+        if af.index.path.is_none() || af.cmd.path.is_none() {
+            bail!("rd-agent files not found in {:?}", top_dir);
+        }
+        if af.report.path.is_none() {
+            warn!("scenario: report.json not found, is rd-agent running?");
+        }
+
+        ScenarioRunner {
+            af,
+            started_at: Instant::now(),
+        }
+        .run(&scn)
+    };
+
+    if let Err(e) = run() {
+        error!("scenario: {:#}", &e);
+        exit(1);
+    }
+}
@@ -1,18 +1,20 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
+use super::sideloader::{SideloaderJob, SideloaderJobs};
 use super::{prepare_bin_file, Config};
 use anyhow::{anyhow, bail, Result};
 use log::{debug, error, info, warn};
-use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use std::process::{Command, Stdio};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use rd_agent_intf::{
-    sideload_svc_name, sysload_svc_name, BenchKnobs, SideloadDefs, SideloadReport, SideloadSpec,
-    Slice, SysReq, SysloadReport,
+    sideload_svc_name, sysload_svc_name, BenchKnobs, RestartPolicy, SideLifecyclePhase,
+    SideloadDefs, SideloadReport, SideloadSpec, Slice, SysReq, SysloadReport,
 };
+use rd_util::systemd::UnitState as US;
 use rd_util::*;
 
 lazy_static::lazy_static! {
@@ -23,6 +25,10 @@ const LINUX_TAR_XZ_URL: &str = "https://cdn.kernel.org/pub/linux/kernel/v5.x/lin
 const LINUX_TAR_PRELOAD: &str = "/usr/share/resctl-demo/linux.tar";
 const LINUX_TAR_XZ_PRELOAD: &str = "/usr/share/resctl-demo/linux.tar.xz";
 
+const LIFECYCLE_CHECK_INTV: Duration = Duration::from_secs(1);
+const HOOK_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+const HOOK_SVC_PREFIX: &str = "rd-hook-";
+
 const SIDE_BINS: [(&str, &[u8]); 6] = [
     ("build-linux.sh", include_bytes!("side/build-linux.sh")),
     ("mem-hog.sh", include_bytes!("side/mem-hog.sh")),
@@ -196,49 +202,444 @@ fn really_remove_dir_all(path: &str) {
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
+enum LcAction {
+    None,
+    Start,
+    Stop,
+}
+
+/// Lifecycle of a sys/sideload - prep and cleanup hooks, restart policy,
+/// max run time and completion marker. The owner starts and stops the
+/// actual service as directed by the returned LcAction.
+struct Lifecycle {
+    name: String,
+    spec: SideloadSpec,
+    envs: Vec<String>,
+    props: Vec<String>,
+    slice: Slice,
+    hook_svc_name: String,
+    working_dir: String,
+    phase: SideLifecyclePhase,
+    hook: Option<Child>,
+    started_at: Instant,
+    restart_at: Instant,
+    nr_restarts: u32,
+    nr_backoffs: u32,
+    failed: bool,
+    completed: bool,
+}
+
+impl Lifecycle {
+    fn new(
+        name: &str,
+        spec: SideloadSpec,
+        envs: Vec<String>,
+        working_dir: &str,
+        slice: Slice,
+    ) -> Self {
+        let now = Instant::now();
+        let kind = match slice {
+            Slice::Side => "sideload",
+            _ => "sysload",
+        };
+        Self {
+            name: name.into(),
+            props: SideRunner::spec_props(&spec),
+            spec,
+            envs,
+            slice,
+            hook_svc_name: format!("{}{}-{}.service", HOOK_SVC_PREFIX, kind, name),
+            working_dir: working_dir.into(),
+            phase: SideLifecyclePhase::Prep,
+            hook: None,
+            started_at: now,
+            restart_at: now,
+            nr_restarts: 0,
+            nr_backoffs: 0,
+            failed: false,
+            completed: false,
+        }
+    }
+
+    /// Hooks run as transient services in the load's slice with the same
+    /// overrides as the load so that they're under the same resource
+    /// control. With --wait, systemd-run exits with the hook's result.
+    fn spawn_hook(&self, cmd: &[String]) -> Result<Child> {
+        let mut command = Command::new("systemd-run");
+        command
+            .args(["--wait", "--quiet", "--collect"])
+            .args(["-p", "TimeoutStopSec=5", "-p", "IOAccounting=true"])
+            .arg("--slice")
+            .arg(self.slice.name())
+            .arg("--unit")
+            .arg(&self.hook_svc_name)
+            .arg("--working-directory")
+            .arg(&self.working_dir);
+        for prop in self.props.iter() {
+            command.arg("-p").arg(prop);
+        }
+        for env in self.envs.iter() {
+            command.arg("-E").arg(env);
+        }
+        command.args(cmd).stdin(Stdio::null()).stdout(Stdio::null());
+        Ok(command.spawn()?)
+    }
+
+    /// Killing systemd-run doesn't stop the hook service, stop it directly.
+    fn stop_hook_svc(&self) {
+        let res = systemd::Unit::new_sys(self.hook_svc_name.clone())
+            .and_then(|mut unit| unit.stop_and_reset());
+        if let Err(e) = res {
+            warn!(
+                "side: Failed to stop hook {:?} of {:?} ({:?})",
+                &self.hook_svc_name, &self.name, &e
+            );
+        }
+    }
+
+    /// Start a new run, running the prep command first if configured.
+    fn begin(&mut self) -> LcAction {
+        self.failed = false;
+        if !self.spec.prep_cmd.is_empty() {
+            match self.spawn_hook(&self.spec.prep_cmd) {
+                Ok(child) => {
+                    debug!("side: {:?} running prep command", &self.name);
+                    self.hook = Some(child);
+                    self.phase = SideLifecyclePhase::Prep;
+                    return LcAction::None;
+                }
+                Err(e) => {
+                    warn!("side: Failed to run prep for {:?} ({:#})", &self.name, &e);
+                    self.failed = true;
+                    return self.end(false);
+                }
+            }
+        }
+        self.phase = SideLifecyclePhase::Running;
+        self.started_at = Instant::now();
+        LcAction::Start
+    }
+
+    /// The current run is over. Run the cleanup command if configured and
+    /// decide whether to restart.
+    fn end(&mut self, stop: bool) -> LcAction {
+        if !self.spec.cleanup_cmd.is_empty() {
+            match self.spawn_hook(&self.spec.cleanup_cmd) {
+                Ok(child) => {
+                    debug!("side: {:?} running cleanup command", &self.name);
+                    self.hook = Some(child);
+                    self.phase = SideLifecyclePhase::Cleanup;
+                }
+                Err(e) => {
+                    warn!(
+                        "side: Failed to run cleanup for {:?} ({:#})",
+                        &self.name, &e
+                    );
+                    self.finish();
+                }
+            }
+        } else {
+            self.finish();
+        }
+
+        match stop {
+            true => LcAction::Stop,
+            false => LcAction::None,
+        }
+    }
+
+    fn finish(&mut self) {
+        let restart = !self.completed
+            && match self.spec.restart {
+                RestartPolicy::Never => false,
+                RestartPolicy::OnFailure => self.failed,
+                RestartPolicy::Always => true,
+            };
+        if !restart {
+            self.phase = SideLifecyclePhase::Done;
+            return;
+        }
+
+        // Runs which lasted longer than the max delay restart the backoff.
+        let delay_max = self.spec.restart_delay_max as u64;
+        if self.started_at.elapsed() >= Duration::from_secs(delay_max) {
+            self.nr_backoffs = 0;
+        }
+        let delay = (self.spec.restart_delay as u64)
+            .saturating_mul(1 << self.nr_backoffs.min(32))
+            .min(delay_max);
+        self.nr_backoffs += 1;
+
+        info!(
+            "side: {:?} {}, restarting in {}s",
+            &self.name,
+            if self.failed { "failed" } else { "exited" },
+            delay
+        );
+        self.restart_at = Instant::now() + Duration::from_secs(delay);
+        self.phase = SideLifecyclePhase::RestartWait;
+    }
+
+    /// Starting the service failed, treat it as a failed run.
+    fn start_failed(&mut self) {
+        self.failed = true;
+        self.end(false);
+    }
+
+    /// Wait for @child for up to HOOK_SHUTDOWN_TIMEOUT. Shutdown runs on
+    /// the runner thread, so a hook which doesn't finish in time is
+    /// stopped instead of stalling the runner.
+    fn wait_hook(&self, mut child: Child) {
+        let timeout_at = Instant::now() + HOOK_SHUTDOWN_TIMEOUT;
+        loop {
+            match child.try_wait() {
+                Ok(Some(_)) => return,
+                Ok(None) if Instant::now() < timeout_at => {
+                    std::thread::sleep(Duration::from_millis(100))
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!(
+                        "side: Failed to wait for hook of {:?} ({:?})",
+                        &self.name, &e
+                    );
+                    break;
+                }
+            }
+        }
+
+        warn!(
+            "side: Hook {:?} of {:?} didn't finish in {}s, stopping",
+            &self.hook_svc_name,
+            &self.name,
+            HOOK_SHUTDOWN_TIMEOUT.as_secs()
+        );
+        self.stop_hook_svc();
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
+    fn reap_hook(&mut self) -> Option<bool> {
+        let child = self.hook.as_mut()?;
+        let success = match child.try_wait() {
+            Ok(Some(status)) => status.success(),
+            Ok(None) => return None,
+            Err(e) => {
+                warn!(
+                    "side: Failed to wait for hook of {:?} ({:?})",
+                    &self.name, &e
+                );
+                false
+            }
+        };
+        self.hook = None;
+        Some(success)
+    }
+
+    fn completion_reached(&self) -> Option<String> {
+        if let Some(marker) = self.spec.completion_marker.as_ref() {
+            if Path::new(&self.working_dir).join(marker).exists() {
+                return Some(format!("completion marker {:?} found", marker));
+            }
+        }
+        if let Some(max) = self.spec.max_run_time {
+            if self.started_at.elapsed() >= Duration::from_secs(max as u64) {
+                return Some(format!("max run time {}s expired", max));
+            }
+        }
+        None
+    }
+
+    fn step(&mut self, state: &US) -> LcAction {
+        match self.phase {
+            SideLifecyclePhase::Prep => match self.reap_hook() {
+                Some(true) => {
+                    self.phase = SideLifecyclePhase::Running;
+                    self.started_at = Instant::now();
+                    LcAction::Start
+                }
+                Some(false) => {
+                    warn!("side: Prep command for {:?} failed", &self.name);
+                    self.failed = true;
+                    self.end(false)
+                }
+                None => LcAction::None,
+            },
+            SideLifecyclePhase::Running => {
+                if let Some(why) = self.completion_reached() {
+                    info!("side: {:?} completed, {}", &self.name, &why);
+                    self.completed = true;
+                    return self.end(true);
+                }
+                match state {
+                    US::Exited => self.end(false),
+                    US::Failed(_) => {
+                        self.failed = true;
+                        self.end(false)
+                    }
+                    _ => LcAction::None,
+                }
+            }
+            SideLifecyclePhase::Cleanup => {
+                match self.reap_hook() {
+                    Some(true) => self.finish(),
+                    Some(false) => {
+                        warn!("side: Cleanup command for {:?} failed", &self.name);
+                        self.finish();
+                    }
+                    None => {}
+                }
+                LcAction::None
+            }
+            SideLifecyclePhase::RestartWait => {
+                if Instant::now() < self.restart_at {
+                    return LcAction::None;
+                }
+                self.nr_restarts += 1;
+                self.begin()
+            }
+            SideLifecyclePhase::Done => LcAction::None,
+        }
+    }
+
+    /// Called on removal after the service is stopped. Makes sure that
+    /// the cleanup command runs for the last run.
+    fn shutdown(&mut self) {
+        if let Some(child) = self.hook.take() {
+            if self.phase == SideLifecyclePhase::Cleanup {
+                self.wait_hook(child);
+                return;
+            }
+            self.stop_hook_svc();
+            self.wait_hook(child);
+        }
+
+        match self.phase {
+            SideLifecyclePhase::Prep | SideLifecyclePhase::Running => {}
+            _ => return,
+        }
+        if self.spec.cleanup_cmd.is_empty() {
+            return;
+        }
+        match self.spawn_hook(&self.spec.cleanup_cmd) {
+            Ok(child) => self.wait_hook(child),
+            Err(e) => warn!(
+                "side: Failed to run cleanup for {:?} ({:#})",
+                &self.name, &e
+            ),
+        }
+    }
+}
+
 pub struct Sysload {
     scr_path: String,
     svc: TransientService,
+    lc: Lifecycle,
 }
 
-impl Drop for Sysload {
-    fn drop(&mut self) {
-        really_remove_dir_all(&self.scr_path);
+impl Sysload {
+    fn act(&mut self, action: LcAction) {
+        match action {
+            LcAction::Start => {
+                if let Err(e) = self.svc.start() {
+                    warn!(
+                        "side: Failed to start sysload {:?} ({:?})",
+                        &self.lc.name, &e
+                    );
+                    self.lc.start_failed();
+                }
+            }
+            LcAction::Stop => {
+                if let Err(e) = self.svc.unit.stop() {
+                    warn!(
+                        "side: Failed to stop sysload {:?} ({:?})",
+                        &self.lc.name, &e
+                    );
+                }
+            }
+            LcAction::None => {}
+        }
     }
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-struct SideloaderJob {
-    id: String,
-    args: Vec<String>,
-    envs: Vec<String>,
-    frozen_expiration: u32,
-    working_dir: String,
+    fn step(&mut self) -> Result<()> {
+        if self.lc.phase == SideLifecyclePhase::Running {
+            self.svc.unit.refresh()?;
+        }
+        let action = self.lc.step(&self.svc.unit.state);
+        self.act(action);
+        Ok(())
+    }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-struct SideloaderJobs {
-    sideloader_jobs: Vec<SideloaderJob>,
+impl Drop for Sysload {
+    fn drop(&mut self) {
+        if let Err(e) = self.svc.unit.stop_and_reset() {
+            error!("side: Failed to stop {:?} ({:?})", &self.lc.name, &e);
+        }
+        self.lc.shutdown();
+        really_remove_dir_all(&self.scr_path);
+    }
 }
 
-impl JsonSave for SideloaderJobs {}
-
 pub struct Sideload {
     name: String,
     scr_path: String,
     job_path: String,
+    jobs: SideloaderJobs,
     unit: systemd::Unit,
+    lc: Lifecycle,
+}
+
+impl Sideload {
+    /// The sideloader starts the job when the job file appears and
+    /// stops it when the job file goes away.
+    fn act(&mut self, action: LcAction) {
+        match action {
+            LcAction::Start => {
+                // Clear the previous run so that its state isn't mistaken
+                // for the new one's.
+                if let Err(e) = self.unit.stop_and_reset() {
+                    warn!("side: Failed to reset {:?} ({:?})", &self.name, &e);
+                }
+                match self.jobs.save(&self.job_path) {
+                    Ok(()) => info!("side: {:?} started", &self.name),
+                    Err(e) => {
+                        warn!("side: Failed to start sideload {:?} ({:?})", &self.name, &e);
+                        self.lc.start_failed();
+                    }
+                }
+            }
+            LcAction::Stop => {
+                if let Err(e) = fs::remove_file(&self.job_path) {
+                    warn!("side: Failed to remove {:?} ({:?})", &self.job_path, &e);
+                }
+            }
+            LcAction::None => {}
+        }
+    }
+
+    fn step(&mut self) -> Result<()> {
+        if self.lc.phase == SideLifecyclePhase::Running {
+            self.unit.refresh()?;
+        }
+        let action = self.lc.step(&self.unit.state);
+        self.act(action);
+        Ok(())
+    }
 }
 
 impl Drop for Sideload {
     fn drop(&mut self) {
-        match fs::remove_file(&self.job_path) {
-            Ok(()) => {}
-            Err(e) => error!("side: Failed to remove {:?} ({:?})", &self.job_path, &e),
+        if Path::new(&self.job_path).exists() {
+            if let Err(e) = fs::remove_file(&self.job_path) {
+                error!("side: Failed to remove {:?} ({:?})", &self.job_path, &e);
+            }
         }
         if let Err(e) = self.unit.stop_and_reset() {
             error!("side: Failed to stop {:?} ({:?})", self.name, &e);
         }
+        self.lc.shutdown();
         really_remove_dir_all(&self.scr_path);
     }
 }
@@ -247,6 +648,7 @@ pub struct SideRunner {
     cfg: Arc<Config>,
     sysloads: BTreeMap<String, Sysload>,
     sideloads: BTreeMap<String, Sideload>,
+    lc_checked_at: Instant,
 }
 
 impl SideRunner {
@@ -255,6 +657,7 @@ impl SideRunner {
             cfg,
             sysloads: BTreeMap::new(),
             sideloads: BTreeMap::new(),
+            lc_checked_at: Instant::now(),
         }
     }
 
@@ -283,11 +686,41 @@ impl SideRunner {
         if spec.args.len() < 1 {
             bail!("{:?} has no command", id);
         }
+        for (knob, weight) in &[
+            ("cpu_weight", spec.cpu_weight),
+            ("io_weight", spec.io_weight),
+        ] {
+            if let Some(w) = weight {
+                if !(1..=10000).contains(w) {
+                    bail!("{:?} has invalid {} {}", id, knob, w);
+                }
+            }
+        }
+        if let Some(nice) = spec.nice {
+            if !(-20..=19).contains(&nice) {
+                bail!("{:?} has invalid nice {}", id, nice);
+            }
+        }
+        if let Some(prio) = spec.ionice_prio {
+            if prio > 7 {
+                bail!("{:?} has invalid ionice_prio {}", id, prio);
+            }
+        }
+        for env in spec.envs.iter() {
+            if !env.contains('=') {
+                bail!("{:?} has invalid env {:?}, should be KEY=VAL", id, env);
+            }
+        }
 
-        spec.args[0] = match find_bin(&spec.args[0], Some(&self.cfg.side_bin_path)) {
-            Some(v) => v.to_str().unwrap().to_string(),
-            None => bail!("failed to resolve binary {:?}", spec.args[0]),
-        };
+        for cmd in &mut [&mut spec.args, &mut spec.prep_cmd, &mut spec.cleanup_cmd] {
+            if cmd.is_empty() {
+                continue;
+            }
+            cmd[0] = match find_bin(&cmd[0], Some(&self.cfg.side_bin_path)) {
+                Some(v) => v.to_str().unwrap().to_string(),
+                None => bail!("failed to resolve binary {:?}", cmd[0]),
+            };
+        }
 
         Ok(spec)
     }
@@ -300,10 +733,59 @@ impl SideRunner {
         }
     }
 
-    fn envs(&self, bench: &BenchKnobs) -> Vec<String> {
+    /// Apply cpu/io weight and memory overrides from @spec.
+    fn apply_spec_resctl(spec: &SideloadSpec, resctl: &mut systemd::UnitResCtl) {
+        if spec.cpu_weight.is_some() {
+            resctl.cpu_weight = spec.cpu_weight;
+        }
+        if spec.io_weight.is_some() {
+            resctl.io_weight = spec.io_weight;
+        }
+        resctl.mem_high = spec.mem_high;
+        resctl.mem_max = spec.mem_max;
+    }
+
+    /// nice/ionice overrides from @spec as systemd properties.
+    fn spec_sched_props(spec: &SideloadSpec) -> Vec<(String, i32)> {
+        let mut props = vec![];
+        if let Some(nice) = spec.nice {
+            props.push(("Nice".into(), nice));
+        }
+        if let Some(class) = spec.ionice_class {
+            props.push(("IOSchedulingClass".into(), class.ioprio_class()));
+        }
+        if let Some(prio) = spec.ionice_prio {
+            props.push(("IOSchedulingPriority".into(), prio as i32));
+        }
+        props
+    }
+
+    /// All overrides from @spec in systemd-run's "KEY=VAL" property format.
+    fn spec_props(spec: &SideloadSpec) -> Vec<String> {
+        let mut resctl: systemd::UnitResCtl = Default::default();
+        Self::apply_spec_resctl(spec, &mut resctl);
+
+        let mut props = vec![];
+        for (key, val) in &[
+            ("CPUWeight", resctl.cpu_weight),
+            ("IOWeight", resctl.io_weight),
+            ("MemoryHigh", resctl.mem_high),
+            ("MemoryMax", resctl.mem_max),
+        ] {
+            if let Some(val) = val {
+                props.push(format!("{}={}", key, val));
+            }
+        }
+        for (key, val) in Self::spec_sched_props(spec) {
+            props.push(format!("{}={}", key, val));
+        }
+        props
+    }
+
+    fn envs(&self, bench: &BenchKnobs, spec: &SideloadSpec) -> Vec<String> {
         let cfg = &self.cfg;
 
-        vec![
+        let mut envs = vec![
             format!("RD_AGENT_BIN={}", &cfg.agent_bin),
             format!("NR_CPUS={}", nr_cpus()),
             format!("TOTAL_MEMORY={}", total_memory()),
@@ -311,9 +793,11 @@ impl SideRunner {
             format!("ROTATIONAL_SWAP={}", if *ROTATIONAL_SWAP { 1 } else { 0 }),
             format!("IO_DEV={}", &cfg.scr_dev),
             format!("IO_DEVNR={}:{}", cfg.scr_devnr.0, cfg.scr_devnr.1),
-            format!("IO_RBPS={}", bench.iocost.model.rbps),
-            format!("IO_WBPS={}", bench.iocost.model.wbps),
-        ]
+            format!("IO_RBPS={}", bench.scr_iocost().model.rbps),
+            format!("IO_WBPS={}", bench.scr_iocost().model.wbps),
+        ];
+        envs.extend(spec.envs.iter().cloned());
+        envs
     }
 
     pub fn apply_sysloads(
@@ -339,22 +823,32 @@ impl SideRunner {
         for name in target_keys.difference(&active_keys) {
             let spec = self.verify_and_lookup_svc(name, target.get(name).unwrap(), defs)?;
 
+            let envs = self.envs(bench, &spec);
             let mut svc = TransientService::new_sys(
                 sysload_svc_name(name),
                 spec.args.clone(),
-                self.envs(bench),
+                envs.clone(),
                 Some(0o002),
             )?;
             let scr_path = Self::prep_scr_dir(&self.cfg.sys_scr_path, name)?;
-            svc.set_slice(Slice::Sys.name()).set_working_dir(&scr_path);
+            let working_dir = spec.working_dir.clone().unwrap_or_else(|| scr_path.clone());
+            svc.set_slice(Slice::Sys.name())
+                .set_working_dir(&working_dir);
             // Set default IO weight to enable IO accounting.
             svc.unit.resctl.io_weight = Some(100);
-
-            let mut sysload = Sysload { scr_path, svc };
-            if let Err(e) = sysload.svc.start() {
-                warn!("side: Failed to start sysload {:?} ({:?})", name, &e);
+            Self::apply_spec_resctl(&spec, &mut svc.unit.resctl);
+            for (key, val) in Self::spec_sched_props(&spec) {
+                svc.add_prop(key, systemd::Prop::I32(val));
             }
 
+            let mut sysload = Sysload {
+                scr_path,
+                svc,
+                lc: Lifecycle::new(name, spec, envs, &working_dir, Slice::Sys),
+            };
+            let action = sysload.lc.begin();
+            sysload.act(action);
+
             self.sysloads.insert(name.clone(), sysload);
         }
 
@@ -385,35 +879,58 @@ impl SideRunner {
             let spec = self.verify_and_lookup_svc(name, target.get(name).unwrap(), defs)?;
             let job_path = format!("{}/{}.json", &self.cfg.sideloader_daemon_jobs_path, name);
             let scr_path = Self::prep_scr_dir(&self.cfg.side_scr_path, name)?;
+            let envs = self.envs(bench, &spec);
+            let working_dir = spec.working_dir.clone().unwrap_or_else(|| scr_path.clone());
 
             let jobs = SideloaderJobs {
                 sideloader_jobs: vec![SideloaderJob {
                     id: name.into(),
                     args: spec.args.clone(),
-                    envs: self.envs(bench),
+                    envs: envs.clone(),
                     frozen_expiration: spec.frozen_exp,
-                    working_dir: scr_path.clone(),
+                    working_dir: working_dir.clone(),
+                    properties: Self::spec_props(&spec),
                 }],
             };
 
-            jobs.save(&job_path)?;
-
-            self.sideloads.insert(
-                name.clone(),
-                Sideload {
-                    name: name.clone(),
-                    scr_path,
-                    job_path,
-                    unit: systemd::Unit::new_sys(sideload_svc_name(&name))?,
-                },
-            );
+            let mut sideload = Sideload {
+                name: name.clone(),
+                scr_path,
+                job_path,
+                jobs,
+                unit: systemd::Unit::new_sys(sideload_svc_name(name))?,
+                lc: Lifecycle::new(name, spec, envs, &working_dir, Slice::Side),
+            };
+            let action = sideload.lc.begin();
+            sideload.act(action);
 
-            info!("side: {:?} started", &name);
+            self.sideloads.insert(name.clone(), sideload);
         }
 
         Ok(())
     }
 
+    /// Drive prep/cleanup hooks, restart policies and completions. Called
+    /// from the runner loop while running.
+    pub fn check_lifecycles(&mut self) {
+        let now = Instant::now();
+        if now.duration_since(self.lc_checked_at) < LIFECYCLE_CHECK_INTV {
+            return;
+        }
+        self.lc_checked_at = now;
+
+        for (name, sysload) in self.sysloads.iter_mut() {
+            if let Err(e) = sysload.step() {
+                warn!("side: Failed to update sysload {:?} ({:?})", name, &e);
+            }
+        }
+        for (name, sideload) in self.sideloads.iter_mut() {
+            if let Err(e) = sideload.step() {
+                warn!("side: Failed to update sideload {:?} ({:?})", name, &e);
+            }
+        }
+    }
+
     pub fn all_svcs(&self) -> HashSet<(String, String)> {
         let mut svcs = HashSet::<(String, String)>::new();
         for (name, _) in self.sysloads.iter() {
@@ -437,6 +954,10 @@ impl SideRunner {
                 SysloadReport {
                     svc: super::svc_refresh_and_report(&mut sysload.svc.unit)?,
                     scr_path: format!("{}/{}", &self.cfg.sys_scr_path, name),
+                    phase: sysload.lc.phase,
+                    nr_restarts: sysload.lc.nr_restarts,
+                    completed: sysload.lc.completed,
+                    usage_key: String::new(),
                 },
             );
         }
@@ -451,6 +972,10 @@ impl SideRunner {
                 SideloadReport {
                     svc: super::svc_refresh_and_report(&mut sideload.unit)?,
                     scr_path: format!("{}/{}", &self.cfg.side_scr_path, name),
+                    phase: sideload.lc.phase,
+                    nr_restarts: sideload.lc.nr_restarts,
+                    completed: sideload.lc.completed,
+                    usage_key: String::new(),
                 },
             );
         }
@@ -524,3 +1049,100 @@ impl Balloon {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lifecycle(restart: RestartPolicy, delay: u32, delay_max: u32) -> Lifecycle {
+        let spec = SideloadSpec {
+            restart,
+            restart_delay: delay,
+            restart_delay_max: delay_max,
+            ..Default::default()
+        };
+        Lifecycle::new("test", spec, vec![], "/nonexistent", Slice::Side)
+    }
+
+    /// Seconds until the pending restart, rounded to the nearest second.
+    fn restart_delay(lc: &Lifecycle) -> u64 {
+        let left = lc.restart_at.saturating_duration_since(Instant::now());
+        (left.as_millis() as u64 + 500) / 1000
+    }
+
+    #[test]
+    fn test_lifecycle_begin_end() {
+        let mut lc = lifecycle(RestartPolicy::Never, 1, 8);
+        assert_eq!(lc.begin(), LcAction::Start);
+        assert_eq!(lc.phase, SideLifecyclePhase::Running);
+
+        assert_eq!(lc.step(&US::Running), LcAction::None);
+        assert_eq!(lc.phase, SideLifecyclePhase::Running);
+
+        assert_eq!(lc.step(&US::Exited), LcAction::None);
+        assert_eq!(lc.phase, SideLifecyclePhase::Done);
+        assert_eq!(lc.step(&US::Exited), LcAction::None);
+        assert_eq!(lc.phase, SideLifecyclePhase::Done);
+    }
+
+    #[test]
+    fn test_lifecycle_restart_policy() {
+        let mut lc = lifecycle(RestartPolicy::OnFailure, 1, 8);
+        lc.begin();
+        lc.step(&US::Exited);
+        assert_eq!(lc.phase, SideLifecyclePhase::Done);
+
+        let mut lc = lifecycle(RestartPolicy::OnFailure, 1, 8);
+        lc.begin();
+        lc.step(&US::Failed("failed".into()));
+        assert!(lc.failed);
+        assert_eq!(lc.phase, SideLifecyclePhase::RestartWait);
+
+        let mut lc = lifecycle(RestartPolicy::OnFailure, 1, 8);
+        lc.begin();
+        lc.start_failed();
+        assert_eq!(lc.phase, SideLifecyclePhase::RestartWait);
+
+        // restart waits for the delay and then begins a new run
+        let mut lc = lifecycle(RestartPolicy::Always, 0, 8);
+        lc.begin();
+        lc.step(&US::Exited);
+        assert_eq!(lc.phase, SideLifecyclePhase::RestartWait);
+        assert_eq!(lc.step(&US::Exited), LcAction::Start);
+        assert_eq!(lc.phase, SideLifecyclePhase::Running);
+        assert_eq!(lc.nr_restarts, 1);
+
+        let mut lc = lifecycle(RestartPolicy::Always, 1, 8);
+        lc.begin();
+        lc.step(&US::Exited);
+        assert_eq!(lc.step(&US::Exited), LcAction::None);
+        assert_eq!(lc.phase, SideLifecyclePhase::RestartWait);
+        assert_eq!(lc.nr_restarts, 0);
+    }
+
+    #[test]
+    fn test_lifecycle_completion() {
+        let mut lc = lifecycle(RestartPolicy::Always, 1, 8);
+        lc.spec.max_run_time = Some(0);
+        lc.begin();
+        assert_eq!(lc.step(&US::Running), LcAction::Stop);
+        assert!(lc.completed);
+        assert_eq!(lc.phase, SideLifecyclePhase::Done);
+    }
+
+    #[test]
+    fn test_lifecycle_backoff() {
+        let mut lc = lifecycle(RestartPolicy::Always, 1, 8);
+        for delay in &[1, 2, 4, 8, 8] {
+            lc.finish();
+            assert_eq!(lc.phase, SideLifecyclePhase::RestartWait);
+            assert_eq!(restart_delay(&lc), *delay);
+        }
+
+        // a run which lasted longer than the max delay resets the backoff
+        lc.started_at = Instant::now() - Duration::from_secs(8);
+        lc.finish();
+        assert_eq!(restart_delay(&lc), 1);
+        assert_eq!(lc.nr_backoffs, 1);
+    }
+}
@@ -1,173 +1,1380 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
-use anyhow::{bail, Result};
-use log::trace;
+//
+// Sideloader - keeps sideloads out of the main workload's way.
+//
+// Sideloads are handed over by side.rs as job files in jobs.d and run as
+// services in sideload.slice. On startup, the sideloader sets
+// sideload.slice's CPUWeight, MemoryHigh and IOWeight through systemd. Once
+// every interval, it samples the CPU usage, sideload.slice's memory and io
+// pressures and free swap, and
+//
+// * configures sideload.slice's cpu.max so that cpu_headroom is left idle,
+//
+// * freezes all sideloads while overloaded - the CPU margin left for
+//   sideloads is too low or the memory pressure is high - and kills the
+//   ones which stay frozen longer than their frozen_expiration,
+//
+// * kills all sideloads when resources become critical - swap is about to
+//   run out or memory or io pressure is severe,
+//
+// * and periodically checks the system configuration and reports what's
+//   missing to isolate the main workload from sideloads, restoring
+//   sideload.slice's memory.high if it drifted.
+//
+use anyhow::{anyhow, Result};
+use crossbeam::channel::{self, Receiver, RecvTimeoutError, Sender};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
 use std::fs;
-use std::io;
-use std::io::prelude::*;
+use std::os::unix::fs::MetadataExt;
+use std::panic;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
 
 use rd_agent_intf::{
-    SideloaderCmd, SideloaderReport, Slice, SliceKnobs, SvcStateReport, SIDELOADER_SVC_NAME,
-    SIDELOAD_SVC_PREFIX,
+    EventKind, SideloaderCmd, SideloaderJobReport, SideloaderReport, Slice, SliceKnobs, SvcReport,
+    SvcStateReport, SIDELOAD_SVC_PREFIX,
 };
+use rd_util::systemd::UnitState as US;
 use rd_util::*;
 
+use super::events;
 use super::Config;
 
-const SIDELOADER_CONFIG: &str = r#"{
-    "sideloader_config": {
-        "main_slice": "workload.slice",
-        "host_slice": "hostcritical.slice",
-        "side_slice": "sideload.slice",
-
-        "main_cpu_weight": __MAIN_CPU_WEIGHT__,
-        "host_cpu_weight": __HOST_CPU_WEIGHT__,
-        "side_cpu_weight": __SIDE_CPU_WEIGHT__,
-        "main_io_weight": __MAIN_IO_WEIGHT__,
-        "host_io_weight": __HOST_IO_WEIGHT__,
-        "side_io_weight": __SIDE_IO_WEIGHT__,
-        "side_memory_high": "100%",
-        "side_swap_max": "50%",
-
-        "cpu_headroom_period": 5,
-        "cpu_headroom": __CPU_HEADROOM__,
-        "cpu_min_avail": 10,
-        "cpu_floor": 5,
-        "cpu_throttle_period": 0.01,
-
-        "overload_cpu_duration": 10,
-        "overload_mempressure_threshold": 50,
-        "overload_hold": 10,
-        "overload_hold_max": 30,
-        "overload_hold_decay_rate": 0.5,
-
-        "critical_swapfree_threshold": "10%",
-        "critical_mempressure_threshold": 75,
-        "critical_iopressure_threshold": 75
-    }
-}
-"#;
-
-fn sideloader_config(cpu_headroom: f64, slice_knobs: &SliceKnobs) -> String {
-    let main_sk = slice_knobs.slices.get(Slice::Work.name()).unwrap();
-    let host_sk = slice_knobs.slices.get(Slice::Host.name()).unwrap();
-    let side_sk = slice_knobs.slices.get(Slice::Side.name()).unwrap();
-
-    SIDELOADER_CONFIG
-        .to_string()
-        .replace("__MAIN_CPU_WEIGHT__", &format!("{}", main_sk.cpu_weight))
-        .replace("__HOST_CPU_WEIGHT__", &format!("{}", host_sk.cpu_weight))
-        .replace("__SIDE_CPU_WEIGHT__", &format!("{}", side_sk.cpu_weight))
-        .replace("__MAIN_IO_WEIGHT__", &format!("{}", main_sk.io_weight))
-        .replace("__HOST_IO_WEIGHT__", &format!("{}", host_sk.io_weight))
-        .replace("__SIDE_IO_WEIGHT__", &format!("{}", side_sk.io_weight))
-        .replace("__CPU_HEADROOM__", &format!("{}", cpu_headroom))
+const INTV: f64 = 1.0;
+const SYSCFG_INTV_ACTIVE: f64 = 10.0;
+const SYSCFG_INTV_IDLE: f64 = 60.0;
+
+/// Sideloader configuration. CPU utilizations and pressures are in
+/// percents, durations in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SideloaderConfig {
+    pub main_cpu_weight: u32,
+    pub host_cpu_weight: u32,
+    pub side_cpu_weight: u32,
+    pub main_io_weight: u32,
+    pub host_io_weight: u32,
+    pub side_io_weight: u32,
+    /// sideload.slice memory.high, fraction of total memory
+    pub side_memory_high: f64,
+    /// sideload.slice memory.swap.max, fraction of total swap
+    pub side_swap_max: f64,
+
+    pub cpu_headroom_period: f64,
+    pub cpu_headroom: f64,
+    pub cpu_min_avail: f64,
+    pub cpu_floor: f64,
+    pub cpu_throttle_period: f64,
+
+    pub overload_cpu_duration: f64,
+    pub overload_mempressure_threshold: f64,
+    pub overload_hold: f64,
+    pub overload_hold_max: f64,
+    pub overload_hold_decay_rate: f64,
+
+    /// Fraction of the swap sideload.slice can use
+    pub critical_swapfree_threshold: f64,
+    pub critical_mempressure_threshold: f64,
+    pub critical_iopressure_threshold: f64,
 }
 
-pub struct Sideloader {
-    daemon_cfg_path: String,
-    daemon_status_path: String,
-    pub svc: TransientService,
+impl Default for SideloaderConfig {
+    fn default() -> Self {
+        Self {
+            main_cpu_weight: 100,
+            host_cpu_weight: 100,
+            side_cpu_weight: 100,
+            main_io_weight: 100,
+            host_io_weight: 100,
+            side_io_weight: 100,
+            side_memory_high: 1.0,
+            side_swap_max: 0.5,
+
+            cpu_headroom_period: 5.0,
+            cpu_headroom: 20.0,
+            cpu_min_avail: 10.0,
+            cpu_floor: 5.0,
+            cpu_throttle_period: 0.01,
+
+            overload_cpu_duration: 10.0,
+            overload_mempressure_threshold: 50.0,
+            overload_hold: 10.0,
+            overload_hold_max: 30.0,
+            overload_hold_decay_rate: 0.5,
+
+            critical_swapfree_threshold: 0.1,
+            critical_mempressure_threshold: 75.0,
+            critical_iopressure_threshold: 75.0,
+        }
+    }
 }
 
-impl Sideloader {
-    pub fn new(cfg: &Config) -> Result<Self> {
-        let mut svc = TransientService::new_sys(
-            SIDELOADER_SVC_NAME.into(),
-            vec![
-                cfg.sideloader_bin.clone(),
-                "--config".into(),
-                cfg.sideloader_daemon_cfg_path.clone(),
-                "--jobdir".into(),
-                cfg.sideloader_daemon_jobs_path.clone(),
-                "--status".into(),
-                cfg.sideloader_daemon_status_path.clone(),
-                "--svc-prefix".into(),
-                SIDELOAD_SVC_PREFIX.into(),
-                "--dev".into(),
-                cfg.scr_dev.clone(),
-                "--dont-fix".into(),
-            ],
-            vec![],
-            Some(0o002),
-        )?;
-        svc.set_slice(Slice::Host.name()).set_restart_always();
+impl SideloaderConfig {
+    pub fn new(cmd: &SideloaderCmd, slice_knobs: &SliceKnobs) -> Self {
+        let main_sk = slice_knobs.slices.get(Slice::Work.name()).unwrap();
+        let host_sk = slice_knobs.slices.get(Slice::Host.name()).unwrap();
+        let side_sk = slice_knobs.slices.get(Slice::Side.name()).unwrap();
 
-        Ok(Self {
-            daemon_cfg_path: cfg.sideloader_daemon_cfg_path.clone(),
-            daemon_status_path: cfg.sideloader_daemon_status_path.clone(),
-            svc,
+        Self {
+            main_cpu_weight: main_sk.cpu_weight,
+            host_cpu_weight: host_sk.cpu_weight,
+            side_cpu_weight: side_sk.cpu_weight,
+            main_io_weight: main_sk.io_weight,
+            host_io_weight: host_sk.io_weight,
+            side_io_weight: side_sk.io_weight,
+            cpu_headroom: cmd.cpu_headroom * 100.0,
+            ..Default::default()
+        }
+    }
+
+    fn nr_intvs(dur: f64) -> usize {
+        ((dur / INTV).ceil() as usize).max(1)
+    }
+
+    fn nr_headroom_intvs(&self) -> usize {
+        Self::nr_intvs(self.cpu_headroom_period)
+    }
+
+    fn nr_overload_intvs(&self) -> usize {
+        Self::nr_intvs(self.overload_cpu_duration)
+    }
+}
+
+/// A job file in jobs.d, written by side.rs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SideloaderJob {
+    pub id: String,
+    pub args: Vec<String>,
+    pub envs: Vec<String>,
+    pub frozen_expiration: u32,
+    pub working_dir: String,
+    pub properties: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SideloaderJobs {
+    pub sideloader_jobs: Vec<SideloaderJob>,
+}
+
+impl JsonLoad for SideloaderJobs {}
+impl JsonSave for SideloaderJobs {}
+
+/// Cumulative CPU times in usecs.
+#[derive(Debug, Default, Clone, Copy)]
+struct CpuSample {
+    total: f64,
+    idle: f64,
+    side: f64,
+}
+
+/// The last CpuSample's, one per interval.
+#[derive(Debug)]
+struct CpuHist {
+    hist: VecDeque<CpuSample>,
+    cap: usize,
+}
+
+impl CpuHist {
+    fn new(nr_intvs: usize) -> Self {
+        Self {
+            hist: VecDeque::new(),
+            cap: nr_intvs + 1,
+        }
+    }
+
+    fn push(&mut self, sample: CpuSample) {
+        if self.hist.len() >= self.cap {
+            self.hist.pop_front();
+        }
+        self.hist.push_back(sample);
+    }
+
+    /// Average percentage of `sel` over the last `nr_intvs`. 0 until enough
+    /// history is accumulated.
+    fn avg<F: Fn(&CpuSample) -> f64>(&self, nr_intvs: usize, sel: F) -> f64 {
+        let len = self.hist.len();
+        if len <= nr_intvs {
+            return 0.0;
+        }
+        let left = &self.hist[len - 1 - nr_intvs];
+        let right = &self.hist[len - 1];
+        let total = right.total - left.total;
+        if total <= 0.0 {
+            return 0.0;
+        }
+        ((sel(right) - sel(left)) / total * 100.0).clamp(0.0, 100.0)
+    }
+
+    fn avg_idle(&self, nr_intvs: usize) -> f64 {
+        self.avg(nr_intvs, |s| s.idle)
+    }
+
+    fn avg_side(&self, nr_intvs: usize) -> f64 {
+        self.avg(nr_intvs, |s| s.side)
+    }
+}
+
+/// Pressures and swap state of sideload.slice.
+#[derive(Debug, Default, Clone, Copy)]
+struct PresSample {
+    memp_1min: f64,
+    memp_5min: f64,
+    iop_5min: f64,
+    swap_total: u64,
+    swap_free: u64,
+}
+
+/// Overload and critical state machine driven by CPU and pressure samples.
+#[derive(Debug)]
+struct Governor {
+    hist: CpuHist,
+    cpu_cur_idle: f64,
+    cpu_cur_side: f64,
+    cpu_avg_idle: f64,
+    cpu_avg_side: f64,
+    cpu_avail: f64,
+    critical_at: Option<f64>,
+    critical_why: Option<String>,
+    overload_at: Option<f64>,
+    overload_why: Option<String>,
+    overload_hold_from: f64,
+    overload_hold: f64,
+}
+
+impl Governor {
+    fn new(cfg: &SideloaderConfig) -> Self {
+        Self {
+            hist: CpuHist::new(cfg.nr_headroom_intvs().max(cfg.nr_overload_intvs())),
+            cpu_cur_idle: 0.0,
+            cpu_cur_side: 0.0,
+            cpu_avg_idle: 0.0,
+            cpu_avg_side: 0.0,
+            cpu_avail: 0.0,
+            critical_at: None,
+            critical_why: None,
+            overload_at: None,
+            overload_why: None,
+            overload_hold_from: 0.0,
+            overload_hold: 0.0,
+        }
+    }
+
+    fn critical(&self) -> bool {
+        self.critical_at.is_some()
+    }
+
+    fn overloaded(&self) -> bool {
+        self.overload_at.is_some()
+    }
+
+    fn hold_left(&self, now: f64) -> f64 {
+        match self.overload_at {
+            Some(_) => (self.overload_hold_from + self.overload_hold - now).max(0.0),
+            None => 0.0,
+        }
+    }
+
+    fn check_critical(cfg: &SideloaderConfig, pres: &PresSample) -> Option<String> {
+        let swap_usable = (pres.swap_total as f64 * cfg.side_swap_max.min(1.0)) as u64;
+        let swapfree_thr = (swap_usable as f64 * cfg.critical_swapfree_threshold) as u64;
+
+        if pres.swap_total > 0 && pres.swap_free <= swapfree_thr {
+            Some(format!(
+                "swap-left {}MB is lower than critical threshold {}MB",
+                pres.swap_free >> 20,
+                swapfree_thr >> 20
+            ))
+        } else if pres.memp_5min >= cfg.critical_mempressure_threshold {
+            Some(format!(
+                "5min memory pressure {:.2} is higher than critical threshold {:.2}",
+                pres.memp_5min, cfg.critical_mempressure_threshold
+            ))
+        } else if pres.iop_5min >= cfg.critical_iopressure_threshold {
+            Some(format!(
+                "5min io pressure {:.2} is higher than critical threshold {:.2}",
+                pres.iop_5min, cfg.critical_iopressure_threshold
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn check_overload(&self, cfg: &SideloaderConfig, pres: &PresSample) -> Option<String> {
+        let side_margin = (self.cpu_avg_side + self.cpu_avg_idle - cfg.cpu_headroom).max(0.0);
+        if side_margin < cfg.cpu_min_avail {
+            Some(format!("cpu margin {:.2} is too low", side_margin))
+        } else if pres.memp_1min >= cfg.overload_mempressure_threshold {
+            Some(format!(
+                "1min memory pressure {:.2} is over the threshold {:.2}",
+                pres.memp_1min, cfg.overload_mempressure_threshold
+            ))
+        } else if self.critical_why.is_some() {
+            Some("resource critical".into())
+        } else {
+            None
+        }
+    }
+
+    fn update(&mut self, cfg: &SideloaderConfig, cpu: CpuSample, pres: &PresSample, now: f64) {
+        self.hist.push(cpu);
+
+        let (nr_headroom, nr_overload) = (cfg.nr_headroom_intvs(), cfg.nr_overload_intvs());
+        self.cpu_cur_idle = self.hist.avg_idle(nr_headroom).min(self.hist.avg_idle(1));
+        self.cpu_cur_side = self.hist.avg_side(nr_headroom).min(self.hist.avg_side(1));
+        self.cpu_avail =
+            (self.cpu_cur_side + self.cpu_cur_idle - cfg.cpu_headroom).max(cfg.cpu_floor);
+        self.cpu_avg_idle = self.hist.avg_idle(nr_overload);
+        self.cpu_avg_side = self.hist.avg_side(nr_overload);
+
+        self.critical_why = Self::check_critical(cfg, pres);
+        match (&self.critical_why, self.critical_at) {
+            (Some(why), None) => {
+                info!("sideloader: Critical, {}", why);
+                events::record(EventKind::SideloaderCritical {
+                    critical: true,
+                    why: why.clone(),
+                });
+                self.critical_at = Some(now);
+            }
+            (None, Some(_)) => {
+                info!("sideloader: Critical condition ended");
+                events::record(EventKind::SideloaderCritical {
+                    critical: false,
+                    why: String::new(),
+                });
+                self.critical_at = None;
+            }
+            _ => {}
+        }
+        if self.critical_why.is_some() {
+            self.overload_hold = cfg.overload_hold_max;
+        }
+
+        match self.check_overload(cfg, pres) {
+            Some(why) => {
+                if self.overload_at.is_none() {
+                    self.overload_at = Some(now);
+                    self.overload_hold =
+                        (cfg.overload_hold + self.overload_hold).min(cfg.overload_hold_max);
+                    info!(
+                        "sideloader: Overloaded, {} (hold={}s)",
+                        &why, self.overload_hold as u64
+                    );
+                    events::record(EventKind::SideloaderOverload {
+                        overload: true,
+                        why: why.clone(),
+                    });
+                }
+                self.overload_why = Some(why);
+                self.overload_hold_from = now;
+            }
+            None => {
+                if self.overload_at.is_some() && now > self.overload_hold_from + self.overload_hold
+                {
+                    info!("sideloader: Overload ended, resuming normal operation");
+                    events::record(EventKind::SideloaderOverload {
+                        overload: false,
+                        why: String::new(),
+                    });
+                    self.overload_at = None;
+                    self.overload_why = None;
+                }
+            }
+        }
+
+        if self.overload_at.is_none() {
+            self.overload_hold = (self.overload_hold - cfg.overload_hold_decay_rate).max(0.0);
+        }
+    }
+}
+
+fn read_cpu_sample() -> Result<(CpuSample, u64)> {
+    let kstat = procfs::KernelStats::new()?;
+    let cpu = &kstat.total;
+    let idle = cpu.idle + cpu.iowait.unwrap_or(0);
+    let total = cpu.user
+        + cpu.nice
+        + cpu.system
+        + idle
+        + cpu.irq.unwrap_or(0)
+        + cpu.softirq.unwrap_or(0)
+        + cpu.steal.unwrap_or(0)
+        + cpu.guest.unwrap_or(0)
+        + cpu.guest_nice.unwrap_or(0);
+    let usecs_per_tick = 1_000_000.0 / procfs::ticks_per_second()? as f64;
+
+    let stat = read_cgroup_flat_keyed_file(&format!("{}/cpu.stat", Slice::Side.cgrp()))?;
+    let side = *stat
+        .get("usage_usec")
+        .ok_or(anyhow!("usage_usec missing in cpu.stat"))?;
+    let nr_throttled = stat.get("nr_throttled").cloned().unwrap_or(0);
+
+    Ok((
+        CpuSample {
+            total: total as f64 * usecs_per_tick,
+            idle: idle as f64 * usecs_per_tick,
+            side: side as f64,
+        },
+        nr_throttled,
+    ))
+}
+
+fn read_full_pressure(knob: &str) -> Result<(f64, f64)> {
+    let path = format!("{}/{}", Slice::Side.cgrp(), knob);
+    let pres = read_cgroup_nested_keyed_file(&path)?;
+    let full = pres
+        .get("full")
+        .ok_or(anyhow!("\"full\" missing in {:?}", &path))?;
+    let avg = |key: &str| -> Result<f64> {
+        Ok(full
+            .get(key)
+            .ok_or(anyhow!("{:?} missing in {:?}", key, &path))?
+            .parse::<f64>()?)
+    };
+    Ok((avg("avg60")?, avg("avg300")?))
+}
+
+fn read_memswap() -> Result<(procfs::Meminfo, u64, u64)> {
+    let mstat = procfs::Meminfo::new()?;
+    let swap_max = match read_one_line(format!("{}/memory.swap.max", Slice::Side.cgrp()))?.trim() {
+        "max" => mstat.swap_total,
+        v => v.parse::<u64>()?,
+    };
+    let swap_cur = read_one_line(format!("{}/memory.swap.current", Slice::Side.cgrp()))?
+        .trim()
+        .parse::<u64>()?;
+    let swap_avail = mstat.swap_total.min(swap_max);
+    let swap_free = swap_avail.saturating_sub(swap_cur).min(mstat.swap_free);
+    Ok((mstat, swap_avail, swap_free))
+}
+
+fn read_pres_sample() -> Result<PresSample> {
+    let (memp_1min, memp_5min) = read_full_pressure("memory.pressure")?;
+    let (_, iop_5min) = read_full_pressure("io.pressure")?;
+    let (mstat, _, swap_free) = read_memswap()?;
+    Ok(PresSample {
+        memp_1min,
+        memp_5min,
+        iop_5min,
+        swap_total: mstat.swap_total,
+        swap_free,
+    })
+}
+
+fn config_cpu_max(cfg: &SideloaderConfig, pct: f64) {
+    let path = format!("{}/cpu.max", Slice::Side.cgrp());
+    let period = (cfg.cpu_throttle_period * 1_000_000.0) as u64;
+    let quota = (nr_cpus() as f64 * period as f64 * pct / 100.0) as u64;
+    let line = format!("{} {}", quota, period);
+
+    if let Ok(cur) = read_one_line(&path) {
+        if cur.trim() == line {
+            return;
+        }
+    }
+    if let Err(e) = write_one_line(&path, &line) {
+        warn!("sideloader: Failed to configure {:?} ({:#})", &path, &e);
+    }
+}
+
+fn side_memory_high(cfg: &SideloaderConfig) -> u64 {
+    (total_memory() as f64 * cfg.side_memory_high) as u64
+}
+
+fn config_side_slice(cfg: &SideloaderConfig) {
+    if let Err(e) = run_command(
+        Command::new("systemctl")
+            .arg("set-property")
+            .arg(Slice::Side.name())
+            .arg(format!("CPUWeight={}", cfg.side_cpu_weight))
+            .arg(format!("MemoryHigh={}", side_memory_high(cfg)))
+            .arg(format!("IOWeight={}", cfg.side_io_weight)),
+        "failed to set sideload.slice properties",
+    ) {
+        warn!(
+            "sideloader: Failed to configure {:?} ({:#})",
+            Slice::Side.name(),
+            &e
+        );
+    }
+}
+
+fn stop_svc(svc_name: &str) {
+    match systemd::Unit::new_sys(svc_name.into()) {
+        Ok(mut unit) => {
+            if let Err(e) = unit.stop_and_reset() {
+                warn!("sideloader: Failed to stop {:?} ({:#})", svc_name, &e);
+            }
+        }
+        Err(e) => warn!("sideloader: Failed to look up {:?} ({:#})", svc_name, &e),
+    }
+}
+
+struct Job {
+    spec: SideloaderJob,
+    ino: u64,
+    svc_name: String,
+    unit: Option<systemd::Unit>,
+    frozen_at: Option<f64>,
+    throttled: bool,
+    kill_why: Option<String>,
+    killed: bool,
+    done: bool,
+}
+
+impl Job {
+    fn new(spec: SideloaderJob, ino: u64) -> Self {
+        Self {
+            svc_name: format!("{}{}.service", SIDELOAD_SVC_PREFIX, &spec.id),
+            spec,
+            ino,
+            unit: None,
+            frozen_at: None,
+            throttled: false,
+            kill_why: None,
+            killed: false,
+            done: false,
+        }
+    }
+
+    fn cgrp(&self) -> String {
+        format!("{}/{}", Slice::Side.cgrp(), &self.svc_name)
+    }
+
+    fn active(&self) -> bool {
+        self.frozen_at.is_none() && !self.done
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let mut cmd = Command::new("systemd-run");
+        cmd.args(["-r", "-p", "TimeoutStopSec=5", "-p", "IOAccounting=true"])
+            .arg("--slice")
+            .arg(Slice::Side.name())
+            .arg("--unit")
+            .arg(&self.svc_name)
+            .arg("--working-directory")
+            .arg(&self.spec.working_dir);
+        for prop in self.spec.properties.iter() {
+            cmd.arg("-p").arg(prop);
+        }
+        for env in self.spec.envs.iter() {
+            cmd.arg("-E").arg(env);
+        }
+        cmd.args(&self.spec.args).stdout(Stdio::null());
+        run_command(&mut cmd, "failed to start sideload")
+    }
+
+    fn set_frozen(&mut self, freeze: bool, now: f64) {
+        let changed = match (self.frozen_at, freeze) {
+            (None, true) => {
+                self.frozen_at = Some(now);
+                true
+            }
+            (Some(_), false) => {
+                self.frozen_at = None;
+                true
+            }
+            _ => false,
+        };
+
+        let path = format!("{}/cgroup.freeze", self.cgrp());
+        if !Path::new(&path).exists() {
+            if changed {
+                warn!("sideloader: Failed to freeze {:?}", &self.spec.id);
+            }
+            return;
+        }
+
+        let val = if freeze { "1" } else { "0" };
+        if let Ok(cur) = read_one_line(&path) {
+            if cur.trim() == val {
+                return;
+            }
+        }
+        if let Err(e) = write_one_line(&path, val) {
+            warn!("sideloader: Failed to update {:?} ({:#})", &path, &e);
+        }
+    }
+
+    fn maybe_kill(&self) {
+        if self.kill_why.is_none() {
+            return;
+        }
+        let pids: Vec<i32> = match fs::read_to_string(format!("{}/cgroup.procs", self.cgrp())) {
+            Ok(v) => v.lines().filter_map(|x| x.trim().parse().ok()).collect(),
+            Err(_) => return,
+        };
+        if !pids.is_empty() {
+            debug!("sideloader: Killing {:?} {:?}", &self.spec.id, &pids);
+            for pid in pids.iter() {
+                unsafe {
+                    libc::kill(*pid, libc::SIGKILL);
+                }
+            }
+            info!(
+                "sideloader: Attempted to kill {:?} ({} processes)",
+                &self.spec.id,
+                pids.len()
+            );
+        }
+    }
+
+    fn kill(&mut self, why: &str) {
+        if self.kill_why.is_none() {
+            self.kill_why = Some(why.into());
+            events::record(EventKind::SideloadKill {
+                id: self.spec.id.clone(),
+                why: why.into(),
+            });
+        }
+        self.maybe_kill();
+    }
+
+    fn refresh(&mut self) {
+        if self.unit.is_none() {
+            self.unit = systemd::Unit::new_sys(self.svc_name.clone()).ok();
+        }
+        if let Some(unit) = self.unit.as_mut() {
+            unit.quiet = true;
+            if unit.refresh().is_ok() {
+                match unit.state {
+                    US::Exited => self.done = true,
+                    US::Failed(_) => {
+                        self.done = true;
+                        self.killed = true;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn report(&self, now: f64) -> SideloaderJobReport {
+        SideloaderJobReport {
+            pending: false,
+            frozen: self.frozen_at.is_some(),
+            frozen_for: self.frozen_at.map(|at| now - at).unwrap_or(0.0),
+            throttled: self.throttled,
+            done: self.done,
+            killed: self.killed,
+            kill_why: self.kill_why.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// System configuration sanity checks. Fixing is left to slices.rs and
+/// friends, only warnings are generated here.
+struct SysCfgChecker {
+    dev: String,
+    devnr: (u32, u32),
+    checked_at: f64,
+    warns: Vec<String>,
+}
+
+impl SysCfgChecker {
+    fn check_rootfs() -> Vec<String> {
+        let mounts = match fs::read_to_string("/proc/mounts") {
+            Ok(v) => v,
+            Err(e) => return vec![format!("failed to read /proc/mounts ({})", &e)],
+        };
+        let root = mounts
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+            .find(|toks| toks.len() >= 4 && toks[1] == "/");
+        match root {
+            None => vec!["failed to find root fs mount entry".into()],
+            Some(toks) if toks[2] != "btrfs" => vec!["root filesystem is not btrfs".into()],
+            Some(toks) if !toks[3].contains("discard=async") => {
+                vec!["async discard disabled on root fs".into()]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn check_memswap(cfg: &SideloaderConfig) -> Vec<String> {
+        let (mstat, swap_avail, _) = match read_memswap() {
+            Ok(v) => v,
+            Err(e) => return vec![format!("failed to read memory and swap states ({:#})", &e)],
+        };
+        let mut warns = vec![];
+
+        if (swap_avail as f64) < 0.9 * (mstat.mem_total as f64 / 4.0) {
+            warns.push(format!(
+                "available swap ({:.2}G) is smaller than 1/4 of physical memory",
+                to_gb(swap_avail)
+            ));
+        }
+        if (swap_avail as f64) < 0.9 * cfg.side_swap_max * mstat.swap_total as f64 {
+            warns.push(format!(
+                "available swap ({:.2}G) is smaller than side-swap-max",
+                to_gb(swap_avail)
+            ));
+        }
+        match read_swappiness() {
+            Ok(v) if v < 60 => warns.push(format!("swappiness ({}) is lower than default 60", v)),
+            Ok(_) => {}
+            Err(e) => warns.push(format!("failed to read swappiness ({:#})", &e)),
+        }
+        warns
+    }
+
+    fn check_freezer() -> Vec<String> {
+        match Path::new(&format!("{}/cgroup.freeze", Slice::Side.cgrp())).exists() {
+            true => vec![],
+            false => vec!["freezer is not available".into()],
+        }
+    }
+
+    fn check_io_latency_off(&self) -> Vec<String> {
+        let devnr = format!("{}:{}", self.devnr.0, self.devnr.1);
+        let mut warns = vec![];
+        for path in glob::glob("/sys/fs/cgroup/**/io.latency")
+            .unwrap()
+            .filter_map(Result::ok)
+        {
+            let path = path.to_string_lossy().to_string();
+            match read_cgroup_nested_keyed_file(&path) {
+                Ok(latcfg) if latcfg.contains_key(&devnr) => {
+                    warns.push(format!("{} has non-null config", &path))
+                }
+                Ok(_) => {}
+                Err(e) => warns.push(format!("failed to check {} ({:#})", &path, &e)),
+            }
+        }
+        warns
+    }
+
+    fn read_mem_knob(path: &str, max: u64) -> Result<u64> {
+        Ok(match read_one_line(path)?.trim() {
+            "max" => max,
+            v => v.parse::<u64>()?,
         })
     }
 
-    fn update_cfg_file(&self, cmd: &SideloaderCmd, slice_knobs: &SliceKnobs) -> Result<()> {
-        let cfg = sideloader_config(cmd.cpu_headroom * 100.0, slice_knobs);
-        if let Ok(mut f) = fs::OpenOptions::new()
-            .read(true)
-            .open(&self.daemon_cfg_path)
+    fn check_main_memory_low() -> Vec<String> {
+        let mstat = match procfs::Meminfo::new() {
+            Ok(v) => v,
+            Err(e) => return vec![format!("failed to read meminfo ({:#})", &e)],
+        };
+        let hugetlb = mstat.hugepages_total.unwrap_or(0) * mstat.hugepagesize.unwrap_or(0);
+        let path = format!("{}/memory.low", Slice::Work.cgrp());
+        match Self::read_mem_knob(&path, mstat.mem_total) {
+            Ok(low) if low < (mstat.mem_total.saturating_sub(hugetlb)) / 3 => {
+                vec![format!("{} is lower than a third of system memory", &path)]
+            }
+            Ok(_) => vec![],
+            Err(e) => vec![format!("failed to check {} ({:#})", &path, &e)],
+        }
+    }
+
+    fn check_side_memory_high(cfg: &SideloaderConfig) -> Vec<String> {
+        let mem_total = total_memory() as u64;
+        let target = side_memory_high(cfg);
+        let path = format!("{}/memory.high", Slice::Side.cgrp());
+        match Self::read_mem_knob(&path, mem_total) {
+            Ok(high) if high >> 20 != target >> 20 => {
+                let mut warns = vec![format!(
+                    "{} memory.high is not {}, fixing",
+                    Slice::Side.name(),
+                    target
+                )];
+                if let Err(e) = write_one_line(&path, &format!("{}", target)) {
+                    warns.push(format!("failed to fix {} ({:#})", &path, &e));
+                }
+                warns
+            }
+            Ok(_) => vec![],
+            Err(e) => vec![format!("failed to check {} ({:#})", &path, &e)],
+        }
+    }
+
+    fn check_weight(slice: Slice, knob: &str, weight: u32) -> Vec<String> {
+        let path = format!("{}/{}", slice.cgrp(), knob);
+        let cur = read_one_line(&path).and_then(|line| {
+            // io.weight is prefixed with "default"
+            let tok = line.split_whitespace().last().unwrap_or("").to_string();
+            Ok(tok.parse::<u32>()?)
+        });
+        match cur {
+            Ok(v) if v == weight => vec![],
+            Ok(_) => vec![format!("{}/{} != {}", slice.name(), knob, weight)],
+            Err(e) => vec![format!("failed to check {} ({:#})", &path, &e)],
+        }
+    }
+
+    fn check_weights(knob: &str, weights: &[(Slice, u32)]) -> Vec<String> {
+        weights
+            .iter()
+            .flat_map(|(slice, weight)| Self::check_weight(*slice, knob, *weight))
+            .collect()
+    }
+
+    fn check_cpu_weights(cfg: &SideloaderConfig) -> Vec<String> {
+        match read_one_line("/sys/fs/cgroup/cgroup.subtree_control") {
+            Ok(line) if line.split_whitespace().any(|x| x == "cpu") => {}
+            _ => return vec!["cpu controller not enabled at root".into()],
+        }
+        Self::check_weights(
+            "cpu.weight",
+            &[
+                (Slice::Work, cfg.main_cpu_weight),
+                (Slice::Host, cfg.host_cpu_weight),
+                (Slice::Side, cfg.side_cpu_weight),
+            ],
+        )
+    }
+
+    fn check_io_weights(&self, cfg: &SideloaderConfig) -> Vec<String> {
+        let devnr = format!("{}:{}", self.devnr.0, self.devnr.1);
+        let enabled = match read_cgroup_nested_keyed_file("/sys/fs/cgroup/io.cost.qos") {
+            Ok(qos) => qos
+                .get(&devnr)
+                .and_then(|v| v.get("enable"))
+                .map(|v| v == "1")
+                .unwrap_or(false),
+            Err(e) => {
+                return vec![format!(
+                    "failed to verify iocost for {} ({:#})",
+                    &self.dev, &e
+                )]
+            }
+        };
+        if !enabled {
+            return vec![format!("iocost not enabled on {}", &self.dev)];
+        }
+        Self::check_weights(
+            "io.weight",
+            &[
+                (Slice::Work, cfg.main_io_weight),
+                (Slice::Host, cfg.host_io_weight),
+                (Slice::Side, cfg.side_io_weight),
+            ],
+        )
+    }
+
+    fn check(&mut self, cfg: &SideloaderConfig, now: f64) {
+        let mut warns = vec![];
+        warns.append(&mut Self::check_rootfs());
+        warns.append(&mut Self::check_memswap(cfg));
+        warns.append(&mut Self::check_freezer());
+        warns.append(&mut self.check_io_latency_off());
+        warns.append(&mut Self::check_main_memory_low());
+        warns.append(&mut Self::check_side_memory_high(cfg));
+        warns.append(&mut Self::check_cpu_weights(cfg));
+        warns.append(&mut self.check_io_weights(cfg));
+
+        if warns != self.warns {
+            if warns.is_empty() {
+                info!("sideloader: System configuration all good");
+            }
+            for (i, w) in warns.iter().enumerate() {
+                warn!("sideloader: SYSCFG[{}] {}", i, w);
+            }
+        }
+        self.warns = warns;
+        self.checked_at = now;
+    }
+
+    fn periodic_check(&mut self, cfg: &SideloaderConfig, intv: f64, now: f64) {
+        if now - self.checked_at >= intv {
+            self.check(cfg, now);
+        }
+    }
+}
+
+struct SideloaderShared {
+    cfg: SideloaderConfig,
+    report: SideloaderReport,
+}
+
+struct Worker {
+    shared: Arc<Mutex<SideloaderShared>>,
+    term_rx: Receiver<()>,
+    jobs_path: String,
+    status_path: String,
+    gov: Governor,
+    syscfg: SysCfgChecker,
+    job_files: BTreeMap<u64, String>,
+    jobs: BTreeMap<String, Job>,
+    pending: BTreeMap<String, Job>,
+    last_nr_throttled: u64,
+}
+
+impl Worker {
+    /// Scan jobs.d and return the job files keyed by inode number so that
+    /// replaced files are detected.
+    fn scan_job_files(&self) -> BTreeMap<u64, String> {
+        let mut files = BTreeMap::new();
+        for path in glob::glob(&format!("{}/*.json", &self.jobs_path))
+            .unwrap()
+            .filter_map(Result::ok)
         {
-            let mut buf = String::new();
-            if let Ok(_) = f.read_to_string(&mut buf) {
-                if cfg == buf {
-                    return Ok(());
+            match fs::symlink_metadata(&path) {
+                Ok(md) if md.file_type().is_file() => {
+                    files.insert(md.ino(), path.to_string_lossy().to_string());
                 }
+                Ok(_) => warn!("sideloader: Invalid file type for {:?}", &path),
+                Err(e) => warn!("sideloader: Failed to stat {:?} ({})", &path, &e),
             }
         }
+        files
+    }
 
-        let mut f = fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&self.daemon_cfg_path)?;
-        f.write_all(cfg.as_ref())?;
-        Ok(())
+    fn process_jobs_dir(&mut self) {
+        let files = self.scan_job_files();
+
+        let gone: Vec<String> = self
+            .jobs
+            .iter()
+            .chain(self.pending.iter())
+            .filter(|(_, job)| !files.contains_key(&job.ino))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in gone.iter() {
+            if let Some(job) = self.jobs.remove(id) {
+                info!("sideloader: Stopping {:?}", &job.svc_name);
+                stop_svc(&job.svc_name);
+            }
+            self.pending.remove(id);
+        }
+        self.job_files.retain(|ino, _| files.contains_key(ino));
+
+        for (ino, path) in files.into_iter() {
+            if self.job_files.contains_key(&ino) {
+                continue;
+            }
+            match SideloaderJobs::load(&path) {
+                Ok(jobs) => {
+                    for spec in jobs.sideloader_jobs.into_iter() {
+                        if self.jobs.contains_key(&spec.id) || self.pending.contains_key(&spec.id) {
+                            warn!("sideloader: Duplicate job id {:?} in {:?}", &spec.id, &path);
+                            continue;
+                        }
+                        self.pending.insert(spec.id.clone(), Job::new(spec, ino));
+                    }
+                }
+                Err(e) => warn!("sideloader: Failed to load {:?} ({:#})", &path, &e),
+            }
+            self.job_files.insert(ino, path);
+        }
     }
 
-    pub fn apply(&mut self, cmd: &SideloaderCmd, slice_knobs: &SliceKnobs) -> Result<()> {
-        self.update_cfg_file(cmd, slice_knobs)?;
-        trace!("sideloader state {:?}", self.svc.unit.state);
-        match self.svc.unit.state {
-            systemd::UnitState::Running => Ok(()),
-            _ => self.svc.start(),
+    /// Stop sideloads left over from an earlier instance.
+    fn stop_strays(&mut self) {
+        self.process_jobs_dir();
+        for path in glob::glob(&format!(
+            "{}/{}*.service",
+            Slice::Side.cgrp(),
+            SIDELOAD_SVC_PREFIX
+        ))
+        .unwrap()
+        .filter_map(Result::ok)
+        {
+            let svc_name = path.file_name().unwrap().to_string_lossy().to_string();
+            let id = &svc_name[SIDELOAD_SVC_PREFIX.len()..svc_name.len() - ".service".len()];
+            if !self.pending.contains_key(id) {
+                info!("sideloader: Stopping stray service {:?}", &svc_name);
+                stop_svc(&svc_name);
+            }
         }
     }
 
-    pub fn report(&mut self) -> Result<SideloaderReport> {
-        let mut rep: SideloaderReport = Default::default();
+    fn step(&mut self, cfg: &SideloaderConfig, now: f64) -> Result<()> {
+        self.process_jobs_dir();
 
-        rep.svc = super::svc_refresh_and_report(&mut self.svc.unit)?;
-        if rep.svc.state != SvcStateReport::Running {
-            return Ok(rep);
+        if !self.gov.overloaded() {
+            for (id, mut job) in std::mem::take(&mut self.pending).into_iter() {
+                info!("sideloader: Starting {:?}", &job.svc_name);
+                if let Err(e) = job.start() {
+                    warn!("sideloader: Failed to start {:?} ({:#})", &id, &e);
+                }
+                self.jobs.insert(id, job);
+            }
         }
 
-        let sf = match JsonRawFile::load(&self.daemon_status_path) {
-            Ok(v) => v,
-            Err(e) => match e.downcast_ref::<io::Error>() {
-                Some(ie) if ie.raw_os_error() == Some(libc::ENOENT) => return Ok(rep),
-                _ => bail!("failed to read {:?} ({:?})", &self.daemon_status_path, &e),
-            },
+        let (cpu, nr_throttled) = read_cpu_sample()?;
+        let pres = read_pres_sample()?;
+        self.gov.update(cfg, cpu, &pres, now);
+
+        let intv = match self.jobs.len() {
+            0 => SYSCFG_INTV_IDLE,
+            _ => SYSCFG_INTV_ACTIVE,
         };
+        self.syscfg.periodic_check(cfg, intv, now);
 
-        let stat = &sf.value["sideloader_status"];
+        if let Some(why) = self.gov.critical_why.as_ref() {
+            let why = format!("resource critical, {}", why);
+            for job in self.jobs.values_mut() {
+                job.kill(&why);
+            }
+        }
+
+        let overloaded = self.gov.overloaded();
+        for job in self.jobs.values_mut() {
+            job.set_frozen(overloaded, now);
+            if let Some(at) = job.frozen_at {
+                if now - at >= job.spec.frozen_expiration as f64 {
+                    job.kill("frozen for too long");
+                }
+            }
+            job.maybe_kill();
+        }
+
+        if self.jobs.values().any(|job| job.active()) {
+            config_cpu_max(cfg, self.gov.cpu_avail);
+        }
+
+        let throttled = nr_throttled > self.last_nr_throttled;
+        self.last_nr_throttled = nr_throttled;
+        for job in self.jobs.values_mut() {
+            job.refresh();
+            job.throttled = throttled && job.active();
+        }
+
+        let rep = self.report(now);
+        if let Err(e) = rep.save(&self.status_path) {
+            warn!(
+                "sideloader: Failed to save {:?} ({:#})",
+                &self.status_path, &e
+            );
+        }
+        self.shared.lock().unwrap().report = rep;
+        Ok(())
+    }
 
-        rep.sysconf_warnings = stat["sysconfig_warnings"]
-            .as_array()
-            .unwrap_or(&vec![])
+    fn report(&self, now: f64) -> SideloaderReport {
+        let mut jobs: BTreeMap<String, SideloaderJobReport> = self
+            .jobs
             .iter()
-            .map(|x| x.as_str().unwrap_or("UNKNOWN").into())
+            .map(|(id, job)| (id.clone(), job.report(now)))
             .collect();
+        for id in self.pending.keys() {
+            jobs.insert(
+                id.clone(),
+                SideloaderJobReport {
+                    pending: true,
+                    ..Default::default()
+                },
+            );
+        }
+
+        SideloaderReport {
+            svc: Default::default(),
+            sysconf_warnings: self.syscfg.warns.clone(),
+            overload: self.gov.overloaded(),
+            overload_why: self.gov.overload_why.clone().unwrap_or_default(),
+            critical: self.gov.critical(),
+            critical_why: self.gov.critical_why.clone().unwrap_or_default(),
+            overload_hold: self.gov.hold_left(now),
+            cpu_avail: self.gov.cpu_avail,
+            jobs,
+        }
+    }
+
+    fn run_inner(mut self) {
+        self.stop_strays();
+        let cfg = self.shared.lock().unwrap().cfg.clone();
+        config_side_slice(&cfg);
+        loop {
+            let cfg = self.shared.lock().unwrap().cfg.clone();
+            if let Err(e) = self.step(&cfg, unix_now_f64()) {
+                warn!("sideloader: Failed to update ({:#})", &e);
+            }
+            match self.term_rx.recv_timeout(Duration::from_secs_f64(INTV)) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => break,
+            }
+        }
+    }
 
-        rep.overload = stat["overload"]["overload_for"].as_f64().unwrap_or(0.0) > 0.0;
-        rep.overload_why = stat["overload"]["overload_why"]
-            .as_str()
-            .unwrap_or("")
-            .into();
+    fn run(self) {
+        if let Err(e) = panic::catch_unwind(panic::AssertUnwindSafe(|| self.run_inner())) {
+            error!("sideloader: Worker thread panicked ({:?})", &e);
+            set_prog_exiting();
+        }
+    }
+}
+
+pub struct Sideloader {
+    jobs_path: String,
+    status_path: String,
+    dev: String,
+    devnr: (u32, u32),
+    shared: Arc<Mutex<SideloaderShared>>,
+    term_tx: Option<Sender<()>>,
+    jh: Option<JoinHandle<()>>,
+}
 
-        rep.critical = stat["overload"]["critical_for"].as_f64().unwrap_or(0.0) > 0.0;
-        rep.critical_why = stat["overload"]["critical_why"]
-            .as_str()
-            .unwrap_or("")
-            .into();
+impl Sideloader {
+    pub fn new(cfg: &Config) -> Result<Self> {
+        Ok(Self {
+            jobs_path: cfg.sideloader_daemon_jobs_path.clone(),
+            status_path: cfg.sideloader_daemon_status_path.clone(),
+            dev: cfg.scr_dev.clone(),
+            devnr: cfg.scr_devnr,
+            shared: Arc::new(Mutex::new(SideloaderShared {
+                cfg: Default::default(),
+                report: Default::default(),
+            })),
+            term_tx: None,
+            jh: None,
+        })
+    }
 
+    pub fn is_running(&self) -> bool {
+        self.jh.is_some()
+    }
+
+    fn start(&mut self) {
+        let cfg = self.shared.lock().unwrap().cfg.clone();
+        let (term_tx, term_rx) = channel::unbounded::<()>();
+        let worker = Worker {
+            shared: self.shared.clone(),
+            term_rx,
+            jobs_path: self.jobs_path.clone(),
+            status_path: self.status_path.clone(),
+            gov: Governor::new(&cfg),
+            syscfg: SysCfgChecker {
+                dev: self.dev.clone(),
+                devnr: self.devnr,
+                checked_at: 0.0,
+                warns: vec![],
+            },
+            job_files: BTreeMap::new(),
+            jobs: BTreeMap::new(),
+            pending: BTreeMap::new(),
+            last_nr_throttled: 0,
+        };
+        info!(
+            "sideloader: Starting, sideloads in {}, main workloads in {}",
+            Slice::Side.name(),
+            Slice::Work.name()
+        );
+        self.term_tx = Some(term_tx);
+        self.jh = Some(spawn(move || worker.run()));
+    }
+
+    pub fn stop(&mut self) {
+        self.term_tx.take();
+        if let Some(jh) = self.jh.take() {
+            jh.join().unwrap();
+            info!("sideloader: Stopped");
+        }
+        self.shared.lock().unwrap().report = Default::default();
+    }
+
+    pub fn apply(&mut self, cmd: &SideloaderCmd, slice_knobs: &SliceKnobs) -> Result<()> {
+        let cfg = SideloaderConfig::new(cmd, slice_knobs);
+        let mut shared = self.shared.lock().unwrap();
+        if shared.cfg != cfg {
+            debug!("sideloader: Updating config to {:?}", &cfg);
+            shared.cfg = cfg;
+        }
+        drop(shared);
+
+        if !self.is_running() {
+            self.start();
+        }
+        Ok(())
+    }
+
+    pub fn report(&mut self) -> Result<SideloaderReport> {
+        let mut rep = self.shared.lock().unwrap().report.clone();
+        rep.svc = SvcReport {
+            name: "sideloader".into(),
+            state: match self.is_running() {
+                true => SvcStateReport::Running,
+                false => SvcStateReport::Exited,
+            },
+        };
         Ok(rep)
     }
 }
+
+impl Drop for Sideloader {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Feeder {
+        cfg: SideloaderConfig,
+        gov: Governor,
+        cpu: CpuSample,
+        now: f64,
+    }
+
+    impl Feeder {
+        fn new() -> Self {
+            let cfg = SideloaderConfig::default();
+            Self {
+                gov: Governor::new(&cfg),
+                cfg,
+                cpu: Default::default(),
+                now: 0.0,
+            }
+        }
+
+        /// Advance `secs` intervals with the given idle and side CPU pcts.
+        fn feed(&mut self, secs: usize, idle: f64, side: f64, pres: &PresSample) {
+            for _ in 0..secs {
+                self.cpu.total += 100.0;
+                self.cpu.idle += idle;
+                self.cpu.side += side;
+                self.now += INTV;
+                self.gov.update(&self.cfg, self.cpu, pres, self.now);
+            }
+        }
+    }
+
+    fn approx_eq(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    fn calm() -> PresSample {
+        PresSample {
+            swap_total: 1 << 30,
+            swap_free: 1 << 29,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cpu_hist() {
+        let mut hist = CpuHist::new(4);
+        assert_eq!(hist.avg_idle(1), 0.0);
+        for i in 0..10 {
+            let i = i as f64;
+            hist.push(CpuSample {
+                total: 100.0 * i,
+                idle: 50.0 * i,
+                side: i * i,
+            });
+        }
+        assert_eq!(hist.hist.len(), 5);
+        assert!(approx_eq(hist.avg_idle(4), 50.0));
+        assert!(approx_eq(hist.avg_side(1), 17.0));
+        assert!(approx_eq(hist.avg_side(4), 14.0));
+        // not enough history
+        assert_eq!(hist.avg_idle(5), 0.0);
+    }
+
+    #[test]
+    fn test_headroom() {
+        let mut f = Feeder::new();
+        let pres = calm();
+
+        // Overloaded until the CPU history fills up.
+        f.feed(1, 80.0, 0.0, &pres);
+        assert!(f.gov.overloaded());
+        f.feed(30, 80.0, 0.0, &pres);
+        assert!(!f.gov.overloaded());
+        assert!(approx_eq(f.gov.cpu_avail, 60.0));
+
+        // CPU used by sideloads is available to them.
+        f.feed(30, 10.0, 50.0, &pres);
+        assert!(!f.gov.overloaded());
+        assert!(approx_eq(f.gov.cpu_avail, 40.0));
+
+        // But never goes below cpu_floor.
+        f.feed(3, 5.0, 0.0, &pres);
+        assert_eq!(f.gov.cpu_avail, f.cfg.cpu_floor);
+    }
+
+    #[test]
+    fn test_overload() {
+        let mut f = Feeder::new();
+        let pres = calm();
+        f.feed(60, 80.0, 0.0, &pres);
+        assert!(!f.gov.overloaded());
+
+        // The 10s average idle drops below headroom + min_avail after 7s.
+        f.feed(6, 5.0, 0.0, &pres);
+        assert!(!f.gov.overloaded());
+        f.feed(1, 5.0, 0.0, &pres);
+        assert!(f.gov.overloaded());
+        assert!(f.gov.overload_why.as_ref().unwrap().contains("cpu margin"));
+        let last_bad = f.now;
+
+        // Stays overloaded for at least overload_hold after recovering.
+        let mut ended_at = None;
+        for _ in 0..60 {
+            f.feed(1, 80.0, 0.0, &pres);
+            if !f.gov.overloaded() {
+                ended_at = Some(f.now);
+                break;
+            }
+        }
+        let ended_at = ended_at.unwrap();
+        assert!(ended_at - last_bad > f.cfg.overload_hold);
+        assert!(ended_at - last_bad <= f.cfg.overload_hold_max + 10.0);
+        assert!(f.gov.overload_why.is_none());
+
+        // Memory pressure overloads too.
+        f.feed(60, 80.0, 0.0, &pres);
+        let memp = PresSample {
+            memp_1min: 60.0,
+            ..pres
+        };
+        f.feed(1, 80.0, 0.0, &memp);
+        assert!(f.gov.overloaded());
+        assert!(!f.gov.critical());
+        assert!(f
+            .gov
+            .overload_why
+            .as_ref()
+            .unwrap()
+            .contains("memory pressure"));
+    }
+
+    #[test]
+    fn test_critical() {
+        let mut f = Feeder::new();
+        let pres = calm();
+        f.feed(60, 80.0, 0.0, &pres);
+
+        let iop = PresSample {
+            iop_5min: 80.0,
+            ..pres
+        };
+        f.feed(1, 80.0, 0.0, &iop);
+        assert!(f.gov.critical());
+        assert!(f.gov.overloaded());
+        assert!(f.gov.critical_why.as_ref().unwrap().contains("io pressure"));
+        assert_eq!(f.gov.overload_why.as_deref(), Some("resource critical"));
+        assert_eq!(f.gov.hold_left(f.now), f.cfg.overload_hold_max);
+
+        // Critical ends immediately but overload is held.
+        f.feed(1, 80.0, 0.0, &pres);
+        assert!(!f.gov.critical());
+        assert!(f.gov.overloaded());
+        f.feed(f.cfg.overload_hold_max as usize, 80.0, 0.0, &pres);
+        assert!(!f.gov.overloaded());
+
+        // Swap running out is critical. 10% of the half of 1G is ~51M.
+        let swap = PresSample {
+            swap_free: 50 << 20,
+            ..pres
+        };
+        f.feed(1, 80.0, 0.0, &swap);
+        assert!(f.gov.critical());
+        assert!(f.gov.critical_why.as_ref().unwrap().contains("swap-left"));
+
+        // Without swap, there's nothing to run out of.
+        let noswap = PresSample {
+            swap_total: 0,
+            swap_free: 0,
+            ..pres
+        };
+        f.feed(1, 80.0, 0.0, &noswap);
+        assert!(!f.gov.critical());
+    }
+}
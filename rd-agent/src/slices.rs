@@ -1,24 +1,41 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
-use anyhow::{Context, Result};
-use enum_iterator::IntoEnumIterator;
+use anyhow::{anyhow, Context, Result};
 use glob::glob;
 use log::{debug, error, info, trace, warn};
 use scan_fmt::scan_fmt;
+use std::collections::BTreeMap;
 use std::ffi::{OsStr, OsString};
-use std::fmt::Write;
+use std::fmt::{self, Write};
 use std::fs;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use super::events;
 use super::Config;
 use rd_agent_intf::{
-    DisableSeqKnobs, EnforceConfig, MemoryKnob, MissedSysReqs, Slice, SliceConfig, SliceKnobs,
-    SysReq,
+    DisableSeqKnobs, EnforceConfig, EventKind, IoBackend, MemoryKnob, MissedSysReqs, Slice,
+    SliceConfig, SliceKnobs, SlicePath, SysReq,
 };
 use rd_util::systemd::UnitState as US;
 use rd_util::*;
 
-pub fn check_other_io_controllers(sr_failed: &mut MissedSysReqs) {
+/// io.max and io.latency files which are configured by us and thus
+/// shouldn't be considered as other IO controller configurations.
+pub fn own_io_ctrl_files(knobs: &SliceKnobs) -> Vec<String> {
+    let mut files = vec![];
+    for slice in knobs.managed_slices() {
+        let sk = &knobs.slices[slice.name()];
+        if !sk.io_max.is_empty() {
+            files.push(slice.cgrp().to_string() + "/io.max");
+        }
+        if knobs.io_backend == IoBackend::IoLatency && sk.io_latency_target.is_some() {
+            files.push(slice.cgrp().to_string() + "/io.latency");
+        }
+    }
+    files
+}
+
+pub fn check_other_io_controllers(sr_failed: &mut MissedSysReqs, own_files: &[String]) {
     let mut failed = None;
     let mut nr_fails = 0;
 
@@ -27,6 +44,7 @@ pub fn check_other_io_controllers(sr_failed: &mut MissedSysReqs) {
         .chain(glob("/sys/fs/cgroup/**/io.max").unwrap())
         .chain(glob("/sys/fs/cgroup/**/io.low").unwrap())
         .filter_map(Result::ok)
+        .filter(|path| !own_files.iter().any(|own| path == Path::new(own)))
     {
         match read_one_line(&path) {
             Ok(line) if line.trim().len() == 0 => continue,
@@ -74,40 +92,81 @@ fn mknob_to_unit_resctl(knob: &MemoryKnob) -> Option<u64> {
     }
 }
 
-fn slice_needs_mem_prot_propagation(slice: Slice) -> bool {
-    match slice {
-        Slice::Work | Slice::Side => false,
-        _ => true,
-    }
+fn slice_needs_mem_prot_propagation(slice: &SlicePath) -> bool {
+    !matches!(slice.builtin(), Some(Slice::Work) | Some(Slice::Side))
 }
 
-fn slice_needs_start_stop(slice: Slice) -> bool {
-    match slice {
-        Slice::Side => true,
-        _ => false,
-    }
+// User slices may not have any members yet, start them so that the
+// cgroups exist. Only sideload.slice is stopped on exit as stopping a
+// slice kills everything in it.
+fn slice_needs_start(slice: &SlicePath) -> bool {
+    matches!(slice.builtin(), Some(Slice::Side) | None)
 }
 
-fn slice_needs_crit_mem_prot(slice: Slice) -> bool {
-    match slice {
-        Slice::Host | Slice::Init => true,
-        _ => false,
-    }
+fn slice_needs_stop(slice: &SlicePath) -> bool {
+    slice.builtin() == Some(Slice::Side)
+}
+
+fn slice_needs_crit_mem_prot(slice: &SlicePath) -> bool {
+    matches!(slice.builtin(), Some(Slice::Host) | Some(Slice::Init))
 }
 
-fn slice_enforce_mem(ecfg: &EnforceConfig, slice: Slice) -> bool {
+fn slice_enforce_mem(ecfg: &EnforceConfig, slice: &SlicePath) -> bool {
     ecfg.mem || (ecfg.crit_mem_prot && slice_needs_crit_mem_prot(slice))
 }
 
-fn build_configlet(
-    slice: Slice,
-    cpu_weight: Option<u32>,
-    io_weight: Option<u32>,
-    mem_min: Option<MemoryKnob>,
-    mem_low: Option<MemoryKnob>,
-    mem_high: Option<MemoryKnob>,
-) -> String {
-    let section = if slice.name().ends_with(".slice") {
+/// Cgroups of other managed slices nested under `slice`. Their configs
+/// are managed directly and should be skipped when propagating.
+fn nested_slices<'a>(slice: &SlicePath, all: &'a [SlicePath]) -> Vec<&'a str> {
+    let prefix = slice.cgrp().to_string() + "/";
+    all.iter()
+        .map(|sp| sp.cgrp())
+        .filter(|cgrp| cgrp.starts_with(&prefix))
+        .collect()
+}
+
+fn is_nested(path: &Path, nested: &[&str]) -> bool {
+    nested.iter().any(|cgrp| path.starts_with(cgrp))
+}
+
+/// Which parts of a SliceConfig are applied to a given slice.
+#[derive(Debug, Default)]
+struct SliceEnforce {
+    cpu: bool,
+    mem: bool,
+    mem_low: bool,
+    io: bool,
+    io_lat: Option<(u32, u32)>,
+    pids: bool,
+}
+
+impl SliceEnforce {
+    fn new(ecfg: &EnforceConfig, slice: &SlicePath) -> Self {
+        let mem = slice_enforce_mem(ecfg, slice);
+        Self {
+            cpu: ecfg.cpu,
+            mem,
+            mem_low: mem,
+            io: ecfg.io,
+            io_lat: None,
+            pids: ecfg.pids,
+        }
+    }
+
+    fn any(&self) -> bool {
+        self.cpu || self.mem || self.io || self.pids
+    }
+}
+
+fn io_max_to_systemd_string(v: Option<u64>) -> String {
+    match v {
+        Some(v) => format!("{}", v),
+        None => "infinity".to_string(),
+    }
+}
+
+fn build_configlet(slice: &SlicePath, sk: &SliceConfig, enf: &SliceEnforce) -> String {
+    let section = if slice.unit_name().ends_with(".slice") {
         "Slice"
     } else {
         "Scope"
@@ -119,27 +178,98 @@ fn build_configlet(
         section
     );
 
-    if let Some(w) = cpu_weight {
-        writeln!(buf, "CPUWeight={}", w).unwrap();
-    }
-    if let Some(w) = io_weight {
-        writeln!(buf, "IOWeight={}", w).unwrap();
+    if enf.cpu {
+        writeln!(buf, "CPUWeight={}", sk.cpu_weight).unwrap();
+        writeln!(buf, "AllowedCPUs={}", &sk.cpuset_cpus).unwrap();
+        match sk.cpu_max_quota {
+            Some(quota) => writeln!(
+                buf,
+                "CPUQuota={:.2}%",
+                quota as f64 / sk.cpu_max_period as f64 * 100.0
+            )
+            .unwrap(),
+            None => writeln!(buf, "CPUQuota=").unwrap(),
+        }
+        writeln!(buf, "CPUQuotaPeriodSec={}us", sk.cpu_max_period).unwrap();
     }
-    if let Some(m) = mem_min {
-        writeln!(buf, "MemoryMin={}", mknob_to_systemd_string(&m, false)).unwrap();
+    if enf.io {
+        writeln!(buf, "IOWeight={}", sk.io_weight).unwrap();
+        for (devnr, iom) in sk.io_max.iter() {
+            let dev = format!("/dev/block/{}", devnr);
+            for (key, v) in &[
+                ("IOReadBandwidthMax", iom.rbps),
+                ("IOWriteBandwidthMax", iom.wbps),
+                ("IOReadIOPSMax", iom.riops),
+                ("IOWriteIOPSMax", iom.wiops),
+            ] {
+                writeln!(buf, "{}={} {}", key, &dev, io_max_to_systemd_string(*v)).unwrap();
+            }
+        }
+        if let (Some(devnr), Some(target)) = (enf.io_lat, sk.io_latency_target) {
+            writeln!(
+                buf,
+                "IODeviceLatencyTargetSec=/dev/block/{}:{} {}us",
+                devnr.0, devnr.1, target
+            )
+            .unwrap();
+        }
     }
-    if let Some(m) = mem_low {
-        writeln!(buf, "MemoryLow={}", mknob_to_systemd_string(&m, false)).unwrap();
+    if enf.mem {
+        writeln!(buf, "AllowedMemoryNodes={}", &sk.cpuset_mems).unwrap();
+        writeln!(
+            buf,
+            "MemoryMin={}",
+            mknob_to_systemd_string(&sk.mem_min, false)
+        )
+        .unwrap();
+        if enf.mem_low {
+            writeln!(
+                buf,
+                "MemoryLow={}",
+                mknob_to_systemd_string(&sk.mem_low, false)
+            )
+            .unwrap();
+        }
+        writeln!(
+            buf,
+            "MemoryHigh={}",
+            mknob_to_systemd_string(&sk.mem_high, true)
+        )
+        .unwrap();
+        writeln!(
+            buf,
+            "MemoryMax={}",
+            mknob_to_systemd_string(&sk.mem_max, true)
+        )
+        .unwrap();
+        writeln!(
+            buf,
+            "MemorySwapMax={}",
+            mknob_to_systemd_string(&sk.mem_swap_max, true)
+        )
+        .unwrap();
+        // Older systemd doesn't know about zswap, skip unless configured.
+        if let MemoryKnob::Bytes(_) = sk.mem_zswap_max {
+            writeln!(
+                buf,
+                "MemoryZSwapMax={}",
+                mknob_to_systemd_string(&sk.mem_zswap_max, true)
+            )
+            .unwrap();
+        }
     }
-    if let Some(m) = mem_high {
-        writeln!(buf, "MemoryHigh={}", mknob_to_systemd_string(&m, true)).unwrap();
+    if enf.pids {
+        match sk.pids_max {
+            Some(v) => writeln!(buf, "TasksMax={}", v).unwrap(),
+            None => writeln!(buf, "TasksMax=infinity").unwrap(),
+        }
     }
 
     buf
 }
 
-fn apply_configlet(slice: Slice, configlet: &str) -> Result<bool> {
-    let path = crate::unit_configlet_path(slice.name(), "resctl");
+fn apply_configlet(slice: &SlicePath, configlet: &str) -> Result<bool> {
+    let path = crate::unit_configlet_path(slice.unit_name(), "resctl");
 
     debug!("resctl: reading {:?} to test for equality", &path);
     if let Ok(mut f) = fs::OpenOptions::new().read(true).open(&path) {
@@ -152,19 +282,19 @@ fn apply_configlet(slice: Slice, configlet: &str) -> Result<bool> {
     }
 
     debug!("resctl: writing updated {:?}", &path);
-    crate::write_unit_configlet(slice.name(), "resctl", &configlet)?;
+    crate::write_unit_configlet(slice.unit_name(), "resctl", configlet)?;
 
-    if slice_needs_start_stop(slice) {
-        match systemd::Unit::new_sys(slice.name().into()) {
+    if slice_needs_start(slice) {
+        match systemd::Unit::new_sys(slice.unit_name().into()) {
             Ok(mut unit) => {
                 if let Err(e) = unit.try_start_nowait() {
-                    warn!("resctl: Failed to start {:?} ({})", slice.name(), &e);
+                    warn!("resctl: Failed to start {:?} ({})", slice.unit_name(), &e);
                 }
             }
             Err(e) => {
                 warn!(
                     "resctl: Failed to create unit for {:?} ({})",
-                    slice.name(),
+                    slice.unit_name(),
                     &e
                 );
             }
@@ -174,14 +304,19 @@ fn apply_configlet(slice: Slice, configlet: &str) -> Result<bool> {
     Ok(true)
 }
 
-fn propagate_one_slice(slice: Slice, resctl: &systemd::UnitResCtl) -> Result<()> {
-    debug!("resctl: propagating {:?} w/ {:?}", slice, &resctl);
+fn propagate_one_slice(
+    slice: &SlicePath,
+    resctl: &systemd::UnitResCtl,
+    nested: &[&str],
+) -> Result<()> {
+    debug!("resctl: propagating {:?} w/ {:?}", slice.name(), &resctl);
 
     for path in glob(&format!("{}/**/*.service", slice.cgrp()))
         .unwrap()
         .chain(glob(&format!("{}/**/*.scope", slice.cgrp())).unwrap())
         .chain(glob(&format!("{}/**/*.slice", slice.cgrp())).unwrap())
         .filter_map(Result::ok)
+        .filter(|path| !is_nested(path, nested))
     {
         let unit_name = path.file_name().unwrap().to_str().unwrap().to_string();
         let unit = systemd::Unit::new_sys(unit_name.clone());
@@ -239,70 +374,82 @@ fn propagate_one_slice(slice: Slice, resctl: &systemd::UnitResCtl) -> Result<()>
     Ok(())
 }
 
-pub fn apply_slices(knobs: &mut SliceKnobs, hashd_mem_size: u64, cfg: &Config) -> Result<()> {
+/// Everything apply_slice_plan() needs to configure the slices, computed
+/// upfront so that planning failures don't leave anything half-applied.
+pub struct SlicePlan {
+    managed: Vec<SlicePath>,
+    configlets: Vec<(SlicePath, String)>,
+    propagations: Vec<(SlicePath, systemd::UnitResCtl)>,
+    enable_iocost: bool,
+}
+
+pub fn plan_slices(knobs: &mut SliceKnobs, hashd_mem_size: u64, cfg: &Config) -> Result<SlicePlan> {
     if knobs.work_mem_low_none {
         let sk = knobs.slices.get_mut(Slice::Work.name()).unwrap();
         sk.mem_low = MemoryKnob::Bytes((hashd_mem_size as f64 * 0.75).ceil() as u64);
     }
 
-    let mut updated = false;
-    for slice in Slice::into_enum_iter() {
-        let enforce_mem = slice_enforce_mem(&cfg.enforce, slice);
-
-        if !cfg.enforce.cpu && !enforce_mem && !cfg.enforce.io {
+    let managed = knobs.managed_slices();
+    let mut configlets = vec![];
+    let mut propagations = vec![];
+    for slice in managed.iter() {
+        let mut enf = SliceEnforce::new(&cfg.enforce, slice);
+        if !enf.any() {
             continue;
         }
 
-        let sk = knobs.slices.get(slice.name()).unwrap();
-        let (cpu_weight, io_weight, mem_min, mem_low, mem_high);
-
-        cpu_weight = match cfg.enforce.cpu {
-            true => Some(sk.cpu_weight),
-            false => None,
-        };
-        io_weight = match cfg.enforce.io {
-            true => Some(sk.io_weight),
-            false => None,
-        };
-
-        if enforce_mem {
-            mem_min = Some(sk.mem_min);
-            mem_high = Some(sk.mem_high);
-            if slice == Slice::Work && knobs.disable_seqs.mem >= super::instance_seq() {
-                mem_low = None;
-            } else {
-                mem_low = Some(sk.mem_low);
-            }
-        } else {
-            mem_min = None;
-            mem_low = None;
-            mem_high = None;
+        if slice.builtin() == Some(Slice::Work) && knobs.disable_seqs.mem >= super::instance_seq() {
+            enf.mem_low = false;
         }
-
-        let configlet = build_configlet(slice, cpu_weight, io_weight, mem_min, mem_low, mem_high);
-        if apply_configlet(slice, &configlet)? {
-            updated = true;
+        if knobs.io_backend == IoBackend::IoLatency && knobs.disable_seqs.io < super::instance_seq()
+        {
+            enf.io_lat = Some(cfg.scr_devnr);
         }
 
-        if enforce_mem && slice_needs_mem_prot_propagation(slice) {
-            let sk = knobs.slices.get(slice.name()).unwrap();
-            let mut resctl = systemd::UnitResCtl::default();
+        let sk = knobs
+            .slices
+            .get(slice.name())
+            .ok_or_else(|| anyhow!("no configuration for {:?}", slice.name()))?;
+        configlets.push((slice.clone(), build_configlet(slice, sk, &enf)));
 
+        if enf.mem && slice_needs_mem_prot_propagation(slice) {
+            let mut resctl = systemd::UnitResCtl::default();
             if !cfg.memcg_recursive_prot() {
                 resctl.mem_min = mknob_to_unit_resctl(&sk.mem_min);
                 resctl.mem_low = mknob_to_unit_resctl(&sk.mem_low);
             }
+            propagations.push((slice.clone(), resctl));
+        }
+    }
+
+    Ok(SlicePlan {
+        managed,
+        configlets,
+        propagations,
+        enable_iocost: knobs.disable_seqs.io < super::instance_seq()
+            && knobs.io_backend == IoBackend::IoCost,
+    })
+}
 
-            propagate_one_slice(slice, &resctl)?;
+pub fn apply_slice_plan(plan: &SlicePlan, cfg: &Config) -> Result<()> {
+    let mut updated = false;
+    for (slice, configlet) in plan.configlets.iter() {
+        if apply_configlet(slice, configlet)? {
+            updated = true;
         }
     }
+    for (slice, resctl) in plan.propagations.iter() {
+        propagate_one_slice(slice, resctl, &nested_slices(slice, &plan.managed))?;
+    }
+    if clear_stale_slices(&plan.managed, &cfg.enforce)? {
+        updated = true;
+    }
     if updated {
         info!("resctl: Applying updated slice configurations");
         systemd::daemon_reload()?;
     }
 
-    let enable_iocost = knobs.disable_seqs.io < super::instance_seq();
-    if let Err(e) = super::bench::iocost_on_off(enable_iocost, cfg) {
+    if let Err(e) = super::bench::iocost_on_off(plan.enable_iocost, cfg) {
         warn!("resctl: Failed to enable/disable iocost ({:?})", &e);
         return Err(e);
     }
@@ -310,38 +457,148 @@ pub fn apply_slices(knobs: &mut SliceKnobs, hashd_mem_size: u64, cfg: &Config) -
     Ok(())
 }
 
-fn clear_one_slice(slice: Slice, ecfg: &EnforceConfig) -> Result<bool> {
-    match systemd::Unit::new_sys(slice.name().into()) {
+pub fn apply_slices(knobs: &mut SliceKnobs, hashd_mem_size: u64, cfg: &Config) -> Result<()> {
+    let plan = plan_slices(knobs, hashd_mem_size, cfg)?;
+    apply_slice_plan(&plan, cfg)
+}
+
+/// Contents of the resctl configlets of all slices and scopes. Used to
+/// restore the exact previous state when a transaction is rolled back.
+pub struct ConfigletSnapshot {
+    files: BTreeMap<PathBuf, String>,
+}
+
+impl ConfigletSnapshot {
+    fn configlet_paths() -> Vec<PathBuf> {
+        ["*.slice", "*.scope"]
+            .iter()
+            .flat_map(|pat| {
+                glob(&crate::unit_configlet_path(pat, "resctl"))
+                    .unwrap()
+                    .filter_map(Result::ok)
+            })
+            .collect()
+    }
+
+    pub fn take() -> Result<Self> {
+        let mut files = BTreeMap::new();
+        for path in Self::configlet_paths() {
+            let body = fs::read_to_string(&path).with_context(|| format!("reading {:?}", &path))?;
+            files.insert(path, body);
+        }
+        Ok(Self { files })
+    }
+
+    /// Write back the snapshotted configlets and remove the ones which
+    /// didn't exist. systemd is reloaded if anything changed.
+    pub fn restore(&self) -> Result<()> {
+        let mut updated = false;
+        for path in Self::configlet_paths() {
+            if !self.files.contains_key(&path) {
+                debug!("resctl: Removing {:?} for rollback", &path);
+                fs::remove_file(&path).with_context(|| format!("removing {:?}", &path))?;
+                updated = true;
+            }
+        }
+        for (path, body) in self.files.iter() {
+            if fs::read_to_string(path).ok().as_ref() == Some(body) {
+                continue;
+            }
+            debug!("resctl: Restoring {:?} for rollback", path);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, body).with_context(|| format!("restoring {:?}", path))?;
+            updated = true;
+        }
+        if updated {
+            info!("resctl: Restored slice configurations");
+            systemd::daemon_reload()?;
+        }
+        Ok(())
+    }
+}
+
+/// Hard limits other than memory.max aren't covered by UnitResCtl. Reset
+/// them directly so that they don't linger after the configlet is gone.
+fn clear_cgrp_limits(cgrp: &str, enf: &SliceEnforce) {
+    let mut resets = vec![];
+    if enf.cpu {
+        resets.push(("cpu.max", "max".to_string()));
+        // partition must be reverted before cpus can be cleared
+        resets.push(("cpuset.cpus.partition", "member".to_string()));
+        resets.push(("cpuset.cpus", "".to_string()));
+    }
+    if enf.io {
+        if let Ok(lines) = read_cgroup_nested_keyed_file(&(cgrp.to_string() + "/io.max")) {
+            for devnr in lines.keys() {
+                resets.push((
+                    "io.max",
+                    format!("{} rbps=max wbps=max riops=max wiops=max", devnr),
+                ));
+            }
+        }
+        if let Ok(lines) = read_cgroup_nested_keyed_file(&(cgrp.to_string() + "/io.latency")) {
+            for devnr in lines.keys() {
+                resets.push(("io.latency", format!("{} target=max", devnr)));
+            }
+        }
+    }
+    if enf.mem {
+        resets.push(("cpuset.mems", "".to_string()));
+        resets.push(("memory.swap.max", "max".to_string()));
+        resets.push(("memory.zswap.max", "max".to_string()));
+    }
+    if enf.pids {
+        resets.push(("pids.max", "max".to_string()));
+    }
+
+    for (file, val) in resets.iter() {
+        let path = format!("{}/{}", cgrp, file);
+        if !Path::new(&path).exists() {
+            continue;
+        }
+        if let Err(e) = write_one_line(&path, val) {
+            debug!(
+                "resctl: Failed to write {:?} to {:?} ({:?})",
+                val, &path, &e
+            );
+        }
+    }
+}
+
+fn clear_one_unit(unit_name: &str, enf: &SliceEnforce, stop: bool) -> Result<bool> {
+    match systemd::Unit::new_sys(unit_name.into()) {
         Ok(mut unit) => {
-            if ecfg.cpu {
+            if enf.cpu {
                 unit.resctl.cpu_weight = None;
             }
-            if slice_enforce_mem(ecfg, slice) {
+            if enf.mem {
                 unit.resctl.mem_min = None;
                 unit.resctl.mem_low = None;
+                unit.resctl.mem_max = None;
             }
-            if ecfg.io {
+            if enf.io {
                 unit.resctl.io_weight = None;
             }
             if let Err(e) = unit.apply() {
-                error!("resctl: Failed to reset {:?} ({})", slice.name(), &e);
+                error!("resctl: Failed to reset {:?} ({})", unit_name, &e);
+            }
+            if let Some(cgrp) = unit.props.string("ControlGroup") {
+                if !cgrp.is_empty() {
+                    clear_cgrp_limits(&format!("/sys/fs/cgroup{}", &cgrp), enf);
+                }
             }
-            if slice_needs_start_stop(slice) {
+            if stop {
                 if let Err(e) = unit.stop() {
-                    error!("resctl: Failed to stop {:?} ({})", slice.name(), &e);
+                    error!("resctl: Failed to stop {:?} ({})", unit_name, &e);
                 }
             }
         }
         Err(e) => {
-            error!(
-                "resctl: Failed to clear unit for {:?} ({})",
-                slice.name(),
-                &e
-            );
+            error!("resctl: Failed to clear unit for {:?} ({})", unit_name, &e);
         }
     }
 
-    let path = crate::unit_configlet_path(slice.name(), "resctl");
+    let path = crate::unit_configlet_path(unit_name, "resctl");
     if Path::new(&path).exists() {
         debug!("resctl: Removing {:?}", &path);
         fs::remove_file(&path)?;
@@ -351,12 +608,60 @@ fn clear_one_slice(slice: Slice, ecfg: &EnforceConfig) -> Result<bool> {
     }
 }
 
-pub fn clear_slices(ecfg: &EnforceConfig) -> Result<()> {
+fn clear_one_slice(slice: &SlicePath, ecfg: &EnforceConfig) -> Result<bool> {
+    clear_one_unit(
+        slice.unit_name(),
+        &SliceEnforce::new(ecfg, slice),
+        slice_needs_stop(slice),
+    )
+}
+
+/// Clear configlets left behind by user slices which were removed from
+/// slices.json. The slices themselves are left alone.
+fn clear_stale_slices(managed: &[SlicePath], ecfg: &EnforceConfig) -> Result<bool> {
     let mut updated = false;
-    for slice in Slice::into_enum_iter() {
-        let enforce_mem = slice_enforce_mem(ecfg, slice);
+    let pattern = crate::unit_configlet_path("*.slice", "resctl");
+    for path in glob(&pattern).unwrap().filter_map(Result::ok) {
+        let unit_name = match path
+            .parent()
+            .and_then(|x| x.file_name())
+            .and_then(|x| x.to_str())
+            .and_then(|x| x.strip_suffix(".d"))
+        {
+            Some(v) => v.to_string(),
+            None => continue,
+        };
+        if managed.iter().any(|sp| sp.unit_name() == unit_name) {
+            continue;
+        }
 
-        if !ecfg.cpu && !enforce_mem && !ecfg.io {
+        info!("resctl: Clearing stale configuration for {:?}", &unit_name);
+        let enf = SliceEnforce {
+            cpu: ecfg.cpu,
+            mem: ecfg.mem,
+            mem_low: ecfg.mem,
+            io: ecfg.io,
+            io_lat: None,
+            pids: ecfg.pids,
+        };
+        match clear_one_unit(&unit_name, &enf, false) {
+            Ok(true) => updated = true,
+            Ok(false) => {}
+            Err(e) => warn!(
+                "resctl: Failed to clear stale configurations for {:?} ({:?})",
+                &unit_name, &e
+            ),
+        }
+    }
+    Ok(updated)
+}
+
+pub fn clear_slices(knobs: &SliceKnobs, ecfg: &EnforceConfig) -> Result<()> {
+    let managed = knobs.managed_slices();
+    let mut updated = false;
+    for slice in managed.iter() {
+        let enf = SliceEnforce::new(ecfg, slice);
+        if !enf.any() {
             continue;
         }
 
@@ -370,8 +675,8 @@ pub fn clear_slices(ecfg: &EnforceConfig) -> Result<()> {
             ),
         }
 
-        if enforce_mem && slice_needs_mem_prot_propagation(slice) {
-            propagate_one_slice(slice, &Default::default())?;
+        if enf.mem && slice_needs_mem_prot_propagation(slice) {
+            propagate_one_slice(slice, &Default::default(), &nested_slices(slice, &managed))?;
         }
     }
     if updated {
@@ -395,6 +700,9 @@ fn fix_overrides(dseqs: &DisableSeqKnobs, cfg: &Config) -> Result<()> {
     if cfg.enforce.io {
         enable += " +io";
     }
+    if cfg.enforce.pids {
+        enable += " +pids";
+    }
 
     if cfg.enforce.crit_mem_prot {
         enable += " +memory";
@@ -439,6 +747,14 @@ fn fix_overrides(dseqs: &DisableSeqKnobs, cfg: &Config) -> Result<()> {
     Ok(())
 }
 
+fn record_fixup(path: &str, expected: impl fmt::Display, found: impl fmt::Debug) {
+    events::record(EventKind::SliceFixup {
+        path: path.into(),
+        expected: format!("{}", expected),
+        found: format!("{:?}", found),
+    });
+}
+
 fn fix_slice_cpu(sk: &SliceConfig, path: &str, enable: bool) -> Result<()> {
     if !enable {
         return Ok(());
@@ -453,9 +769,40 @@ fn fix_slice_cpu(sk: &SliceConfig, path: &str, enable: bool) -> Result<()> {
                 "resctl: {:?} should be {} but is {:?}, fixing",
                 &cpu_weight_path, sk.cpu_weight, &v
             );
+            record_fixup(&cpu_weight_path, sk.cpu_weight, &v);
             write_one_line(&cpu_weight_path, &format!("{}", sk.cpu_weight))?;
         }
     }
+
+    let cpu_max_path = path.to_string() + "/cpu.max";
+    trace!("resctl: verify: {:?}", &cpu_max_path);
+    let line = read_one_line(&cpu_max_path)?;
+    let period = sk.cpu_max_period;
+    let matches = match scan_fmt!(&line, "{} {d}", String, u64) {
+        Ok((cur, cur_period)) if cur_period == period => {
+            match (cur.parse::<u64>(), sk.cpu_max_quota) {
+                (Err(_), None) => cur == "max",
+                // systemd configures the quota as a percentage, allow rounding
+                (Ok(cur), Some(quota)) => {
+                    (cur as f64 - quota as f64).abs() <= period as f64 / 10000.0 + 1.0
+                }
+                _ => false,
+            }
+        }
+        _ => false,
+    };
+    if !matches {
+        let expected = match sk.cpu_max_quota {
+            Some(quota) => format!("{} {}", quota, period),
+            None => format!("max {}", period),
+        };
+        info!(
+            "resctl: {:?} should be {:?} but is {:?}, fixing",
+            &cpu_max_path, &expected, &line
+        );
+        record_fixup(&cpu_max_path, &expected, &line);
+        write_one_line(&cpu_max_path, &expected)?;
+    }
     Ok(())
 }
 
@@ -473,12 +820,162 @@ fn fix_slice_io(sk: &SliceConfig, path: &str, enable: bool) -> Result<()> {
                 "resctl: {:?} should be {} but is {:?}, fixing",
                 &io_weight_path, sk.io_weight, &v
             );
+            record_fixup(&io_weight_path, sk.io_weight, &v);
             write_one_line(&io_weight_path, &format!("default {}", sk.io_weight))?;
         }
     }
     Ok(())
 }
 
+fn fix_slice_io_max(sk: &SliceConfig, path: &str, enable: bool) -> Result<()> {
+    if !enable {
+        return Ok(());
+    }
+    let io_max_path = path.to_string() + "/io.max";
+    trace!("resctl: verify: {:?}", &io_max_path);
+    let cur = read_cgroup_nested_keyed_file(&io_max_path)?;
+
+    let fmt_limit = |v: Option<u64>| match v {
+        Some(v) => format!("{}", v),
+        None => "max".to_string(),
+    };
+
+    let mut devnrs: Vec<&String> = sk.io_max.keys().collect();
+    devnrs.extend(cur.keys().filter(|devnr| !sk.io_max.contains_key(*devnr)));
+
+    for devnr in devnrs {
+        let iom = sk.io_max.get(devnr).cloned().unwrap_or_default();
+        let expected = [
+            ("rbps", fmt_limit(iom.rbps)),
+            ("wbps", fmt_limit(iom.wbps)),
+            ("riops", fmt_limit(iom.riops)),
+            ("wiops", fmt_limit(iom.wiops)),
+        ];
+        let matches = expected.iter().all(|(key, val)| {
+            match cur.get(devnr.as_str()).and_then(|kv| kv.get(*key)) {
+                Some(v) => v == val,
+                None => val == "max",
+            }
+        });
+        if matches {
+            continue;
+        }
+
+        let line = expected.iter().fold(devnr.to_string(), |acc, (key, val)| {
+            format!("{} {}={}", acc, key, val)
+        });
+        info!(
+            "resctl: {:?} should have {:?} but has {:?}, fixing",
+            &io_max_path,
+            &line,
+            cur.get(devnr.as_str())
+        );
+        record_fixup(&io_max_path, &line, cur.get(devnr.as_str()));
+        write_one_line(&io_max_path, &line)?;
+    }
+    Ok(())
+}
+
+fn fix_cpuset_list(path: &str, target: &str) -> Result<()> {
+    if !Path::new(path).exists() {
+        if !target.is_empty() {
+            debug!("resctl: {:?} doesn't exist, cpuset not enabled?", path);
+        }
+        return Ok(());
+    }
+    trace!("resctl: verify: {:?}", path);
+    let line = read_one_line(path)?;
+    if parse_cpulist(&line)? != parse_cpulist(target)? {
+        info!(
+            "resctl: {:?} should be {:?} but is {:?}, fixing",
+            path, target, &line
+        );
+        record_fixup(path, target, &line);
+        write_one_line(path, target)?;
+    }
+    Ok(())
+}
+
+fn fix_slice_cpuset(sk: &SliceConfig, path: &str, cpu: bool, mem: bool) -> Result<()> {
+    if cpu {
+        fix_cpuset_list(&(path.to_string() + "/cpuset.cpus"), &sk.cpuset_cpus)?;
+
+        let part_path = path.to_string() + "/cpuset.cpus.partition";
+        if Path::new(&part_path).exists() {
+            trace!("resctl: verify: {:?}", &part_path);
+            let line = read_one_line(&part_path)?;
+            let target = sk.cpuset_partition.cgrp_str();
+            if line.split_whitespace().next() != Some(target) {
+                info!(
+                    "resctl: {:?} should be {:?} but is {:?}, fixing",
+                    &part_path, target, &line
+                );
+                record_fixup(&part_path, target, &line);
+                write_one_line(&part_path, target)?;
+                let line = read_one_line(&part_path)?;
+                if line.contains("invalid") {
+                    warn!("resctl: {:?} is invalid ({:?})", &part_path, &line);
+                }
+            }
+        }
+    }
+    if mem {
+        fix_cpuset_list(&(path.to_string() + "/cpuset.mems"), &sk.cpuset_mems)?;
+    }
+    Ok(())
+}
+
+fn fix_slice_io_lat(sk: &SliceConfig, path: &str, devnr: (u32, u32), enable: bool) -> Result<()> {
+    let io_lat_path = path.to_string() + "/io.latency";
+    trace!("resctl: verify: {:?}", &io_lat_path);
+    let cur = read_cgroup_nested_keyed_file(&io_lat_path)?;
+    let devnr = format!("{}:{}", devnr.0, devnr.1);
+
+    let cur_target = cur
+        .get(&devnr)
+        .and_then(|kv| kv.get("target"))
+        .and_then(|v| v.parse::<u64>().ok());
+    let target = match enable {
+        true => sk.io_latency_target,
+        false => None,
+    };
+    if cur_target != target {
+        let expected = match target {
+            Some(v) => format!("{} target={}", &devnr, v),
+            None => format!("{} target=max", &devnr),
+        };
+        info!(
+            "resctl: {:?} should have {:?} but has {:?}, fixing",
+            &io_lat_path, &expected, &cur_target
+        );
+        record_fixup(&io_lat_path, &expected, cur_target);
+        write_one_line(&io_lat_path, &expected)?;
+    }
+    Ok(())
+}
+
+fn fix_slice_pids(sk: &SliceConfig, path: &str) -> Result<()> {
+    let pids_max_path = path.to_string() + "/pids.max";
+    if !Path::new(&pids_max_path).exists() {
+        return Ok(());
+    }
+    trace!("resctl: verify: {:?}", &pids_max_path);
+    let line = read_one_line(&pids_max_path)?;
+    let expected = match sk.pids_max {
+        Some(v) => format!("{}", v),
+        None => "max".to_string(),
+    };
+    if line != expected {
+        info!(
+            "resctl: {:?} should be {:?} but is {:?}, fixing",
+            &pids_max_path, &expected, &line
+        );
+        record_fixup(&pids_max_path, &expected, &line);
+        write_one_line(&pids_max_path, &expected)?;
+    }
+    Ok(())
+}
+
 fn fix_cgrp_mem(path: &str, is_limit: bool, knob: MemoryKnob) -> Result<()> {
     trace!("resctl: verify: {:?}", path);
     let line = read_one_line(path)?;
@@ -501,6 +998,7 @@ fn fix_cgrp_mem(path: &str, is_limit: bool, knob: MemoryKnob) -> Result<()> {
         "resctl: {:?} should be {:?} but is {:?}, fixing",
         path, &expected, &line
     );
+    record_fixup(path, &expected, &line);
     write_one_line(path, &expected)?;
 
     let file = Path::new(path)
@@ -530,10 +1028,16 @@ fn fix_cgrp_mem(path: &str, is_limit: bool, knob: MemoryKnob) -> Result<()> {
     unit.apply()
 }
 
-fn fix_recursive_mem_prot(parent: &str, file: &str, knob: MemoryKnob) -> Result<()> {
+fn fix_recursive_mem_prot(
+    parent: &str,
+    file: &str,
+    knob: MemoryKnob,
+    nested: &[&str],
+) -> Result<()> {
     for p in glob(&format!("{}/*/**/{}", parent, file))
         .unwrap()
         .filter_map(Result::ok)
+        .filter(|p| !is_nested(p, nested))
     {
         if let Err(e) = fix_cgrp_mem(p.to_str().unwrap(), false, knob) {
             warn!(
@@ -552,11 +1056,21 @@ fn fix_slice_mem(
     verify_mem_high: bool,
     propagate_mem_prot: bool,
     recursive_mem_prot: bool,
+    nested: &[&str],
 ) -> Result<()> {
     if enable {
         fix_cgrp_mem(&(path.to_string() + "/memory.min"), false, sk.mem_min)?;
         fix_cgrp_mem(&(path.to_string() + "/memory.low"), false, sk.mem_low)?;
-        fix_cgrp_mem(&(path.to_string() + "/memory.max"), true, MemoryKnob::None)?;
+        fix_cgrp_mem(&(path.to_string() + "/memory.max"), true, sk.mem_max)?;
+        for (file, knob) in &[
+            ("/memory.swap.max", sk.mem_swap_max),
+            ("/memory.zswap.max", sk.mem_zswap_max),
+        ] {
+            let knob_path = path.to_string() + file;
+            if Path::new(&knob_path).exists() {
+                fix_cgrp_mem(&knob_path, true, *knob)?;
+            }
+        }
 
         if verify_mem_high {
             fix_cgrp_mem(&(path.to_string() + "/memory.high"), true, sk.mem_high)?;
@@ -564,11 +1078,11 @@ fn fix_slice_mem(
 
         if propagate_mem_prot {
             if recursive_mem_prot {
-                fix_recursive_mem_prot(path, "memory.min", MemoryKnob::Bytes(0))?;
-                fix_recursive_mem_prot(path, "memory.low", MemoryKnob::Bytes(0))?;
+                fix_recursive_mem_prot(path, "memory.min", MemoryKnob::Bytes(0), nested)?;
+                fix_recursive_mem_prot(path, "memory.low", MemoryKnob::Bytes(0), nested)?;
             } else {
-                fix_recursive_mem_prot(path, "memory.min", sk.mem_min)?;
-                fix_recursive_mem_prot(path, "memory.low", sk.mem_low)?;
+                fix_recursive_mem_prot(path, "memory.min", sk.mem_min, nested)?;
+                fix_recursive_mem_prot(path, "memory.low", sk.mem_low, nested)?;
             }
         }
     } else {
@@ -590,14 +1104,22 @@ pub fn verify_and_fix_slices(
     if (cfg.enforce.cpu && ((dseqs.cpu < seq) != line.contains("cpu")))
         || (cfg.enforce.io && !line.contains("io"))
         || (cfg.enforce.crit_mem_prot && !line.contains("memory"))
+        || (cfg.enforce.pids && !line.contains("pids"))
     {
         info!("resctl: Controller enable state disagrees with overrides, fixing");
+        record_fixup(
+            "/sys/fs/cgroup/cgroup.subtree_control",
+            "controllers enabled per overrides",
+            &line,
+        );
         fix_overrides(dseqs, cfg)?;
     }
 
     let recursive_mem_prot = cfg.memcg_recursive_prot();
+    let uses_cpuset = knobs.uses_cpuset();
 
-    for slice in Slice::into_enum_iter() {
+    let managed = knobs.managed_slices();
+    for slice in managed.iter() {
         let sk = knobs.slices.get(slice.name()).unwrap();
 
         let path = slice.cgrp();
@@ -610,11 +1132,26 @@ pub fn verify_and_fix_slices(
         }
         if cfg.enforce.io {
             fix_slice_io(&sk, path, dseqs.io < seq)?;
+            fix_slice_io_max(sk, path, dseqs.io < seq)?;
+            if knobs.io_backend == IoBackend::IoLatency {
+                fix_slice_io_lat(sk, path, cfg.scr_devnr, dseqs.io < seq)?;
+            }
+        }
+        if cfg.enforce.pids {
+            fix_slice_pids(sk, path)?;
+        }
+        if uses_cpuset {
+            fix_slice_cpuset(
+                sk,
+                path,
+                cfg.enforce.cpu,
+                slice_enforce_mem(&cfg.enforce, slice),
+            )?;
         }
 
         if slice_enforce_mem(&cfg.enforce, slice) {
-            let (enable_mem, verify_mem_high) = match slice {
-                Slice::Work => (dseqs.mem < seq, !workload_senpai),
+            let (enable_mem, verify_mem_high) = match slice.builtin() {
+                Some(Slice::Work) => (dseqs.mem < seq, !workload_senpai),
                 _ => (true, true),
             };
             let propagate_mem_prot = slice_needs_mem_prot_propagation(slice);
@@ -626,12 +1163,22 @@ pub fn verify_and_fix_slices(
                 verify_mem_high,
                 propagate_mem_prot,
                 recursive_mem_prot,
+                &nested_slices(slice, &managed),
             )?;
         }
     }
 
+    if cfg.enforce.io
+        && knobs.io_backend == IoBackend::IoLatency
+        && super::bench::iocost_enabled(cfg).unwrap_or(false)
+    {
+        info!("resctl: iocost should be disabled with io.latency backend, fixing");
+        record_fixup("/sys/fs/cgroup/io.cost.qos", "enable=0", "enable=1");
+        super::bench::iocost_on_off(false, cfg)?;
+    }
+
     if cfg.enforce.io {
-        check_other_io_controllers(&mut Default::default());
+        check_other_io_controllers(&mut Default::default(), &own_io_ctrl_files(knobs));
     }
     Ok(())
 }
@@ -0,0 +1,390 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Context, Result};
+use log::{error, info, warn};
+
+use rd_agent_intf::{ConfigTxnOutcome, ConfigTxnReport, OomdKnobs, SideloaderCmd, SliceKnobs};
+use rd_util::*;
+
+use super::slices::{self, ConfigletSnapshot, SlicePlan};
+use super::{Config, SysObjs};
+
+//
+// Slice, oomd and sideloader configuration changes are applied as a
+// transaction. The full slice plan is computed before anything is
+// touched, the changes are applied and then verified by reading back the
+// cgroup files with verify_and_fix_slices(). If any step fails, the
+// configlets are restored from the snapshot taken at the beginning and
+// the last committed configurations are re-applied.
+//
+// Only configurations which went through a successful transaction count
+// as committed. Until a part is committed, a failed transaction covering
+// it can't be rolled back and ends up RollbackFailed.
+//
+// The snapshot only covers the configlets. The cgroup knobs which
+// verify_and_fix_slices() writes directly aren't snapshotted. They're
+// restored by the verification pass of the re-apply which writes back
+// the committed values, so they aren't restored if the re-apply fails
+// before that or in bypass mode.
+//
+
+/// Which parts of the configuration a transaction covers.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TxnParts {
+    pub slices: bool,
+    pub oomd: bool,
+    pub sideloader: bool,
+}
+
+impl TxnParts {
+    pub fn any(&self) -> bool {
+        self.slices || self.oomd || self.sideloader
+    }
+
+    fn names(&self) -> Vec<String> {
+        let mut names = vec![];
+        for (name, on) in &[
+            ("slices", self.slices),
+            ("oomd", self.oomd),
+            ("sideloader", self.sideloader),
+        ] {
+            if *on {
+                names.push(name.to_string());
+            }
+        }
+        names
+    }
+}
+
+/// Configurations covered by transactions, None if never committed.
+#[derive(Clone, Default)]
+struct TxnConfigs {
+    slices: Option<SliceKnobs>,
+    oomd: Option<OomdKnobs>,
+    sideloader: Option<SideloaderCmd>,
+}
+
+impl TxnConfigs {
+    fn covers(&self, parts: TxnParts) -> bool {
+        (!parts.slices || self.slices.is_some())
+            && (!parts.oomd || self.oomd.is_some())
+            && (!parts.sideloader || self.sideloader.is_some())
+    }
+}
+
+/// What transactions are applied to - the system in rd-agent. The
+/// transaction logic only goes through this so that it can be tested.
+trait TxnTarget {
+    type Prep;
+
+    /// Copy the in-memory configurations of `parts` into `to`.
+    fn save(&self, parts: TxnParts, to: &mut TxnConfigs);
+    /// Set the in-memory configurations of `parts` which exist in `from`.
+    fn load(&mut self, parts: TxnParts, from: &TxnConfigs);
+    /// Plan and take the snapshot without changing anything.
+    fn prep(&mut self, parts: TxnParts) -> Result<Self::Prep>;
+    fn apply(&mut self, parts: TxnParts, prep: &Self::Prep) -> Result<()>;
+    /// Restore the snapshot taken by prep().
+    fn restore(&mut self, parts: TxnParts, prep: &Self::Prep) -> Result<()>;
+}
+
+struct SysTarget<'a> {
+    sobjs: &'a mut SysObjs,
+    hashd_mem_size: u64,
+    cfg: &'a Config,
+}
+
+impl TxnTarget for SysTarget<'_> {
+    type Prep = (Option<SlicePlan>, ConfigletSnapshot);
+
+    fn save(&self, parts: TxnParts, to: &mut TxnConfigs) {
+        if parts.slices {
+            to.slices = Some(self.sobjs.slice_file.data.clone());
+        }
+        if parts.oomd {
+            to.oomd = Some(self.sobjs.oomd.file.data.clone());
+        }
+        if parts.sideloader {
+            to.sideloader = Some(self.sobjs.cmd_file.data.sideloader.clone());
+        }
+    }
+
+    fn load(&mut self, parts: TxnParts, from: &TxnConfigs) {
+        if let (true, Some(v)) = (parts.slices, from.slices.as_ref()) {
+            self.sobjs.slice_file.data = v.clone();
+        }
+        if let (true, Some(v)) = (parts.oomd, from.oomd.as_ref()) {
+            self.sobjs.oomd.file.data = v.clone();
+        }
+        if let (true, Some(v)) = (parts.sideloader, from.sideloader.as_ref()) {
+            self.sobjs.cmd_file.data.sideloader = v.clone();
+        }
+    }
+
+    fn prep(&mut self, parts: TxnParts) -> Result<Self::Prep> {
+        let mut plan = None;
+        if parts.slices {
+            plan = Some(
+                slices::plan_slices(
+                    &mut self.sobjs.slice_file.data,
+                    self.hashd_mem_size,
+                    self.cfg,
+                )
+                .context("planning slices")?,
+            );
+        }
+        Ok((plan, ConfigletSnapshot::take().context("taking snapshot")?))
+    }
+
+    fn apply(&mut self, parts: TxnParts, prep: &Self::Prep) -> Result<()> {
+        let (sobjs, cfg) = (&mut *self.sobjs, self.cfg);
+        if let Some(plan) = prep.0.as_ref() {
+            slices::apply_slice_plan(plan, cfg).context("applying slices")?;
+        }
+        if parts.oomd && cfg.enforce.oomd {
+            sobjs.oomd.apply().context("applying oomd")?;
+        }
+        if parts.sideloader && cfg.enforce.all() {
+            sobjs
+                .sideloader
+                .apply(&sobjs.cmd_file.data.sideloader, &sobjs.slice_file.data)
+                .context("applying sideloader")?;
+        }
+        if !cfg.bypass {
+            let workload_senpai = sobjs.oomd.workload_senpai_enabled();
+            slices::verify_and_fix_slices(&sobjs.slice_file.data, workload_senpai, cfg)
+                .context("verifying slices")?;
+        }
+        Ok(())
+    }
+
+    fn restore(&mut self, parts: TxnParts, prep: &Self::Prep) -> Result<()> {
+        if parts.slices {
+            prep.1.restore().context("restoring configlets")?;
+        }
+        Ok(())
+    }
+}
+
+/// The last committed configurations and the outcome of the last
+/// transaction.
+#[derive(Default)]
+pub struct ConfigTxn {
+    committed: TxnConfigs,
+    pub report: ConfigTxnReport,
+}
+
+impl ConfigTxn {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn rollback<T: TxnTarget>(
+        &self,
+        parts: TxnParts,
+        prep: &T::Prep,
+        target: &mut T,
+    ) -> Result<()> {
+        if !self.committed.covers(parts) {
+            bail!("no committed configuration to roll back to");
+        }
+        target.load(parts, &self.committed);
+        target.restore(parts, prep)?;
+        let prep = target.prep(parts)?;
+        target.apply(parts, &prep)
+    }
+
+    fn run_on<T: TxnTarget>(&mut self, parts: TxnParts, target: &mut T) {
+        let rep = &mut self.report;
+        rep.seq += 1;
+        rep.at = unix_now();
+        rep.parts = parts.names();
+        rep.error.clear();
+        rep.rollback_error.clear();
+
+        // Neither planning nor taking the snapshot changes anything on the
+        // system. Failing here only needs the in-memory configurations to
+        // be reverted.
+        let prep = match target.prep(parts) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("txn: Aborting {:?} ({:#})", &rep.parts, &e);
+                rep.outcome = ConfigTxnOutcome::Aborted;
+                rep.error = format!("{:#}", &e);
+                target.load(parts, &self.committed);
+                return;
+            }
+        };
+
+        match target.apply(parts, &prep) {
+            Ok(()) => {
+                info!("txn: Committed {:?}", &rep.parts);
+                rep.outcome = ConfigTxnOutcome::Committed;
+                target.save(parts, &mut self.committed);
+            }
+            Err(e) => {
+                warn!(
+                    "txn: Failed to apply {:?}, rolling back ({:#})",
+                    &rep.parts, &e
+                );
+                let error = format!("{:#}", &e);
+                let res = self.rollback(parts, &prep, target);
+                let rep = &mut self.report;
+                rep.error = error;
+                match res {
+                    Ok(()) => {
+                        info!("txn: Rolled back {:?}", &rep.parts);
+                        rep.outcome = ConfigTxnOutcome::RolledBack;
+                    }
+                    Err(e) => {
+                        error!("txn: Failed to roll back {:?} ({:#})", &rep.parts, &e);
+                        rep.outcome = ConfigTxnOutcome::RollbackFailed;
+                        rep.rollback_error = format!("{:#}", &e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Apply `parts` of the configurations in `sobjs` as a transaction. On
+    /// failure, `sobjs` is reverted to the last committed configurations.
+    pub fn run(&mut self, parts: TxnParts, sobjs: &mut SysObjs, hashd_mem_size: u64, cfg: &Config) {
+        let mut target = SysTarget {
+            sobjs,
+            hashd_mem_size,
+            cfg,
+        };
+        self.run_on(parts, &mut target);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only the sideloader part is tracked, cpu_headroom identifies the
+    /// configuration.
+    struct MockTarget {
+        headroom: f64,
+        fail_prep: u32,
+        fail_apply: u32,
+        nr_restores: u32,
+        applied: Vec<f64>,
+    }
+
+    impl MockTarget {
+        fn new(headroom: f64) -> Self {
+            Self {
+                headroom,
+                fail_prep: 0,
+                fail_apply: 0,
+                nr_restores: 0,
+                applied: vec![],
+            }
+        }
+    }
+
+    impl TxnTarget for MockTarget {
+        type Prep = ();
+
+        fn save(&self, parts: TxnParts, to: &mut TxnConfigs) {
+            if parts.sideloader {
+                to.sideloader = Some(SideloaderCmd {
+                    cpu_headroom: self.headroom,
+                });
+            }
+        }
+
+        fn load(&mut self, parts: TxnParts, from: &TxnConfigs) {
+            if let (true, Some(v)) = (parts.sideloader, from.sideloader.as_ref()) {
+                self.headroom = v.cpu_headroom;
+            }
+        }
+
+        fn prep(&mut self, _parts: TxnParts) -> Result<()> {
+            if self.fail_prep > 0 {
+                self.fail_prep -= 1;
+                bail!("prep failed");
+            }
+            Ok(())
+        }
+
+        fn apply(&mut self, _parts: TxnParts, _prep: &()) -> Result<()> {
+            if self.fail_apply > 0 {
+                self.fail_apply -= 1;
+                bail!("apply failed");
+            }
+            self.applied.push(self.headroom);
+            Ok(())
+        }
+
+        fn restore(&mut self, _parts: TxnParts, _prep: &()) -> Result<()> {
+            self.nr_restores += 1;
+            Ok(())
+        }
+    }
+
+    const SIDELOADER: TxnParts = TxnParts {
+        slices: false,
+        oomd: false,
+        sideloader: true,
+    };
+
+    #[test]
+    fn test_commit_and_rollback() {
+        let mut txn = ConfigTxn::new();
+        let mut target = MockTarget::new(0.1);
+
+        txn.run_on(SIDELOADER, &mut target);
+        assert_eq!(txn.report.outcome, ConfigTxnOutcome::Committed);
+        assert_eq!(target.applied, vec![0.1]);
+
+        // the failed 0.2 is reverted and 0.1 re-applied
+        target.headroom = 0.2;
+        target.fail_apply = 1;
+        txn.run_on(SIDELOADER, &mut target);
+        assert_eq!(txn.report.outcome, ConfigTxnOutcome::RolledBack);
+        assert_eq!(txn.report.error, "apply failed");
+        assert_eq!(target.headroom, 0.1);
+        assert_eq!(target.nr_restores, 1);
+        assert_eq!(target.applied, vec![0.1, 0.1]);
+
+        // so is 0.3 but re-applying 0.1 fails too
+        target.headroom = 0.3;
+        target.fail_apply = 2;
+        txn.run_on(SIDELOADER, &mut target);
+        assert_eq!(txn.report.outcome, ConfigTxnOutcome::RollbackFailed);
+        assert_eq!(txn.report.rollback_error, "apply failed");
+        assert_eq!(target.headroom, 0.1);
+        assert_eq!(target.nr_restores, 2);
+
+        // a prep failure doesn't touch anything
+        target.headroom = 0.4;
+        target.fail_prep = 1;
+        txn.run_on(SIDELOADER, &mut target);
+        assert_eq!(txn.report.outcome, ConfigTxnOutcome::Aborted);
+        assert_eq!(target.headroom, 0.1);
+        assert_eq!(target.nr_restores, 2);
+        assert_eq!(target.applied, vec![0.1, 0.1]);
+    }
+
+    #[test]
+    fn test_rollback_without_commit() {
+        let mut txn = ConfigTxn::new();
+        let mut target = MockTarget::new(0.1);
+
+        target.fail_apply = 1;
+        txn.run_on(SIDELOADER, &mut target);
+        assert_eq!(txn.report.outcome, ConfigTxnOutcome::RollbackFailed);
+        assert_eq!(
+            txn.report.rollback_error,
+            "no committed configuration to roll back to"
+        );
+        assert_eq!(target.headroom, 0.1);
+        assert_eq!(target.nr_restores, 0);
+        assert!(target.applied.is_empty());
+
+        txn.run_on(SIDELOADER, &mut target);
+        assert_eq!(txn.report.outcome, ConfigTxnOutcome::Committed);
+        assert_eq!(target.applied, vec![0.1]);
+    }
+}
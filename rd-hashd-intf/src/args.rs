@@ -20,6 +20,7 @@ lazy_static::lazy_static! {
              -L, --log-size=[SIZE]         'Maximum log retention (default: {dfl_log_size:.2}G)'
              -i, --interval=[SECS]         'Summary report interval, 0 to disable (default: {dfl_intv}s)'
              -R, --rotational=[BOOL]       'Force rotational detection to either true or false'
+                 --numa-node=[NODE]        'Bind CPUs and anon memory to the NUMA node'
              -a, --args=[FILE]             'Load base command line arguments from FILE'
                  --keep-cache              'Don't drop page cache for testfiles on startup'
                  --clear-testfiles         'Clear testfiles before preparing them'
@@ -76,6 +77,7 @@ pub struct Args {
     pub log_size: u64,
     pub interval: u32,
     pub rotational: Option<bool>,
+    pub numa_node: Option<u32>,
 
     #[serde(skip)]
     pub keep_cache: bool,
@@ -134,6 +136,7 @@ impl Args {
             log_size: mem_size as u64 / 2,
             interval: 10,
             rotational: None,
+            numa_node: None,
             clear_testfiles: false,
             keep_cache: false,
             bench_preload_cache: None,
@@ -303,6 +306,14 @@ impl JsonArgs for Args {
             };
             updated_base = true;
         }
+        if let Some(v) = matches.value_of("numa-node") {
+            self.numa_node = if !v.is_empty() {
+                Some(v.parse::<u32>().unwrap())
+            } else {
+                None
+            };
+            updated_base = true;
+        }
 
         self.keep_cache = matches.is_present("keep-cache");
         if let Some(v) = matches.value_of("bench-preload-cache") {
@@ -176,6 +176,18 @@ fn main() {
         }
     };
 
+    //
+    // Bind to the NUMA node before any thread or anon area is created so
+    // that everything inherits the placement.
+    //
+    if let Some(node) = args.numa_node {
+        match numa::bind_cpus_to_node(node) {
+            Ok(()) => info!("Bound CPUs to NUMA node {}", node),
+            Err(e) => warn!("Failed to bind CPUs to NUMA node {} ({:#})", node, &e),
+        }
+        anon_area::AnonArea::set_numa_node(Some(node));
+    }
+
     debug_assert!({
         warn!("Built with debug profile, may be too slow for nominal behaviors");
         true
@@ -1,12 +1,16 @@
 use super::PAGE_SIZE;
+use log::warn;
 use num::Integer;
 use rand::rngs::SmallRng;
 use rand::SeedableRng;
 use std::alloc::{alloc, dealloc, Layout};
 use std::cell::RefCell;
+use std::sync::atomic::{AtomicI64, Ordering};
 
 std::thread_local!(static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy()));
 
+static NUMA_NODE: AtomicI64 = AtomicI64::new(-1);
+
 struct AnonUnit {
     data: *mut u8,
     layout: Layout,
@@ -15,10 +19,14 @@ struct AnonUnit {
 impl AnonUnit {
     fn new(size: usize) -> Self {
         let layout = Layout::from_size_align(size, *PAGE_SIZE).unwrap();
-        Self {
-            data: unsafe { alloc(layout) },
-            layout,
+        let data = unsafe { alloc(layout) };
+        if let Some(node) = AnonArea::numa_node() {
+            // data is a fresh page-aligned allocation of size bytes
+            if let Err(e) = unsafe { super::numa::mbind_to_node(data, size, node) } {
+                warn!("anon_area: Failed to bind to NUMA node {} ({:#})", node, &e);
+            }
         }
+        Self { data, layout }
     }
 }
 
@@ -55,6 +63,19 @@ impl AnonArea {
         area
     }
 
+    /// Bind the memory of the anon areas allocated afterwards to the NUMA
+    /// node. None allocates according to the task's memory policy.
+    pub fn set_numa_node(node: Option<u32>) {
+        NUMA_NODE.store(node.map(|v| v as i64).unwrap_or(-1), Ordering::Relaxed);
+    }
+
+    pub fn numa_node() -> Option<u32> {
+        match NUMA_NODE.load(Ordering::Relaxed) {
+            v if v < 0 => None,
+            v => Some(v as u32),
+        }
+    }
+
     pub fn size(&self) -> usize {
         self.size
     }
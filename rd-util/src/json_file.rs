@@ -330,3 +330,66 @@ impl JsonRawFile {
         Ok(())
     }
 }
+
+/// Apply `patch` to `target` following JSON merge patch (RFC 7396)
+/// semantics. Objects are merged recursively, `null` removes the key and
+/// everything else replaces the target value.
+pub fn json_merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    let patch_map = match patch {
+        serde_json::Value::Object(v) => v,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+
+    if !target.is_object() {
+        *target = serde_json::Value::Object(Default::default());
+    }
+    let target_map = target.as_object_mut().unwrap();
+
+    for (key, val) in patch_map.iter() {
+        if val.is_null() {
+            target_map.remove(key);
+        } else {
+            json_merge_patch(
+                target_map
+                    .entry(key.clone())
+                    .or_insert(serde_json::Value::Null),
+                val,
+            );
+        }
+    }
+}
+
+/// Look up the dot-separated `path` in `val`. Array elements are selected
+/// by index. As map keys may contain dots (e.g. slice names), the longest
+/// matching key wins at each level.
+pub fn json_select<'a>(val: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    fn select<'a>(val: &'a serde_json::Value, comps: &[&str]) -> Option<&'a serde_json::Value> {
+        if comps.is_empty() {
+            return Some(val);
+        }
+        match val {
+            serde_json::Value::Object(map) => {
+                for nr in (1..=comps.len()).rev() {
+                    if let Some(child) = map.get(&comps[..nr].join(".")) {
+                        if let Some(v) = select(child, &comps[nr..]) {
+                            return Some(v);
+                        }
+                    }
+                }
+                None
+            }
+            serde_json::Value::Array(vals) => vals
+                .get(comps[0].parse::<usize>().ok()?)
+                .and_then(|child| select(child, &comps[1..])),
+            _ => None,
+        }
+    }
+
+    if path.is_empty() {
+        return Some(val);
+    }
+    select(val, &path.split('.').collect::<Vec<&str>>())
+}
@@ -7,7 +7,7 @@ use log::{error, info, warn};
 use scan_fmt::scan_fmt;
 use simplelog as sl;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::env;
 use std::ffi::{CString, OsStr, OsString};
 use std::fmt::Write as FmtWrite;
@@ -30,13 +30,15 @@ pub mod anon_area;
 pub mod iocost;
 pub mod journal_tailer;
 pub mod json_file;
+pub mod numa;
 pub mod storage_info;
 pub mod systemd;
 
 pub use iocost::{IoCostModelParams, IoCostQoSParams, IoCostSysSave};
 pub use journal_tailer::*;
 pub use json_file::{
-    JsonArgs, JsonArgsHelper, JsonConfigFile, JsonLoad, JsonRawFile, JsonReportFile, JsonSave,
+    json_merge_patch, json_select, JsonArgs, JsonArgsHelper, JsonConfigFile, JsonLoad, JsonRawFile,
+    JsonReportFile, JsonSave,
 };
 pub use storage_info::*;
 pub use systemd::TransientService;
@@ -628,6 +630,26 @@ pub fn parse_frac(input: &str) -> Result<f64> {
     Ok(v)
 }
 
+/// Parse kernel list format, e.g. "0-3,8,10-11", used by cpuset.cpus and
+/// cpuset.mems. An empty string yields an empty set.
+pub fn parse_cpulist(input: &str) -> Result<BTreeSet<u32>> {
+    let mut set = BTreeSet::new();
+    for tok in input.trim().split(',').map(|x| x.trim()) {
+        if tok.is_empty() {
+            continue;
+        }
+        let (first, last) = match tok.find('-') {
+            Some(idx) => (tok[..idx].parse::<u32>(), tok[idx + 1..].parse::<u32>()),
+            None => (tok.parse::<u32>(), tok.parse::<u32>()),
+        };
+        match (first, last) {
+            (Ok(first), Ok(last)) if first <= last => set.extend(first..=last),
+            _ => bail!("invalid list entry {:?} in {:?}", tok, input),
+        }
+    }
+    Ok(set)
+}
+
 fn is_executable<P: AsRef<Path>>(path_in: P) -> bool {
     let path = path_in.as_ref();
     match path.metadata() {
@@ -973,4 +995,20 @@ mod tests {
             println!("{} -> {} ({})", pair.1, result, pair.0);
         }
     }
+
+    #[test]
+    fn test_parse_cpulist() {
+        for pair in &[
+            (vec![], ""),
+            (vec![3], "3"),
+            (vec![0, 1, 2, 3, 8, 10, 11], "0-3,8,10-11"),
+            (vec![1, 2, 5], " 1-2, 5\n"),
+        ] {
+            let result = super::parse_cpulist(pair.1).unwrap();
+            assert_eq!(pair.0, result.into_iter().collect::<Vec<u32>>());
+        }
+        for input in &["3-1", "a", "1,-2", "1-"] {
+            assert!(super::parse_cpulist(input).is_err());
+        }
+    }
 }
@@ -0,0 +1,140 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Result};
+use scan_fmt::scan_fmt;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+
+use super::{parse_cpulist, read_one_line};
+
+const NODE_SYSFS: &str = "/sys/devices/system/node";
+
+// linux/mempolicy.h
+const MPOL_BIND: libc::c_long = 2;
+
+/// Online NUMA nodes. Kernels without NUMA support report a single node 0.
+pub fn online_nodes() -> Result<BTreeSet<u32>> {
+    match read_one_line(format!("{}/online", NODE_SYSFS)) {
+        Ok(line) => parse_cpulist(&line),
+        Err(_) => Ok(vec![0].into_iter().collect()),
+    }
+}
+
+/// CPUs which belong to `node`.
+pub fn node_cpus(node: u32) -> Result<BTreeSet<u32>> {
+    parse_cpulist(&read_one_line(format!(
+        "{}/node{}/cpulist",
+        NODE_SYSFS, node
+    ))?)
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NodeMeminfo {
+    pub total: u64,
+    pub free: u64,
+    pub used: u64,
+}
+
+fn parse_node_meminfo<R: BufRead>(reader: R) -> NodeMeminfo {
+    let mut mi = NodeMeminfo::default();
+    for line in reader.lines().map_while(Result::ok) {
+        if let Ok((key, kb)) = scan_fmt!(&line, "Node {*d} {/[^:]+/}: {d} kB", String, u64) {
+            match key.as_str() {
+                "MemTotal" => mi.total = kb << 10,
+                "MemFree" => mi.free = kb << 10,
+                "MemUsed" => mi.used = kb << 10,
+                _ => {}
+            }
+        }
+    }
+    mi
+}
+
+/// Read the per-node MemTotal, MemFree and MemUsed in bytes.
+pub fn read_node_meminfo(node: u32) -> Result<NodeMeminfo> {
+    let path = format!("{}/node{}/meminfo", NODE_SYSFS, node);
+    let f = fs::OpenOptions::new().read(true).open(&path)?;
+    let mi = parse_node_meminfo(BufReader::new(f));
+    if mi.total == 0 {
+        bail!("MemTotal missing in {:?}", &path);
+    }
+    Ok(mi)
+}
+
+/// Restrict the calling thread, and the threads it creates afterwards, to
+/// the CPUs of `node`. CPUs which don't fit in cpu_set_t are skipped.
+pub fn bind_cpus_to_node(node: u32) -> Result<()> {
+    let cpus: Vec<u32> = node_cpus(node)?
+        .into_iter()
+        .filter(|cpu| (*cpu as usize) < libc::CPU_SETSIZE as usize)
+        .collect();
+    if cpus.is_empty() {
+        bail!("NUMA node {} doesn't have any CPU", node);
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for cpu in cpus.iter() {
+            libc::CPU_SET(*cpu as usize, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) < 0 {
+            bail!(
+                "sched_setaffinity failed ({:?})",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Bind the memory area at `addr` to `node` with MPOL_BIND. Must be called
+/// before the pages are faulted in to take effect.
+///
+/// # Safety
+///
+/// `addr` must be page aligned and point to a mapping of at least `len`
+/// bytes which is owned by the caller.
+pub unsafe fn mbind_to_node(addr: *mut u8, len: usize, node: u32) -> Result<()> {
+    let bits = 8 * std::mem::size_of::<libc::c_ulong>();
+    let mut nodemask = vec![0 as libc::c_ulong; node as usize / bits + 1];
+    nodemask[node as usize / bits] |= 1 << (node as usize % bits);
+
+    let ret = libc::syscall(
+        libc::SYS_mbind,
+        addr,
+        len as libc::c_ulong,
+        MPOL_BIND,
+        nodemask.as_ptr(),
+        // the kernel ignores the last bit of maxnode
+        (nodemask.len() * bits + 1) as libc::c_ulong,
+        0 as libc::c_uint,
+    );
+    if ret < 0 {
+        bail!(
+            "mbind to node {} failed ({:?})",
+            node,
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_node_meminfo() {
+        let meminfo = b"\
+Node 1 MemTotal:       65842052 kB
+Node 1 MemFree:        52127716 kB
+Node 1 MemUsed:        13714336 kB
+Node 1 SwapCached:            0 kB
+Node 1 Active(file):    2351884 kB
+Node 1 HugePages_Total:     0
+Node 1 HugePages_Free:      0
+";
+        let mi = super::parse_node_meminfo(&meminfo[..]);
+        assert_eq!(mi.total, 65842052 << 10);
+        assert_eq!(mi.free, 52127716 << 10);
+        assert_eq!(mi.used, 13714336 << 10);
+    }
+}
@@ -51,6 +51,7 @@ fn wrap_rustbus_result<T>(r: RbResult<T>) -> Result<T> {
 
 #[derive(Debug)]
 pub enum Prop {
+    I32(i32),
     U32(u32),
     U64(u64),
     Bool(bool),
@@ -60,6 +61,7 @@ pub enum Prop {
 // define the variant with a fitting marshal and unmarshal impl
 rustbus::dbus_variant_sig!(PropVariant,
                            Bool => bool;
+                           I32 => i32;
                            U32 => u32;
                            U64 => u64;
                            String => String;
@@ -551,6 +553,7 @@ impl Unit {
 
     pub fn set_prop(&mut self, key: &str, prop: Prop) -> Result<()> {
         let props = match prop {
+            Prop::I32(v) => PropVariant::I32(v),
             Prop::U32(v) => PropVariant::U32(v),
             Prop::U64(v) => PropVariant::U64(v),
             Prop::Bool(v) => PropVariant::Bool(v),
@@ -767,6 +770,7 @@ impl TransientService {
         let mut extra_props = self.unit.resctl_props();
         for (k, v) in self.extra_props.iter() {
             let variant = match v {
+                Prop::I32(v) => PropVariant::I32(*v),
                 Prop::U32(v) => PropVariant::U32(*v),
                 Prop::U64(v) => PropVariant::U64(*v),
                 Prop::Bool(v) => PropVariant::Bool(*v),
@@ -108,6 +108,16 @@ impl<'a> Base<'a> {
         bench.iocost_dev_fwrev = dev_fwrev;
         bench.iocost_dev_size = dev_size;
 
+        // the scratch device may have been renumbered, re-key its parameters
+        let devnr = format!("{}:{}", iocost_sys_save.devnr.0, iocost_sys_save.devnr.1);
+        if bench.iocost_devnr != devnr {
+            if let Some(mut knobs) = bench.iocost.remove(&bench.iocost_devnr) {
+                knobs.devnr = devnr.clone();
+                bench.iocost.insert(devnr.clone(), knobs);
+            }
+            bench.iocost_devnr = devnr;
+        }
+
         if args.iocost_from_sys {
             if !iocost_sys_save.enable {
                 bail!(
@@ -116,15 +126,17 @@ impl<'a> Base<'a> {
                 );
             }
             bench.iocost_seq = 1;
-            bench.iocost.model = iocost_sys_save.model.clone();
-            bench.iocost.qos = iocost_sys_save.qos.clone();
+            let scr_iocost = bench.scr_iocost_mut()?;
+            scr_iocost.model = iocost_sys_save.model.clone();
+            scr_iocost.qos = iocost_sys_save.qos.clone();
             info!("Using iocost parameters from \"/sys/fs/cgroup/io.cost.model,qos\"");
         }
 
         if args.iocost_qos_ovr != Default::default() {
-            let qos_cfg = IoCostQoSCfg::new(&bench.iocost.qos, &args.iocost_qos_ovr);
+            let scr_qos = bench.scr_iocost().qos;
+            let qos_cfg = IoCostQoSCfg::new(&scr_qos, &args.iocost_qos_ovr);
             info!("base: iocost QoS overrides: {}", qos_cfg.format());
-            bench.iocost.qos = qos_cfg.calc().unwrap();
+            bench.scr_iocost_mut()?.qos = qos_cfg.calc().unwrap();
         }
 
         if let Some(size) = args.hashd_size {
@@ -251,14 +263,10 @@ impl<'a> Base<'a> {
         );
         info!("base:   model: {}", &iocost_knobs.model);
         info!("base:   qos: {}", &iocost_knobs.qos);
-        self.apply_bench_knobs(
-            BenchKnobs {
-                iocost: iocost_knobs,
-                iocost_seq: self.bench_knobs.iocost_seq + 1,
-                ..self.bench_knobs.clone()
-            },
-            commit,
-        )
+        let mut bench_knobs = self.bench_knobs.clone();
+        *bench_knobs.scr_iocost_mut()? = iocost_knobs;
+        bench_knobs.iocost_seq += 1;
+        self.apply_bench_knobs(bench_knobs, commit)
     }
 
     pub fn set_hashd_mem_size(&mut self, mem_size: usize, commit: bool) -> Result<()> {
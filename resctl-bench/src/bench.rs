@@ -18,7 +18,7 @@ use super::parse_json_value_or_dump;
 use super::progress::BenchProgress;
 use super::run::{RunCtx, WorkloadMon};
 use super::study::*;
-use rd_agent_intf::{AgentFiles, EnforceConfig, Slice, SysReq, ROOT_SLICE};
+use rd_agent_intf::{AgentFiles, EnforceConfig, Slice, SysReq, HASHD_A, ROOT_SLICE};
 use resctl_bench_intf::{format_job_props, JobProps, JobSpec};
 
 use rd_util::*;
@@ -74,7 +74,7 @@ impl Job for IoCostParamsJob {
             Some(BenchProgress::new().monitor_systemd_unit(IOCOST_BENCH_SVC_NAME)),
         )?;
 
-        let result = rctx.access_agent_files(|af| af.bench.data.iocost.clone());
+        let result = rctx.access_agent_files(|af| af.bench.data.scr_iocost());
 
         Ok(serde_json::to_value(&result).unwrap())
     }
@@ -266,7 +266,8 @@ impl IoCostQoSJob {
         };
 
         let msg = "iocost-qos: Existing result doesn't match the current configuration";
-        if prec.base_model != bench.iocost.model || prec.base_qos != bench.iocost.qos {
+        let iocost = bench.scr_iocost();
+        if prec.base_model != iocost.model || prec.base_qos != iocost.qos {
             warn!("{} (iocost parameter mismatch)", &msg);
             return false;
         }
@@ -376,7 +377,7 @@ impl IoCostQoSJob {
         let qos = if qos_cfg.ovr.off {
             None
         } else {
-            Some(rctx.access_agent_files(|af| af.bench.data.iocost.qos.clone()))
+            Some(rctx.access_agent_files(|af| af.bench.data.scr_iocost().qos))
         };
 
         Ok(IoCostQoSRecordRun {
@@ -473,6 +474,7 @@ impl Job for IoCostQoSJob {
             rctx.maybe_run_nested_iocost_params()?;
             bench_knobs = rctx.bench_knobs().clone();
         }
+        let scr_iocost = bench_knobs.scr_iocost();
 
         let (prev_matches, mut prev_rec) = match rctx.prev_job_data() {
             Some(pd) => {
@@ -485,8 +487,8 @@ impl Job for IoCostQoSJob {
             None => (
                 true,
                 IoCostQoSRecord {
-                    base_model: bench_knobs.iocost.model.clone(),
-                    base_qos: bench_knobs.iocost.qos.clone(),
+                    base_model: scr_iocost.model.clone(),
+                    base_qos: scr_iocost.qos.clone(),
                     dither_dist: self.dither_dist,
                     ..Default::default()
                 },
@@ -495,7 +497,7 @@ impl Job for IoCostQoSJob {
 
         // Mark the ones with too low a max rate to run.
         if !self.ign_min_perf {
-            let abs_min_vrate = iocost_min_vrate(&bench_knobs.iocost.model);
+            let abs_min_vrate = iocost_min_vrate(&scr_iocost.model);
             for ovr in self.runs.iter_mut() {
                 ovr.skip_or_adj(abs_min_vrate);
             }
@@ -505,7 +507,7 @@ impl Job for IoCostQoSJob {
         // without waiting for the benches to run.
         let mut nr_to_run = 0;
         for (i, ovr) in self.runs.iter().enumerate() {
-            let qos_cfg = IoCostQoSCfg::new(&bench_knobs.iocost.qos, ovr);
+            let qos_cfg = IoCostQoSCfg::new(&scr_iocost.qos, ovr);
             let mut skip = false;
             let mut extra_state = " ";
             if ovr.skip {
@@ -552,7 +554,7 @@ impl Job for IoCostQoSJob {
 
         let mut runs = vec![];
         for (i, ovr) in self.runs.iter().enumerate() {
-            let qos_cfg = IoCostQoSCfg::new(&bench_knobs.iocost.qos, ovr);
+            let qos_cfg = IoCostQoSCfg::new(&scr_iocost.qos, ovr);
             if let Some(recr) = Self::find_matching_rec_run(&ovr, &prev_rec) {
                 runs.push(Some(recr.clone()));
                 continue;
@@ -610,8 +612,8 @@ impl Job for IoCostQoSJob {
         runs.resize(self.runs.len(), None);
 
         Ok(serde_json::to_value(&IoCostQoSRecord {
-            base_model: bench_knobs.iocost.model,
-            base_qos: bench_knobs.iocost.qos,
+            base_model: scr_iocost.model,
+            base_qos: scr_iocost.qos,
             mem_profile: rctx.mem_info().profile,
             runs,
             dither_dist: self.dither_dist,
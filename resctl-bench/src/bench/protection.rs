@@ -42,7 +42,7 @@ fn ws_status(mon: &WorkloadMon, af: &AgentFiles) -> Result<(bool, String)> {
         status,
         "load:{:>4}% lat:{:>5} swap:{:>4}%",
         format4_pct(mon.hashd_loads[0]),
-        format_duration(rep.hashd[0].lat.ctl),
+        format_duration(rep.hashd[HASHD_A].lat.ctl),
         format4_pct_dashed(swap_usage)
     )
     .unwrap();
@@ -1,6 +1,6 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use super::super::*;
-use rd_agent_intf::{bandit_report::BanditMemHogReport, Report, Slice};
+use rd_agent_intf::{bandit_report::BanditMemHogReport, Report, Slice, HASHD_A};
 use std::cell::RefCell;
 use std::collections::{BTreeMap, VecDeque};
 
@@ -390,7 +390,8 @@ impl MemHog {
 
         // Determine the baseline latency. We need it for the latency impact
         // study. Run it first.
-        let mut study_base_lat = StudyMean::new(|arg| [arg.rep.hashd[0].lat.ctl].repeat(arg.cnt));
+        let mut study_base_lat =
+            StudyMean::new(|arg| [arg.rep.hashd[HASHD_A].lat.ctl].repeat(arg.cnt));
 
         Studies::new()
             .add(&mut study_base_lat)
@@ -406,7 +407,7 @@ impl MemHog {
         let last_nr_done = RefCell::new(None);
         let mut study_isol = StudyMeanPcts::new(
             sel_delta_calc(
-                |arg| arg.rep.hashd[0].nr_done,
+                |arg| arg.rep.hashd[HASHD_A].nr_done,
                 |arg, cur, last| Self::calc_isol((cur - last) as f64 / arg.dur, rec.base_rps),
                 &last_nr_done,
             ),
@@ -415,7 +416,7 @@ impl MemHog {
         let mut study_lat_imp = StudyMeanPcts::new(
             |arg| {
                 [Self::calc_lat_imp(
-                    arg.rep.hashd[0].lat.ctl.max(base_lat),
+                    arg.rep.hashd[HASHD_A].lat.ctl.max(base_lat),
                     base_lat,
                 )]
                 .repeat(arg.cnt)
@@ -660,7 +661,7 @@ impl MemHog {
 
         let mut study_isol = StudyMeanPcts::new(
             |arg| {
-                let nr_done = arg.rep.hashd[0].nr_done;
+                let nr_done = arg.rep.hashd[HASHD_A].nr_done;
                 match last_nr_done.replace(Some(nr_done)) {
                     Some(last) => [Self::calc_isol(
                         (nr_done - last) as f64 / arg.dur,
@@ -675,7 +676,7 @@ impl MemHog {
         let mut study_lat_imp = StudyMeanPcts::new(
             |arg| {
                 [Self::calc_lat_imp(
-                    arg.rep.hashd[0].lat.ctl.max(*base_lat.borrow()),
+                    arg.rep.hashd[HASHD_A].lat.ctl.max(*base_lat.borrow()),
                     *base_lat.borrow(),
                 )]
                 .repeat(arg.cnt)
@@ -75,7 +75,7 @@ impl MemHogTune {
                     return true;
                 }
 
-                if !is_last && af.report.data.hashd[0].rps < fail_rps_thr {
+                if !is_last && af.report.data.hashd[HASHD_A].rps < fail_rps_thr {
                     fail_cnt += 1;
                     fail_cnt > early_fail_cnt
                 } else {
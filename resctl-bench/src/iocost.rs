@@ -84,7 +84,7 @@ impl<'a, 'b> IoCostQoSCfg<'a, 'b> {
                 rctx.apply_iocost_knobs(
                     IoCostKnobs {
                         qos,
-                        ..rctx.bench_knobs().iocost.clone()
+                        ..rctx.bench_knobs().scr_iocost()
                     },
                     false,
                 )?;
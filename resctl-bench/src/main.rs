@@ -2,13 +2,14 @@
 use anyhow::{bail, Context, Error, Result};
 use indicatif::{ProgressBar, ProgressStyle};
 use log::{debug, error, info, warn};
+use std::collections::BTreeSet;
 use std::fmt::Write;
 use std::io::Write as IoWrite;
 use std::path::Path;
 use std::process::{exit, Command};
 use std::sync::{Arc, Mutex};
 
-use rd_agent_intf::MissedSysReqs;
+use rd_agent_intf::{MissedSysReqs, ReportStore};
 use rd_util::*;
 use resctl_bench_intf::{Args, Mode};
 
@@ -327,6 +328,25 @@ impl Program {
 
         info!("Packed {}/{} reports", nr_packed, nr_packed + nr_skipped);
 
+        // Reports in the segment store are packed a whole segment at a time.
+        let mut seg_files = BTreeSet::new();
+        for per in pers.iter() {
+            seg_files.extend(ReportStore::segment_files(&rctx.report_path(), *per)?);
+        }
+        for path in seg_files.iter() {
+            let target_path = format!(
+                "{}/{}",
+                &repdir,
+                path.file_name().unwrap().to_str().unwrap()
+            );
+            debug!("Packing {:?} as {:?}", path, &target_path);
+            tgz.append_path_with_name(path, &target_path)
+                .with_context(|| format!("Packing {:?}", path))?;
+        }
+        if !seg_files.is_empty() {
+            info!("Packed {} report segment files", seg_files.len());
+        }
+
         let gz = tgz.into_inner().context("Finishing up archive")?;
         gz.finish().into_result().context("Finishing up gzip")?;
         Ok(())
@@ -17,9 +17,9 @@ use super::{Program, AGENT_BIN};
 use crate::job::{FormatOpts, JobCtx, JobCtxs, JobData, SysInfo};
 use rd_agent_intf::{
     AgentFiles, EnforceConfig, HashdKnobs, IoCostKnobs, MemoryKnob, MissedSysReqs, ReportIter,
-    ReportPathIter, RunnerState, Slice, SvcStateReport, SysReq, AGENT_SVC_NAME, HASHD_A_SVC_NAME,
-    HASHD_BENCH_SVC_NAME, HASHD_B_SVC_NAME, IOCOST_BENCH_SVC_NAME, SIDELOAD_SVC_PREFIX,
-    SYSLOAD_SVC_PREFIX,
+    ReportPathIter, RunnerState, Slice, SvcStateReport, SysReq, AGENT_SVC_NAME, HASHD_A,
+    HASHD_A_SVC_NAME, HASHD_B, HASHD_BENCH_SVC_NAME, HASHD_B_SVC_NAME, IOCOST_BENCH_SVC_NAME,
+    SIDELOAD_SVC_PREFIX, SYSLOAD_SVC_PREFIX,
 };
 use rd_util::*;
 use resctl_bench_intf::{JobSpec, Mode};
@@ -939,7 +939,7 @@ impl<'a, 'b> RunCtx<'a, 'b> {
         let mut next_seq = 0;
         self.access_agent_files(|af| {
             next_seq = af.bench.data.hashd_seq + 1;
-            af.cmd.data.hashd[0].log_bps = log_bps.unwrap_or(dfl_params.log_bps);
+            af.cmd.data.hashd_mut(HASHD_A).log_bps = log_bps.unwrap_or(dfl_params.log_bps);
             af.cmd.data.bench_hashd_balloon_size = self.base.balloon_size_hashd_bench();
             af.cmd.data.bench_hashd_args = extra_args;
             af.cmd.data.bench_hashd_seq = next_seq;
@@ -983,13 +983,13 @@ impl<'a, 'b> RunCtx<'a, 'b> {
 
         self.access_agent_files(|af| {
             af.cmd.data.cmd_seq += 1;
-            af.cmd.data.hashd[0].active = true;
-            af.cmd.data.hashd[0].rps_target_ratio = load;
+            af.cmd.data.hashd_mut(HASHD_A).active = true;
+            af.cmd.data.hashd_mut(HASHD_A).rps_target_ratio = load;
             af.cmd.save().unwrap();
         });
         self.cmd_barrier().context("Waiting for hashd start ack")?;
         self.wait_cond(
-            |af, _| af.report.data.hashd[0].svc.state == SvcStateReport::Running,
+            |af, _| af.report.data.hashd[HASHD_A].svc.state == SvcStateReport::Running,
             Some(CMD_TIMEOUT),
             None,
         )
@@ -1025,13 +1025,13 @@ impl<'a, 'b> RunCtx<'a, 'b> {
                 }
                 last_at = ts;
 
-                if rep.hashd[0].svc.state != SvcStateReport::Running {
-                    err = Some(anyhow!("rd-hashd not running ({:?})", rep.hashd[0].svc.state));
+                if rep.hashd[HASHD_A].svc.state != SvcStateReport::Running {
+                    err = Some(anyhow!("rd-hashd not running ({:?})", rep.hashd[HASHD_A].svc.state));
                     return true;
                 }
 
-                let load = rep.hashd[0].rps / bench.hashd.rps_max as f64;
-                let rps_slopes = rps_sloper.push(rep.hashd[0].rps);
+                let load = rep.hashd[HASHD_A].rps / bench.hashd.rps_max as f64;
+                let rps_slopes = rps_sloper.push(rep.hashd[HASHD_A].rps);
                 let mem_slopes = mem_sloper.push(match rep.usages.get(HASHD_A_SVC_NAME) {
                     Some (usage) => usage.mem_bytes as f64,
                     None => 0.0,
@@ -1047,7 +1047,7 @@ impl<'a, 'b> RunCtx<'a, 'b> {
                 progress.set_status(&format!(
                     "load:{:>5}% lat:{:>5} rps-slp/err:{:+6.2}%/{:+6.2}% mem-sz/slp/err:{:>5}/{:+6.2}%/{:+6.2}%",
                     format_pct(load),
-                    format_duration(rep.hashd[0].lat.ctl),
+                    format_duration(rep.hashd[HASHD_A].lat.ctl),
                     rps_slope * TO_PCT,
                     rps_eslope * TO_PCT,
                     format_size(rep.usages[HASHD_A_SVC_NAME].mem_bytes),
@@ -1116,12 +1116,12 @@ impl<'a, 'b> RunCtx<'a, 'b> {
 
         self.access_agent_files(|af| {
             af.cmd.data.cmd_seq += 1;
-            af.cmd.data.hashd[0].active = false;
+            af.cmd.data.hashd_mut(HASHD_A).active = false;
             af.cmd.save().unwrap();
         });
         self.cmd_barrier().context("Waiting for hashd stop ack")?;
         self.wait_cond(
-            |af, _| af.report.data.hashd[0].svc.state != SvcStateReport::Running,
+            |af, _| af.report.data.hashd[HASHD_A].svc.state != SvcStateReport::Running,
             Some(CMD_TIMEOUT),
             None,
         )
@@ -1475,15 +1475,15 @@ impl WorkloadMon {
                 let rep = &af.report.data;
                 let bench = &af.bench.data;
 
-                if (self.hashd[0] && rep.hashd[0].svc.state != SvcStateReport::Running)
-                    || (self.hashd[1] && rep.hashd[1].svc.state != SvcStateReport::Running)
+                if (self.hashd[0] && rep.hashd[HASHD_A].svc.state != SvcStateReport::Running)
+                    || (self.hashd[1] && rep.hashd[HASHD_B].svc.state != SvcStateReport::Running)
                 {
                     let mut states = String::new();
                     if self.hashd[0] {
-                        write!(states, ", hashd-A {:?}", rep.hashd[0].svc.state).unwrap();
+                        write!(states, ", hashd-A {:?}", rep.hashd[HASHD_A].svc.state).unwrap();
                     }
                     if self.hashd[1] {
-                        write!(states, ", hashd-B {:?}", rep.hashd[1].svc.state).unwrap();
+                        write!(states, ", hashd-B {:?}", rep.hashd[HASHD_B].svc.state).unwrap();
                     }
                     result = Err(anyhow!("hashd failed while waiting{}", &states));
                     return true;
@@ -1520,8 +1520,8 @@ impl WorkloadMon {
                 }
 
                 self.hashd_loads = [
-                    rep.hashd[0].rps / bench.hashd.rps_max as f64,
-                    rep.hashd[1].rps / bench.hashd.rps_max as f64,
+                    rep.hashd[HASHD_A].rps / bench.hashd.rps_max as f64,
+                    rep.hashd[HASHD_B].rps / bench.hashd.rps_max as f64,
                 ];
                 self.time_remaining = match self.timeout.as_ref() {
                     Some(timeout) => {
@@ -1571,14 +1571,14 @@ impl WorkloadMon {
                 status,
                 "load:{:>4}% lat:{:>5} ",
                 format4_pct(mon.hashd_loads[0]),
-                format_duration(rep.hashd[0].lat.ctl)
+                format_duration(rep.hashd[HASHD_A].lat.ctl)
             )
             .unwrap(),
             (false, true) => write!(
                 status,
                 "load:{:>4}% lat:{:>5}",
                 format4_pct(mon.hashd_loads[1]),
-                format_duration(rep.hashd[1].lat.ctl)
+                format_duration(rep.hashd[HASHD_B].lat.ctl)
             )
             .unwrap(),
             (true, true) => write!(
@@ -1586,8 +1586,8 @@ impl WorkloadMon {
                 "load:{:>4}%/{:>4}% lat:{:>5}/{:>5}",
                 format4_pct(mon.hashd_loads[0]),
                 format4_pct(mon.hashd_loads[1]),
-                format_duration(rep.hashd[0].lat.ctl),
-                format_duration(rep.hashd[1].lat.ctl),
+                format_duration(rep.hashd[HASHD_A].lat.ctl),
+                format_duration(rep.hashd[HASHD_B].lat.ctl),
             )
             .unwrap(),
             _ => {}
@@ -279,6 +279,14 @@ pub struct ResourceStat {
     pub psi_cpu: PctsMap,
     pub psi_mem: (PctsMap, PctsMap),
     pub psi_io: (PctsMap, PctsMap),
+    #[serde(default)]
+    pub psi_irq: PctsMap,
+    #[serde(default)]
+    pub kpsi_cpu: PctsMap,
+    #[serde(default)]
+    pub kpsi_mem: (PctsMap, PctsMap),
+    #[serde(default)]
+    pub kpsi_io: (PctsMap, PctsMap),
 
     pub mem_stat: BTreeMap<String, PctsMap>,
     pub io_stat: BTreeMap<String, PctsMap>,
@@ -356,6 +364,26 @@ impl ResourceStat {
         print_pcts_line(out, fn_len, "mem-full%", &self.psi_mem.1, format_pct, None);
         print_pcts_line(out, fn_len, "io-some%", &self.psi_io.0, format_pct, None);
         print_pcts_line(out, fn_len, "io-full%", &self.psi_io.1, format_pct, None);
+        print_pcts_line(out, fn_len, "irq-full%", &self.psi_irq, format_pct, None);
+        print_pcts_line(out, fn_len, "kcpu-some%", &self.kpsi_cpu, format_pct, None);
+        print_pcts_line(
+            out,
+            fn_len,
+            "kmem-some%",
+            &self.kpsi_mem.0,
+            format_pct,
+            None,
+        );
+        print_pcts_line(
+            out,
+            fn_len,
+            "kmem-full%",
+            &self.kpsi_mem.1,
+            format_pct,
+            None,
+        );
+        print_pcts_line(out, fn_len, "kio-some%", &self.kpsi_io.0, format_pct, None);
+        print_pcts_line(out, fn_len, "kio-full%", &self.kpsi_io.1, format_pct, None);
 
         if opts.rstat == 0 {
             return;
@@ -398,6 +426,7 @@ pub struct ResourceStatStudyCtx {
     cpu_stall: RefCell<Option<f64>>,
     mem_stalls: (RefCell<Option<f64>>, RefCell<Option<f64>>),
     io_stalls: (RefCell<Option<f64>>, RefCell<Option<f64>>),
+    irq_stall: RefCell<Option<f64>>,
     stats: Vec<RefCell<Option<f64>>>,
 }
 
@@ -421,6 +450,7 @@ impl ResourceStatStudyCtx {
         self.mem_stalls.1.replace(None);
         self.io_stalls.0.replace(None);
         self.io_stalls.1.replace(None);
+        self.irq_stall.replace(None);
 
         for v in self.stats.iter() {
             v.replace(None);
@@ -446,6 +476,17 @@ pub struct ResourceStatStudy<'a> {
         Box<dyn StudyMeanPctsTrait + 'a>,
         Box<dyn StudyMeanPctsTrait + 'a>,
     ),
+    psi_irq_study: Box<dyn StudyMeanPctsTrait + 'a>,
+    // The kernel's own avg10 running averages
+    kpsi_cpu_study: Box<dyn StudyMeanPctsTrait + 'a>,
+    kpsi_mem_studies: (
+        Box<dyn StudyMeanPctsTrait + 'a>,
+        Box<dyn StudyMeanPctsTrait + 'a>,
+    ),
+    kpsi_io_studies: (
+        Box<dyn StudyMeanPctsTrait + 'a>,
+        Box<dyn StudyMeanPctsTrait + 'a>,
+    ),
     mem_stat_studies: Vec<Box<dyn StudyMeanPctsTrait + 'a>>,
     io_stat_studies: Vec<Box<dyn StudyMeanPctsTrait + 'a>>,
     vmstat_studies: Vec<Box<dyn StudyMeanPctsTrait + 'a>>,
@@ -575,6 +616,34 @@ impl<'a> ResourceStatStudy<'a> {
                     None,
                 )),
             ),
+            psi_irq_study: Box::new(StudyMeanPcts::new(
+                sel_delta(move |arg| arg.rep.usages[name].irq_stalls.1, &ctx.irq_stall),
+                None,
+            )),
+            kpsi_cpu_study: Box::new(StudyMeanPcts::new(
+                move |arg| [arg.rep.usages[name].cpu_psi.some.avg10].repeat(arg.cnt),
+                None,
+            )),
+            kpsi_mem_studies: (
+                Box::new(StudyMeanPcts::new(
+                    move |arg| [arg.rep.usages[name].mem_psi.some.avg10].repeat(arg.cnt),
+                    None,
+                )),
+                Box::new(StudyMeanPcts::new(
+                    move |arg| [arg.rep.usages[name].mem_psi.full.avg10].repeat(arg.cnt),
+                    None,
+                )),
+            ),
+            kpsi_io_studies: (
+                Box::new(StudyMeanPcts::new(
+                    move |arg| [arg.rep.usages[name].io_psi.some.avg10].repeat(arg.cnt),
+                    None,
+                )),
+                Box::new(StudyMeanPcts::new(
+                    move |arg| [arg.rep.usages[name].io_psi.full.avg10].repeat(arg.cnt),
+                    None,
+                )),
+            ),
             mem_stat_studies: MEM_STAT_KEYS
                 .iter()
                 .map(|key| {
@@ -625,6 +694,12 @@ impl<'a> ResourceStatStudy<'a> {
             self.psi_mem_studies.1.as_study_mut(),
             self.psi_io_studies.0.as_study_mut(),
             self.psi_io_studies.1.as_study_mut(),
+            self.psi_irq_study.as_study_mut(),
+            self.kpsi_cpu_study.as_study_mut(),
+            self.kpsi_mem_studies.0.as_study_mut(),
+            self.kpsi_mem_studies.1.as_study_mut(),
+            self.kpsi_io_studies.0.as_study_mut(),
+            self.kpsi_io_studies.1.as_study_mut(),
         ];
         for study in self
             .mem_stat_studies
@@ -656,6 +731,16 @@ impl<'a> ResourceStatStudy<'a> {
                 self.psi_io_studies.0.result(pcts),
                 self.psi_io_studies.1.result(pcts),
             ),
+            psi_irq: self.psi_irq_study.result(pcts),
+            kpsi_cpu: self.kpsi_cpu_study.result(pcts),
+            kpsi_mem: (
+                self.kpsi_mem_studies.0.result(pcts),
+                self.kpsi_mem_studies.1.result(pcts),
+            ),
+            kpsi_io: (
+                self.kpsi_io_studies.0.result(pcts),
+                self.kpsi_io_studies.1.result(pcts),
+            ),
             mem_stat: MEM_STAT_KEYS
                 .iter()
                 .zip(self.mem_stat_studies.iter())
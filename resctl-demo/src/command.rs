@@ -8,7 +8,7 @@ use std::thread::sleep;
 use std::time::{Duration, SystemTime};
 
 use super::{agent, AGENT_FILES};
-use rd_agent_intf::{Cmd, HashdCmd, MemoryKnob, Slice};
+use rd_agent_intf::{Cmd, HashdCmd, MemoryKnob, Slice, HASHD_A, HASHD_B};
 use rd_util::*;
 
 lazy_static::lazy_static! {
@@ -66,7 +66,10 @@ impl CmdState {
         self.bench_hashd_cur = bench.hashd_seq;
         self.bench_iocost_cur = bench.iocost_seq;
 
-        self.hashd = cmd.hashd.clone();
+        self.hashd = [
+            cmd.hashd.get(HASHD_A).cloned().unwrap_or_default(),
+            cmd.hashd.get(HASHD_B).cloned().unwrap_or_default(),
+        ];
         self.sys_cpu_ratio =
             slices[Slice::Sys].cpu_weight as f64 / slices[Slice::Work].cpu_weight as f64;
         self.sys_io_ratio =
@@ -112,12 +115,12 @@ impl CmdState {
         }
         cmd.bench_iocost_seq = self.bench_iocost_next;
 
-        cmd.hashd = self.hashd.clone();
-        if cmd.hashd[0].rps_target_ratio == 1.0 {
-            cmd.hashd[0].rps_target_ratio = 10.0;
-        }
-        if cmd.hashd[1].rps_target_ratio == 1.0 {
-            cmd.hashd[1].rps_target_ratio = 10.0;
+        for (name, hashd) in [HASHD_A, HASHD_B].iter().zip(self.hashd.iter()) {
+            let hc = cmd.hashd_mut(name);
+            *hc = hashd.clone();
+            if hc.rps_target_ratio == 1.0 {
+                hc.rps_target_ratio = 10.0;
+            }
         }
         cmd.sideloads = self.sideloads.clone();
         cmd.sysloads = self.sysloads.clone();
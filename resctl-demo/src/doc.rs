@@ -190,7 +190,7 @@ fn exec_one_cmd(siv: &mut Cursive, cmd: &RdCmd) {
     info!("executing {:?}", cmd);
 
     let mut cs = CMD_STATE.lock().unwrap();
-    let wbps = AGENT_FILES.bench().iocost.model.wbps as f64;
+    let wbps = AGENT_FILES.bench().scr_iocost().model.wbps as f64;
 
     match cmd {
         RdCmd::On(sw) | RdCmd::Off(sw) => {
@@ -440,7 +440,7 @@ fn format_knob_val(knob: &RdKnob, ratio: f64) -> String {
         }
         RdKnob::HashdAMem | RdKnob::HashdBMem => format_size(ratio * bench.hashd.mem_size as f64),
         RdKnob::HashdALogBps | RdKnob::HashdBLogBps => {
-            format_size(ratio * bench.iocost.model.wbps as f64)
+            format_size(ratio * bench.scr_iocost().model.wbps as f64)
         }
         RdKnob::MemMargin => format_size(ratio * total_memory() as f64),
         RdKnob::Balloon => format_size(ratio * total_memory() as f64),
@@ -536,7 +536,7 @@ fn hashd_cmd_anon_addr_stdev(hashd: &HashdCmd) -> f64 {
 }
 
 fn refresh_knobs(siv: &mut Cursive, doc: &RdDoc, cs: &CmdState) {
-    let wbps = AGENT_FILES.bench().iocost.model.wbps as f64;
+    let wbps = AGENT_FILES.bench().scr_iocost().model.wbps as f64;
 
     for knob in doc.knobs.iter() {
         let val = match knob {
@@ -24,7 +24,7 @@ use super::{
     get_layout, kick_refresh, Layout, AGENT_FILES, COLOR_ACTIVE, COLOR_ALERT, COLOR_GRAPH_1,
     COLOR_GRAPH_2, COLOR_GRAPH_3, COLOR_INACTIVE, TEMP_DIR,
 };
-use rd_agent_intf::Report;
+use rd_agent_intf::{Report, HASHD_A, HASHD_B};
 use rd_util::*;
 
 const GRAPH_X_ADJ: usize = 20;
@@ -480,6 +480,10 @@ enum PlotId {
     WorkIoPsiFull,
     SideIoPsiFull,
     SysIoPsiFull,
+    WorkMemPsiFullAvg10,
+    WorkMemPsiFullAvg60,
+    WorkIoPsiFullAvg10,
+    WorkIoPsiFullAvg60,
     ReadLatP50,
     ReadLatP90,
     ReadLatP99,
@@ -491,18 +495,20 @@ enum PlotId {
 }
 
 fn plot_spec_factory(id: PlotId) -> PlotSpec {
-    fn rps_spec(idx: usize, range_factor: f64) -> PlotSpec {
+    fn rps_spec(name: &'static str, range_factor: f64) -> PlotSpec {
         PlotSpec {
-            sel: Box::new(move |rep: &Report| rep.hashd[idx].rps),
+            sel: Box::new(move |rep: &Report| rep.hashd.get(name).map(|h| h.rps).unwrap_or(0.0)),
             aggr: PlotDataAggr::AVG,
             title: Box::new(|| "rps".into()),
             min: Box::new(|| 0.0),
             max: Box::new(move || AGENT_FILES.bench().hashd.rps_max as f64 * range_factor),
         }
     }
-    fn lat_spec(idx: usize) -> PlotSpec {
+    fn lat_spec(name: &'static str) -> PlotSpec {
         PlotSpec {
-            sel: Box::new(move |rep: &Report| rep.hashd[idx].lat.ctl * 1000.0),
+            sel: Box::new(move |rep: &Report| {
+                rep.hashd.get(name).map(|h| h.lat.ctl).unwrap_or(0.0) * 1000.0
+            }),
             aggr: PlotDataAggr::MAX,
             title: Box::new(|| "lat".into()),
             min: Box::new(|| 0.0),
@@ -620,6 +626,33 @@ fn plot_spec_factory(id: PlotId) -> PlotSpec {
             max: Box::new(|| 100.0),
         }
     }
+    fn psi_full_avg_spec(slice: &'static str, res: &'static str, avg: u32) -> PlotSpec {
+        PlotSpec {
+            sel: Box::new(move |rep: &Report| {
+                let usage = rep.usages.get(slice).unwrap();
+                let psi = match res {
+                    "mem" => &usage.mem_psi,
+                    _ => &usage.io_psi,
+                };
+                match avg {
+                    10 => psi.full.avg10 * 100.0,
+                    60 => psi.full.avg60 * 100.0,
+                    _ => psi.full.avg300 * 100.0,
+                }
+            }),
+            aggr: PlotDataAggr::AVG,
+            title: Box::new(move || {
+                format!(
+                    "{}-{}-psi-full-avg{}",
+                    slice.trim_end_matches(".slice"),
+                    res,
+                    avg
+                )
+            }),
+            min: Box::new(|| 0.0),
+            max: Box::new(|| 100.0),
+        }
+    }
     fn io_lat_spec(iotype: &'static str, pct: &'static str) -> PlotSpec {
         PlotSpec {
             sel: Box::new(move |rep: &Report| rep.iolat.map[iotype][pct] * 1000.0),
@@ -631,11 +664,11 @@ fn plot_spec_factory(id: PlotId) -> PlotSpec {
     }
 
     match id {
-        PlotId::HashdARps => rps_spec(0, 1.1),
-        PlotId::HashdALat => lat_spec(0),
-        PlotId::HashdBRps => rps_spec(1, 1.1),
-        PlotId::HashdBLat => lat_spec(1),
-        PlotId::HashdARpsMax100 => rps_spec(0, 1.0),
+        PlotId::HashdARps => rps_spec(HASHD_A, 1.1),
+        PlotId::HashdALat => lat_spec(HASHD_A),
+        PlotId::HashdBRps => rps_spec(HASHD_B, 1.1),
+        PlotId::HashdBLat => lat_spec(HASHD_B),
+        PlotId::HashdARpsMax100 => rps_spec(HASHD_A, 1.0),
         PlotId::WorkCpu => cpu_spec("workload.slice"),
         PlotId::SideCpu => cpu_spec("sideload.slice"),
         PlotId::SysCpu => cpu_spec("system.slice"),
@@ -670,6 +703,10 @@ fn plot_spec_factory(id: PlotId) -> PlotSpec {
         PlotId::WorkIoPsiFull => io_psi_spec("workload.slice", true),
         PlotId::SideIoPsiFull => io_psi_spec("sideload.slice", true),
         PlotId::SysIoPsiFull => io_psi_spec("system.slice", true),
+        PlotId::WorkMemPsiFullAvg10 => psi_full_avg_spec("workload.slice", "mem", 10),
+        PlotId::WorkMemPsiFullAvg60 => psi_full_avg_spec("workload.slice", "mem", 60),
+        PlotId::WorkIoPsiFullAvg10 => psi_full_avg_spec("workload.slice", "io", 10),
+        PlotId::WorkIoPsiFullAvg60 => psi_full_avg_spec("workload.slice", "io", 60),
         PlotId::ReadLatP50 => io_lat_spec("read", "50"),
         PlotId::ReadLatP90 => io_lat_spec("read", "90"),
         PlotId::ReadLatP99 => io_lat_spec("read", "99"),
@@ -709,6 +746,8 @@ pub enum GraphTag {
     IoPsiSome,
     MemPsiFull,
     IoPsiFull,
+    MemPsiKernel,
+    IoPsiKernel,
     ReadLat,
     WriteLat,
     IoCost,
@@ -811,6 +850,24 @@ static ALL_GRAPHS: &[(GraphTag, &str, &[PlotId])] = &[
             PlotId::SysIoPsiFull,
         ],
     ),
+    (
+        GraphTag::MemPsiKernel,
+        "Workload memory full pressure vs. kernel averages",
+        &[
+            PlotId::WorkMemPsiFull,
+            PlotId::WorkMemPsiFullAvg10,
+            PlotId::WorkMemPsiFullAvg60,
+        ],
+    ),
+    (
+        GraphTag::IoPsiKernel,
+        "Workload IO full pressure vs. kernel averages",
+        &[
+            PlotId::WorkIoPsiFull,
+            PlotId::WorkIoPsiFullAvg10,
+            PlotId::WorkIoPsiFullAvg60,
+        ],
+    ),
     (
         GraphTag::ReadLat,
         "IO read latencies (msecs)",
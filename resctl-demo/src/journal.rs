@@ -13,7 +13,7 @@ use std::sync::{Arc, Mutex};
 
 use rd_agent_intf::{
     AGENT_SVC_NAME, HASHD_BENCH_SVC_NAME, IOCOST_BENCH_SVC_NAME, OOMD_SVC_NAME,
-    SIDELOADER_SVC_NAME, SIDELOAD_SVC_PREFIX, SYSLOAD_SVC_PREFIX,
+    SIDELOAD_SVC_PREFIX, SYSLOAD_SVC_PREFIX,
 };
 use rd_util::journal_tailer::{JournalMsg, JournalTailer};
 
@@ -236,7 +236,7 @@ pub enum JournalViewId {
 pub fn updater_factory(cb_sink: CbSink, id: JournalViewId) -> Vec<Updater> {
     match id {
         JournalViewId::Default => {
-            let top_svcs = vec![AGENT_SVC_NAME, OOMD_SVC_NAME, SIDELOADER_SVC_NAME];
+            let top_svcs = vec![AGENT_SVC_NAME, OOMD_SVC_NAME];
             let mut bot_svcs = vec![HASHD_BENCH_SVC_NAME, IOCOST_BENCH_SVC_NAME];
 
             let side_svcs: Vec<String> = SIDELOAD_NAMES
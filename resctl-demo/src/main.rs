@@ -28,8 +28,7 @@ use graph::GraphSetId;
 use journal::JournalViewId;
 use rd_agent_intf::{
     AGENT_SVC_NAME, HASHD_A_SVC_NAME, HASHD_BENCH_SVC_NAME, HASHD_B_SVC_NAME,
-    IOCOST_BENCH_SVC_NAME, OOMD_SVC_NAME, SIDELOADER_SVC_NAME, SIDELOAD_SVC_PREFIX,
-    SYSLOAD_SVC_PREFIX,
+    IOCOST_BENCH_SVC_NAME, OOMD_SVC_NAME, SIDELOAD_SVC_PREFIX, SYSLOAD_SVC_PREFIX,
 };
 use rd_util::*;
 
@@ -91,7 +90,6 @@ lazy_static::lazy_static! {
         let mut names: Vec<String> = vec![
             AGENT_SVC_NAME.into(),
             OOMD_SVC_NAME.into(),
-            SIDELOADER_SVC_NAME.into(),
             HASHD_A_SVC_NAME.into(),
             HASHD_B_SVC_NAME.into(),
             HASHD_BENCH_SVC_NAME.into(),
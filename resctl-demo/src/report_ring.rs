@@ -8,7 +8,7 @@ use std::iter::Iterator;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
-use rd_agent_intf::Report;
+use rd_agent_intf::{Report, ReportIter};
 use rd_util::*;
 
 use super::AGENT_FILES;
@@ -85,19 +85,18 @@ impl ReportRing {
 
         debug!("Loading {:?}..{:?}", load_from, now);
 
-        for at in (load_from..=now).step_by(self.cadence as usize) {
-            let path = format!("{}/{}.json", &dir, at);
-            let rep = match Report::load(&path) {
+        for (rep, at) in ReportIter::new(&dir, (load_from, now + 1)).with_step(self.cadence) {
+            let rep = match rep {
                 Ok(v) => v,
                 Err(e) => {
                     match e.downcast_ref::<io::Error>() {
                         Some(ie) if ie.raw_os_error() == Some(libc::ENOENT) => {}
-                        _ => warn!("Failed to load {:?} ({:?})", &path, &e),
+                        _ => warn!("Failed to load report {} in {:?} ({:?})", at, &dir, &e),
                     }
                     continue;
                 }
             };
-            debug!("Loaded {:?}", &path);
+            debug!("Loaded report {} in {:?}", at, &dir);
             self.ring.push_back(ReportRecord { at, rep });
         }
 
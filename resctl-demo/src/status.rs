@@ -12,7 +12,8 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use rd_agent_intf::{
     HashdReport, OomdReport, ResCtlReport, RunnerState, SideloadReport, SideloaderReport,
-    SvcStateReport, SysloadReport, UsageReport, HASHD_A_SVC_NAME, HASHD_B_SVC_NAME,
+    SvcStateReport, SysloadReport, UsageReport, HASHD_A, HASHD_A_SVC_NAME, HASHD_B,
+    HASHD_B_SVC_NAME,
 };
 use rd_util::*;
 
@@ -119,6 +120,10 @@ impl UpdateWorker {
             } else {
                 line.append_styled(" -senpai", *COLOR_ALERT);
             }
+
+            if rep.builtin {
+                line.append_plain(format!("  (builtin, kills: {})", rep.nr_kills));
+            }
         }
 
         siv.call_on_name("status-oomd", |v: &mut TextView| {
@@ -295,14 +300,14 @@ impl UpdateWorker {
         Self::refresh_sideload_status(siv, &rep.sideloader, &rep.sideloads);
         Self::refresh_sysload_status(siv, &rep.sysloads);
 
-        let use_ab = rep.hashd[1].svc.state == SvcStateReport::Running;
+        let use_ab = rep.hashd[HASHD_B].svc.state == SvcStateReport::Running;
         if let Some(usage_a) = rep.usages.get(HASHD_A_SVC_NAME) {
-            Self::refresh_hashd_status(siv, &rep.hashd[0], usage_a, false, use_ab);
+            Self::refresh_hashd_status(siv, &rep.hashd[HASHD_A], usage_a, false, use_ab);
         } else {
             error!("Failed to find {:?} in usage report", HASHD_A_SVC_NAME);
         }
         if let Some(usage_b) = rep.usages.get(HASHD_B_SVC_NAME) {
-            Self::refresh_hashd_status(siv, &rep.hashd[1], usage_b, true, use_ab);
+            Self::refresh_hashd_status(siv, &rep.hashd[HASHD_B], usage_b, true, use_ab);
         } else {
             error!("Failed to find {:?} in usage report", HASHD_B_SVC_NAME);
         }